@@ -5,7 +5,7 @@ use std::collections::HashMap;
 use std::fs;
 use std::path::PathBuf;
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
 pub struct KeyBinding {
     pub key: String,
     pub modifiers: Vec<String>,
@@ -115,6 +115,21 @@ impl Default for KeyBindings {
         // Line navigation
         normal_mode.insert("move_to_line_start".to_string(), KeyBinding::new("^"));
         normal_mode.insert("move_to_line_end".to_string(), KeyBinding::new("$"));
+        normal_mode.insert("match_bracket".to_string(), KeyBinding::new("%"));
+        normal_mode.insert("move_paragraph_forward".to_string(), KeyBinding::new("}"));
+        normal_mode.insert("move_paragraph_backward".to_string(), KeyBinding::new("{"));
+        normal_mode.insert("move_sentence_forward".to_string(), KeyBinding::new(")"));
+        normal_mode.insert("move_sentence_backward".to_string(), KeyBinding::new("("));
+        normal_mode.insert("expand_selection".to_string(), KeyBinding::new("up").with_modifier("alt"));
+        normal_mode.insert("shrink_selection".to_string(), KeyBinding::new("down").with_modifier("alt"));
+
+        // Line operations, on the selected lines in visual mode
+        normal_mode.insert("join_lines".to_string(), KeyBinding::new("J"));
+        normal_mode.insert("duplicate_lines".to_string(), KeyBinding::new("d").with_modifier("alt"));
+        normal_mode.insert("move_lines_down".to_string(), KeyBinding::new("j").with_modifier("alt"));
+        normal_mode.insert("move_lines_up".to_string(), KeyBinding::new("k").with_modifier("alt"));
+        normal_mode.insert("increment_number".to_string(), KeyBinding::new("a").with_modifier("ctrl"));
+        normal_mode.insert("decrement_number".to_string(), KeyBinding::new("x").with_modifier("ctrl"));
 
         // File navigation - using simple keys for now
         normal_mode.insert("move_to_file_start".to_string(), KeyBinding::new("g"));
@@ -178,6 +193,10 @@ impl Default for KeyBindings {
             "show_help".to_string(),
             KeyBinding::new("h").with_modifier("ctrl"),
         );
+        normal_mode.insert(
+            "command_palette".to_string(),
+            KeyBinding::new("p").with_modifier("ctrl"),
+        );
 
         let mut insert_mode = HashMap::new();
         insert_mode.insert("normal_mode".to_string(), KeyBinding::new("esc"));
@@ -193,12 +212,23 @@ impl Default for KeyBindings {
         file_finder_mode.insert("select".to_string(), KeyBinding::new("enter"));
         file_finder_mode.insert("next".to_string(), KeyBinding::new("down"));
         file_finder_mode.insert("previous".to_string(), KeyBinding::new("up"));
+        file_finder_mode.insert("toggle_hidden".to_string(), KeyBinding::new("h").with_modifier("alt"));
+        file_finder_mode.insert("cycle_scope".to_string(), KeyBinding::new("s").with_modifier("alt"));
+        file_finder_mode.insert("preview".to_string(), KeyBinding::new("tab"));
+        file_finder_mode.insert("create_file".to_string(), KeyBinding::new("n").with_modifier("alt"));
 
         let mut token_search_mode = HashMap::new();
         token_search_mode.insert("cancel".to_string(), KeyBinding::new("esc"));
         token_search_mode.insert("select".to_string(), KeyBinding::new("enter"));
         token_search_mode.insert("next".to_string(), KeyBinding::new("down"));
         token_search_mode.insert("previous".to_string(), KeyBinding::new("up"));
+        token_search_mode.insert("toggle_regex".to_string(), KeyBinding::new("r").with_modifier("alt"));
+        token_search_mode.insert("toggle_case_sensitive".to_string(), KeyBinding::new("c").with_modifier("alt"));
+        token_search_mode.insert("toggle_whole_word".to_string(), KeyBinding::new("w").with_modifier("alt"));
+        token_search_mode.insert("edit_glob".to_string(), KeyBinding::new("g").with_modifier("alt"));
+        token_search_mode.insert("replace".to_string(), KeyBinding::new("r").with_modifier("ctrl"));
+        token_search_mode.insert("preview".to_string(), KeyBinding::new("tab"));
+        token_search_mode.insert("edit_results".to_string(), KeyBinding::new("e").with_modifier("alt"));
 
         Self {
             normal_mode,
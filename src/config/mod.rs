@@ -1,11 +1,13 @@
 use anyhow::{Context, Result};
 use dirs;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 mod key_bindings;
-pub use key_bindings::KeyBindings;
+mod presets;
+pub use key_bindings::{KeyBinding, KeyBindings};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Config {
@@ -13,17 +15,207 @@ pub struct Config {
     pub theme: Theme,
     #[serde(default = "default_tab_size")]
     pub tab_size: usize,
+    /// Insert spaces up to the next tab stop for Tab in insert mode,
+    /// instead of a tab character
+    #[serde(default = "default_expand_tab")]
+    pub expand_tab: bool,
+    /// Apply the options in vim modelines (`vim: set ts=2 et:`) near the
+    /// start or end of files as they're opened
+    #[serde(default)]
+    pub modelines: bool,
     #[serde(default = "default_line_numbers")]
     pub line_numbers: bool,
+    /// What the line number column shows: "absolute" numbers, "relative"
+    /// distances from the cursor line, or "hybrid" (relative, with the
+    /// absolute number on the cursor line)
+    #[serde(default = "default_line_number_mode")]
+    pub line_number_mode: String,
+    /// Highlight the line the cursor is on
+    #[serde(default)]
+    pub cursorline: bool,
+    /// Highlight the column the cursor is in
+    #[serde(default)]
+    pub cursorcolumn: bool,
+    /// Columns marked with a ruler, like [80, 100]
+    #[serde(default)]
+    pub color_columns: Vec<usize>,
+    /// Draw tabs, trailing spaces and non-breaking spaces with the
+    /// `list_chars` glyphs
+    #[serde(default)]
+    pub list: bool,
+    #[serde(default)]
+    pub list_chars: ListChars,
+    /// Highlight spaces and tabs at the ends of lines
+    #[serde(default = "default_highlight_trailing_whitespace")]
+    pub highlight_trailing_whitespace: bool,
+    /// Show where the view is in the file on the editor's right border, with
+    /// ticks at functions, types and section headers
+    #[serde(default = "default_scrollbar")]
+    pub scrollbar: bool,
+    /// Mark lines that differ from the file on disk in the gutter
+    #[serde(default = "default_unsaved_markers")]
+    pub unsaved_markers: bool,
+    /// Remove trailing whitespace from every line before writing a buffer
+    #[serde(default)]
+    pub trim_on_save: bool,
+    /// Indent lines pasted with `p` and `P` like the cursor line, as `]p`
+    /// and `[p` do
+    #[serde(default)]
+    pub reindent_paste: bool,
     #[serde(default = "default_wrap_text")]
     pub wrap_text: bool,
+    /// Columns kept between the cursor and the left and right edges when
+    /// long lines scroll sideways, with `wrap_text` off
+    #[serde(default = "default_sidescrolloff")]
+    pub sidescrolloff: usize,
+    /// Width `gq` wraps lines to
+    #[serde(default = "default_text_width")]
+    pub text_width: usize,
+    /// Rows the diagnostics panel takes below the buffer; unset, it fills
+    /// the whole editor area
+    #[serde(default)]
+    pub diagnostics_height: Option<u16>,
+    /// Show the first diagnostic of each line as dim text after its end
+    #[serde(default = "default_diagnostic_virtual_text")]
+    pub diagnostic_virtual_text: bool,
+    /// Syntax highlighting theme, one of the themes bundled with syntect
+    #[serde(default = "default_syntax_theme")]
+    pub syntax_theme: String,
+    /// Reload the config whenever config.toml changes on disk
+    #[serde(default)]
+    pub watch_config: bool,
+    /// Run the buffer's formatter before writing it to disk
+    #[serde(default = "default_format_on_save")]
+    pub format_on_save: bool,
+    /// Write files through a temporary file renamed over them, so a crash
+    /// mid-write can't leave one cut short
+    #[serde(default = "default_atomic_save")]
+    pub atomic_save: bool,
+    /// Keep a copy of each file as it was before the last write, as `file~`
+    #[serde(default)]
+    pub backup: bool,
+    /// Directory for backups instead of next to each file; `~/` is expanded
+    #[serde(default)]
+    pub backup_dir: Option<String>,
+    /// Trash directory `:Delete` moves files to instead of the system
+    /// trash; `~/` is expanded
+    #[serde(default)]
+    pub trash_dir: Option<String>,
+    /// Formatter commands keyed by file extension; they read the buffer on
+    /// stdin and write the formatted text to stdout
+    #[serde(default = "default_formatters")]
+    pub formatters: HashMap<String, String>,
+    /// Compiler preset used by Ctrl+d, keyed by file extension, overriding the
+    /// built-in choice (cargo, go, tsc, pytest or make)
+    #[serde(default)]
+    pub compilers: HashMap<String, String>,
+    /// Commands `:run` uses for the current file, keyed by file extension,
+    /// with `{file}` and `{dir}` standing for its path and directory
+    #[serde(default)]
+    pub runners: HashMap<String, String>,
+    /// Lint commands run on a file when it's saved, like
+    /// `[linters.python]`, whose reports become its diagnostics
+    #[serde(default)]
+    pub linters: HashMap<String, LinterConfig>,
+    /// Environment variables for the commands zim starts: cargo, compilers,
+    /// `:!` commands, jobs and `:run`
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    /// How `:imports` merges Rust use statements: "crate", "module" or "item"
+    #[serde(default = "default_imports_granularity")]
+    pub imports_granularity: String,
+    /// Most results a token search (Ctrl+t) loads for one query
+    #[serde(default = "default_token_search_max_results")]
+    pub token_search_max_results: usize,
+    /// Silently reload buffers without unsaved changes when their file is
+    /// changed by another program
+    #[serde(default = "default_autoread")]
+    pub autoread: bool,
+    /// Show the cursor's byte and character offset in the status line
+    #[serde(default)]
+    pub show_offset: bool,
+    /// Close brackets and quotes as they're typed in insert mode
+    #[serde(default = "default_auto_pairs")]
+    pub auto_pairs: bool,
+    /// Where Ctrl+x Ctrl+f resolves relative paths in insert mode: "buffer"
+    /// (the file's directory) or "project"
+    #[serde(default = "default_path_completion_root")]
+    pub path_completion_root: String,
+    /// Key that `<leader>` stands for in keymap sequences
+    #[serde(default = "default_leader")]
+    pub leader: String,
+    /// Milliseconds a started key sequence waits for its next key
+    #[serde(default = "default_keymap_timeout")]
+    pub keymap_timeout: u64,
+    /// Most frames drawn a second, for slow links; unset, a frame is drawn
+    /// as soon as something changes
+    #[serde(default)]
+    pub max_fps: Option<u32>,
+    /// Normal mode key sequences, like "<leader>ff", mapped to a key binding
+    /// command name or a command line starting with `:`
+    #[serde(default = "default_keymap")]
+    pub keymap: HashMap<String, String>,
+    /// Built-in keybinding preset: "vim", or "emacs" for modeless editing
+    /// with Emacs keys
+    #[serde(default = "default_keymap_preset")]
+    pub keymap_preset: String,
+    /// Insert mode key sequences, written like `keymap`, added to or
+    /// overriding the ones from the preset
+    #[serde(default)]
+    pub insert_keymap: HashMap<String, String>,
+    /// Make arrow keys, Ctrl+S, Ctrl+Q, Ctrl+C and Ctrl+V work like in
+    /// mainstream editors, with a bar of hints showing the vim equivalents
+    #[serde(default)]
+    pub soft_mode: bool,
+    #[serde(default)]
+    pub ai: AiConfig,
+    /// How zim signals that a long background job finished
+    #[serde(default)]
+    pub notify: NotifyConfig,
     #[serde(default)]
     pub key_bindings: KeyBindings,
 }
 
 fn default_tab_size() -> usize { 4 }
+fn default_expand_tab() -> bool { true }
 fn default_line_numbers() -> bool { true }
+fn default_line_number_mode() -> String { "absolute".to_string() }
+fn default_highlight_trailing_whitespace() -> bool { true }
+fn default_scrollbar() -> bool { true }
+fn default_diagnostic_virtual_text() -> bool { true }
+fn default_unsaved_markers() -> bool { true }
 fn default_wrap_text() -> bool { true }
+fn default_sidescrolloff() -> usize { 5 }
+fn default_text_width() -> usize { 80 }
+fn default_syntax_theme() -> String { "Solarized (dark)".to_string() }
+fn default_format_on_save() -> bool { false }
+fn default_atomic_save() -> bool { true }
+fn default_autoread() -> bool { true }
+fn default_auto_pairs() -> bool { true }
+fn default_token_search_max_results() -> usize { 10_000 }
+fn default_imports_granularity() -> String { "crate".to_string() }
+fn default_path_completion_root() -> String { "buffer".to_string() }
+fn default_leader() -> String { "space".to_string() }
+fn default_keymap_timeout() -> u64 { 1000 }
+fn default_keymap_preset() -> String { "vim".to_string() }
+fn default_keymap() -> HashMap<String, String> {
+    let mut keymap = HashMap::new();
+    keymap.insert("<leader>ff".to_string(), "find_file".to_string());
+    keymap.insert("<leader>fs".to_string(), "token_search".to_string());
+    keymap.insert("<leader>w".to_string(), ":w".to_string());
+    keymap.insert("<leader>q".to_string(), ":q".to_string());
+    keymap.insert("<leader>bn".to_string(), "next_tab".to_string());
+    keymap.insert("<leader>bp".to_string(), "prev_tab".to_string());
+    keymap.insert("<leader>bd".to_string(), "close_tab".to_string());
+    keymap.insert("<leader>h".to_string(), "show_help".to_string());
+    keymap.insert("<leader>r".to_string(), ":run".to_string());
+    keymap
+}
+fn default_formatters() -> HashMap<String, String> {
+    let mut formatters = HashMap::new();
+    formatters.insert("rs".to_string(), "rustfmt --edition 2021".to_string());
+    formatters
+}
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Theme {
@@ -39,6 +231,12 @@ pub struct Theme {
     pub status_line_bg: String,
     #[serde(default = "default_status_line_fg")]
     pub status_line_fg: String,
+    /// Background of the cursor line and column
+    #[serde(default = "default_cursorline")]
+    pub cursorline: String,
+    /// Background of the `color_columns` rulers
+    #[serde(default = "default_color_column")]
+    pub color_column: String,
 }
 
 fn default_background() -> String { "#282c34".to_string() }
@@ -47,6 +245,167 @@ fn default_selection() -> String { "#3e4451".to_string() }
 fn default_cursor() -> String { "#528bff".to_string() }
 fn default_status_line_bg() -> String { "#4b5263".to_string() }
 fn default_status_line_fg() -> String { "#abb2bf".to_string() }
+fn default_cursorline() -> String { "#2c313a".to_string() }
+fn default_color_column() -> String { "#3b4048".to_string() }
+
+/// Glyphs that show whitespace in list mode
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ListChars {
+    #[serde(default = "default_list_tab")]
+    pub tab: String,
+    #[serde(default = "default_list_trail")]
+    pub trail: String,
+    #[serde(default = "default_list_nbsp")]
+    pub nbsp: String,
+}
+
+fn default_list_tab() -> String { "→".to_string() }
+fn default_list_trail() -> String { "·".to_string() }
+fn default_list_nbsp() -> String { "␣".to_string() }
+
+impl Default for ListChars {
+    fn default() -> Self {
+        Self {
+            tab: default_list_tab(),
+            trail: default_list_trail(),
+            nbsp: default_list_nbsp(),
+        }
+    }
+}
+
+impl ListChars {
+    /// Change glyphs from a vim-style list like "tab:>,trail:-,nbsp:+"
+    pub fn set(&mut self, list: &str) -> Result<()> {
+        for item in list.split(',').filter(|item| !item.is_empty()) {
+            let (name, glyph) = item.split_once(':')
+                .with_context(|| format!("Expected name:glyph, not {}", item))?;
+            match name {
+                "tab" => self.tab = glyph.to_string(),
+                "trail" => self.trail = glyph.to_string(),
+                "nbsp" => self.nbsp = glyph.to_string(),
+                _ => return Err(anyhow::anyhow!("Unknown listchars item: {}", name)),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A `[linters.<name>]` entry
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LinterConfig {
+    /// Shell command that lints a file; `{file}` stands for its path, which
+    /// is added at the end when it isn't used
+    pub command: String,
+    /// Extensions of the files it lints; when empty, the usual ones for the
+    /// entry's name (`python`, `javascript`, `typescript`, `go`, `ruby`,
+    /// `shell`, `c`, `cpp`), or the name itself as an extension
+    #[serde(default)]
+    pub extensions: Vec<String>,
+    /// How its report reads: "json", or "lines" for `file:line:col:
+    /// message`; guessed from the output when unset
+    #[serde(default)]
+    pub format: Option<String>,
+}
+
+/// Settings for AI-assisted features
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AiConfig {
+    /// Shell command that reads a prompt on stdin and prints the reply; AI
+    /// features are off while this is unset
+    #[serde(default)]
+    pub command: Option<String>,
+    /// Environment variables for the command. A value like `$OPENAI_API_KEY`
+    /// or `${OPENAI_API_KEY}` is read from zim's own environment, so keys
+    /// don't have to be written here
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    /// Command that prints the API key, like `pass show openai`; it runs once
+    /// per session and the key is passed to the AI command in `key_env`
+    #[serde(default)]
+    pub key_cmd: Option<String>,
+    /// Variable the key from `key_cmd` is passed in (`ZIM_AI_KEY` if unset)
+    #[serde(default)]
+    pub key_env: Option<String>,
+    /// Give up on a request after this long
+    #[serde(default = "default_ai_timeout_seconds")]
+    pub timeout_seconds: u64,
+    /// How many more times to try a request that failed (but not one that
+    /// timed out)
+    #[serde(default = "default_ai_retries")]
+    pub retries: u32,
+    /// Most requests sent in any minute; 0 for no limit
+    #[serde(default = "default_ai_requests_per_minute")]
+    pub requests_per_minute: u32,
+    /// Send no requests; AI features fall back to their non-AI behavior
+    #[serde(default)]
+    pub offline: bool,
+    /// Suggest a continuation as ghost text when typing pauses in insert
+    /// mode, accepted with Tab
+    #[serde(default)]
+    pub inline_completion: bool,
+    /// How long typing has to pause before a suggestion is asked for
+    #[serde(default = "default_ai_inline_delay_ms")]
+    pub inline_delay_ms: u64,
+}
+
+fn default_ai_timeout_seconds() -> u64 { 30 }
+fn default_ai_retries() -> u32 { 1 }
+fn default_ai_requests_per_minute() -> u32 { 10 }
+fn default_ai_inline_delay_ms() -> u64 { 600 }
+
+impl Default for AiConfig {
+    fn default() -> Self {
+        Self {
+            command: None,
+            env: HashMap::new(),
+            key_cmd: None,
+            key_env: None,
+            timeout_seconds: default_ai_timeout_seconds(),
+            retries: default_ai_retries(),
+            requests_per_minute: default_ai_requests_per_minute(),
+            offline: false,
+            inline_completion: false,
+            inline_delay_ms: default_ai_inline_delay_ms(),
+        }
+    }
+}
+
+/// What to do when a background job finishes, per kind of job: "none",
+/// "bell" to ring the terminal bell, "flash" to flash the status line, or
+/// "both"
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NotifyConfig {
+    /// cargo check and clippy, and compiler presets
+    #[serde(default = "default_notify")]
+    pub build: String,
+    /// `:!` commands, like `:!cargo test`
+    #[serde(default = "default_notify")]
+    pub shell: String,
+    /// Requests to the AI command
+    #[serde(default = "default_notify")]
+    pub ai: String,
+    /// Token searches
+    #[serde(default = "default_notify")]
+    pub search: String,
+    /// Jobs that finish sooner than this are not signalled
+    #[serde(default = "default_notify_after_seconds")]
+    pub after_seconds: u64,
+}
+
+fn default_notify() -> String { "none".to_string() }
+fn default_notify_after_seconds() -> u64 { 5 }
+
+impl Default for NotifyConfig {
+    fn default() -> Self {
+        Self {
+            build: default_notify(),
+            shell: default_notify(),
+            ai: default_notify(),
+            search: default_notify(),
+            after_seconds: default_notify_after_seconds(),
+        }
+    }
+}
 
 // The default implementations now use the default functions we defined above
 impl Default for Config {
@@ -54,8 +413,52 @@ impl Default for Config {
         Self {
             theme: Theme::default(),
             tab_size: default_tab_size(),
+            expand_tab: default_expand_tab(),
+            modelines: false,
             line_numbers: default_line_numbers(),
+            line_number_mode: default_line_number_mode(),
+            cursorline: false,
+            cursorcolumn: false,
+            color_columns: Vec::new(),
+            list: false,
+            list_chars: ListChars::default(),
+            highlight_trailing_whitespace: default_highlight_trailing_whitespace(),
+            scrollbar: default_scrollbar(),
+            unsaved_markers: default_unsaved_markers(),
+            trim_on_save: false,
+            reindent_paste: false,
             wrap_text: default_wrap_text(),
+            sidescrolloff: default_sidescrolloff(),
+            text_width: default_text_width(),
+            diagnostics_height: None,
+            diagnostic_virtual_text: default_diagnostic_virtual_text(),
+            syntax_theme: default_syntax_theme(),
+            watch_config: false,
+            format_on_save: default_format_on_save(),
+            atomic_save: default_atomic_save(),
+            backup: false,
+            backup_dir: None,
+            trash_dir: None,
+            formatters: default_formatters(),
+            compilers: HashMap::new(),
+            runners: HashMap::new(),
+            linters: HashMap::new(),
+            env: HashMap::new(),
+            imports_granularity: default_imports_granularity(),
+            token_search_max_results: default_token_search_max_results(),
+            autoread: default_autoread(),
+            show_offset: false,
+            auto_pairs: default_auto_pairs(),
+            path_completion_root: default_path_completion_root(),
+            leader: default_leader(),
+            keymap_timeout: default_keymap_timeout(),
+            max_fps: None,
+            keymap: default_keymap(),
+            keymap_preset: default_keymap_preset(),
+            insert_keymap: HashMap::new(),
+            soft_mode: false,
+            ai: AiConfig::default(),
+            notify: NotifyConfig::default(),
             key_bindings: KeyBindings::default(),
         }
     }
@@ -70,6 +473,8 @@ impl Default for Theme {
             cursor: default_cursor(),
             status_line_bg: default_status_line_bg(),
             status_line_fg: default_status_line_fg(),
+            cursorline: default_cursorline(),
+            color_column: default_color_column(),
         }
     }
 }
@@ -121,6 +526,51 @@ impl Config {
         }
     }
 
+    /// Whether the emacs preset is picked, so editing happens in insert mode
+    pub fn is_emacs(&self) -> bool {
+        self.keymap_preset == "emacs"
+    }
+
+    /// Normal mode sequences: the built-in ones and soft mode's keys with
+    /// the user's `keymap` on top
+    pub fn normal_sequences(&self) -> HashMap<String, String> {
+        let mut sequences = presets::builtin();
+        if self.soft_mode {
+            sequences.extend(presets::soft());
+        }
+        sequences.extend(self.keymap.clone());
+        sequences
+    }
+
+    /// Insert mode sequences of the keymap preset and soft mode, with the
+    /// user's `insert_keymap` on top
+    pub fn insert_sequences(&self) -> Result<HashMap<String, String>> {
+        let mut sequences = match self.keymap_preset.as_str() {
+            "vim" => HashMap::new(),
+            "emacs" => presets::emacs(),
+            other => return Err(anyhow::anyhow!("Unknown keymap preset: {} (use vim or emacs)", other)),
+        };
+        if self.soft_mode {
+            sequences.extend(presets::soft());
+        }
+        sequences.extend(self.insert_keymap.clone());
+        Ok(sequences)
+    }
+
+    /// Where the config file lives
+    pub fn path() -> Result<PathBuf> {
+        Ok(get_config_dir()?.join("config.toml"))
+    }
+
+    /// Read a config file, reporting what's wrong with it instead of
+    /// falling back to the defaults like `load`
+    pub fn read(path: &Path) -> Result<Self> {
+        let config_str = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file: {:?}", path))?;
+        toml::from_str(&config_str)
+            .map_err(|e| anyhow::anyhow!("Invalid config file {:?}: {}", path, e))
+    }
+
     pub fn save(&self) -> Result<()> {
         let config_dir = get_config_dir()?;
         let config_path = config_dir.join("config.toml");
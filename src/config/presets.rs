@@ -0,0 +1,96 @@
+use std::collections::HashMap;
+
+/// Normal mode sequences that are always there: the `*` register (the
+/// primary selection), pasting at the cursor line's indentation, moving
+/// through the undo tree, case and reflow operators, numbering selected
+/// lines and scrolling sideways
+pub fn builtin() -> HashMap<String, String> {
+    [
+        ("\"*p", "paste_primary"),
+        ("\"*P", "paste_primary_before"),
+        ("\"*y", "yank_primary"),
+        ("]p", "paste_indented"),
+        ("[p", "paste_indented_before"),
+        ("]P", "paste_indented_before"),
+        ("[P", "paste_indented_before"),
+        ("g-", "undo_older"),
+        ("g+", "undo_newer"),
+        ("gu", "lowercase"),
+        ("gU", "uppercase"),
+        ("g~", "toggle_case"),
+        ("gq", "reflow"),
+        ("g<C-a>", "increment_sequence"),
+        ("g<C-x>", "decrement_sequence"),
+        ("zh", "scroll_left"),
+        ("zl", "scroll_right"),
+        ("zH", "scroll_half_left"),
+        ("zL", "scroll_half_right"),
+    ]
+    .into_iter()
+    .map(|(keys, command)| (keys.to_string(), command.to_string()))
+    .collect()
+}
+
+/// Mainstream editor keys added to the normal and insert mode keymaps in
+/// soft mode
+pub fn soft() -> HashMap<String, String> {
+    [
+        ("<left>", "move_left"),
+        ("<down>", "move_down"),
+        ("<up>", "move_up"),
+        ("<right>", "move_right"),
+        ("<home>", "move_to_line_start"),
+        ("<end>", "move_to_line_end"),
+        ("<C-s>", ":w"),
+        ("<C-q>", ":q"),
+        ("<C-c>", "copy"),
+        ("<C-v>", "paste"),
+    ]
+    .into_iter()
+    .map(|(keys, command)| (keys.to_string(), command.to_string()))
+    .collect()
+}
+
+/// Emacs-style insert mode sequences for `keymap_preset = "emacs"`, which
+/// also keeps the editor in insert mode so it works without modes
+pub fn emacs() -> HashMap<String, String> {
+    [
+        // Navigation
+        ("<C-f>", "move_right"),
+        ("<C-b>", "move_left"),
+        ("<C-n>", "move_down"),
+        ("<C-p>", "move_up"),
+        ("<C-a>", "move_to_line_start"),
+        ("<C-e>", "move_to_line_end"),
+        ("<A-f>", "move_word_forward"),
+        ("<A-b>", "move_word_backward"),
+        ("<A-lt>", "move_to_file_start"),
+        ("<A-gt>", "move_to_file_end"),
+        ("<C-v>", "page_down"),
+        ("<A-v>", "page_up"),
+        // Killing and yanking
+        ("<C-d>", "delete_char_forward"),
+        ("<C-k>", "kill_line"),
+        ("<A-d>", "kill_word"),
+        ("<C-space>", "set_mark"),
+        ("<C-w>", "kill_region"),
+        ("<A-w>", "copy_region"),
+        ("<C-y>", "yank"),
+        ("<C-/>", "undo"),
+        ("<C-_>", "undo"),
+        ("<C-g>", "cancel"),
+        // Search
+        ("<C-s>", "token_search"),
+        ("<C-r>", "token_search"),
+        // Files, buffers and commands
+        ("<C-x><C-s>", ":w"),
+        ("<C-x><C-f>", "find_file"),
+        ("<C-x><C-c>", ":q"),
+        ("<C-x>k", "close_tab"),
+        ("<C-x>b", "next_tab"),
+        ("<A-x>", "command_mode"),
+    ]
+    .into_iter()
+    .map(|(keys, command)| (keys.to_string(), command.to_string()))
+    .collect()
+}
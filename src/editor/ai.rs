@@ -0,0 +1,226 @@
+use crate::config::AiConfig;
+use anyhow::{Context, Result};
+use std::collections::VecDeque;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// Variable the key from `key_cmd` is passed in when `key_env` isn't set
+const DEFAULT_KEY_ENV: &str = "ZIM_AI_KEY";
+
+/// Variables to run the AI command with
+pub struct AiEnvironment {
+    pub vars: Vec<(String, String)>,
+    /// Values taken from zim's environment or printed by `key_cmd`, which
+    /// must not show up in messages
+    pub secrets: Vec<String>,
+}
+
+/// Work out the AI command's environment from `[ai]`: `env` values, where
+/// `$NAME` or `${NAME}` is read from zim's own environment, and the key
+/// printed by `key_cmd`
+pub fn environment(config: &AiConfig) -> Result<AiEnvironment> {
+    let mut environment = AiEnvironment { vars: Vec::new(), secrets: Vec::new() };
+    for (name, value) in &config.env {
+        let reference = value.strip_prefix("${").and_then(|rest| rest.strip_suffix('}'))
+            .or_else(|| value.strip_prefix('$'));
+        let value = match reference {
+            Some(variable) => {
+                let value = std::env::var(variable)
+                    .map_err(|_| anyhow::anyhow!("AI env {}: ${} isn't set", name, variable))?;
+                environment.secrets.push(value.clone());
+                value
+            },
+            None => value.clone(),
+        };
+        environment.vars.push((name.clone(), value));
+    }
+
+    if let Some(key_cmd) = &config.key_cmd {
+        let output = Command::new("sh").arg("-c").arg(key_cmd).stdin(Stdio::null()).output()
+            .with_context(|| format!("Failed to run key_cmd: {}", key_cmd))?;
+        let key = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        if !output.status.success() || key.is_empty() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let reason = stderr.lines().find(|line| !line.trim().is_empty()).unwrap_or("printed no key");
+            return Err(anyhow::anyhow!("key_cmd failed: {}", reason));
+        }
+        environment.secrets.push(key.clone());
+        let key_env = config.key_env.clone().unwrap_or_else(|| DEFAULT_KEY_ENV.to_string());
+        environment.vars.push((key_env, key));
+    }
+    Ok(environment)
+}
+
+/// The AI command ran past `timeout_seconds` and was stopped
+#[derive(Debug)]
+pub struct TimedOut(pub Duration);
+
+impl std::fmt::Display for TimedOut {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "AI command timed out after {}s", self.0.as_secs())
+    }
+}
+
+impl std::error::Error for TimedOut {}
+
+/// Keeps requests under `requests_per_minute`
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    /// When the requests of the last minute were sent
+    sent: VecDeque<Instant>,
+}
+
+impl RateLimiter {
+    /// Count a request sent at `now`, or refuse it if `per_minute` have
+    /// already been sent in the last minute
+    pub fn acquire(&mut self, per_minute: u32, now: Instant) -> Result<()> {
+        while self.sent.front().is_some_and(|sent| now.duration_since(*sent) >= MINUTE) {
+            self.sent.pop_front();
+        }
+        if per_minute > 0 && self.sent.len() >= per_minute as usize {
+            let wait = MINUTE - now.duration_since(self.sent[0]);
+            return Err(anyhow::anyhow!("AI rate limit reached, try again in {}s", wait.as_secs().max(1)));
+        }
+        self.sent.push_back(now);
+        Ok(())
+    }
+}
+
+const MINUTE: Duration = Duration::from_secs(60);
+
+/// Send a prompt to the configured AI provider and return its reply
+///
+/// The provider is any shell command that reads the prompt on stdin and
+/// writes the reply to stdout, e.g. `llm -m gpt-4o` or a small script
+/// around an HTTP API. This keeps API clients and credentials out of the
+/// editor. A failed request is tried again `retries` times, waiting a
+/// little longer each time, and one that runs past `timeout_seconds` is
+/// killed and fails with [`TimedOut`].
+pub fn complete(config: &AiConfig, command: &str, prompt: &str, working_dir: Option<&Path>, env: &[(String, String)]) -> Result<String> {
+    complete_unless_cancelled(config, command, prompt, working_dir, env, &AtomicBool::new(false))
+}
+
+/// [`complete`] on a worker thread, which kills the command and gives up
+/// once `cancel` is set
+pub fn complete_unless_cancelled(
+    config: &AiConfig,
+    command: &str,
+    prompt: &str,
+    working_dir: Option<&Path>,
+    env: &[(String, String)],
+    cancel: &AtomicBool,
+) -> Result<String> {
+    let timeout = Duration::from_secs(config.timeout_seconds.max(1));
+    let mut attempt = 0;
+    loop {
+        match run(command, prompt, working_dir, env, timeout, cancel) {
+            Err(e) if attempt < config.retries && e.downcast_ref::<TimedOut>().is_none() && !cancel.load(Ordering::Relaxed) => {
+                std::thread::sleep(Duration::from_millis(250 << attempt.min(4)));
+                attempt += 1;
+            },
+            result => return result,
+        }
+    }
+}
+
+fn run(command: &str, prompt: &str, working_dir: Option<&Path>, env: &[(String, String)], timeout: Duration, cancel: &AtomicBool) -> Result<String> {
+    let mut process = Command::new("sh");
+    process
+        .arg("-c")
+        .arg(command)
+        .envs(env.iter().map(|(name, value)| (name, value)))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+
+    if let Some(dir) = working_dir {
+        process.current_dir(dir);
+    }
+
+    let mut child = process
+        .spawn()
+        .with_context(|| format!("Failed to start AI command: {}", command))?;
+
+    // Write the prompt and read the output from separate threads so a long
+    // reply can't deadlock the pipes, while this one watches the clock
+    let mut stdin = child.stdin.take().context("Failed to open AI command stdin")?;
+    let prompt = prompt.to_string();
+    let writer = std::thread::spawn(move || stdin.write_all(prompt.as_bytes()));
+    let stdout = read_in_background(child.stdout.take());
+    let stderr = read_in_background(child.stderr.take());
+
+    let started = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait().with_context(|| format!("Failed to run AI command: {}", command))? {
+            break status;
+        }
+        if started.elapsed() >= timeout {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(TimedOut(timeout).into());
+        }
+        if cancel.load(Ordering::Relaxed) {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(anyhow::anyhow!("AI request cancelled"));
+        }
+        std::thread::sleep(Duration::from_millis(20));
+    };
+    let _ = writer.join();
+    let stdout = stdout.join().unwrap_or_default();
+    let stderr = stderr.join().unwrap_or_default();
+
+    if !status.success() {
+        let stderr = String::from_utf8_lossy(&stderr);
+        let first_line = stderr.lines().find(|line| !line.trim().is_empty()).unwrap_or("unknown error");
+        return Err(anyhow::anyhow!("AI command failed: {}", first_line));
+    }
+
+    let reply = String::from_utf8(stdout).context("AI command produced invalid UTF-8")?;
+    if reply.trim().is_empty() {
+        return Err(anyhow::anyhow!("AI command returned nothing"));
+    }
+    Ok(reply)
+}
+
+fn read_in_background(pipe: Option<impl Read + Send + 'static>) -> JoinHandle<Vec<u8>> {
+    std::thread::spawn(move || {
+        let mut bytes = Vec::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut bytes);
+        }
+        bytes
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_requests_are_limited_and_timed_out() {
+        let mut limiter = RateLimiter::default();
+        let start = Instant::now();
+        assert!(limiter.acquire(2, start).is_ok());
+        assert!(limiter.acquire(2, start + Duration::from_secs(10)).is_ok());
+        let refused = limiter.acquire(2, start + Duration::from_secs(20)).unwrap_err();
+        assert_eq!(refused.to_string(), "AI rate limit reached, try again in 40s");
+        assert!(limiter.acquire(2, start + MINUTE).is_ok());
+        assert!(limiter.acquire(0, start + MINUTE).is_ok());
+
+        let config = AiConfig { timeout_seconds: 1, retries: 3, ..AiConfig::default() };
+        let started = Instant::now();
+        let error = complete(&config, "sleep 5", "", None, &[]).unwrap_err();
+        assert_eq!(error.to_string(), "AI command timed out after 1s");
+        assert!(started.elapsed() < Duration::from_secs(3), "a timeout isn't retried");
+
+        // The second try finds the file the first one left
+        let dir = tempfile::tempdir().unwrap();
+        let flaky = "if [ -e tried ]; then cat; else touch tried; exit 1; fi";
+        assert_eq!(complete(&config, flaky, "reply", Some(dir.path()), &[]).unwrap(), "reply");
+    }
+}
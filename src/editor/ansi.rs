@@ -0,0 +1,117 @@
+/// A color set by an escape sequence: one of the 256 palette colors (the
+/// first 16 being the standard and bright ones) or an RGB color
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnsiColor {
+    Indexed(u8),
+    Rgb(u8, u8, u8),
+}
+
+/// The text attributes in effect at some point of a line
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AnsiStyle {
+    pub fg: Option<AnsiColor>,
+    pub bg: Option<AnsiColor>,
+    pub bold: bool,
+    pub dim: bool,
+    pub italic: bool,
+    pub underline: bool,
+}
+
+impl AnsiStyle {
+    /// Apply the parameters of an SGR sequence (`ESC [ ... m`)
+    fn apply(&mut self, params: &str) {
+        let mut codes = params.split(';').map(|code| code.parse::<u16>().unwrap_or(0));
+        while let Some(code) = codes.next() {
+            match code {
+                0 => *self = AnsiStyle::default(),
+                1 => self.bold = true,
+                2 => self.dim = true,
+                3 => self.italic = true,
+                4 => self.underline = true,
+                22 => (self.bold, self.dim) = (false, false),
+                23 => self.italic = false,
+                24 => self.underline = false,
+                30..=37 => self.fg = Some(AnsiColor::Indexed((code - 30) as u8)),
+                38 => self.fg = extended_color(&mut codes),
+                39 => self.fg = None,
+                40..=47 => self.bg = Some(AnsiColor::Indexed((code - 40) as u8)),
+                48 => self.bg = extended_color(&mut codes),
+                49 => self.bg = None,
+                90..=97 => self.fg = Some(AnsiColor::Indexed((code - 90 + 8) as u8)),
+                100..=107 => self.bg = Some(AnsiColor::Indexed((code - 100 + 8) as u8)),
+                _ => {},
+            }
+        }
+    }
+}
+
+/// The color after `38` or `48`: `5;n` for a palette color, `2;r;g;b` for RGB
+fn extended_color(codes: &mut impl Iterator<Item = u16>) -> Option<AnsiColor> {
+    let mut next = || codes.next().map(|code| code.min(255) as u8);
+    match next()? {
+        5 => Some(AnsiColor::Indexed(next()?)),
+        2 => Some(AnsiColor::Rgb(next()?, next()?, next()?)),
+        _ => None,
+    }
+}
+
+/// Split a line of terminal output into runs of text with their style,
+/// dropping escape sequences other than colors and text attributes
+pub fn parse(line: &str) -> Vec<(AnsiStyle, String)> {
+    let mut runs: Vec<(AnsiStyle, String)> = Vec::new();
+    let mut style = AnsiStyle::default();
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\x1b' {
+            match runs.last_mut() {
+                Some((last, text)) if *last == style => text.push(c),
+                _ => runs.push((style, c.to_string())),
+            }
+            continue;
+        }
+        if chars.next_if_eq(&'[').is_none() {
+            // Not a control sequence, so it's two characters long
+            chars.next();
+            continue;
+        }
+        let mut params = String::new();
+        for c in chars.by_ref() {
+            if ('\x40'..='\x7e').contains(&c) {
+                if c == 'm' {
+                    style.apply(&params);
+                }
+                break;
+            }
+            params.push(c);
+        }
+    }
+    runs
+}
+
+/// A line of terminal output without its escape sequences
+pub fn strip(line: &str) -> String {
+    parse(line).into_iter().map(|(_, text)| text).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_colors_and_attributes_split_the_line() {
+        let line = "\x1b[0m\x1b[1m\x1b[38;5;9merror[E0308]\x1b[0m\x1b[1m: mismatched types\x1b[0m \x1b[2Kdone";
+        let runs = parse(line);
+        let bold = AnsiStyle { bold: true, ..AnsiStyle::default() };
+        assert_eq!(runs, [
+            (AnsiStyle { fg: Some(AnsiColor::Indexed(9)), ..bold }, "error[E0308]".to_string()),
+            (bold, ": mismatched types".to_string()),
+            (AnsiStyle::default(), " done".to_string()),
+        ]);
+        assert_eq!(strip(line), "error[E0308]: mismatched types done");
+
+        let runs = parse("\x1b[32;48;2;10;20;30mok\x1b[39m!");
+        assert_eq!(runs[0].0.fg, Some(AnsiColor::Indexed(2)));
+        assert_eq!(runs[0].0.bg, Some(AnsiColor::Rgb(10, 20, 30)));
+        assert_eq!((runs[1].0.fg, runs[1].1.as_str()), (None, "!"));
+    }
+}
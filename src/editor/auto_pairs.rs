@@ -0,0 +1,125 @@
+/// The character that closes `c`, if it opens a pair
+pub fn closing_char(c: char) -> Option<char> {
+    match c {
+        '(' => Some(')'),
+        '[' => Some(']'),
+        '{' => Some('}'),
+        '"' => Some('"'),
+        '\'' => Some('\''),
+        _ => None,
+    }
+}
+
+/// Whether typing `c` at byte column `x` should also insert its closing
+/// character
+///
+/// Pairs are only added before whitespace, punctuation or the end of the
+/// line, so typing in front of a word doesn't leave a stray closer. Quotes
+/// aren't paired after a letter, as in `don't`, and single quotes are left
+/// alone entirely where they're mostly lifetimes.
+pub fn should_pair(line: &str, x: usize, c: char, pair_single_quotes: bool) -> bool {
+    if closing_char(c).is_none() || (c == '\'' && !pair_single_quotes) {
+        return false;
+    }
+    let next = line.get(x..).and_then(|rest| rest.chars().next());
+    if next.is_some_and(|next| !next.is_whitespace() && !matches!(next, ')' | ']' | '}' | ',' | ';' | ':')) {
+        return false;
+    }
+    if matches!(c, '"' | '\'') {
+        let previous = line.get(..x).and_then(|before| before.chars().next_back());
+        if previous.is_some_and(|previous| previous.is_alphanumeric() || previous == c) {
+            return false;
+        }
+    }
+    true
+}
+
+/// Whether the cursor at byte column `x` sits inside an empty pair, like `(|)`
+pub fn in_empty_pair(line: &str, x: usize) -> bool {
+    in_pair(line, x, |_| true)
+}
+
+/// Whether the cursor sits between an empty pair of brackets, where Enter
+/// opens an indented line
+pub fn in_empty_brackets(line: &str, x: usize) -> bool {
+    in_pair(line, x, |c| matches!(c, '(' | '[' | '{'))
+}
+
+fn in_pair(line: &str, x: usize, opener: impl Fn(char) -> bool) -> bool {
+    let (Some(before), Some(after)) = (line.get(..x), line.get(x..)) else { return false };
+    match (before.chars().next_back(), after.chars().next()) {
+        (Some(open), Some(close)) => opener(open) && closing_char(open) == Some(close),
+        _ => false,
+    }
+}
+
+/// Closing characters inserted by auto-pairs, which typing the same
+/// character steps over instead of doubling
+#[derive(Debug, Default)]
+pub struct AutoClosed {
+    positions: Vec<(usize, usize)>,
+}
+
+impl AutoClosed {
+    pub fn push(&mut self, x: usize, y: usize) {
+        self.positions.push((x, y));
+    }
+
+    /// Text of `len` bytes was inserted at `(x, y)`
+    pub fn inserted(&mut self, x: usize, y: usize, len: usize) {
+        for position in self.positions.iter_mut().filter(|(px, py)| *py == y && *px >= x) {
+            position.0 += len;
+        }
+    }
+
+    /// `len` bytes were removed starting at `(x, y)`
+    pub fn removed(&mut self, x: usize, y: usize, len: usize) {
+        self.positions.retain(|(px, py)| *py != y || *px < x || *px >= x + len);
+        for position in self.positions.iter_mut().filter(|(px, py)| *py == y && *px >= x + len) {
+            position.0 -= len;
+        }
+    }
+
+    /// Forget the closer at `(x, y)`, returning whether there was one
+    pub fn take(&mut self, x: usize, y: usize) -> bool {
+        let count = self.positions.len();
+        self.positions.retain(|position| *position != (x, y));
+        self.positions.len() != count
+    }
+
+    pub fn clear(&mut self) {
+        self.positions.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_should_pair() {
+        assert!(should_pair("foo", 3, '(', true));
+        assert!(should_pair("foo()", 4, '[', true));
+        assert!(!should_pair("foo bar", 4, '(', true));
+        assert!(should_pair("x = ", 4, '"', true));
+        assert!(!should_pair("don", 3, '\'', true));
+        assert!(!should_pair("fn f<", 5, '\'', false));
+        assert!(!should_pair("x", 1, 'a', true));
+    }
+
+    #[test]
+    fn test_tracks_auto_closed_positions_through_edits() {
+        let mut closed = AutoClosed::default();
+        closed.push(5, 0);
+        closed.inserted(3, 0, 2);
+        closed.inserted(3, 1, 2);
+        assert!(!closed.take(5, 0));
+        closed.removed(2, 0, 1);
+        assert!(closed.take(6, 0));
+        assert!(!closed.take(6, 0));
+
+        assert!(in_empty_pair("f()", 2));
+        assert!(!in_empty_brackets("\"\"", 1));
+        assert!(in_empty_brackets("{}", 1));
+    }
+}
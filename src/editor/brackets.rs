@@ -0,0 +1,349 @@
+/// How a language writes comments and strings, so brackets inside them
+/// aren't paired with brackets in code
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct BracketRules {
+    line_comment: Option<&'static str>,
+    block_comment: Option<(&'static str, &'static str)>,
+    /// Block comments nest, as in Rust
+    nested_comments: bool,
+    quotes: &'static [char],
+    /// Strings may continue onto the next line
+    multiline_strings: bool,
+    /// Rust's raw strings and the char literal / lifetime distinction
+    rust: bool,
+}
+
+impl BracketRules {
+    /// Rules for a syntect syntax name, or plain text when there's no syntax
+    pub fn for_syntax(name: Option<&str>) -> Self {
+        let c_like = BracketRules {
+            line_comment: Some("//"),
+            block_comment: Some(("/*", "*/")),
+            quotes: &['"', '\''],
+            ..Default::default()
+        };
+        let hash_comments = BracketRules {
+            line_comment: Some("#"),
+            quotes: &['"', '\''],
+            ..Default::default()
+        };
+
+        match name.unwrap_or("") {
+            "Rust" => BracketRules {
+                nested_comments: true,
+                quotes: &['"'],
+                multiline_strings: true,
+                rust: true,
+                ..c_like
+            },
+            "JavaScript" | "TypeScript" | "TypeScriptReact" | "JavaScript (Babel)" | "Go" => BracketRules {
+                quotes: &['"', '\'', '`'],
+                ..c_like
+            },
+            "C" | "C++" | "C#" | "Java" | "Objective-C" | "Objective-C++" | "Scala"
+            | "PHP" | "D" | "Groovy" | "Swift" | "Kotlin" | "Dart" => c_like,
+            "CSS" | "LESS" | "Sass" | "SCSS" => BracketRules {
+                line_comment: None,
+                ..c_like
+            },
+            "Python" | "Ruby" | "Perl" | "R" | "YAML" | "TOML" | "Makefile" | "Nim"
+            | "Elixir" | "CMake" => hash_comments,
+            name if name.contains("Shell") => hash_comments,
+            "SQL" | "Lua" | "Haskell" | "Ada" => BracketRules {
+                line_comment: Some("--"),
+                quotes: &['"', '\''],
+                ..Default::default()
+            },
+            "JSON" => BracketRules {
+                quotes: &['"'],
+                ..Default::default()
+            },
+            _ => BracketRules::default(),
+        }
+    }
+}
+
+/// A bracket and the comment or string it sits in
+///
+/// `region` is 0 for code and a distinct number for every comment and string,
+/// so only brackets in the same region pair up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Bracket {
+    x: usize,
+    y: usize,
+    ch: char,
+    region: usize,
+}
+
+enum State {
+    Code,
+    Comment { depth: usize, region: usize },
+    Str { quote: char, region: usize },
+    RawStr { hashes: usize, region: usize },
+}
+
+/// Lines looked at in each direction when highlighting the pair under the
+/// cursor, so a bracket with no match doesn't scan the whole file each frame
+pub const HIGHLIGHT_WINDOW: usize = 500;
+
+/// The bracket paired with the one at `(x, y)`
+///
+/// With a `window`, only that many lines around the cursor are searched.
+pub fn matching_bracket(lines: &[String], x: usize, y: usize, rules: &BracketRules, window: Option<usize>) -> Option<(usize, usize)> {
+    if !lines.get(y).and_then(|line| line.get(x..)).is_some_and(|rest| rest.starts_with(is_bracket)) {
+        return None;
+    }
+    let (from, to) = match window {
+        Some(window) => (y.saturating_sub(window), (y + window + 1).min(lines.len())),
+        None => (0, lines.len()),
+    };
+    let brackets = scan(lines, from, to, rules);
+    let index = brackets.iter().position(|bracket| bracket.x == x && bracket.y == y)?;
+    pair(&brackets, index)
+}
+
+/// Target of the `%` motion: the bracket under the cursor's pair, or the pair
+/// of the next bracket on the line when the cursor isn't on one
+pub fn jump_target(lines: &[String], x: usize, y: usize, rules: &BracketRules) -> Option<(usize, usize)> {
+    let brackets = scan(lines, 0, lines.len(), rules);
+    let index = brackets.iter().position(|bracket| bracket.y == y && bracket.x >= x)?;
+    pair(&brackets, index)
+}
+
+/// Bracket pairs around the text from `(x, y)` positions `start` to `end`,
+/// as their opening and closing positions, innermost first
+///
+/// A pair counts when its brackets are at or outside both ends, so
+/// selecting a pair's brackets still lists it.
+pub fn enclosing_pairs(lines: &[String], start: (usize, usize), end: (usize, usize), rules: &BracketRules) -> Vec<((usize, usize), (usize, usize))> {
+    let before = |(ax, ay): (usize, usize), (bx, by): (usize, usize)| (ay, ax) <= (by, bx);
+    let mut open: Vec<Bracket> = Vec::new();
+    let mut pairs = Vec::new();
+    for bracket in scan(lines, 0, lines.len(), rules) {
+        if matches!(bracket.ch, '(' | '[' | '{') {
+            open.push(bracket);
+            continue;
+        }
+        // Unbalanced closing brackets are skipped
+        let Some(index) = open.iter().rposition(|opening| opening.region == bracket.region) else { continue };
+        if open[index].ch != counterpart(bracket.ch) {
+            continue;
+        }
+        let opening = open.remove(index);
+        let (from, to) = ((opening.x, opening.y), (bracket.x, bracket.y));
+        if before(from, start) && before(end, (bracket.x + 1, bracket.y)) {
+            pairs.push((from, to));
+        }
+    }
+    pairs.sort_by_key(|&((x, y), _)| std::cmp::Reverse((y, x)));
+    pairs
+}
+
+fn is_bracket(ch: char) -> bool {
+    matches!(ch, '(' | ')' | '[' | ']' | '{' | '}')
+}
+
+fn counterpart(ch: char) -> char {
+    match ch {
+        '(' => ')',
+        ')' => '(',
+        '[' => ']',
+        ']' => '[',
+        '{' => '}',
+        _ => '{',
+    }
+}
+
+fn pair(brackets: &[Bracket], index: usize) -> Option<(usize, usize)> {
+    let start = brackets[index];
+    let opening = matches!(start.ch, '(' | '[' | '{');
+    let close = counterpart(start.ch);
+    let candidates: Box<dyn Iterator<Item = &Bracket>> = if opening {
+        Box::new(brackets[index + 1..].iter())
+    } else {
+        Box::new(brackets[..index].iter().rev())
+    };
+
+    let mut depth = 0;
+    for bracket in candidates.filter(|bracket| bracket.region == start.region) {
+        if bracket.ch == start.ch {
+            depth += 1;
+        } else if bracket.ch == close {
+            if depth == 0 {
+                return Some((bracket.x, bracket.y));
+            }
+            depth -= 1;
+        }
+    }
+    None
+}
+
+/// Brackets in lines `from..to`, tagged with their comment or string region
+fn scan(lines: &[String], from: usize, to: usize, rules: &BracketRules) -> Vec<Bracket> {
+    let mut brackets = Vec::new();
+    let mut state = State::Code;
+    let mut regions = 0;
+
+    for (y, line) in lines.iter().enumerate().take(to).skip(from) {
+        let bytes = line.as_bytes();
+        let mut i = 0;
+        while i < line.len() {
+            let rest = &line[i..];
+            let ch = rest.chars().next().unwrap_or(' ');
+            match state {
+                State::Code => {
+                    if rules.line_comment.is_some_and(|start| rest.starts_with(start)) {
+                        regions += 1;
+                        for (offset, ch) in rest.char_indices().filter(|(_, ch)| is_bracket(*ch)) {
+                            brackets.push(Bracket { x: i + offset, y, ch, region: regions });
+                        }
+                        break;
+                    }
+                    if let Some((open, _)) = rules.block_comment.filter(|(open, _)| rest.starts_with(open)) {
+                        regions += 1;
+                        state = State::Comment { depth: 1, region: regions };
+                        i += open.len();
+                        continue;
+                    }
+                    if rules.rust {
+                        if let Some((hashes, len)) = raw_string_start(line, i) {
+                            regions += 1;
+                            state = State::RawStr { hashes, region: regions };
+                            i += len;
+                            continue;
+                        }
+                        if ch == '\'' {
+                            if let Some(len) = char_literal(rest) {
+                                i += len;
+                                continue;
+                            }
+                        }
+                    }
+                    if rules.quotes.contains(&ch) {
+                        regions += 1;
+                        state = State::Str { quote: ch, region: regions };
+                    } else if is_bracket(ch) {
+                        brackets.push(Bracket { x: i, y, ch, region: 0 });
+                    }
+                },
+                State::Comment { depth, region } => {
+                    let (open, close) = rules.block_comment.unwrap_or_default();
+                    if rest.starts_with(close) {
+                        state = if depth > 1 { State::Comment { depth: depth - 1, region } } else { State::Code };
+                        i += close.len();
+                        continue;
+                    }
+                    if rules.nested_comments && rest.starts_with(open) {
+                        state = State::Comment { depth: depth + 1, region };
+                        i += open.len();
+                        continue;
+                    }
+                    if is_bracket(ch) {
+                        brackets.push(Bracket { x: i, y, ch, region });
+                    }
+                },
+                State::Str { quote, region } => {
+                    if ch == '\\' {
+                        i += 1 + rest[1..].chars().next().map_or(0, char::len_utf8);
+                        continue;
+                    }
+                    if ch == quote {
+                        state = State::Code;
+                    } else if is_bracket(ch) {
+                        brackets.push(Bracket { x: i, y, ch, region });
+                    }
+                },
+                State::RawStr { hashes, region } => {
+                    if ch == '"' && bytes[i + 1..].iter().take_while(|b| **b == b'#').count() >= hashes {
+                        state = State::Code;
+                        i += 1 + hashes;
+                        continue;
+                    }
+                    if is_bracket(ch) {
+                        brackets.push(Bracket { x: i, y, ch, region });
+                    }
+                },
+            }
+            i += ch.len_utf8();
+        }
+
+        if matches!(state, State::Str { .. }) && !rules.multiline_strings {
+            state = State::Code;
+        }
+    }
+    brackets
+}
+
+/// The number of `#`s and the length of a Rust raw string's opening, like
+/// `r#"` or `br"`, starting at byte `i`
+fn raw_string_start(line: &str, i: usize) -> Option<(usize, usize)> {
+    let bytes = line.as_bytes();
+    if i > 0 && (bytes[i - 1].is_ascii_alphanumeric() || bytes[i - 1] == b'_') {
+        return None;
+    }
+    let prefix = if line[i..].starts_with("br") { 2 } else if line[i..].starts_with('r') { 1 } else { return None };
+    let hashes = bytes[i + prefix..].iter().take_while(|b| **b == b'#').count();
+    (bytes.get(i + prefix + hashes) == Some(&b'"')).then_some((hashes, prefix + hashes + 1))
+}
+
+/// Length of a Rust char literal like `'{'` or `'\n'`, or `None` for a
+/// lifetime
+fn char_literal(rest: &str) -> Option<usize> {
+    let mut chars = rest.char_indices().skip(1);
+    let (_, first) = chars.next()?;
+    if first == '\\' {
+        // Escapes run to the closing quote, as in '\u{7b}'
+        return rest.get(3..)?.find('\'').map(|end| end + 4);
+    }
+    let (end, next) = chars.next()?;
+    (next == '\'').then_some(end + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(text: &str) -> Vec<String> {
+        text.lines().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn test_matches_across_lines_skipping_strings_and_comments() {
+        let rust = BracketRules::for_syntax(Some("Rust"));
+        let source = lines(r##"fn main() {
+    let s = "}";
+    // { not code
+    /* ) /* nested } */ */
+    let c = '}';
+    let r = r#"{"#;
+    foo::<'a>(c);
+}"##);
+        assert_eq!(matching_bracket(&source, 10, 0, &rust, None), Some((0, 7)));
+        assert_eq!(matching_bracket(&source, 0, 7, &rust, Some(HIGHLIGHT_WINDOW)), Some((10, 0)));
+        assert_eq!(matching_bracket(&source, 13, 6, &rust, None), Some((15, 6)));
+        assert_eq!(matching_bracket(&source, 4, 0, &rust, None), None);
+
+        // Without syntax information every bracket counts
+        let plain = BracketRules::for_syntax(None);
+        assert_eq!(matching_bracket(&source, 10, 0, &plain, None), Some((13, 1)));
+    }
+
+    #[test]
+    fn test_brackets_pair_within_the_same_string() {
+        let python = BracketRules::for_syntax(Some("Python"));
+        let source = lines("print(\"(a)\")  # )");
+        assert_eq!(matching_bracket(&source, 5, 0, &python, None), Some((11, 0)));
+        assert_eq!(matching_bracket(&source, 7, 0, &python, None), Some((9, 0)));
+        assert_eq!(matching_bracket(&source, 16, 0, &python, None), None);
+    }
+
+    #[test]
+    fn test_jump_from_before_a_bracket() {
+        let rules = BracketRules::for_syntax(Some("C"));
+        let source = lines("if x {\n  y[0];\n}");
+        assert_eq!(jump_target(&source, 0, 0, &rules), Some((0, 2)));
+        assert_eq!(jump_target(&source, 5, 0, &rules), Some((0, 2)));
+        assert_eq!(jump_target(&source, 0, 1, &rules), Some((5, 1)));
+        assert_eq!(jump_target(&source, 6, 1, &rules), None);
+    }
+}
@@ -1,14 +1,24 @@
-use anyhow::{Context, Result};
-use std::fs;
+use anyhow::Result;
 use std::collections::HashSet;
 use super::cursor::Cursor;
-use similar::{ChangeTag, TextDiff};
+use similar::{ChangeTag, DiffTag, TextDiff};
 use syntect::parsing::SyntaxReference;
 use std::sync::Arc;
+use std::cell::RefCell;
 use std::cmp::{min, max};
+use std::hash::{DefaultHasher, Hash, Hasher};
 use super::history::{History, EditorAction, ActionType};
+use super::encoding::{self, LineEnding};
+use super::paged::{PagedFile, LARGE_FILE_BYTES};
+use super::ids::BufferId;
+use super::hunks::{self, WriteReview};
+use super::save::{self, WriteOptions};
+use std::path::Path;
+use encoding_rs::{Encoding, UTF_8};
 
 pub struct Buffer {
+    /// Stays the same while the buffer is open, whatever it's renamed to
+    pub id: BufferId,
     pub lines: Vec<String>,
     pub file_path: Option<String>,
     pub modified_lines: HashSet<usize>,
@@ -16,11 +26,32 @@ pub struct Buffer {
     pub syntax: Option<Arc<SyntaxReference>>,
     pub selection_start: Option<(usize, usize)>, // (line, column)
     pub history: History,
+    /// Read-only buffers (message logs, command output) reject all edits
+    pub read_only: bool,
+    /// Keep picking up content appended to the file (`:set autoread-follow`)
+    pub follow: bool,
+    /// Encoding the file is read and written in (`:set fileencoding=`)
+    pub encoding: &'static Encoding,
+    /// Line endings written on save (`:set fileformat=`); lines are always
+    /// split on LF in the buffer
+    pub line_ending: LineEnding,
+    /// Whether the file starts with a byte order mark, kept on save
+    pub bom: bool,
+    /// Large and binary files are shown from disk a screen at a time
+    /// instead of being loaded into `lines`
+    pub paged: Option<PagedFile>,
+    /// The lines as last read from or written to the file, which unsaved
+    /// changes are marked against
+    saved_lines: Option<Vec<String>>,
+    /// `unsaved_lines` for the lines with this hash, worked out again only
+    /// once they change or the file is saved
+    unsaved: RefCell<Option<(u64, HashSet<usize>)>>,
 }
 
 impl Buffer {
     pub fn new() -> Self {
         Self {
+            id: BufferId::next(),
             lines: vec![String::new()],
             file_path: None,
             modified_lines: HashSet::new(),
@@ -28,6 +59,14 @@ impl Buffer {
             syntax: None,
             selection_start: None,
             history: History::new(),
+            read_only: false,
+            follow: false,
+            encoding: UTF_8,
+            line_ending: LineEnding::Unix,
+            bom: false,
+            paged: None,
+            saved_lines: None,
+            unsaved: RefCell::new(None),
         }
     }
     
@@ -38,6 +77,11 @@ impl Buffer {
     
     /// Undo the last action
     pub fn undo(&mut self, cursor: &mut Cursor) -> bool {
+        if self.read_only {
+            return false;
+        }
+        
+        self.end_undo_group(cursor);
         self.history.start_undo_or_redo();
         
         let result = if let Some(action) = self.history.undo_action() {
@@ -234,6 +278,11 @@ impl Buffer {
                         false
                     }
                 },
+                ActionType::ReplaceInLine { position, old_text, new_text } => {
+                    // To undo a replacement, put the old text back
+                    let (y, x) = position;
+                    self.splice_line(y, x, new_text.len(), &old_text)
+                },
             }
         } else {
             false
@@ -245,6 +294,11 @@ impl Buffer {
     
     /// Redo the previously undone action
     pub fn redo(&mut self, cursor: &mut Cursor) -> bool {
+        if self.read_only {
+            return false;
+        }
+        
+        self.end_undo_group(cursor);
         self.history.start_undo_or_redo();
         
         let result = if let Some(action) = self.history.redo_action() {
@@ -482,6 +536,11 @@ impl Buffer {
                         false
                     }
                 },
+                ActionType::ReplaceInLine { position, old_text, new_text } => {
+                    // To redo a replacement, swap the new text in again
+                    let (y, x) = position;
+                    self.splice_line(y, x, old_text.len(), &new_text)
+                },
             }
         } else {
             false
@@ -496,6 +555,36 @@ impl Buffer {
         result
     }
     
+    /// Record the edits from here until `end_undo_group` as one undo step,
+    /// so a change typed over several keys is undone at once
+    pub fn begin_undo_group(&mut self, cursor: &Cursor) {
+        if !self.read_only {
+            self.history.begin_group(&self.lines, *cursor);
+        }
+    }
+
+    /// Finish the step started by `begin_undo_group`
+    pub fn end_undo_group(&mut self, cursor: &Cursor) {
+        self.history.end_group(&self.lines, *cursor);
+    }
+
+    /// Move through the undo tree to the state of `node`, undoing back to
+    /// where its branch splits off and redoing down it
+    pub fn undo_to(&mut self, node: usize, cursor: &mut Cursor) -> bool {
+        if self.read_only {
+            return false;
+        }
+        let mut changed = false;
+        for _ in 0..self.history.undos_to(node) {
+            changed |= self.undo(cursor);
+        }
+        self.history.follow(node);
+        while self.history.current() != node && self.redo(cursor) {
+            changed = true;
+        }
+        changed
+    }
+
     /// Helper function to delete text between two positions
     fn delete_between(&mut self, start_line: usize, start_col: usize, end_line: usize, end_col: usize) -> bool {
         if start_line >= self.lines.len() || end_line >= self.lines.len() {
@@ -757,6 +846,10 @@ impl Buffer {
     /// 
     /// If is_visual_line is true, entire lines will be deleted regardless of cursor column
     pub fn delete_selection(&mut self, cursor: &mut Cursor, is_visual_line: bool) -> bool {
+        if self.read_only {
+            return false;
+        }
+        
         if let Some(start) = self.selection_start {
             let (start_line, start_col) = start;
             let (end_line, end_col) = (cursor.y, cursor.x);
@@ -953,9 +1046,93 @@ impl Buffer {
     /// This implementation uses Rust's built-in line iterator for more readable
     /// and potentially more efficient line handling.
     pub fn load_file(&mut self, path: &str) -> Result<()> {
-        // Read the file content
-        let content = fs::read_to_string(path)
-            .with_context(|| format!("Failed to read file: {}", path))?;
+        // Large and binary files open read-only and paged
+        let was_paged = self.paged.is_some();
+        self.paged = PagedFile::open(path, LARGE_FILE_BYTES)?;
+        if self.paged.is_some() {
+            self.lines = vec![String::new()];
+            self.file_path = Some(path.to_string());
+            self.read_only = true;
+            self.modified_lines.clear();
+            self.is_modified = false;
+            self.set_saved_lines(None);
+            return Ok(());
+        } else if was_paged {
+            self.read_only = false;
+        }
+        
+        // Read the file content, remembering how it was stored
+        self.set_decoded(encoding::read(path)?);
+        self.set_saved_lines(Some(self.lines.clone()));
+        
+        // Store the file path
+        self.file_path = Some(path.to_string());
+        
+        // Clear modification state
+        self.modified_lines.clear();
+        self.is_modified = false;
+        
+        Ok(())
+    }
+
+    /// Re-read the buffer's file as a single undo step, so `u` brings back
+    /// what was in the buffer before and older history stays reachable
+    ///
+    /// The buffer ends up unmodified, with the cursor kept in bounds.
+    /// Returns true if the content changed.
+    pub fn reload(&mut self, cursor: &mut Cursor) -> Result<bool> {
+        let path = self.file_path.clone().ok_or_else(|| anyhow::anyhow!("No file path specified"))?;
+        let was_paged = self.paged.is_some();
+        if was_paged || PagedFile::open(&path, LARGE_FILE_BYTES)?.is_some() {
+            // Paged files have no lines in memory to undo back to
+            self.load_file(&path)?;
+            self.history.clear();
+            *cursor = Cursor { x: 0, y: 0 };
+            return Ok(true);
+        }
+
+        self.end_undo_group(cursor);
+        let cursor_before = *cursor;
+        let old_lines = std::mem::take(&mut self.lines);
+        self.set_decoded(encoding::read(&path)?);
+        self.set_saved_lines(Some(self.lines.clone()));
+        self.modified_lines.clear();
+        self.is_modified = false;
+        if self.lines == old_lines {
+            return Ok(false);
+        }
+
+        cursor.y = cursor.y.min(self.lines.len() - 1);
+        cursor.x = cursor.x.min(self.lines[cursor.y].len());
+        self.history.push(EditorAction {
+            action_type: ActionType::SetContent {
+                old_lines,
+                new_lines: self.lines.clone(),
+            },
+            cursor_before,
+            cursor_after: *cursor,
+        });
+        Ok(true)
+    }
+
+    /// Load text that didn't come from a file, like a pipe into `zim -`,
+    /// as an unnamed buffer
+    pub fn load_unnamed(&mut self, bytes: &[u8]) {
+        self.set_decoded(encoding::decode(bytes));
+        self.set_saved_lines(None);
+        self.file_path = None;
+        self.paged = None;
+        self.read_only = false;
+        self.modified_lines.clear();
+        self.is_modified = false;
+    }
+
+    /// Replace the lines with decoded text, remembering how it was stored
+    fn set_decoded(&mut self, decoded: encoding::Decoded) {
+        self.encoding = decoded.encoding;
+        self.line_ending = decoded.line_ending;
+        self.bom = decoded.bom;
+        let content = decoded.text;
         
         // Calculate an approximate capacity to reduce reallocations
         // Assume average of 40 chars per line as a heuristic
@@ -979,18 +1156,52 @@ impl Buffer {
                 self.lines.push(String::new());
             }
         }
+    }
+
+    /// Pick up content that was appended to the file since it was read
+    ///
+    /// Only the new text is added, so a growing log doesn't lose the
+    /// history or modification marks. Falls back to a full reload when the
+    /// file was truncated or rewritten. Returns true if the buffer changed.
+    pub fn append_from_disk(&mut self) -> Result<bool> {
+        let path = match &self.file_path {
+            Some(path) => path.clone(),
+            None => return Ok(false),
+        };
         
-        // Store the file path
-        self.file_path = Some(path.to_string());
+        if self.paged.is_some() {
+            return Ok(false);
+        }
+        let disk_content = encoding::read(&path)?.text;
+        let content = self.get_content();
+        if disk_content == content {
+            return Ok(false);
+        }
         
-        // Clear modification state
-        self.modified_lines.clear();
-        self.is_modified = false;
+        let appended = match disk_content.strip_prefix(content.as_str()) {
+            Some(appended) => appended,
+            None => {
+                self.load_file(&path)?;
+                return Ok(true);
+            }
+        };
         
-        Ok(())
+        // The appended text first continues the last line, then adds new ones
+        let mut new_lines = appended.split('\n');
+        if let (Some(rest), Some(last)) = (new_lines.next(), self.lines.last_mut()) {
+            last.push_str(rest);
+        }
+        self.lines.extend(new_lines.map(|line| line.to_string()));
+        self.set_saved_lines(Some(self.lines.clone()));
+        
+        Ok(true)
     }
 
     pub fn insert_char_at_cursor(&mut self, c: char, cursor: &Cursor) {
+        if self.read_only {
+            return;
+        }
+        
         // Create the action before modifying the buffer
         let cursor_before = *cursor;
         let cursor_after = Cursor { x: cursor.x + 1, y: cursor.y };
@@ -1036,6 +1247,10 @@ impl Buffer {
     }
 
     pub fn delete_char_at_cursor(&mut self, cursor: &Cursor) {
+        if self.read_only {
+            return;
+        }
+        
         // Create the action before modifying the buffer
         let cursor_before = *cursor;
         let cursor_after = *cursor; // Cursor doesn't move after delete
@@ -1062,6 +1277,10 @@ impl Buffer {
     }
     
     pub fn delete_line(&mut self, line_idx: usize) {
+        if self.read_only {
+            return;
+        }
+        
         if line_idx < self.lines.len() {
             // Save line content before deleting
             let content = self.lines[line_idx].clone();
@@ -1111,6 +1330,10 @@ impl Buffer {
     }
     
     pub fn join_line(&mut self, line_idx: usize) {
+        if self.read_only {
+            return;
+        }
+        
         // Join current line with the next line
         if line_idx < self.lines.len() - 1 {
             // Save info for the history
@@ -1157,6 +1380,10 @@ impl Buffer {
     }
 
     pub fn insert_newline_at_cursor(&mut self, cursor: &Cursor) {
+        if self.read_only {
+            return;
+        }
+        
         // Create the action before modifying the buffer
         let cursor_before = *cursor;
         let cursor_after = Cursor { x: 0, y: cursor.y + 1 };
@@ -1200,6 +1427,10 @@ impl Buffer {
 
     /// Open a new line below the current line and return the line number
     pub fn open_line_below(&mut self, line_idx: usize) -> usize {
+        if self.read_only {
+            return line_idx;
+        }
+        
         // Create the action before modifying the buffer
         let cursor_before = Cursor { x: 0, y: line_idx };
         let cursor_after = Cursor { x: 0, y: line_idx + 1 };
@@ -1229,6 +1460,10 @@ impl Buffer {
     
     /// Open a new line above the current line and return the line number
     pub fn open_line_above(&mut self, line_idx: usize) -> usize {
+        if self.read_only {
+            return line_idx;
+        }
+        
         // Create the action before modifying the buffer
         let cursor_before = Cursor { x: 0, y: line_idx };
         let cursor_after = Cursor { x: 0, y: line_idx };
@@ -1263,6 +1498,15 @@ impl Buffer {
         self.lines.len()
     }
 
+    /// The last of the file's lines, which ex ranges like `%` and `$` end
+    /// on: the empty line after a final newline only stands for it
+    pub fn last_line(&self) -> usize {
+        match self.lines.len() {
+            count if count > 1 && self.lines[count - 1].is_empty() => count - 2,
+            count => count.saturating_sub(1),
+        }
+    }
+
     pub fn line_length(&self, y: usize) -> usize {
         if y < self.lines.len() {
             self.lines[y].len()
@@ -1270,6 +1514,43 @@ impl Buffer {
             0
         }
     }
+
+    /// Byte and character offsets of a position from the start of the
+    /// buffer, counting each line break as one byte
+    pub fn offsets_at(&self, x: usize, y: usize) -> (usize, usize) {
+        let mut bytes = 0;
+        let mut chars = 0;
+        for line in self.lines.iter().take(y) {
+            bytes += line.len() + 1;
+            chars += line.chars().count() + 1;
+        }
+
+        let line = self.get_line(y);
+        let x = floor_char_boundary(line, x);
+        (bytes + x, chars + line[..x].chars().count())
+    }
+
+    /// The position at a byte offset, or at a character offset if `chars`
+    /// is set; `None` if the offset is past the end of the buffer
+    ///
+    /// An offset inside a multi-byte character or at a line break lands on
+    /// that character or at the end of the line.
+    pub fn position_at_offset(&self, offset: usize, chars: bool) -> Option<(usize, usize)> {
+        let mut remaining = offset;
+        for (y, line) in self.lines.iter().enumerate() {
+            let length = if chars { line.chars().count() } else { line.len() };
+            if remaining <= length {
+                let x = if chars {
+                    line.char_indices().nth(remaining).map_or(line.len(), |(index, _)| index)
+                } else {
+                    floor_char_boundary(line, remaining)
+                };
+                return Some((x, y));
+            }
+            remaining -= length + 1;
+        }
+        None
+    }
     
     /// Get the entire content of the buffer as a single string
     /// 
@@ -1301,6 +1582,10 @@ impl Buffer {
     
     /// Set the entire content of the buffer from a string
     pub fn set_content(&mut self, content: &str) -> Result<()> {
+        if self.read_only {
+            return Err(anyhow::anyhow!("Buffer is read-only"));
+        }
+        
         // Save original content for history
         let old_lines = self.lines.clone();
         let cursor_before = Cursor { x: 0, y: 0 };
@@ -1352,9 +1637,116 @@ impl Buffer {
         Ok(())
     }
     
+    /// Replace the buffer content with a reformatted version of it
+    ///
+    /// Only lines that actually differ are replaced and marked as modified,
+    /// and the cursor stays on the same logical line. The change is recorded
+    /// as a single undo step. Returns true if anything changed.
+    pub fn apply_formatted_content(&mut self, content: &str, cursor: &mut Cursor) -> bool {
+        if self.read_only {
+            return false;
+        }
+        
+        let mut new_lines = Vec::new();
+        let mut start = 0;
+        for (i, c) in content.char_indices() {
+            if c == '\n' {
+                new_lines.push(content[start..i].to_string());
+                start = i + 1;
+            }
+        }
+        new_lines.push(content[start..].to_string());
+        
+        if new_lines == self.lines {
+            return false;
+        }
+        
+        let cursor_before = *cursor;
+        let mut new_cursor_y = None;
+        let mut modified_lines = HashSet::new();
+        
+        {
+            let old_refs: Vec<&str> = self.lines.iter().map(|line| line.as_str()).collect();
+            let new_refs: Vec<&str> = new_lines.iter().map(|line| line.as_str()).collect();
+            let diff = TextDiff::from_slices(&old_refs, &new_refs);
+            for op in diff.ops() {
+                let (tag, old_range, new_range) = op.as_tag_tuple();
+                let contains_cursor = old_range.contains(&cursor.y);
+                
+                if tag == DiffTag::Equal {
+                    // Unchanged lines keep their modification state and the cursor
+                    for (offset, old_idx) in old_range.clone().enumerate() {
+                        if self.modified_lines.contains(&old_idx) {
+                            modified_lines.insert(new_range.start + offset);
+                        }
+                    }
+                    if contains_cursor {
+                        new_cursor_y = Some(new_range.start + (cursor.y - old_range.start));
+                    }
+                } else {
+                    modified_lines.extend(new_range.clone());
+                    if contains_cursor {
+                        let offset = cursor.y - old_range.start;
+                        let last_new_line = new_range.end.max(new_range.start + 1) - 1;
+                        new_cursor_y = Some(min(new_range.start + offset, last_new_line));
+                    }
+                }
+            }
+        }
+        
+        let old_lines = std::mem::replace(&mut self.lines, new_lines);
+        self.modified_lines = modified_lines;
+        self.is_modified = true;
+        
+        // Keep the cursor on the same logical line, clamped to the new content
+        cursor.y = min(new_cursor_y.unwrap_or(cursor.y), self.lines.len() - 1);
+        cursor.x = min(cursor.x, self.lines[cursor.y].len());
+        
+        self.history.push(EditorAction {
+            action_type: ActionType::SetContent {
+                old_lines,
+                new_lines: self.lines.clone(),
+            },
+            cursor_before,
+            cursor_after: *cursor,
+        });
+        
+        true
+    }
+    
+    /// Remove spaces and tabs from the ends of lines as a single undo step,
+    /// returning how many lines changed
+    pub fn trim_trailing_whitespace(&mut self, cursor: &mut Cursor) -> usize {
+        let trimmed: Vec<&str> = self.lines.iter()
+            .map(|line| line.trim_end_matches([' ', '\t']))
+            .collect();
+        let count = trimmed.iter().zip(&self.lines).filter(|(trimmed, line)| trimmed.len() != line.len()).count();
+        let content = trimmed.join("\n");
+        if count == 0 || !self.apply_formatted_content(&content, cursor) {
+            return 0;
+        }
+        count
+    }
+
+    /// Fill a scratch buffer with generated content and make it read-only
+    ///
+    /// The change is not recorded in history and does not mark the buffer
+    /// as modified, since the content is owned by the editor.
+    pub fn set_read_only_content(&mut self, lines: Vec<String>) {
+        self.lines = if lines.is_empty() { vec![String::new()] } else { lines };
+        self.modified_lines.clear();
+        self.is_modified = false;
+        self.selection_start = None;
+        self.history.clear();
+        self.read_only = true;
+        self.set_saved_lines(None);
+    }
+    
     /// Save the buffer content to a file
-    pub fn save(&mut self, path: Option<&str>) -> Result<String> {
-        use std::fs;
+    pub fn save(&mut self, path: Option<&str>, options: &WriteOptions) -> Result<String> {
+        if self.read_only {
+            return Err(anyhow::anyhow!("Buffer is read-only"));
+        }
         
         let file_path = match path {
             // Use provided path if given
@@ -1366,9 +1758,7 @@ impl Buffer {
             },
         };
         
-        // Get content and write to file
-        let content = self.get_content();
-        fs::write(&file_path, content)?;
+        save::write_file(Path::new(&file_path), &self.encode_for_file(&self.get_content())?, options)?;
         
         // Update file path if it was newly set
         if path.is_some() {
@@ -1376,13 +1766,120 @@ impl Buffer {
         }
         
         // Clear modification state after successful save
-        self.modified_lines.clear();
-        self.is_modified = false;
+        self.mark_saved();
         
         // Return the path that was saved to
         Ok(file_path)
     }
+
+    /// Record that the buffer's content is what's now in its file
+    pub fn mark_saved(&mut self) {
+        self.modified_lines.clear();
+        self.is_modified = false;
+        self.set_saved_lines(Some(self.lines.clone()));
+    }
+
+    /// The buffer as it would be written to its file
+    pub fn file_bytes(&self) -> Result<Vec<u8>> {
+        self.encode_for_file(&self.get_content())
+    }
+    
+    /// Save to the buffer's file, leaving out the hunks `review` excludes;
+    /// they stay in the buffer, which is still modified
+    pub fn save_excluding(&mut self, review: &WriteReview, options: &WriteOptions) -> Result<String> {
+        if self.read_only {
+            return Err(anyhow::anyhow!("Buffer is read-only"));
+        }
+        let file_path = self.file_path.clone().ok_or_else(|| anyhow::anyhow!("No file path specified"))?;
+        let saved = encoding::read(&file_path)?.text;
+        let content = hunks::apply_except(&saved, &self.get_content(), &review.hunks, &review.excluded);
+        save::write_file(Path::new(&file_path), &self.encode_for_file(&content)?, options)?;
+        self.set_saved_lines(Some(content.split('\n').map(String::from).collect()));
+
+        self.modified_lines = (0..self.lines.len()).filter(|&line| review.is_excluded(line)).collect();
+        self.is_modified = !review.excluded.is_empty();
+        Ok(file_path)
+    }
+
+    /// Take `lines` as the file's content from now on
+    fn set_saved_lines(&mut self, lines: Option<Vec<String>>) {
+        self.saved_lines = lines;
+        self.unsaved.take();
+    }
+
+    /// Lines that differ from the file as last read or saved, including the
+    /// line where saved lines were deleted, for the unsaved changes gutter
+    ///
+    /// Unlike `diff_with_disk`, this doesn't read the file, and the diff is
+    /// kept until the buffer changes, so it's cheap enough for every render.
+    pub fn unsaved_lines(&self) -> HashSet<usize> {
+        if !self.is_modified || self.saved_lines.is_none() {
+            return HashSet::new();
+        }
+        let mut hasher = DefaultHasher::new();
+        self.lines.hash(&mut hasher);
+        let hash = hasher.finish();
+        let mut cached = self.unsaved.borrow_mut();
+        match cached.as_ref() {
+            Some((cached_hash, lines)) if *cached_hash == hash => lines.clone(),
+            _ => cached.insert((hash, self.diff_saved_lines())).1.clone(),
+        }
+    }
+
+    /// Lines that differ from `saved_lines`, as for `unsaved_lines`
+    fn diff_saved_lines(&self) -> HashSet<usize> {
+        let mut lines = HashSet::new();
+        let Some(saved) = self.saved_lines.as_ref() else { return lines };
+        let saved: Vec<&str> = saved.iter().map(String::as_str).collect();
+        let current: Vec<&str> = self.lines.iter().map(String::as_str).collect();
+        let diff = TextDiff::from_slices(&saved, &current);
+        for op in diff.ops() {
+            let (tag, _, new_range) = op.as_tag_tuple();
+            if tag == DiffTag::Equal {
+                continue;
+            }
+            if new_range.is_empty() {
+                lines.insert(new_range.start.min(self.lines.len() - 1));
+            } else {
+                lines.extend(new_range);
+            }
+        }
+        lines
+    }
+
+    /// Text in the file's encoding and line endings
+    fn encode_for_file(&self, content: &str) -> Result<Vec<u8>> {
+        if self.line_ending != LineEnding::Unix {
+            return encoding::encode(&content.replace('\n', self.line_ending.as_str()), self.encoding, self.bom);
+        }
+        encoding::encode(content, self.encoding, self.bom)
+    }
     
+    /// Whether the text ends with a newline, which leaves an empty last line
+    pub fn has_final_newline(&self) -> bool {
+        self.lines.last().is_none_or(String::is_empty)
+    }
+
+    /// Add or remove the newline at the end of the text, as one undo step
+    /// Returns true if the buffer changed
+    pub fn set_final_newline(&mut self, newline: bool, cursor: &mut Cursor) -> bool {
+        if newline == self.has_final_newline() || self.lines.len() < 2 && !newline {
+            return false;
+        }
+        let last = self.lines.len() - 1;
+        let mut edit_cursor = *cursor;
+        let changed = if newline {
+            let end = (last, self.lines[last].len());
+            self.replace_range(end, end, "\n", &mut edit_cursor)
+        } else {
+            self.replace_range((last - 1, self.lines[last - 1].len()), (last, 0), "", &mut edit_cursor)
+        }.is_some();
+        // Leave the cursor where it was, unless that line is gone
+        cursor.y = cursor.y.min(self.lines.len() - 1);
+        cursor.x = cursor.x.min(self.lines[cursor.y].len());
+        changed
+    }
+
     /// Check if line is modified
     pub fn is_line_modified(&self, line_idx: usize) -> bool {
         self.modified_lines.contains(&line_idx)
@@ -1402,6 +1899,10 @@ impl Buffer {
     /// Delete word at cursor position
     /// Returns true if deletion was successful
     pub fn delete_word_at_cursor(&mut self, cursor: &mut Cursor) -> bool {
+        if self.read_only {
+            return false;
+        }
+        
         // Create the action before modifying the buffer
         let cursor_before = *cursor;
         let cursor_after = *cursor; // Cursor doesn't move after delete word
@@ -1470,6 +1971,10 @@ impl Buffer {
     /// Delete from cursor to end of line
     /// Returns true if deletion was successful
     pub fn delete_to_end_of_line(&mut self, cursor: &Cursor) -> bool {
+        if self.read_only {
+            return false;
+        }
+        
         // Create the action before modifying the buffer
         let cursor_before = *cursor;
         let cursor_after = *cursor; // Cursor doesn't move 
@@ -1506,9 +2011,206 @@ impl Buffer {
         true
     }
     
+    /// Replace the text between columns `start` and `end` of a line, leaving
+    /// the cursor after the new text
+    /// Returns true if the line was changed
+    pub fn replace_in_line(&mut self, y: usize, start: usize, end: usize, text: &str, cursor: &mut Cursor) -> bool {
+        if self.read_only || y >= self.lines.len() {
+            return false;
+        }
+        let Some(old_text) = self.lines[y].get(start..end).map(str::to_string) else {
+            return false;
+        };
+        if old_text == text {
+            return false;
+        }
+
+        let cursor_before = *cursor;
+        self.splice_line(y, start, old_text.len(), text);
+        *cursor = Cursor { x: start + text.len(), y };
+
+        self.history.push(EditorAction {
+            action_type: ActionType::ReplaceInLine {
+                position: (y, start),
+                old_text,
+                new_text: text.to_string(),
+            },
+            cursor_before,
+            cursor_after: *cursor,
+        });
+
+        true
+    }
+
+    /// Text between two (line, column) positions, in either order
+    pub fn text_in_range(&self, from: (usize, usize), to: (usize, usize)) -> String {
+        let (start, end) = (from.min(to), from.max(to));
+        if start.0 >= self.lines.len() {
+            return String::new();
+        }
+        let end = min(end, (self.lines.len() - 1, usize::MAX));
+        let clamp = |line: &str, x: usize| floor_char_boundary(line, x.min(line.len()));
+        if start.0 == end.0 {
+            let line = &self.lines[start.0];
+            return line[clamp(line, start.1)..clamp(line, end.1)].to_string();
+        }
+        let first = &self.lines[start.0];
+        let last = &self.lines[end.0];
+        let mut text = first[clamp(first, start.1)..].to_string();
+        for line in &self.lines[start.0 + 1..end.0] {
+            text.push('\n');
+            text.push_str(line);
+        }
+        text.push('\n');
+        text.push_str(&last[..clamp(last, end.1)]);
+        text
+    }
+
+    /// Replace the text between two (line, column) positions, in either
+    /// order, leaving the cursor after the new text
+    ///
+    /// Unlike `replace_in_line` both texts can span lines. The change is
+    /// recorded as a single undo step. Returns the replaced text, or None
+    /// if nothing changed.
+    pub fn replace_range(&mut self, from: (usize, usize), to: (usize, usize), text: &str, cursor: &mut Cursor) -> Option<String> {
+        if self.read_only || from.0.max(to.0) >= self.lines.len() {
+            return None;
+        }
+        let (start, end) = (from.min(to), from.max(to));
+        let start = (start.0, floor_char_boundary(&self.lines[start.0], start.1.min(self.lines[start.0].len())));
+        let end = (end.0, floor_char_boundary(&self.lines[end.0], end.1.min(self.lines[end.0].len())));
+        let old_text = self.text_in_range(start, end);
+
+        if start.0 == end.0 && !text.contains('\n') {
+            return self.replace_in_line(start.0, start.1, end.1, text, cursor).then_some(old_text);
+        }
+
+        let mut new_lines: Vec<String> = text.split('\n').map(str::to_string).collect();
+        let last_inserted = new_lines.len() - 1;
+        let end_x = new_lines[last_inserted].len() + if last_inserted == 0 { start.1 } else { 0 };
+        new_lines[0].insert_str(0, &self.lines[start.0][..start.1]);
+        new_lines[last_inserted].push_str(&self.lines[end.0][end.1..]);
+        let mut content = self.lines[..start.0].to_vec();
+        content.extend(new_lines);
+        content.extend_from_slice(&self.lines[end.0 + 1..]);
+
+        if !self.apply_formatted_content(&content.join("\n"), cursor) {
+            return None;
+        }
+        *cursor = Cursor { x: end_x, y: start.0 + last_inserted };
+        Some(old_text)
+    }
+
+    /// Join lines `start..=end` into one, like `J`
+    ///
+    /// Each joined line loses its indentation and is separated by a single
+    /// space, left out after trailing whitespace, before a closing bracket
+    /// and around empty lines. A single line is joined with the next. The
+    /// cursor goes to the last join. Returns false if there was nothing to
+    /// join.
+    pub fn join_lines(&mut self, start: usize, end: usize, cursor: &mut Cursor) -> bool {
+        let end = end.max(start + 1);
+        if self.read_only || end >= self.lines.len() {
+            return false;
+        }
+        let mut joined = self.lines[start].clone();
+        let mut join_x = joined.len();
+        for line in &self.lines[start + 1..=end] {
+            let line = line.trim_start();
+            join_x = joined.len();
+            if !joined.is_empty() && !line.is_empty() && !joined.ends_with([' ', '\t']) && !line.starts_with([')', ']', '}']) {
+                joined.push(' ');
+            }
+            joined.push_str(line);
+        }
+
+        let mut content = self.lines[..start].to_vec();
+        content.push(joined);
+        content.extend_from_slice(&self.lines[end + 1..]);
+        if !self.apply_formatted_content(&content.join("\n"), cursor) {
+            return false;
+        }
+        *cursor = Cursor { x: join_x, y: start };
+        true
+    }
+
+    /// Insert a copy of lines `start..=end` below them, moving the cursor
+    /// to the same place in the copy
+    pub fn duplicate_lines(&mut self, start: usize, end: usize, cursor: &mut Cursor) -> bool {
+        if self.read_only || end >= self.lines.len() {
+            return false;
+        }
+        let mut content = self.lines[..=end].to_vec();
+        content.extend_from_slice(&self.lines[start..]);
+        let target = Cursor { x: cursor.x, y: cursor.y + end + 1 - start };
+        if !self.apply_formatted_content(&content.join("\n"), cursor) {
+            return false;
+        }
+        *cursor = target;
+        true
+    }
+
+    /// Move lines `start..=end` one line down or up, past their neighbour,
+    /// taking the cursor with them. Returns false at the end of the buffer.
+    pub fn move_lines(&mut self, start: usize, end: usize, down: bool, cursor: &mut Cursor) -> bool {
+        if self.read_only || end >= self.lines.len() || (down && end + 1 >= self.lines.len()) || (!down && start == 0) {
+            return false;
+        }
+        let mut content = self.lines.clone();
+        let target = match down {
+            true => {
+                content[start..=end + 1].rotate_right(1);
+                Cursor { x: cursor.x, y: cursor.y + 1 }
+            },
+            false => {
+                content[start - 1..=end].rotate_left(1);
+                Cursor { x: cursor.x, y: cursor.y - 1 }
+            },
+        };
+        if !self.apply_formatted_content(&content.join("\n"), cursor) {
+            return false;
+        }
+        *cursor = target;
+        true
+    }
+
+    /// Replace lines `start..=end` with what `transform` makes of them, as
+    /// one undo step, putting the cursor at the start of the first line.
+    /// Returns false if nothing changed.
+    pub fn transform_lines(&mut self, start: usize, end: usize, cursor: &mut Cursor, transform: impl FnOnce(&[String]) -> Vec<String>) -> bool {
+        if self.read_only || start > end || end >= self.lines.len() {
+            return false;
+        }
+        let mut content = self.lines[..start].to_vec();
+        content.extend(transform(&self.lines[start..=end]));
+        content.extend_from_slice(&self.lines[end + 1..]);
+        if !self.apply_formatted_content(&content.join("\n"), cursor) {
+            return false;
+        }
+        *cursor = Cursor { x: 0, y: start.min(self.lines.len().saturating_sub(1)) };
+        true
+    }
+
+    /// Replace `len` bytes of a line starting at column `x`
+    fn splice_line(&mut self, y: usize, x: usize, len: usize, text: &str) -> bool {
+        match self.lines.get_mut(y) {
+            Some(line) if line.get(x..x + len).is_some() => {
+                line.replace_range(x..x + len, text);
+                self.mark_line_modified(y);
+                self.is_modified = true;
+                true
+            },
+            _ => false,
+        }
+    }
+
     /// Delete from start of line to cursor
     /// Returns true if deletion was successful
     pub fn delete_to_beginning_of_line(&mut self, cursor: &Cursor) -> bool {
+        if self.read_only {
+            return false;
+        }
+        
         // Create the action before modifying the buffer
         let cursor_before = *cursor;
         let cursor_after = Cursor { x: 0, y: cursor.y }; // Cursor moves to start of line
@@ -1546,6 +2248,16 @@ impl Buffer {
     }
     
     // We use the similar crate for diffing, so we no longer need the load_file_for_diff method
+
+    /// What a save would change in the file, for the save confirmation
+    pub fn review_against_disk(&self) -> Result<WriteReview> {
+        let Some(path) = &self.file_path else { return Ok(WriteReview::default()) };
+        if self.paged.is_some() || !std::path::Path::new(path).exists() {
+            return Ok(WriteReview::default());
+        }
+        let disk_content = encoding::read(path)?.text;
+        Ok(WriteReview::new(&disk_content, &self.get_content()))
+    }
     
     /// Find differences between current buffer and on-disk version using sophisticated diff algorithm
     pub fn diff_with_disk(&self) -> Result<HashSet<usize>> {
@@ -1555,9 +2267,12 @@ impl Buffer {
             None => return Ok(HashSet::new()),
         };
         
+        if self.paged.is_some() {
+            return Ok(HashSet::new());
+        }
+        
         // Read the disk content
-        let disk_content = fs::read_to_string(path)
-            .with_context(|| format!("Failed to read file: {}", path))?;
+        let disk_content = encoding::read(path)?.text;
         
         // Get the buffer content as a single string
         let buffer_content = self.get_content();
@@ -1609,9 +2324,25 @@ impl Buffer {
     }
 }
 
+impl Default for Buffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The largest index no greater than `index` that starts a character
+fn floor_char_boundary(line: &str, index: usize) -> usize {
+    let mut index = index.min(line.len());
+    while !line.is_char_boundary(index) {
+        index -= 1;
+    }
+    index
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::fs;
     
     #[test]
     fn test_delete_line_with_undo() {
@@ -1725,4 +2456,184 @@ mod tests {
         assert_eq!(buffer.lines[1], "");
         assert_eq!(buffer.lines[2], "Second line");
     }
-}
\ No newline at end of file
+    
+    #[test]
+    fn test_trim_trailing_whitespace_is_one_undo_step() {
+        let mut buffer = Buffer::new();
+        buffer.lines = vec!["fn main() {  ".to_string(), "\tlet x = 1;\t".to_string(), "}".to_string()];
+        let mut cursor = Cursor { x: 12, y: 0 };
+
+        assert_eq!(buffer.trim_trailing_whitespace(&mut cursor), 2);
+        assert_eq!(buffer.lines, ["fn main() {", "\tlet x = 1;", "}"]);
+        assert_eq!(cursor.x, 11);
+        assert_eq!(buffer.trim_trailing_whitespace(&mut cursor), 0);
+
+        buffer.undo(&mut cursor);
+        assert_eq!(buffer.lines, ["fn main() {  ", "\tlet x = 1;\t", "}"]);
+    }
+
+    #[test]
+    fn test_apply_formatted_content_is_minimal() {
+        let mut buffer = Buffer::new();
+        buffer.lines = vec![
+            "fn main(){".to_string(),
+            "let x=1;".to_string(),
+            "}".to_string(),
+            "".to_string(),
+            "fn helper() {}".to_string(),
+        ];
+        
+        // Cursor sits on an unchanged line after the reformatted block
+        let mut cursor = Cursor { x: 3, y: 4 };
+        let formatted = "fn main() {\n    let x = 1;\n}\n\nfn helper() {}";
+        assert!(buffer.apply_formatted_content(formatted, &mut cursor));
+        
+        assert_eq!(buffer.lines[1], "    let x = 1;");
+        assert_eq!((cursor.y, cursor.x), (4, 3));
+        
+        // Only the reformatted lines are marked as modified
+        assert!(buffer.is_line_modified(0));
+        assert!(buffer.is_line_modified(1));
+        assert!(!buffer.is_line_modified(4));
+        
+        // Formatting again is a no-op, and one undo restores the original
+        assert!(!buffer.apply_formatted_content(formatted, &mut cursor));
+        assert!(buffer.undo(&mut cursor));
+        assert_eq!(buffer.lines[0], "fn main(){");
+        assert_eq!(buffer.lines[1], "let x=1;");
+    }
+
+    #[test]
+    fn test_append_from_disk() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("app.log");
+        let path_str = path.to_str().unwrap();
+        fs::write(&path, "first\npartial").unwrap();
+        
+        let mut buffer = Buffer::new();
+        buffer.load_file(path_str).unwrap();
+        assert!(!buffer.append_from_disk().unwrap());
+        
+        // Appended text continues the last line and adds new ones
+        fs::write(&path, "first\npartial line\nsecond\n").unwrap();
+        assert!(buffer.append_from_disk().unwrap());
+        assert_eq!(buffer.lines, vec!["first", "partial line", "second", ""]);
+        
+        // A truncated file is read again from scratch
+        fs::write(&path, "rotated\n").unwrap();
+        assert!(buffer.append_from_disk().unwrap());
+        assert_eq!(buffer.lines, vec!["rotated", ""]);
+    }
+
+    #[test]
+    fn test_save_keeps_encoding_and_line_endings() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notes.txt");
+        let path_str = path.to_str().unwrap();
+        fs::write(&path, b"caf\xe9\r\nend\r\n").unwrap();
+        
+        let mut buffer = Buffer::new();
+        buffer.load_file(path_str).unwrap();
+        assert_eq!(buffer.lines, vec!["café", "end", ""]);
+        assert_eq!(buffer.line_ending, LineEnding::Dos);
+        assert!(buffer.diff_with_disk().unwrap().is_empty());
+        
+        buffer.lines[1] = "fin".to_string();
+        buffer.save(None, &WriteOptions::default()).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"caf\xe9\r\nfin\r\n");
+        
+        buffer.line_ending = LineEnding::Unix;
+        buffer.encoding = UTF_8;
+        buffer.save(None, &WriteOptions::default()).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "café\nfin\n");
+    }
+
+    #[test]
+    fn test_offsets_round_trip() {
+        let mut buffer = Buffer::new();
+        buffer.lines = vec!["héllo".to_string(), "".to_string(), "wörld".to_string()];
+
+        // "héllo\n\n" is 8 bytes and 7 characters
+        assert_eq!(buffer.offsets_at(3, 2), (11, 9));
+        assert_eq!(buffer.position_at_offset(11, false), Some((3, 2)));
+        assert_eq!(buffer.position_at_offset(9, true), Some((3, 2)));
+
+        // Inside "é" lands on it, and a line break is the end of its line
+        assert_eq!(buffer.position_at_offset(2, false), Some((1, 0)));
+        assert_eq!(buffer.position_at_offset(6, false), Some((6, 0)));
+        assert_eq!(buffer.position_at_offset(14, false), Some((6, 2)));
+        assert_eq!(buffer.position_at_offset(15, false), None);
+    }
+
+    #[test]
+    fn test_unsaved_lines_follow_the_saved_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notes.txt");
+        fs::write(&path, "one\ntwo\nthree\nfour").unwrap();
+        let mut buffer = Buffer::new();
+        buffer.load_file(path.to_str().unwrap()).unwrap();
+        assert!(buffer.unsaved_lines().is_empty());
+
+        let mut cursor = Cursor { x: 0, y: 0 };
+        buffer.insert_char_at_cursor('X', &cursor);
+        buffer.delete_line(2);
+        assert_eq!(buffer.lines, ["Xone", "two", "four"]);
+        // The line now where "three" was stands in for it
+        assert_eq!(buffer.unsaved_lines(), HashSet::from([0, 2]));
+
+        buffer.save(None, &WriteOptions::default()).unwrap();
+        assert!(buffer.unsaved_lines().is_empty());
+        buffer.undo(&mut cursor);
+        assert_eq!(buffer.unsaved_lines(), HashSet::from([2]));
+        buffer.redo(&mut cursor);
+        assert!(buffer.unsaved_lines().is_empty());
+    }
+
+    #[test]
+    fn test_join_duplicate_and_move_lines() {
+        let mut buffer = Buffer::new();
+        buffer.lines = ["call(", "    first,", "    second", ")", "", "end"].iter().map(|line| line.to_string()).collect();
+        let mut cursor = Cursor { x: 0, y: 0 };
+
+        assert!(buffer.join_lines(0, 3, &mut cursor));
+        assert_eq!(buffer.lines, ["call( first, second)", "", "end"]);
+        assert_eq!((cursor.y, cursor.x), (0, 19));
+        // Empty lines join without a space
+        assert!(buffer.join_lines(0, 0, &mut cursor));
+        assert_eq!(buffer.lines, ["call( first, second)", "end"]);
+        assert!(!buffer.join_lines(1, 1, &mut cursor));
+
+        cursor = Cursor { x: 2, y: 0 };
+        assert!(buffer.duplicate_lines(0, 0, &mut cursor));
+        assert_eq!(buffer.lines, ["call( first, second)", "call( first, second)", "end"]);
+        assert_eq!((cursor.y, cursor.x), (1, 2));
+
+        assert!(buffer.move_lines(1, 2, false, &mut cursor));
+        assert_eq!(buffer.lines, ["call( first, second)", "end", "call( first, second)"]);
+        assert_eq!(cursor.y, 0);
+        assert!(!buffer.move_lines(0, 1, false, &mut cursor));
+
+        // Each edit is one undo step
+        buffer.undo(&mut cursor);
+        assert_eq!(buffer.lines, ["call( first, second)", "call( first, second)", "end"]);
+        buffer.undo(&mut cursor);
+        buffer.undo(&mut cursor);
+        buffer.undo(&mut cursor);
+        assert_eq!(buffer.lines.len(), 6);
+    }
+
+    #[test]
+    fn test_transform_lines_is_one_undo_step() {
+        let mut buffer = Buffer::new();
+        buffer.lines = ["c", "b", "a", "end"].iter().map(|line| line.to_string()).collect();
+        let mut cursor = Cursor { x: 0, y: 2 };
+
+        assert!(buffer.transform_lines(0, 2, &mut cursor, |lines| lines.iter().rev().cloned().collect()));
+        assert_eq!(buffer.lines, ["a", "b", "c", "end"]);
+        assert_eq!((cursor.y, cursor.x), (0, 0));
+        assert!(!buffer.transform_lines(0, 1, &mut cursor, |lines| lines.to_vec()));
+
+        buffer.undo(&mut cursor);
+        assert_eq!(buffer.lines, ["c", "b", "a", "end"]);
+    }
+}
@@ -0,0 +1,71 @@
+use regex::Regex;
+use std::sync::OnceLock;
+
+/// The panel under the buffer showing a `:build` or `:run` job's output
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BuildPanel {
+    pub job: usize,
+    /// Line of the output picked with j/k, for Enter
+    pub selected: usize,
+    /// Keep the last line selected as output comes in
+    pub follow: bool,
+}
+
+impl BuildPanel {
+    pub fn new(job: usize) -> Self {
+        Self { job, selected: 0, follow: true }
+    }
+
+    /// The selected line, following the end of `line_count` lines
+    pub fn selected(&self, line_count: usize) -> usize {
+        let last = line_count.saturating_sub(1);
+        if self.follow { last } else { self.selected.min(last) }
+    }
+
+    /// Move the selection by `delta` lines; reaching the end follows it again
+    pub fn move_selection(&mut self, delta: isize, line_count: usize) {
+        let last = line_count.saturating_sub(1);
+        self.selected = self.selected(line_count).saturating_add_signed(delta).min(last);
+        self.follow = self.selected == last;
+    }
+}
+
+/// The `file:line:column` a line of compiler or panic output points at,
+/// with 1-based line and column (the column is 1 when there's none)
+///
+/// This covers rustc's `--> src/main.rs:3:5` as well as panics and most
+/// other tools' `path:line:col: message`.
+pub fn location(line: &str) -> Option<(String, usize, usize)> {
+    static PATTERN: OnceLock<Regex> = OnceLock::new();
+    let pattern = PATTERN.get_or_init(|| {
+        Regex::new(r"(?:^|[\s(])(?P<file>[\w./\\-]*\w\.\w+):(?P<line>\d+)(?::(?P<col>\d+))?").expect("valid location pattern")
+    });
+    let captures = pattern.captures(line)?;
+    let line_number = captures["line"].parse().ok().filter(|line| *line > 0)?;
+    let column = captures.name("col").and_then(|col| col.as_str().parse().ok()).unwrap_or(1);
+    Some((captures["file"].to_string(), line_number, column))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_output_lines_point_at_files() {
+        assert_eq!(location("  --> src/main.rs:3:5"), Some(("src/main.rs".to_string(), 3, 5)));
+        assert_eq!(
+            location("thread 'main' panicked at src/bin/tool.rs:12:9:"),
+            Some(("src/bin/tool.rs".to_string(), 12, 9)),
+        );
+        assert_eq!(location("main.go:7: undefined: x"), Some(("main.go".to_string(), 7, 1)));
+        assert_eq!(location("   Compiling zim v0.1.0 (/home/me/zim)"), None);
+        assert_eq!(location("Finished in 12:30"), None);
+
+        let mut panel = BuildPanel::new(1);
+        assert_eq!(panel.selected(10), 9);
+        panel.move_selection(-3, 10);
+        assert_eq!((panel.selected(20), panel.follow), (6, false));
+        panel.move_selection(30, 20);
+        assert!(panel.follow);
+    }
+}
@@ -0,0 +1,107 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Where cargo runs for a file: the package holding it, and the workspace
+/// that package is a member of, which the paths in cargo's output are
+/// relative to (the package itself when it isn't in a workspace)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CargoProject {
+    pub package: PathBuf,
+    pub workspace: PathBuf,
+}
+
+impl CargoProject {
+    /// The project of the file at `path`, which should be absolute
+    pub fn for_file(path: &Path) -> Option<Self> {
+        let package = path.ancestors()
+            .skip(1)
+            .find(|dir| dir.join("Cargo.toml").is_file())?;
+        Some(Self::for_package(package))
+    }
+
+    /// The project of the package in `package`, looking for a workspace
+    /// above it that lists it as a member
+    pub fn for_package(package: &Path) -> Self {
+        let workspace = package.ancestors()
+            .find(|dir| workspace_members(dir).is_some_and(|members| members.contains(package)))
+            .unwrap_or(package);
+        Self { package: package.to_path_buf(), workspace: workspace.to_path_buf() }
+    }
+}
+
+/// The packages a workspace manifest lists, and leaves out
+struct Members {
+    root: PathBuf,
+    members: Vec<String>,
+    exclude: Vec<String>,
+}
+
+impl Members {
+    fn contains(&self, package: &Path) -> bool {
+        if package == self.root {
+            return true;
+        }
+        let Ok(relative) = package.strip_prefix(&self.root) else { return false };
+        let relative = relative.to_string_lossy().replace('\\', "/");
+        let matches = |patterns: &[String]| patterns.iter().any(|pattern| glob_matches(pattern.trim_end_matches('/'), &relative));
+        matches(&self.members) && !matches(&self.exclude)
+    }
+}
+
+/// The members of the workspace whose manifest is in `dir`, or None if
+/// there's no workspace there
+fn workspace_members(dir: &Path) -> Option<Members> {
+    let text = fs::read_to_string(dir.join("Cargo.toml")).ok()?;
+    let manifest: toml::Value = toml::from_str(&text).ok()?;
+    let workspace = manifest.get("workspace")?;
+    let list = |key: &str| -> Vec<String> {
+        workspace.get(key).and_then(|value| value.as_array()).map(|values| {
+            values.iter().filter_map(|value| value.as_str()).map(|value| value.trim_start_matches("./").to_string()).collect()
+        }).unwrap_or_default()
+    };
+    Some(Members { root: dir.to_path_buf(), members: list("members"), exclude: list("exclude") })
+}
+
+/// Match a workspace member pattern like `crates/*` against a relative
+/// path, with `*` and `?` matching within one path component
+fn glob_matches(pattern: &str, path: &str) -> bool {
+    let patterns: Vec<&str> = pattern.split('/').collect();
+    let components: Vec<&str> = path.split('/').collect();
+    patterns.len() == components.len()
+        && patterns.iter().zip(&components).all(|(pattern, component)| wildcard_matches(pattern.as_bytes(), component.as_bytes()))
+}
+
+fn wildcard_matches(pattern: &[u8], text: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => text.is_empty(),
+        Some((b'*', rest)) => (0..=text.len()).any(|skip| wildcard_matches(rest, &text[skip..])),
+        Some((b'?', rest)) => !text.is_empty() && wildcard_matches(rest, &text[1..]),
+        Some((c, rest)) => text.first() == Some(c) && wildcard_matches(rest, &text[1..]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_packages_find_their_workspace() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let root = dir.path().canonicalize()?;
+        fs::write(root.join("Cargo.toml"), "[workspace]\nmembers = [\"crates/*\", \"tools/cli\"]\nexclude = [\"crates/old\"]\n")?;
+        for package in ["crates/core", "crates/old", "tools/cli", "tools/other"] {
+            fs::create_dir_all(root.join(package).join("src"))?;
+            fs::write(root.join(package).join("Cargo.toml"), "[package]\nname = \"x\"\n")?;
+        }
+
+        let project = CargoProject::for_file(&root.join("crates/core/src/lib.rs")).unwrap();
+        assert_eq!(project, CargoProject { package: root.join("crates/core"), workspace: root.clone() });
+        assert_eq!(CargoProject::for_file(&root.join("tools/cli/src/main.rs")).unwrap().workspace, root);
+
+        // Excluded and unlisted packages stand alone
+        assert_eq!(CargoProject::for_file(&root.join("crates/old/src/lib.rs")).unwrap().workspace, root.join("crates/old"));
+        assert_eq!(CargoProject::for_file(&root.join("tools/other/src/main.rs")).unwrap().workspace, root.join("tools/other"));
+        assert!(CargoProject::for_file(Path::new("/no/such/dir/main.rs")).is_none());
+        Ok(())
+    }
+}
@@ -0,0 +1,233 @@
+use super::diagnostics::{Diagnostic, DiagnosticCollection, DiagnosticSeverity, TextSpan};
+use regex::Regex;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// A built-in "compiler" preset: the command Ctrl+d runs for a project and
+/// how its output is turned into diagnostics
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compiler {
+    Cargo,
+    Go,
+    Tsc,
+    Pytest,
+    Make,
+}
+
+impl Compiler {
+    pub const ALL: [Compiler; 5] = [
+        Compiler::Cargo,
+        Compiler::Go,
+        Compiler::Tsc,
+        Compiler::Pytest,
+        Compiler::Make,
+    ];
+
+    /// Name used in the config file and with `:compiler`
+    pub fn name(&self) -> &'static str {
+        match self {
+            Compiler::Cargo => "cargo",
+            Compiler::Go => "go",
+            Compiler::Tsc => "tsc",
+            Compiler::Pytest => "pytest",
+            Compiler::Make => "make",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|compiler| compiler.name() == name)
+    }
+
+    /// Shell command that checks the project
+    pub fn command(&self) -> &'static str {
+        match self {
+            Compiler::Cargo => "cargo check --message-format=human",
+            Compiler::Go => "go build ./...",
+            Compiler::Tsc => "tsc --noEmit --pretty false",
+            Compiler::Pytest => "pytest -q --tb=line",
+            Compiler::Make => "make",
+        }
+    }
+
+    /// Files that mark the root directory of a project using this compiler
+    fn project_markers(&self) -> &'static [&'static str] {
+        match self {
+            Compiler::Cargo => &["Cargo.toml"],
+            Compiler::Go => &["go.mod"],
+            Compiler::Tsc => &["tsconfig.json"],
+            Compiler::Pytest => &["pytest.ini", "pyproject.toml", "setup.cfg", "tox.ini"],
+            Compiler::Make => &["Makefile", "makefile", "GNUmakefile"],
+        }
+    }
+
+    /// The preset normally used for a file type
+    fn for_extension(extension: &str) -> Option<Self> {
+        match extension {
+            "rs" => Some(Compiler::Cargo),
+            "go" => Some(Compiler::Go),
+            "ts" | "tsx" | "mts" | "cts" => Some(Compiler::Tsc),
+            "py" => Some(Compiler::Pytest),
+            "c" | "h" | "cc" | "cpp" | "hpp" | "cxx" => Some(Compiler::Make),
+            _ => None,
+        }
+    }
+
+    /// Pick the preset for a file, preferring the per-extension choice from
+    /// the config over the built-in one
+    pub fn for_file(path: &str, overrides: &HashMap<String, String>) -> Option<Self> {
+        let extension = Path::new(path).extension()?.to_str()?;
+        match overrides.get(extension) {
+            Some(name) => Self::from_name(name),
+            None => Self::for_extension(extension),
+        }
+    }
+
+    /// Walk up from a file to the directory holding this compiler's project file
+    pub fn find_project_root(&self, file_path: &str) -> Option<PathBuf> {
+        let path = Path::new(file_path).canonicalize().ok()?;
+        path.ancestors()
+            .skip(1)
+            .find(|dir| self.project_markers().iter().any(|marker| dir.join(marker).exists()))
+            .map(Path::to_path_buf)
+    }
+
+    /// Parse the preset's output into diagnostics for the current file
+    ///
+    /// This covers go, gcc/clang (through make), pytest's `--tb=line` and
+    /// tsc's `file(line,col): error TS1234: message` form. Cargo output has
+    /// its own, richer parser.
+    pub fn parse_output(&self, output: &str, root: &Path, current_file: &str) -> DiagnosticCollection {
+        parse_lines(output, root, current_file, DiagnosticSeverity::Error)
+    }
+}
+
+/// Parse `file:line:col: message` style output, and tsc's
+/// `file(line,col): message`, into diagnostics for the current file, with
+/// reported paths taken relative to `root`
+///
+/// Messages that don't start with a severity get `default`.
+pub fn parse_lines(output: &str, root: &Path, current_file: &str, default: DiagnosticSeverity) -> DiagnosticCollection {
+    let mut diagnostics = DiagnosticCollection::new();
+    let pattern = Regex::new(
+        r"^(?P<file>[^\s:(][^:(]*?)(?:\((?P<tsc_line>\d+),(?P<tsc_col>\d+)\)|:(?P<line>\d+)(?::(?P<col>\d+))?):\s*(?P<message>.*)$",
+    ).expect("valid diagnostic pattern");
+
+    let current = match Path::new(current_file).canonicalize() {
+        Ok(path) => path,
+        Err(_) => return diagnostics,
+    };
+
+    for line in output.lines() {
+        let Some(captures) = pattern.captures(line.trim_end()) else { continue };
+
+        let reported_file = &captures["file"];
+        let matches_current = root.join(reported_file).canonicalize()
+            .is_ok_and(|path| path == current);
+        if !matches_current {
+            continue;
+        }
+
+        let line_number: usize = captures.name("line")
+            .or_else(|| captures.name("tsc_line"))
+            .and_then(|m| m.as_str().parse().ok())
+            .unwrap_or(1);
+        let column: usize = captures.name("col")
+            .or_else(|| captures.name("tsc_col"))
+            .and_then(|m| m.as_str().parse().ok())
+            .unwrap_or(1);
+
+        let (severity, message) = split_severity(&captures["message"], default.clone());
+        let start_column = column.saturating_sub(1);
+        let end_column = match severity {
+            DiagnosticSeverity::Error => start_column + 5,
+            _ => start_column + 10,
+        };
+
+        let diagnostic = Diagnostic::new(
+            message,
+            severity,
+            TextSpan::new(line_number.saturating_sub(1), start_column, end_column),
+        )
+        .with_file_path(reported_file)
+        .with_original_line(line_number);
+        diagnostics.add_diagnostic(diagnostic);
+    }
+
+    diagnostics
+}
+
+/// Split a leading "error:", "warning:" or "note:" (optionally followed by a
+/// code, as in tsc's "error TS2322:") off a message. Messages without one
+/// get `default`: errors for compilers, which is how go and pytest report
+/// problems.
+fn split_severity(message: &str, default: DiagnosticSeverity) -> (DiagnosticSeverity, &str) {
+    let prefixes = [
+        ("error", DiagnosticSeverity::Error),
+        ("fatal error", DiagnosticSeverity::Error),
+        ("warning", DiagnosticSeverity::Warning),
+        ("note", DiagnosticSeverity::Information),
+    ];
+
+    for (prefix, severity) in prefixes {
+        let Some(rest) = message.strip_prefix(prefix) else { continue };
+        let Some((code, text)) = rest.split_once(':') else { continue };
+        // Allow an error code between the keyword and the colon
+        if code.is_empty() || (code.starts_with(' ') && !code.trim().contains(' ')) {
+            return (severity, text.trim());
+        }
+    }
+
+    (default, message.trim())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_compiler_selection() {
+        let mut overrides = HashMap::new();
+        assert_eq!(Compiler::for_file("main.go", &overrides), Some(Compiler::Go));
+        assert_eq!(Compiler::for_file("src/app.tsx", &overrides), Some(Compiler::Tsc));
+        assert_eq!(Compiler::for_file("notes.txt", &overrides), None);
+
+        overrides.insert("c".to_string(), "make".to_string());
+        overrides.insert("py".to_string(), "make".to_string());
+        assert_eq!(Compiler::for_file("test_app.py", &overrides), Some(Compiler::Make));
+    }
+
+    #[test]
+    fn test_parse_output_formats() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("src")).unwrap();
+        let file = dir.path().join("src/app.ts");
+        fs::write(&file, "let x: number = 'a';\n").unwrap();
+        fs::write(dir.path().join("other.ts"), "").unwrap();
+        let current = file.to_str().unwrap();
+
+        let output = "\
+src/app.ts(1,5): error TS2322: Type 'string' is not assignable to type 'number'.
+other.ts(3,1): error TS1005: ';' expected.
+./src/app.ts:2:1: warning: unused variable
+src/app.ts:3: AssertionError: assert 1 == 2
+make: *** [Makefile:3: all] Error 1";
+
+        let diagnostics = Compiler::Tsc.parse_output(output, dir.path(), current);
+        let all = diagnostics.get_all_diagnostics();
+        assert_eq!(all.len(), 3);
+
+        let first = &diagnostics.get_diagnostics_for_line(0).unwrap()[0];
+        assert_eq!(first.severity, DiagnosticSeverity::Error);
+        assert_eq!(first.message, "Type 'string' is not assignable to type 'number'.");
+        assert_eq!(first.span.start_column, 4);
+
+        let second = &diagnostics.get_diagnostics_for_line(1).unwrap()[0];
+        assert_eq!(second.severity, DiagnosticSeverity::Warning);
+        assert_eq!(second.message, "unused variable");
+
+        let third = &diagnostics.get_diagnostics_for_line(2).unwrap()[0];
+        assert_eq!(third.severity, DiagnosticSeverity::Error);
+        assert_eq!(third.message, "AssertionError: assert 1 == 2");
+    }
+}
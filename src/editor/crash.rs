@@ -0,0 +1,96 @@
+use anyhow::{Context, Result};
+use std::backtrace::Backtrace;
+use std::fs;
+use std::panic::PanicHookInfo;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The files open when zim last drew, for the crash report; the panic hook
+/// can't reach the editor itself
+static OPEN_FILES: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// Note which files are open, to be listed if zim crashes
+pub fn note_open_files(files: Vec<String>) {
+    if let Ok(mut open) = OPEN_FILES.lock() {
+        if *open != files {
+            *open = files;
+        }
+    }
+}
+
+/// On a panic, write a crash report to `dir`. A panic on the thread this
+/// is called from, the main one, also runs `restore` to give the terminal
+/// back and says where the report is before the usual panic message.
+///
+/// A worker thread panicking, like the highlighter's, doesn't end zim, so
+/// its report is written quietly and the terminal is left to the interface
+/// that's still drawing into it.
+pub fn install_panic_hook(restore: fn(), dir: PathBuf) {
+    let default_hook = std::panic::take_hook();
+    let main = std::thread::current().id();
+    std::panic::set_hook(Box::new(move |info: &PanicHookInfo| {
+        let on_main = std::thread::current().id() == main;
+        if on_main {
+            restore();
+        }
+        // try_lock, as the panic may have come while it was held
+        let files = OPEN_FILES.try_lock().map(|files| files.clone()).unwrap_or_default();
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|elapsed| elapsed.as_secs()).unwrap_or(0);
+        let report = report(&info.to_string(), &Backtrace::force_capture().to_string(), &files, now);
+        let written = write_report(&dir, &report, now);
+        if !on_main {
+            return;
+        }
+        match written {
+            Ok(path) => eprintln!("zim crashed. A crash report for a bug report is in {}\n", path.display()),
+            Err(e) => eprintln!("zim crashed, and the crash report couldn't be written: {:#}\n", e),
+        }
+        default_hook(info);
+    }));
+}
+
+/// The text of a crash report: the version, the panic and its backtrace,
+/// and the files that were open (`now` is in seconds since the Unix epoch)
+fn report(panic: &str, backtrace: &str, files: &[String], now: u64) -> String {
+    let mut text = format!(
+        "zim {} crash report\nTime: {} (Unix)\nSystem: {} {}\n\n{}\n\nOpen files:\n",
+        env!("CARGO_PKG_VERSION"), now, std::env::consts::OS, std::env::consts::ARCH, panic
+    );
+    if files.is_empty() {
+        text.push_str("  (none)\n");
+    }
+    for file in files {
+        text.push_str(&format!("  {}\n", file));
+    }
+    text.push_str(&format!("\nBacktrace:\n{}\n", backtrace));
+    text
+}
+
+/// Write a report to `crash-<time>.log` in `dir`, returning its path
+fn write_report(dir: &Path, report: &str, now: u64) -> Result<PathBuf> {
+    fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    let path = dir.join(format!("crash-{}.log", now));
+    fs::write(&path, report).with_context(|| format!("Failed to write {}", path.display()))?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crash_reports_list_the_panic_and_open_files() -> Result<()> {
+        let files = vec!["src/main.rs (modified)".to_string(), "[No Name]".to_string()];
+        let text = report("panicked at src/editor/mod.rs:10:5:\nindex out of bounds", "0: zim::main", &files, 1_700_000_000);
+        assert!(text.starts_with(&format!("zim {} crash report\nTime: 1700000000", env!("CARGO_PKG_VERSION"))));
+        assert!(text.contains("index out of bounds\n\nOpen files:\n  src/main.rs (modified)\n  [No Name]\n"));
+        assert!(text.ends_with("Backtrace:\n0: zim::main\n"));
+
+        let dir = tempfile::tempdir()?;
+        let path = write_report(&dir.path().join("config"), &text, 1_700_000_000)?;
+        assert_eq!(path, dir.path().join("config/crash-1700000000.log"));
+        assert_eq!(fs::read_to_string(path)?, text);
+        Ok(())
+    }
+}
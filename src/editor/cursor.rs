@@ -63,6 +63,57 @@ impl Cursor {
         self.y = buffer.line_count().saturating_sub(1);
         self.x = buffer.line_length(self.y);
     }
+
+    /// Move to the end of the next word, across lines (Emacs' M-f)
+    pub fn move_word_forward(&mut self, buffer: &Buffer) {
+        loop {
+            let line = buffer.get_line(self.y).as_bytes();
+            while self.x < line.len() && !is_word_byte(line[self.x]) {
+                self.x += 1;
+            }
+            if self.x < line.len() || self.y + 1 >= buffer.line_count() {
+                break;
+            }
+            self.y += 1;
+            self.x = 0;
+        }
+        let line = buffer.get_line(self.y).as_bytes();
+        while self.x < line.len() && is_word_byte(line[self.x]) {
+            self.x += 1;
+        }
+    }
+
+    /// Move to the start of the previous word, across lines (Emacs' M-b)
+    pub fn move_word_backward(&mut self, buffer: &Buffer) {
+        loop {
+            let line = buffer.get_line(self.y).as_bytes();
+            self.x = self.x.min(line.len());
+            while self.x > 0 && !is_word_byte(line[self.x - 1]) {
+                self.x -= 1;
+            }
+            if self.x > 0 || self.y == 0 {
+                break;
+            }
+            self.y -= 1;
+            self.x = buffer.line_length(self.y);
+        }
+        let line = buffer.get_line(self.y).as_bytes();
+        while self.x > 0 && is_word_byte(line[self.x - 1]) {
+            self.x -= 1;
+        }
+    }
+}
+
+impl Default for Cursor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Whether a byte is part of a word; bytes of non-ASCII characters count,
+/// so word motions never stop inside a character
+fn is_word_byte(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || byte == b'_' || !byte.is_ascii()
 }
 
 #[cfg(test)]
@@ -217,4 +268,21 @@ mod tests {
         assert_eq!(cursor.y, 3); // Last line index
         assert_eq!(cursor.x, 6); // "Fourth" length
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_word_motions() {
+        let buffer = create_test_buffer();
+        let mut cursor = Cursor::new();
+
+        cursor.move_word_forward(&buffer);
+        assert_eq!((cursor.x, cursor.y), (5, 0)); // End of "First"
+        cursor.move_word_forward(&buffer);
+        cursor.move_word_forward(&buffer);
+        assert_eq!((cursor.x, cursor.y), (6, 1)); // End of "Second"
+
+        cursor.move_word_backward(&buffer);
+        assert_eq!((cursor.x, cursor.y), (0, 1));
+        cursor.move_word_backward(&buffer);
+        assert_eq!((cursor.x, cursor.y), (6, 0)); // Start of "line"
+    }
+}
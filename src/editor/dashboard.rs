@@ -0,0 +1,137 @@
+use super::file_finder::RecentFile;
+use super::history::age;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// How many recent projects and files the dashboard lists, numbered 1-9
+pub const MAX_PROJECTS: usize = 3;
+pub const MAX_FILES: usize = 6;
+
+/// Files that mark the root of a project when there's no `.git` above
+const PROJECT_MARKERS: [&str; 6] = ["Cargo.toml", "package.json", "go.mod", "pyproject.toml", "Makefile", ".hg"];
+
+/// What picking a dashboard entry does
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DashboardAction {
+    FindFile,
+    NewFile,
+    RecentFiles,
+    Help,
+    Quit,
+    /// Open the file finder in a project, making it the working directory
+    OpenProject(PathBuf),
+    OpenFile(String),
+}
+
+/// A line of the start dashboard, with the key that picks it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DashboardEntry {
+    pub key: Option<char>,
+    pub section: &'static str,
+    pub label: String,
+    /// Shown dimmed after the label, like when a file was last opened
+    pub detail: String,
+    pub action: DashboardAction,
+}
+
+impl DashboardEntry {
+    fn new(key: Option<char>, section: &'static str, label: String, detail: String, action: DashboardAction) -> Self {
+        Self { key, section, label, detail, action }
+    }
+}
+
+/// The dashboard's entries: the actions, then the projects of the most
+/// recently opened files and those files themselves, numbered from 1
+///
+/// `recent` is newest first, as `FileFinder::recent_files` lists them, and
+/// `now` is in seconds since the Unix epoch.
+pub fn entries(recent: &[RecentFile], now: u64) -> Vec<DashboardEntry> {
+    let actions = [
+        ('f', "Find file", DashboardAction::FindFile),
+        ('e', "New file", DashboardAction::NewFile),
+        ('r', "Recent files", DashboardAction::RecentFiles),
+        ('?', "Help", DashboardAction::Help),
+        ('q', "Quit", DashboardAction::Quit),
+    ];
+    let mut entries: Vec<DashboardEntry> = actions.into_iter()
+        .map(|(key, label, action)| DashboardEntry::new(Some(key), "Actions", label.to_string(), String::new(), action))
+        .collect();
+
+    let opened = |file: &RecentFile| age(Duration::from_secs(now.saturating_sub(file.last_opened)));
+    let mut projects: Vec<PathBuf> = Vec::new();
+    for file in recent {
+        if projects.len() == MAX_PROJECTS {
+            break;
+        }
+        let Some(root) = Path::new(&file.path).canonicalize().ok().and_then(|path| project_root(&path)) else { continue };
+        if !projects.contains(&root) {
+            entries.push(DashboardEntry::new(None, "Recent projects", display_dir(&root), opened(file), DashboardAction::OpenProject(root.clone())));
+            projects.push(root);
+        }
+    }
+    for file in recent.iter().take(MAX_FILES) {
+        entries.push(DashboardEntry::new(None, "Recent files", file.path.clone(), opened(file), DashboardAction::OpenFile(file.path.clone())));
+    }
+
+    for (number, entry) in entries.iter_mut().filter(|entry| entry.key.is_none()).enumerate() {
+        entry.key = char::from_digit(number as u32 + 1, 10);
+    }
+    entries
+}
+
+/// The root of the project holding `file`: the nearest directory above it
+/// with a `.git`, or failing that with a manifest like `Cargo.toml`
+pub fn project_root(file: &Path) -> Option<PathBuf> {
+    let has = |dir: &Path, markers: &[&str]| markers.iter().any(|marker| dir.join(marker).exists());
+    file.ancestors().skip(1).find(|dir| has(dir, &[".git"]))
+        .or_else(|| file.ancestors().skip(1).find(|dir| has(dir, &PROJECT_MARKERS)))
+        .map(Path::to_path_buf)
+}
+
+/// A directory with the home directory shortened to `~`
+fn display_dir(dir: &Path) -> String {
+    match dirs::home_dir().and_then(|home| dir.strip_prefix(home).ok().map(Path::to_path_buf)) {
+        Some(relative) if relative.as_os_str().is_empty() => "~".to_string(),
+        Some(relative) => format!("~/{}", relative.display()),
+        None => dir.display().to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_recent_files_and_their_projects_are_numbered() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let root = dir.path().canonicalize()?;
+        fs::create_dir_all(root.join("app/.git"))?;
+        fs::create_dir_all(root.join("app/crates/core/src"))?;
+        fs::write(root.join("app/crates/core/Cargo.toml"), "")?;
+        fs::create_dir_all(root.join("tool"))?;
+        fs::write(root.join("tool/go.mod"), "")?;
+        let files = ["app/crates/core/src/lib.rs", "tool/main.go", "app/README.md"];
+        for file in files {
+            fs::write(root.join(file), "")?;
+        }
+
+        let recent: Vec<RecentFile> = files.iter().enumerate().map(|(index, file)| RecentFile {
+            path: root.join(file).to_string_lossy().to_string(),
+            last_opened: 1000 - index as u64 * 60,
+            count: 1,
+        }).collect();
+        let entries = entries(&recent, 1000);
+        let projects: Vec<_> = entries.iter().filter(|entry| entry.section == "Recent projects").collect();
+        assert_eq!(projects.len(), 2);
+        assert_eq!(projects[0].action, DashboardAction::OpenProject(root.join("app")));
+        assert_eq!((projects[1].key, projects[1].detail.as_str()), (Some('2'), "1m ago"));
+        assert_eq!(projects[1].action, DashboardAction::OpenProject(root.join("tool")));
+
+        let files: Vec<_> = entries.iter().filter(|entry| entry.section == "Recent files").collect();
+        assert_eq!(files.len(), 3);
+        assert_eq!(files[0].key, Some('3'));
+        assert_eq!(entries.iter().find(|entry| entry.key == Some('e')).unwrap().action, DashboardAction::NewFile);
+        Ok(())
+    }
+}
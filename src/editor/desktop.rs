@@ -0,0 +1,79 @@
+use super::primary;
+use anyhow::{anyhow, Context, Result};
+use std::io::ErrorKind;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// Shown when there's no tool to set the system clipboard with
+pub const NO_CLIPBOARD: &str = "no system clipboard tool (pbcopy, wl-copy, xclip, xsel or clip), so only in zim's";
+
+/// Commands that set the system clipboard, best first
+fn clipboard_commands(wayland: bool, x11: bool) -> Vec<&'static [&'static str]> {
+    if cfg!(target_os = "macos") {
+        return vec![&["pbcopy"]];
+    }
+    if cfg!(windows) {
+        return vec![&["clip"]];
+    }
+    let mut commands: Vec<&'static [&'static str]> = Vec::new();
+    if wayland {
+        commands.push(&["wl-copy"]);
+    }
+    if x11 {
+        commands.extend([&["xclip", "-i", "-selection", "clipboard"][..], &["xsel", "--clipboard", "--input"]]);
+    }
+    commands
+}
+
+/// Put `text` on the system clipboard, returning false if there's no tool
+/// to do it with
+pub fn copy(text: &str) -> Result<bool> {
+    let commands = clipboard_commands(std::env::var_os("WAYLAND_DISPLAY").is_some(), std::env::var_os("DISPLAY").is_some());
+    primary::write_with(commands, text)
+}
+
+/// The command that shows `path` in the system file manager: selected in
+/// its folder where the file manager can do that, else the folder opened
+fn reveal_command(path: &Path) -> Vec<String> {
+    let path = path.to_string_lossy().to_string();
+    if cfg!(target_os = "macos") {
+        vec!["open".to_string(), "-R".to_string(), path]
+    } else if cfg!(windows) {
+        vec!["explorer".to_string(), format!("/select,{}", path)]
+    } else {
+        let folder = Path::new(&path).parent().map_or(path.clone(), |parent| parent.to_string_lossy().to_string());
+        vec!["xdg-open".to_string(), folder]
+    }
+}
+
+/// Show `path` in the system file manager without waiting for it
+pub fn reveal(path: &Path) -> Result<()> {
+    let command = reveal_command(path);
+    let mut child = match Command::new(&command[0])
+        .args(&command[1..])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+    {
+        Err(e) if e.kind() == ErrorKind::NotFound => return Err(anyhow!("{} isn't installed", command[0])),
+        result => result.with_context(|| format!("Failed to run {}", command[0]))?,
+    };
+    // Reaped in the background, as the opener may take a moment
+    std::thread::spawn(move || child.wait());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_commands_for_the_platform() {
+        if cfg!(target_os = "linux") {
+            assert_eq!(reveal_command(Path::new("/home/me/src/main.rs")), ["xdg-open", "/home/me/src"]);
+            assert_eq!(clipboard_commands(true, true).iter().map(|command| command[0]).collect::<Vec<_>>(), ["wl-copy", "xclip", "xsel"]);
+            assert!(clipboard_commands(false, false).is_empty());
+        }
+    }
+}
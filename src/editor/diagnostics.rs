@@ -1,4 +1,5 @@
 use std::collections::HashMap;
+use std::path::Path;
 
 /// Severity level of a diagnostic
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -9,6 +10,18 @@ pub enum DiagnosticSeverity {
     Hint,
 }
 
+impl DiagnosticSeverity {
+    /// Most severe first, for sorting
+    fn rank(&self) -> u8 {
+        match self {
+            DiagnosticSeverity::Error => 0,
+            DiagnosticSeverity::Warning => 1,
+            DiagnosticSeverity::Information => 2,
+            DiagnosticSeverity::Hint => 3,
+        }
+    }
+}
+
 /// Represents a span of text in the editor (line and column range)
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct TextSpan {
@@ -32,6 +45,67 @@ impl TextSpan {
     }
 }
 
+/// A replacement of text the compiler marked as machine-applicable
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FixEdit {
+    /// 0-based line and character column where the replaced text starts
+    pub start: (usize, usize),
+    /// Where it ends, exclusive
+    pub end: (usize, usize),
+    /// The text from `start` to `end` when the fix was suggested
+    pub original: String,
+    pub replacement: String,
+}
+
+/// A fix suggested along with a diagnostic, which can be applied to the buffer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SuggestedFix {
+    /// The help message describing the fix
+    pub message: String,
+    pub edits: Vec<FixEdit>,
+}
+
+impl SuggestedFix {
+    /// First and last lines the edits touch
+    pub fn line_range(&self) -> (usize, usize) {
+        let start = self.edits.iter().map(|edit| edit.start.0).min().unwrap_or(0);
+        let end = self.edits.iter().map(|edit| edit.end.0).max().unwrap_or(0);
+        (start, end)
+    }
+
+    /// Whether `lines`, which start at line `first`, still have the text
+    /// each edit replaces, so the fix can be applied to them
+    pub fn matches(&self, lines: &[String], first: usize) -> bool {
+        let text = lines.join("\n");
+        self.edits.iter().all(|edit| {
+            let start = fix_offset(lines, first, &text, edit.start);
+            let end = fix_offset(lines, first, &text, edit.end).max(start);
+            text[start..end] == edit.original
+        })
+    }
+
+    /// Apply the edits to `lines`, which start at line `first`
+    pub fn apply(&self, lines: &[String], first: usize) -> Vec<String> {
+        let mut text = lines.join("\n");
+        let mut edits = self.edits.iter().collect::<Vec<_>>();
+        // From the end, so earlier offsets stay valid
+        edits.sort_by_key(|edit| std::cmp::Reverse(edit.start));
+        for edit in edits {
+            let start = fix_offset(lines, first, &text, edit.start);
+            let end = fix_offset(lines, first, &text, edit.end).max(start);
+            text.replace_range(start..end, &edit.replacement);
+        }
+        text.split('\n').map(str::to_string).collect()
+    }
+
+    fn shift(&mut self, delta: isize) {
+        for edit in &mut self.edits {
+            edit.start.0 = edit.start.0.saturating_add_signed(delta);
+            edit.end.0 = edit.end.0.saturating_add_signed(delta);
+        }
+    }
+}
+
 /// A diagnostic message for the editor
 #[derive(Debug, Clone)]
 pub struct Diagnostic {
@@ -45,6 +119,10 @@ pub struct Diagnostic {
     pub file_path: String,
     /// Original line number from the diagnostic (1-indexed)
     pub original_line_number: usize,
+    /// Tool that reported it, like `check`, `clippy` or `tsc`
+    pub source: String,
+    /// A fix the compiler is sure about, from cargo's JSON output
+    pub fix: Option<SuggestedFix>,
 }
 
 impl Diagnostic {
@@ -57,6 +135,8 @@ impl Diagnostic {
             additional_info: Vec::new(),
             file_path: String::new(),
             original_line_number: span.line + 1, // Default to 1-indexed from span
+            source: String::new(),
+            fix: None,
         }
     }
     
@@ -79,6 +159,11 @@ impl Diagnostic {
         self.original_line_number = line;
         self
     }
+
+    pub fn with_fix(mut self, fix: SuggestedFix) -> Self {
+        self.fix = Some(fix);
+        self
+    }
     
     /// Returns a formatted string with all diagnostic information
     pub fn format_full_message(&self) -> String {
@@ -138,6 +223,41 @@ impl DiagnosticCollection {
     pub fn clear(&mut self) {
         self.diagnostics_by_line.clear();
     }
+
+    /// Take out the diagnostic on `line` with `message`
+    pub fn remove(&mut self, line: usize, message: &str) -> Option<Diagnostic> {
+        let diagnostics = self.diagnostics_by_line.get_mut(&line)?;
+        let index = diagnostics.iter().position(|diagnostic| diagnostic.message == message)?;
+        let diagnostic = diagnostics.remove(index);
+        if diagnostics.is_empty() {
+            self.diagnostics_by_line.remove(&line);
+        }
+        Some(diagnostic)
+    }
+
+    /// Move the diagnostics below `line` by `delta` lines, after an edit
+    /// added or removed lines above them
+    pub fn shift_lines_after(&mut self, line: usize, delta: isize) {
+        if delta == 0 {
+            return;
+        }
+        let moved = self.diagnostics_by_line.keys().copied().filter(|l| *l > line).collect::<Vec<_>>();
+        let moved = moved.into_iter().filter_map(|l| self.diagnostics_by_line.remove(&l)).collect::<Vec<_>>();
+        for mut diagnostic in moved.into_iter().flatten() {
+            diagnostic.span.line = diagnostic.span.line.saturating_add_signed(delta);
+            if let Some(fix) = &mut diagnostic.fix {
+                fix.shift(delta);
+            }
+            self.add_diagnostic(diagnostic);
+        }
+    }
+
+    /// Record which tool reported every diagnostic in the collection
+    pub fn set_source(&mut self, source: &str) {
+        for diagnostic in self.diagnostics_by_line.values_mut().flatten() {
+            diagnostic.source = source.to_string();
+        }
+    }
     
     pub fn get_diagnostics_for_line(&self, line: usize) -> Option<&Vec<Diagnostic>> {
         self.diagnostics_by_line.get(&line)
@@ -177,6 +297,24 @@ impl DiagnosticCollection {
         }
     }
     
+    /// Diagnostics passing `filter`, in the panel's `sort` order; ties are
+    /// broken by location, so the order never changes between frames
+    pub fn get_sorted_diagnostics(&self, filter: &crate::editor::DiagnosticFilter, sort: crate::editor::DiagnosticSort) -> Vec<&Diagnostic> {
+        use crate::editor::DiagnosticSort;
+
+        let mut diagnostics = self.get_filtered_diagnostics(filter);
+        let location = |d: &Diagnostic| (d.file_path.clone(), d.span.line, d.span.start_column);
+        diagnostics.sort_by(|a, b| {
+            let order = match sort {
+                DiagnosticSort::Location => std::cmp::Ordering::Equal,
+                DiagnosticSort::Severity => a.severity.rank().cmp(&b.severity.rank()),
+                DiagnosticSort::Message => a.message.to_lowercase().cmp(&b.message.to_lowercase()),
+            };
+            order.then_with(|| location(a).cmp(&location(b))).then_with(|| a.message.cmp(&b.message))
+        });
+        diagnostics
+    }
+    
     /// Returns the number of errors in the collection
     pub fn error_count(&self) -> usize {
         self.get_all_diagnostics()
@@ -231,8 +369,46 @@ impl DiagnosticCollection {
 
 impl DiagnosticCollection {
     /// Parse diagnostics from cargo output, filtering to only include the current file
-    pub fn parse_cargo_output(mut self, output: &str, current_file_path: &str) -> Self {
+    ///
+    /// The output can be in cargo's human format or its JSON format
+    /// (`--message-format=json`), where each message carries the rendered
+    /// human text along with the fixes the compiler suggests. Paths in it
+    /// are relative to the workspace `root`, so a member crate's files are
+    /// told apart from same-named files in other members.
+    pub fn parse_cargo_output(mut self, output: &str, root: &Path, current_file_path: &str) -> Self {
         self.clear();
+
+        let mut human = String::new();
+        for line in output.lines() {
+            let message = line.starts_with('{')
+                .then(|| serde_json::from_str::<serde_json::Value>(line).ok())
+                .flatten();
+            let Some(message) = message else {
+                human.push_str(line);
+                human.push('\n');
+                continue;
+            };
+            if message["reason"] != "compiler-message" {
+                continue;
+            }
+            let message = &message["message"];
+            let mut rendered = Self::new();
+            rendered.parse_rendered(message["rendered"].as_str().unwrap_or_default(), root, current_file_path);
+            let mut fix = suggested_fix(message);
+            let main_message = message["message"].as_str().unwrap_or_default();
+            for mut diagnostic in rendered.diagnostics_by_line.into_values().flatten() {
+                if diagnostic.message == main_message {
+                    diagnostic.fix = fix.take().filter(|(file, _)| *file == diagnostic.file_path).map(|(_, fix)| fix);
+                }
+                self.add_diagnostic(diagnostic);
+            }
+        }
+        self.parse_rendered(&human, root, current_file_path);
+        self
+    }
+
+    /// Parse cargo's human output, adding the diagnostics for the current file
+    fn parse_rendered(&mut self, output: &str, root: &Path, current_file_path: &str) {
         
         // Normalize the current file path
         let current_file = std::path::PathBuf::from(current_file_path)
@@ -331,14 +507,12 @@ impl DiagnosticCollection {
                             }
                             
                             // Try to match in various ways (from most specific to least specific)
-                            is_current_file = 
-                                // 1. Direct match of canonical paths (most reliable)
-                                match (reported_file.canonicalize(), current_file.canonicalize()) {
-                                    (Ok(a), Ok(b)) => a == b,
-                                    _ => false
-                                } ||
+                            is_current_file = match (root.join(&reported_file).canonicalize(), current_file.canonicalize()) {
+                                // 1. Direct match of canonical paths (most reliable), which
+                                // tells apart same-named files in different workspace members
+                                (Ok(a), Ok(b)) => a == b,
                                 // 2. Direct string match of the paths
-                                reported_file_path == current_file_path ||
+                                _ => reported_file_path == current_file_path ||
                                 // 3. If current_file_path is a suffix of reported_file_path (e.g. src/main.rs matches /home/user/project/src/main.rs)
                                 reported_file_path.ends_with(current_file_path) ||
                                 // 4. If current_file_path contains just a filename and it matches the reported filename
@@ -348,7 +522,8 @@ impl DiagnosticCollection {
                                 // 5. If the filenames match and the paths have src/ in common
                                 (reported_filename == current_filename &&
                                  (reported_file_path.ends_with(&format!("src/{}", current_filename)) ||
-                                  current_file_path.ends_with(&format!("src/{}", current_filename))));
+                                  current_file_path.ends_with(&format!("src/{}", current_filename)))),
+                            };
                             
                             // Parse line and column if it's for the current file
                             if is_current_file && file_parts.len() > 1 {
@@ -458,8 +633,125 @@ impl DiagnosticCollection {
             
             i += 1;
         }
-        
-        self
     }
 }
 
+/// The text cargo prints for its JSON output, with each message rendered
+/// as in the human format
+pub fn rendered_cargo_output(output: &str) -> String {
+    output.lines()
+        .map(|line| match serde_json::from_str::<serde_json::Value>(line) {
+            Ok(message) if line.starts_with('{') => message["message"]["rendered"].as_str().unwrap_or_default().to_string(),
+            _ => format!("{}\n", line),
+        })
+        .collect()
+}
+
+/// Byte offset in `text`, `lines` starting at line `first` joined, of a
+/// line and character column, clamped to the text
+fn fix_offset(lines: &[String], first: usize, text: &str, (line, column): (usize, usize)) -> usize {
+    let line_start: usize = lines.iter().take(line.saturating_sub(first)).map(|l| l.len() + 1).sum();
+    let line_start = line_start.min(text.len());
+    let rest = &text[line_start..];
+    line_start + rest.char_indices().nth(column).map_or(rest.len(), |(i, _)| i)
+}
+
+/// The text a cargo JSON span covers, from the highlighted part of each of
+/// its lines
+fn span_text(span: &serde_json::Value) -> Option<String> {
+    let lines = span["text"].as_array()?.iter().map(|line| {
+        let (start, end) = (line["highlight_start"].as_u64()? as usize, line["highlight_end"].as_u64()? as usize);
+        let text = line["text"].as_str()?;
+        Some(text.chars().skip(start.saturating_sub(1)).take(end.saturating_sub(start)).collect::<String>())
+    }).collect::<Option<Vec<_>>>()?;
+    Some(lines.join("\n"))
+}
+
+/// The first machine-applicable fix among a cargo JSON message's children,
+/// with the file it edits; fixes spanning several files are left out
+fn suggested_fix(message: &serde_json::Value) -> Option<(String, SuggestedFix)> {
+    message["children"].as_array()?.iter().find_map(|child| {
+        let spans = child["spans"].as_array()?.iter()
+            .filter(|span| span["suggestion_applicability"] == "MachineApplicable" && span["suggested_replacement"].is_string())
+            .collect::<Vec<_>>();
+        let file = spans.first()?["file_name"].as_str()?.to_string();
+        if spans.iter().any(|span| span["file_name"] != file.as_str()) {
+            return None;
+        }
+        let position = |line: &serde_json::Value, column: &serde_json::Value| -> Option<(usize, usize)> {
+            Some(((line.as_u64()? as usize).saturating_sub(1), (column.as_u64()? as usize).saturating_sub(1)))
+        };
+        let edits = spans.iter().map(|span| Some(FixEdit {
+            start: position(&span["line_start"], &span["column_start"])?,
+            end: position(&span["line_end"], &span["column_end"])?,
+            original: span_text(span)?,
+            replacement: span["suggested_replacement"].as_str()?.to_string(),
+        })).collect::<Option<Vec<_>>>()?;
+        let message = child["message"].as_str().unwrap_or("apply the suggestion").to_string();
+        Some((file, SuggestedFix { message, edits }))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::editor::{DiagnosticFilter, DiagnosticSort};
+
+    #[test]
+    fn test_diagnostics_sort_by_location_severity_or_message() {
+        let mut diagnostics = DiagnosticCollection::new();
+        diagnostics.add_diagnostic(Diagnostic::new("unused variable", DiagnosticSeverity::Warning, TextSpan::new(2, 4, 8)));
+        diagnostics.add_diagnostic(Diagnostic::new("mismatched types", DiagnosticSeverity::Error, TextSpan::new(9, 0, 5)));
+        diagnostics.add_diagnostic(Diagnostic::new("Borrowed value", DiagnosticSeverity::Error, TextSpan::new(2, 0, 5)));
+        diagnostics.set_source("clippy");
+
+        let messages = |sort| diagnostics.get_sorted_diagnostics(&DiagnosticFilter::All, sort).iter().map(|d| d.message.as_str()).collect::<Vec<_>>();
+        assert_eq!(messages(DiagnosticSort::Location), ["Borrowed value", "unused variable", "mismatched types"]);
+        assert_eq!(messages(DiagnosticSort::Severity), ["Borrowed value", "mismatched types", "unused variable"]);
+        assert_eq!(messages(DiagnosticSort::Message), ["Borrowed value", "mismatched types", "unused variable"]);
+        assert!(diagnostics.get_all_diagnostics().iter().all(|d| d.source == "clippy"));
+    }
+
+    #[test]
+    fn test_cargo_json_fixes_parse_and_apply() {
+        let rendered = "warning: unused variable: `count`\n --> src/main.rs:2:9\n  |\n2 |     let count = 1;\n  |         ^^^^^ help: if this is intentional, prefix it with an underscore: `_count`\n";
+        let message = serde_json::json!({
+            "reason": "compiler-message",
+            "message": {
+                "message": "unused variable: `count`",
+                "rendered": rendered,
+                "children": [{
+                    "message": "if this is intentional, prefix it with an underscore",
+                    "spans": [{
+                        "file_name": "src/main.rs",
+                        "line_start": 2, "column_start": 9, "line_end": 2, "column_end": 14,
+                        "text": [{"text": "    let count = 1;", "highlight_start": 9, "highlight_end": 14}],
+                        "suggested_replacement": "_count",
+                        "suggestion_applicability": "MachineApplicable",
+                    }],
+                }],
+            },
+        });
+        let output = format!("{}\n{{\"reason\":\"build-finished\",\"success\":true}}\n", message);
+        let diagnostics = DiagnosticCollection::new().parse_cargo_output(&output, Path::new("."), "src/main.rs");
+
+        let all = diagnostics.get_all_diagnostics();
+        assert_eq!(all.len(), 1);
+        assert_eq!((all[0].span.line, all[0].span.start_column), (1, 8));
+        let fix = all[0].fix.as_ref().unwrap();
+        assert_eq!(fix.line_range(), (1, 1));
+        let lines = ["fn main() {".to_string(), "    let count = 1;".to_string()];
+        assert_eq!(fix.apply(&lines[1..], 1), ["    let _count = 1;"]);
+        assert!(fix.matches(&lines[1..], 1));
+        assert!(!fix.matches(&["    let total = 1;".to_string()], 1));
+
+        // Removing a whole line reaches to the start of the next one
+        let fix = SuggestedFix {
+            message: "remove the unused import".to_string(),
+            edits: vec![FixEdit { start: (0, 0), end: (1, 0), original: "use std::fs;\n".to_string(), replacement: String::new() }],
+        };
+        let lines = ["use std::fs;".to_string(), "fn main() {}".to_string()];
+        assert!(fix.matches(&lines, 0));
+        assert_eq!(fix.apply(&lines, 0), ["fn main() {}"]);
+    }
+}
@@ -0,0 +1,216 @@
+use anyhow::{Context, Result};
+use std::cmp::Ordering;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// What a directory listing is sorted by; `s` cycles through them as in netrw
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ListingSort {
+    #[default]
+    Name,
+    /// Newest first
+    Modified,
+    /// Largest first
+    Size,
+}
+
+impl ListingSort {
+    pub fn name(self) -> &'static str {
+        match self {
+            ListingSort::Name => "name",
+            ListingSort::Modified => "time",
+            ListingSort::Size => "size",
+        }
+    }
+
+    fn next(self) -> Self {
+        match self {
+            ListingSort::Name => ListingSort::Modified,
+            ListingSort::Modified => ListingSort::Size,
+            ListingSort::Size => ListingSort::Name,
+        }
+    }
+}
+
+/// A file or directory in a listing
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListingEntry {
+    pub name: String,
+    pub is_dir: bool,
+    pub size: u64,
+    pub modified: Option<SystemTime>,
+}
+
+/// A netrw-style listing of a directory (`zim dir`, `:Explore`), with `..`
+/// first to go up and directories before files
+#[derive(Debug, Clone, Default)]
+pub struct DirectoryListing {
+    pub dir: PathBuf,
+    pub entries: Vec<ListingEntry>,
+    pub selected: usize,
+    pub sort: ListingSort,
+    pub reversed: bool,
+}
+
+impl DirectoryListing {
+    /// List `dir`, sorted by name
+    pub fn open(dir: &Path) -> Result<Self> {
+        let dir = dir.canonicalize().with_context(|| format!("Failed to open directory: {}", dir.display()))?;
+        let mut listing = DirectoryListing { dir, ..Default::default() };
+        listing.read()?;
+        Ok(listing)
+    }
+
+    /// Read the directory again, keeping the same entry selected if it's
+    /// still there
+    pub fn read(&mut self) -> Result<()> {
+        let selected = self.selected().map(|entry| entry.name.clone());
+        let mut entries = Vec::new();
+        for entry in fs::read_dir(&self.dir).with_context(|| format!("Failed to read directory: {}", self.dir.display()))? {
+            let Ok(entry) = entry else { continue };
+            // Follow symlinks, so a link to a directory can be entered
+            let Ok(metadata) = fs::metadata(entry.path()).or_else(|_| entry.metadata()) else { continue };
+            entries.push(ListingEntry {
+                name: entry.file_name().to_string_lossy().to_string(),
+                is_dir: metadata.is_dir(),
+                size: metadata.len(),
+                modified: metadata.modified().ok(),
+            });
+        }
+        self.entries = entries;
+        self.sort_entries();
+        self.select(selected.as_deref());
+        Ok(())
+    }
+
+    pub fn selected(&self) -> Option<&ListingEntry> {
+        self.entries.get(self.selected)
+    }
+
+    /// The path of an entry
+    pub fn path(&self, entry: &ListingEntry) -> PathBuf {
+        if entry.name == ".." {
+            self.dir.parent().unwrap_or(&self.dir).to_path_buf()
+        } else {
+            self.dir.join(&entry.name)
+        }
+    }
+
+    pub fn select_next(&mut self) {
+        self.selected = (self.selected + 1).min(self.entries.len().saturating_sub(1));
+    }
+
+    pub fn select_previous(&mut self) {
+        self.selected = self.selected.saturating_sub(1);
+    }
+
+    pub fn select_first(&mut self) {
+        self.selected = 0;
+    }
+
+    pub fn select_last(&mut self) {
+        self.selected = self.entries.len().saturating_sub(1);
+    }
+
+    /// List a directory inside this one, or the parent for `..`
+    pub fn enter(&mut self, entry: &ListingEntry) -> Result<()> {
+        if entry.name == ".." {
+            return self.go_up();
+        }
+        self.change_dir(self.dir.join(&entry.name), None)
+    }
+
+    /// List the parent directory, with the one just left selected (`-`)
+    pub fn go_up(&mut self) -> Result<()> {
+        let Some(parent) = self.dir.parent().map(Path::to_path_buf) else { return Ok(()) };
+        let left = self.dir.file_name().map(|name| name.to_string_lossy().to_string());
+        self.change_dir(parent, left)
+    }
+
+    /// Sort by the next of name, time and size (`s`)
+    pub fn cycle_sort(&mut self) {
+        self.sort = self.sort.next();
+        self.resort();
+    }
+
+    /// Reverse the order (`r`)
+    pub fn toggle_reversed(&mut self) {
+        self.reversed = !self.reversed;
+        self.resort();
+    }
+
+    fn change_dir(&mut self, dir: PathBuf, select: Option<String>) -> Result<()> {
+        let previous = std::mem::replace(&mut self.dir, dir);
+        self.selected = 0;
+        if let Err(e) = self.read() {
+            self.dir = previous;
+            self.read()?;
+            return Err(e);
+        }
+        self.select(select.as_deref());
+        Ok(())
+    }
+
+    fn resort(&mut self) {
+        let selected = self.selected().map(|entry| entry.name.clone());
+        self.sort_entries();
+        self.select(selected.as_deref());
+    }
+
+    fn select(&mut self, name: Option<&str>) {
+        self.selected = name
+            .and_then(|name| self.entries.iter().position(|entry| entry.name == name))
+            .unwrap_or(self.selected)
+            .min(self.entries.len().saturating_sub(1));
+    }
+
+    fn sort_entries(&mut self) {
+        self.entries.retain(|entry| entry.name != "..");
+        let (sort, reversed) = (self.sort, self.reversed);
+        self.entries.sort_by(|a, b| {
+            let order = match sort {
+                ListingSort::Name => Ordering::Equal,
+                ListingSort::Modified => b.modified.cmp(&a.modified),
+                ListingSort::Size => b.size.cmp(&a.size),
+            }.then_with(|| a.name.to_lowercase().cmp(&b.name.to_lowercase()));
+            let order = if reversed { order.reverse() } else { order };
+            b.is_dir.cmp(&a.is_dir).then(order)
+        });
+        if self.dir.parent().is_some() {
+            self.entries.insert(0, ListingEntry { name: "..".to_string(), is_dir: true, size: 0, modified: None });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_listings_sort_and_move_between_directories() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        fs::create_dir(dir.path().join("src"))?;
+        fs::write(dir.path().join("src/lib.rs"), "")?;
+        fs::write(dir.path().join("b.txt"), "a bigger file")?;
+        fs::write(dir.path().join("A.md"), "small")?;
+
+        let mut listing = DirectoryListing::open(dir.path())?;
+        let names = |listing: &DirectoryListing| listing.entries.iter().map(|entry| entry.name.clone()).collect::<Vec<_>>();
+        assert_eq!(names(&listing), ["..", "src", "A.md", "b.txt"]);
+        listing.cycle_sort();
+        listing.cycle_sort();
+        assert_eq!((listing.sort, names(&listing)), (ListingSort::Size, vec!["..".into(), "src".into(), "b.txt".into(), "A.md".into()]));
+        listing.toggle_reversed();
+        assert_eq!(names(&listing), ["..", "src", "A.md", "b.txt"]);
+
+        listing.selected = 1;
+        let src = listing.selected().cloned().unwrap();
+        listing.enter(&src)?;
+        assert_eq!(names(&listing), ["..", "lib.rs"]);
+        assert_eq!(listing.path(&listing.entries[1]), dir.path().canonicalize()?.join("src/lib.rs"));
+        listing.go_up()?;
+        assert_eq!(listing.selected().map(|entry| entry.name.as_str()), Some("src"));
+        Ok(())
+    }
+}
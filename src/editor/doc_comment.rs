@@ -0,0 +1,297 @@
+use anyhow::Result;
+use regex::Regex;
+
+/// The parts of a Rust function signature a doc comment talks about
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FunctionSignature {
+    /// Line holding the `fn` keyword
+    pub line: usize,
+    pub indent: String,
+    pub name: String,
+    /// Parameter names, not counting `self`
+    pub params: Vec<String>,
+    /// Return type, if any
+    pub returns: Option<String>,
+    /// Takes `self` in some form
+    pub is_method: bool,
+}
+
+impl FunctionSignature {
+    fn returns_result(&self) -> bool {
+        self.returns.as_deref().is_some_and(|returns| {
+            returns.starts_with("Result") || returns.contains("::Result") || returns.starts_with("io::Result")
+        })
+    }
+}
+
+/// Find the function the cursor is on or inside of
+///
+/// This looks upwards from the cursor for the nearest `fn` line, then reads
+/// the signature up to its body, so signatures split over several lines
+/// work.
+pub fn find_function(lines: &[String], cursor_line: usize) -> Option<FunctionSignature> {
+    let fn_pattern = Regex::new(
+        r#"^(\s*)(?:pub(?:\([^)]*\))?\s+)?(?:const\s+)?(?:async\s+)?(?:unsafe\s+)?(?:extern\s+"[^"]*"\s+)?fn\s+([A-Za-z_][A-Za-z0-9_]*)"#,
+    ).ok()?;
+
+    let start = cursor_line.min(lines.len().checked_sub(1)?);
+    let (line, captures) = (0..=start).rev()
+        .find_map(|index| fn_pattern.captures(&lines[index]).map(|captures| (index, captures)))?;
+
+    // Join the signature lines up to the body or the end of a declaration
+    let mut signature = String::new();
+    for text in lines.iter().skip(line).take(20) {
+        signature.push_str(text.trim());
+        signature.push(' ');
+        if text.contains('{') || text.trim_end().ends_with(';') {
+            break;
+        }
+    }
+
+    let open = signature.find('(')?;
+    let close = matching_paren(&signature, open)?;
+    let mut params = Vec::new();
+    let mut is_method = false;
+    for param in split_top_level(&signature[open + 1..close]) {
+        let name = param.split(':').next().unwrap_or("").trim();
+        let name = name.strip_prefix("mut ").unwrap_or(name).trim();
+        if name.is_empty() {
+            continue;
+        }
+        if name.ends_with("self") {
+            is_method = true;
+        } else {
+            params.push(name.to_string());
+        }
+    }
+
+    let rest = &signature[close + 1..];
+    let returns = rest.trim_start().strip_prefix("->").map(|returns| {
+        let end = [" where ", "{", ";"].iter()
+            .filter_map(|token| returns.find(token))
+            .min()
+            .unwrap_or(returns.len());
+        returns[..end].trim().to_string()
+    });
+
+    Some(FunctionSignature {
+        line,
+        indent: captures[1].to_string(),
+        name: captures[2].to_string(),
+        params,
+        returns,
+        is_method,
+    })
+}
+
+fn matching_paren(text: &str, open: usize) -> Option<usize> {
+    let mut depth = 0;
+    for (index, c) in text.char_indices().skip_while(|(index, _)| *index < open) {
+        match c {
+            '(' => depth += 1,
+            ')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(index);
+                }
+            },
+            _ => {},
+        }
+    }
+    None
+}
+
+/// Split a parameter list on the commas that aren't inside generics,
+/// tuples or closures' argument lists
+fn split_top_level(text: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut depth = 0i32;
+    let mut start = 0;
+    let mut previous = ' ';
+    for (index, c) in text.char_indices() {
+        match c {
+            '<' | '(' | '[' => depth += 1,
+            // The `>` of `->` in a closure type doesn't close anything
+            '>' if previous == '-' => {},
+            '>' | ')' | ']' => depth -= 1,
+            ',' if depth == 0 => {
+                parts.push(&text[start..index]);
+                start = index + 1;
+            },
+            _ => {},
+        }
+        previous = c;
+    }
+    parts.push(&text[start..]);
+    parts
+}
+
+/// Where the doc comment goes: above any attributes on the function
+///
+/// Fails if the function already has a doc comment.
+pub fn insertion_line(lines: &[String], signature: &FunctionSignature) -> Result<usize> {
+    let mut line = signature.line;
+    while line > 0 && lines[line - 1].trim_start().starts_with("#[") {
+        line -= 1;
+    }
+    if line > 0 && lines[line - 1].trim_start().starts_with("///") {
+        return Err(anyhow::anyhow!("`{}` already has a doc comment", signature.name));
+    }
+    Ok(line)
+}
+
+/// A doc comment skeleton with sections for the signature
+pub fn skeleton(signature: &FunctionSignature) -> Vec<String> {
+    let mut doc = vec![format!("TODO: Summarize what `{}` does.", signature.name)];
+
+    if !signature.params.is_empty() {
+        doc.push(String::new());
+        doc.push("# Arguments".to_string());
+        doc.push(String::new());
+        for param in &signature.params {
+            doc.push(format!("* `{}` - TODO", param));
+        }
+    }
+
+    if signature.returns_result() {
+        doc.push(String::new());
+        doc.push("# Errors".to_string());
+        doc.push(String::new());
+        doc.push("TODO: Describe when this returns an error.".to_string());
+    }
+
+    let call = format!(
+        "{}{}({})",
+        if signature.is_method { "value." } else { "" },
+        signature.name,
+        signature.params.join(", ")
+    );
+    let example = if signature.returns.is_some() {
+        format!("let result = {};", call)
+    } else {
+        format!("{};", call)
+    };
+    doc.extend([
+        String::new(),
+        "# Examples".to_string(),
+        String::new(),
+        "```ignore".to_string(),
+        example,
+        "```".to_string(),
+    ]);
+
+    as_doc_lines(&doc, &signature.indent)
+}
+
+/// Prompt asking the AI provider to fill in the skeleton for a function
+pub fn ai_prompt(lines: &[String], signature: &FunctionSignature) -> String {
+    // Send the function itself, up to its closing brace
+    let mut depth = 0i32;
+    let mut function = Vec::new();
+    for line in lines.iter().skip(signature.line).take(200) {
+        function.push(line.as_str());
+        depth += line.matches('{').count() as i32 - line.matches('}').count() as i32;
+        if depth <= 0 && (line.contains('}') || line.trim_end().ends_with(';')) {
+            break;
+        }
+    }
+
+    format!(
+        "Write a Rust doc comment for this function. Reply with only the comment text, \
+         without `///` markers, following this outline:\n\n{}\n\nFunction:\n\n{}\n",
+        skeleton(signature).iter()
+            .map(|line| line.trim_start().trim_start_matches("///").trim_start())
+            .collect::<Vec<_>>()
+            .join("\n"),
+        function.join("\n")
+    )
+}
+
+/// Turn an AI reply into doc comment lines, dropping code fences the model
+/// may have wrapped it in
+pub fn doc_lines_from_reply(reply: &str, indent: &str) -> Vec<String> {
+    let mut text: Vec<&str> = reply.trim().lines().collect();
+    if text.first().is_some_and(|line| line.trim_start().starts_with("```"))
+        && text.last().is_some_and(|line| line.trim() == "```")
+        && text.len() >= 2
+    {
+        text = text[1..text.len() - 1].to_vec();
+    }
+
+    let text: Vec<String> = text.iter()
+        .map(|line| {
+            let line = line.trim_end();
+            let line = line.trim_start().strip_prefix("///").map(|rest| rest.strip_prefix(' ').unwrap_or(rest)).unwrap_or(line);
+            line.to_string()
+        })
+        .collect();
+    as_doc_lines(&text, indent)
+}
+
+fn as_doc_lines(text: &[String], indent: &str) -> Vec<String> {
+    text.iter()
+        .map(|line| if line.is_empty() {
+            format!("{}///", indent)
+        } else {
+            format!("{}/// {}", indent, line)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(text: &str) -> Vec<String> {
+        text.lines().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn test_find_multiline_signature() {
+        let source = lines("\
+impl Store {
+    #[inline]
+    pub async fn load(
+        &mut self,
+        path: &Path,
+        mut retries: usize,
+        map: HashMap<String, (u8, u8)>,
+        on_done: impl Fn(u8) -> u8,
+    ) -> anyhow::Result<Vec<u8>> where T: Clone {
+        let x = 1;
+    }
+}");
+        let signature = find_function(&source, 9).unwrap();
+        assert_eq!(signature.line, 2);
+        assert_eq!(signature.indent, "    ");
+        assert_eq!(signature.name, "load");
+        assert_eq!(signature.params, vec!["path", "retries", "map", "on_done"]);
+        assert_eq!(signature.returns.as_deref(), Some("anyhow::Result<Vec<u8>>"));
+        assert!(signature.is_method);
+        assert_eq!(insertion_line(&source, &signature).unwrap(), 1);
+
+        let doc = skeleton(&signature);
+        assert_eq!(doc[0], "    /// TODO: Summarize what `load` does.");
+        assert!(doc.contains(&"    /// * `retries` - TODO".to_string()));
+        assert!(doc.contains(&"    /// # Errors".to_string()));
+        assert!(doc.contains(&"    /// let result = value.load(path, retries, map, on_done);".to_string()));
+        assert!(!doc.iter().any(|line| line.ends_with(' ')));
+    }
+
+    #[test]
+    fn test_skeleton_sections_follow_signature() {
+        let source = lines("/// Already here\nfn tick() {}\n");
+        let signature = find_function(&source, 1).unwrap();
+        assert!(insertion_line(&source, &signature).is_err());
+
+        let doc = skeleton(&signature);
+        assert!(!doc.iter().any(|line| line.contains("# Arguments") || line.contains("# Errors")));
+        assert!(doc.contains(&"/// tick();".to_string()));
+
+        let reply = "```\n/// Advance the clock.\n\nCalled once per frame.\n```\n";
+        assert_eq!(
+            doc_lines_from_reply(reply, "  "),
+            vec!["  /// Advance the clock.", "  ///", "  /// Called once per frame."]
+        );
+    }
+}
@@ -0,0 +1,158 @@
+use anyhow::{Context, Result};
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, UTF_8, WINDOWS_1252};
+use std::fs;
+
+/// How a file ends its lines, by vim's `fileformat` names
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LineEnding {
+    #[default]
+    Unix,
+    Dos,
+}
+
+impl LineEnding {
+    pub fn name(self) -> &'static str {
+        match self {
+            LineEnding::Unix => "unix",
+            LineEnding::Dos => "dos",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "unix" | "lf" => Ok(LineEnding::Unix),
+            "dos" | "crlf" => Ok(LineEnding::Dos),
+            _ => Err(anyhow::anyhow!("Unknown fileformat: {} (use unix or dos)", name)),
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LineEnding::Unix => "\n",
+            LineEnding::Dos => "\r\n",
+        }
+    }
+
+    /// Dos when every line break in `text` is a CRLF; files that mix both
+    /// are read as unix, keeping their CRs, like vim does
+    fn detect(text: &str) -> Self {
+        let breaks = text.matches('\n').count();
+        if breaks > 0 && text.matches("\r\n").count() == breaks {
+            LineEnding::Dos
+        } else {
+            LineEnding::Unix
+        }
+    }
+}
+
+/// A file's text with LF line breaks, and how it was stored on disk
+pub struct Decoded {
+    pub text: String,
+    pub encoding: &'static Encoding,
+    pub line_ending: LineEnding,
+    pub bom: bool,
+}
+
+/// Decode a file's bytes: a byte order mark decides the encoding, then
+/// anything that's valid UTF-8 is UTF-8 and the rest is read as Latin-1
+/// (windows-1252, which every byte decodes in)
+pub fn decode(bytes: &[u8]) -> Decoded {
+    let (encoding, bom_len) = match Encoding::for_bom(bytes) {
+        Some((encoding, bom_len)) => (encoding, bom_len),
+        None if std::str::from_utf8(bytes).is_ok() => (UTF_8, 0),
+        None => (WINDOWS_1252, 0),
+    };
+    let (text, _) = encoding.decode_without_bom_handling(&bytes[bom_len..]);
+    let line_ending = LineEnding::detect(&text);
+    // Only all-CRLF files lose their CRs, so mixed ones are written back as
+    // they were
+    let text = match line_ending {
+        LineEnding::Dos => text.replace("\r\n", "\n"),
+        LineEnding::Unix => text.into_owned(),
+    };
+    Decoded { text, encoding, line_ending, bom: bom_len > 0 }
+}
+
+/// Read and decode a file
+pub fn read(path: &str) -> Result<Decoded> {
+    let bytes = fs::read(path).with_context(|| format!("Failed to read file: {}", path))?;
+    Ok(decode(&bytes))
+}
+
+/// Encode text for writing, failing rather than writing characters the
+/// encoding can't represent as something else
+pub fn encode(text: &str, encoding: &'static Encoding, bom: bool) -> Result<Vec<u8>> {
+    // encoding_rs only decodes UTF-16, so it's done by hand
+    if encoding == UTF_16LE || encoding == UTF_16BE {
+        let units = if bom { Some('\u{feff}' as u16) } else { None }
+            .into_iter()
+            .chain(text.encode_utf16());
+        return Ok(units
+            .flat_map(|unit| if encoding == UTF_16LE { unit.to_le_bytes() } else { unit.to_be_bytes() })
+            .collect());
+    }
+
+    let (bytes, _, unmappable) = encoding.encode(text);
+    if unmappable {
+        let c = text.chars()
+            .find(|c| encoding.encode(c.encode_utf8(&mut [0; 4])).2)
+            .unwrap_or('?');
+        return Err(anyhow::anyhow!("Can't write {:?} as {}", c, name(encoding)));
+    }
+    let mut bytes = bytes.into_owned();
+    if bom && encoding == UTF_8 {
+        bytes.splice(0..0, [0xef, 0xbb, 0xbf]);
+    }
+    Ok(bytes)
+}
+
+/// Whether files in `encoding` can start with a byte order mark
+pub fn has_bom(encoding: &'static Encoding) -> bool {
+    encoding == UTF_8 || encoding == UTF_16LE || encoding == UTF_16BE
+}
+
+/// The encoding for a `fileencoding` name like utf-8, latin1 or utf-16le
+pub fn from_label(label: &str) -> Result<&'static Encoding> {
+    Encoding::for_label(label.as_bytes())
+        .filter(|encoding| encoding.output_encoding() == *encoding || *encoding == UTF_16LE || *encoding == UTF_16BE)
+        .with_context(|| format!("Unknown fileencoding: {}", label))
+}
+
+/// An encoding's name as shown in messages and the status line
+pub fn name(encoding: &'static Encoding) -> String {
+    encoding.name().to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_and_encode_round_trip() {
+        let decoded = decode(b"one\r\ntwo\r\n");
+        assert_eq!(decoded.text, "one\ntwo\n");
+        assert_eq!((decoded.encoding, decoded.line_ending, decoded.bom), (UTF_8, LineEnding::Dos, false));
+
+        // Mixed line endings are read as unix, with the CRs kept so they
+        // come back the same
+        let decoded = decode(b"one\r\ntwo\n");
+        assert_eq!((decoded.text.as_str(), decoded.line_ending), ("one\r\ntwo\n", LineEnding::Unix));
+        assert_eq!(encode(&decoded.text, decoded.encoding, decoded.bom).unwrap(), b"one\r\ntwo\n");
+
+        // Bytes that aren't UTF-8 are Latin-1
+        let decoded = decode(b"caf\xe9\n");
+        assert_eq!((decoded.text.as_str(), decoded.encoding), ("café\n", WINDOWS_1252));
+        assert_eq!(encode("café\n", decoded.encoding, false).unwrap(), b"caf\xe9\n");
+        assert!(encode("日本\n", decoded.encoding, false).is_err());
+
+        // A byte order mark picks the encoding and is written back
+        let decoded = decode(b"\xff\xfeh\0i\0");
+        assert_eq!((decoded.text.as_str(), decoded.encoding, decoded.bom), ("hi", UTF_16LE, true));
+        assert_eq!(encode("hi", UTF_16LE, true).unwrap(), b"\xff\xfeh\0i\0");
+        assert_eq!(encode("hi", UTF_8, true).unwrap(), b"\xef\xbb\xbfhi");
+
+        assert_eq!(from_label("latin1").unwrap(), WINDOWS_1252);
+        assert!(from_label("iso-2022-kr").is_err());
+        assert_eq!(LineEnding::from_name("crlf").unwrap(), LineEnding::Dos);
+    }
+}
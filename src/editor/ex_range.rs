@@ -0,0 +1,121 @@
+use anyhow::{anyhow, Result};
+
+/// The lines an ex command works on, 0-based and inclusive
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineRange {
+    pub start: usize,
+    pub end: usize,
+}
+
+/// What addresses are resolved against: the cursor line, how many lines
+/// there are (leaving out the empty one after a final newline), and where
+/// marks are
+pub struct Lines<'a> {
+    pub current: usize,
+    pub count: usize,
+    /// The line of a mark: `a` to `z`, or `<` and `>` for the visual selection
+    pub mark: &'a dyn Fn(char) -> Option<usize>,
+}
+
+/// Split the range off the front of an ex command and resolve it, returning
+/// None for the range when the command doesn't start with one
+///
+/// A range is `%` for every line, or one or two addresses separated by `,`
+/// or `;`. An address is a line number, `.` for the cursor line, `$` for the
+/// last, or `'x` for a mark, followed by any number of `+N` and `-N`
+/// offsets; a bare offset like `+5` counts from the cursor line. Ranges
+/// given backwards are swapped.
+pub fn parse<'c>(cmd: &'c str, lines: &Lines) -> Result<(Option<LineRange>, &'c str)> {
+    if let Some(rest) = cmd.strip_prefix('%') {
+        let last = lines.count.saturating_sub(1);
+        return Ok((Some(LineRange { start: 0, end: last }), rest.trim_start()));
+    }
+    let Some((first, rest)) = address(cmd, lines)? else { return Ok((None, cmd)) };
+    let (second, rest) = match rest.strip_prefix([',', ';']) {
+        Some(after) => match address(after, lines)? {
+            Some((second, rest)) => (second, rest),
+            // `10,` means `10,.`
+            None => (lines.current, after),
+        },
+        None => (first, rest),
+    };
+    for line in [first, second] {
+        if line >= lines.count.max(1) {
+            return Err(anyhow!("Invalid range: line {} is past the end", line + 1));
+        }
+    }
+    Ok((Some(LineRange { start: first.min(second), end: first.max(second) }), rest.trim_start()))
+}
+
+/// Parse one address at the start of `text`, returning its 0-based line and
+/// what follows it
+fn address<'c>(text: &'c str, lines: &Lines) -> Result<Option<(usize, &'c str)>> {
+    let text = text.trim_start();
+    let (base, mut rest) = if let Some(rest) = text.strip_prefix('.') {
+        (lines.current as i64, rest)
+    } else if let Some(rest) = text.strip_prefix('$') {
+        (lines.count as i64 - 1, rest)
+    } else if let Some(rest) = text.strip_prefix('\'') {
+        let name = rest.chars().next().ok_or_else(|| anyhow!("Missing mark name"))?;
+        let line = (lines.mark)(name).ok_or_else(|| anyhow!("Mark not set: '{}", name))?;
+        (line as i64, &rest[name.len_utf8()..])
+    } else if text.starts_with(|c: char| c.is_ascii_digit()) {
+        let (number, rest) = split_number(text);
+        // Line 0 stands for the first line, as in vim
+        (number.max(1) - 1, rest)
+    } else if text.starts_with(['+', '-']) {
+        (lines.current as i64, text)
+    } else {
+        return Ok(None);
+    };
+
+    let mut line = base;
+    while let Some(sign) = rest.chars().next().filter(|c| matches!(c, '+' | '-')) {
+        let (number, after) = split_number(&rest[1..]);
+        // `+` and `-` alone move one line
+        let number = if after.len() == rest.len() - 1 { 1 } else { number };
+        line += if sign == '+' { number } else { -number };
+        rest = after;
+    }
+    if line < 0 {
+        return Err(anyhow!("Invalid range: line {} is before the start", line + 1));
+    }
+    Ok(Some((line as usize, rest)))
+}
+
+fn split_number(text: &str) -> (i64, &str) {
+    let end = text.find(|c: char| !c.is_ascii_digit()).unwrap_or(text.len());
+    (text[..end].parse().unwrap_or(i64::MAX / 2), &text[end..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ranges_resolve_to_lines() -> Result<()> {
+        let mark = |name| match name {
+            'a' => Some(3),
+            '<' => Some(5),
+            '>' => Some(7),
+            _ => None,
+        };
+        let lines = Lines { current: 9, count: 20, mark: &mark };
+        let range = |start, end| Some(LineRange { start, end });
+
+        assert_eq!(parse("%s/a/b/", &lines)?, (range(0, 19), "s/a/b/"));
+        assert_eq!(parse("10,12d", &lines)?, (range(9, 11), "d"));
+        assert_eq!(parse(".,+5 sort", &lines)?, (range(9, 14), "sort"));
+        assert_eq!(parse("'<,'>y", &lines)?, (range(5, 7), "y"));
+        assert_eq!(parse("'a;$-1", &lines)?, (range(3, 18), ""));
+        assert_eq!(parse("-", &lines)?, (range(8, 8), ""));
+        assert_eq!(parse("12,3w >> log", &lines)?, (range(2, 11), "w >> log"));
+        assert_eq!(parse("sort", &lines)?, (None, "sort"));
+        assert_eq!(parse("!ls", &lines)?, (None, "!ls"));
+
+        assert!(parse("1,21d", &lines).is_err());
+        assert!(parse("'b,.d", &lines).is_err());
+        assert!(parse("-10", &lines).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,149 @@
+use super::brackets::{self, BracketRules};
+
+/// A selection from `(line, column)` to just before `(line, column)`
+pub type Span = ((usize, usize), (usize, usize));
+
+/// The next bigger piece of code around `span`: the word, the inside of the
+/// brackets, the brackets, the call they belong to, the whole statement,
+/// then the block around that and the item it opens, such as a function
+///
+/// Comments and strings are understood through `rules`, so brackets in them
+/// don't count. Returns `None` when the whole file is already selected.
+pub fn expand(lines: &[String], span: Span, rules: &BracketRules) -> Option<Span> {
+    let (start, end) = span;
+    let mut candidates = Vec::new();
+    if start.0 == end.0 {
+        candidates.extend(word(lines, start));
+    }
+    candidates.push(statement(lines, start, end));
+
+    let flip = |(y, x): (usize, usize)| (x, y);
+    for (open, close) in brackets::enclosing_pairs(lines, flip(start), flip(end), rules) {
+        let (open, close) = (flip(open), flip(close));
+        let after_close = (close.0, close.1 + 1);
+        candidates.push(trim(lines, (open.0, open.1 + 1), close));
+        candidates.push((open, after_close));
+        candidates.push((call_start(lines, open), after_close));
+        // The item or statement the brackets belong to, like `fn x() { .. }`
+        candidates.push((first_non_blank(lines, open.0), statement_end(lines, after_close)));
+    }
+    let last = lines.len().saturating_sub(1);
+    candidates.push(((0, 0), (last, line_len(lines, last))));
+
+    candidates.into_iter()
+        .filter(|&(from, to)| from <= to && from <= start && end <= to && (from, to) != span)
+        .min_by_key(|&(from, to)| (offset(lines, to) - offset(lines, from), std::cmp::Reverse(from)))
+}
+
+/// The word at `at`, or the one the cursor sits just after
+fn word(lines: &[String], (y, x): (usize, usize)) -> Option<Span> {
+    let line = lines.get(y)?;
+    let is_word = |c: char| c.is_alphanumeric() || c == '_';
+    let at_word = line[x.min(line.len())..].starts_with(is_word);
+    let after_word = line[..x.min(line.len())].ends_with(is_word);
+    if !at_word && !after_word {
+        return None;
+    }
+    let from = line[..x].rfind(|c| !is_word(c)).map_or(0, |i| i + line[i..].chars().next().map_or(1, char::len_utf8));
+    let to = line[x..].find(|c| !is_word(c)).map_or(line.len(), |i| x + i);
+    Some(((y, from), (y, to)))
+}
+
+/// The lines the span is on, without their indentation or trailing spaces
+fn statement(lines: &[String], start: (usize, usize), end: (usize, usize)) -> Span {
+    let end_line = lines.get(end.0).map(String::as_str).unwrap_or("");
+    let to = end_line.trim_end().len().max(end.1);
+    ((start.0, first_non_blank(lines, start.0).1.min(start.1)), (end.0, to))
+}
+
+/// The span without the whitespace at its ends, such as the lines inside a
+/// block without the line breaks after `{` and before `}`
+fn trim(lines: &[String], mut from: (usize, usize), mut to: (usize, usize)) -> Span {
+    while from < to {
+        let line = &lines[from.0];
+        match line[from.1..].chars().next() {
+            Some(c) if c.is_whitespace() => from.1 += c.len_utf8(),
+            Some(_) => break,
+            None => from = (from.0 + 1, 0),
+        }
+    }
+    while from < to {
+        match lines[to.0][..to.1].chars().next_back() {
+            Some(c) if c.is_whitespace() => to.1 -= c.len_utf8(),
+            Some(_) => break,
+            None => to = (to.0 - 1, line_len(lines, to.0 - 1)),
+        }
+    }
+    (from, to)
+}
+
+/// Where the call or index expression that an opening bracket belongs to
+/// starts, going back over a name, a path like `a::b.c` and a `!` or turbofish
+fn call_start(lines: &[String], (y, x): (usize, usize)) -> (usize, usize) {
+    let line = &lines[y];
+    let from = line[..x]
+        .rfind(|c: char| !(c.is_alphanumeric() || "_.:!<>&".contains(c)))
+        .map_or(0, |i| i + 1);
+    (y, from)
+}
+
+/// Where a statement ending at `at` really ends, taking in a `;` or `,`
+/// right after it
+fn statement_end(lines: &[String], (y, x): (usize, usize)) -> (usize, usize) {
+    match lines[y][x..].chars().next() {
+        Some(';' | ',') => (y, x + 1),
+        _ => (y, x),
+    }
+}
+
+fn first_non_blank(lines: &[String], y: usize) -> (usize, usize) {
+    let line = lines.get(y).map(String::as_str).unwrap_or("");
+    (y, line.len() - line.trim_start().len())
+}
+
+fn line_len(lines: &[String], y: usize) -> usize {
+    lines.get(y).map_or(0, String::len)
+}
+
+/// Byte offset of a position, counting a line break after each line
+fn offset(lines: &[String], (y, x): (usize, usize)) -> usize {
+    lines.iter().take(y).map(|line| line.len() + 1).sum::<usize>() + x
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_selection_grows_from_word_to_function() {
+        let rust = BracketRules::for_syntax(Some("Rust"));
+        let lines: Vec<String> = [
+            "fn main() {",
+            "    let total = add(first, \")\");",
+            "    println!(\"{}\", total);",
+            "}",
+            "",
+        ].iter().map(|line| line.to_string()).collect();
+        let text = |((sy, sx), (ey, ex)): Span| -> String {
+            if sy == ey { lines[sy][sx..ex].to_string() } else { format!("{}..{}", &lines[sy][sx..], &lines[ey][..ex]) }
+        };
+
+        let mut span = ((1, 22), (1, 22));
+        let mut steps = Vec::new();
+        while let Some(next) = expand(&lines, span, &rust) {
+            steps.push(text(next));
+            span = next;
+        }
+        assert_eq!(steps, vec![
+            "first",
+            "first, \")\"",
+            "(first, \")\")",
+            "add(first, \")\")",
+            "let total = add(first, \")\");",
+            "let total = add(first, \")\");..    println!(\"{}\", total);",
+            "{..}",
+            "fn main() {..}",
+            "fn main() {..",
+        ]);
+    }
+}
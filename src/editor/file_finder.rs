@@ -1,18 +1,146 @@
+use super::syntax::HighlightedLine;
 use anyhow::{Context, Result};
 use fuzzy_matcher::{skim::SkimMatcherV2, FuzzyMatcher};
-use walkdir::WalkDir;
-use std::collections::VecDeque;
-use std::path::Path;
+use ignore::WalkBuilder;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::time::{SystemTime, UNIX_EPOCH};
 
+/// Recently opened files listed before the rest when the query is empty
 const MAX_RECENT_FILES: usize = 10;
 
+/// Files whose visits are remembered for frecency ranking
+const MAX_VISITS: usize = 500;
+
+/// Most results kept for a query
+const MAX_MATCHES: usize = 100;
+
+/// Which directory the finder lists, cycled with Alt+s
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FinderScope {
+    /// The tab's working directory
+    WorkingDir,
+    /// The nearest directory with a `Cargo.toml` above the current file,
+    /// e.g. one crate of a workspace
+    Project,
+    /// The current file's directory
+    FileDir,
+}
+
+impl FinderScope {
+    pub fn next(self) -> Self {
+        match self {
+            FinderScope::WorkingDir => FinderScope::Project,
+            FinderScope::Project => FinderScope::FileDir,
+            FinderScope::FileDir => FinderScope::WorkingDir,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            FinderScope::WorkingDir => "cwd",
+            FinderScope::Project => "project",
+            FinderScope::FileDir => "file dir",
+        }
+    }
+}
+
+/// A file matching the finder's query
+#[derive(Debug, Clone)]
+pub struct FileMatch {
+    pub path: String,
+    pub score: i64,
+    /// Character indices in `path` that matched the query, for highlighting
+    pub positions: Vec<usize>,
+}
+
+/// The first lines of the selected file, shown beside the finder's results
+pub struct FilePreview {
+    pub path: String,
+    pub lines: Vec<HighlightedLine>,
+    /// Shown instead of the content, e.g. for binary files
+    pub message: Option<String>,
+}
+
+/// How often and how recently a file was opened
+#[derive(Debug, Clone, Copy)]
+struct Visit {
+    count: u32,
+    /// Seconds since the Unix epoch
+    last: u64,
+    /// Line and column the cursor was at when the file was left
+    cursor: Option<(usize, usize)>,
+}
+
+/// A file opened before, as `:recent` lists it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecentFile {
+    pub path: String,
+    /// When it was last opened, in seconds since the Unix epoch
+    pub last_opened: u64,
+    pub count: u32,
+}
+
+/// A visit as it's kept in `recent.toml`, with an absolute path so it
+/// means the same file whichever directory zim starts in
+#[derive(Debug, Serialize, Deserialize)]
+struct StoredVisit {
+    path: PathBuf,
+    count: u32,
+    last: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    line: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    column: Option<usize>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct StoredHistory {
+    #[serde(default)]
+    files: Vec<StoredVisit>,
+}
+
+impl Visit {
+    /// Frecency in the style of z/zoxide: the visit count, weighted by how
+    /// long ago the last visit was
+    fn frecency(&self, now: u64) -> i64 {
+        let age = now.saturating_sub(self.last);
+        let weight = match age {
+            age if age < 60 * 60 => 16,
+            age if age < 24 * 60 * 60 => 8,
+            age if age < 7 * 24 * 60 * 60 => 2,
+            _ => 1,
+        };
+        self.count as i64 * weight
+    }
+}
+
 pub struct FileFinder {
     query: String,
     files: Vec<String>,
-    matches: Vec<(String, i64)>,
+    matches: Vec<FileMatch>,
     selected_index: usize,
     matcher: SkimMatcherV2,
-    recent_files: VecDeque<String>,
+    /// Opened files, used to rank results by frecency
+    visits: HashMap<String, Visit>,
+    /// List hidden and gitignored files too (Alt+h)
+    include_hidden: bool,
+    /// Results of a directory scan running on a background thread
+    pending_scan: Option<Receiver<Vec<String>>>,
+    /// Directory listed instead of the current directory, e.g. the tab's
+    /// working directory
+    root: Option<PathBuf>,
+    /// What `root` was chosen from, kept between uses of the finder
+    scope: FinderScope,
+    /// File the visits are saved to, so they outlast the session
+    history_store: Option<PathBuf>,
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|elapsed| elapsed.as_secs()).unwrap_or(0)
 }
 
 impl FileFinder {
@@ -23,131 +151,344 @@ impl FileFinder {
             matches: Vec::new(),
             selected_index: 0,
             matcher: SkimMatcherV2::default(),
-            recent_files: VecDeque::with_capacity(MAX_RECENT_FILES),
+            visits: HashMap::new(),
+            include_hidden: false,
+            pending_scan: None,
+            root: None,
+            scope: FinderScope::WorkingDir,
+            history_store: None,
+        }
+    }
+
+    /// Load the visits saved at `store` by earlier sessions, and save them
+    /// there from now on
+    pub fn load_history(&mut self, store: PathBuf) -> Result<()> {
+        let history: StoredHistory = match fs::read_to_string(&store) {
+            Ok(text) => toml::from_str(&text).map_err(|e| anyhow::anyhow!("Invalid {:?}: {}", store, e))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => StoredHistory::default(),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {:?}", store)),
+        };
+        let cwd = std::env::current_dir().ok();
+        for stored in history.files {
+            // Files under the current directory go by the relative paths
+            // the rest of the editor uses for them
+            let path = match cwd.as_deref().and_then(|cwd| stored.path.strip_prefix(cwd).ok()) {
+                Some(relative) => relative.to_string_lossy().to_string(),
+                None => stored.path.to_string_lossy().to_string(),
+            };
+            let cursor = stored.line.map(|line| (line, stored.column.unwrap_or(0)));
+            let visit = Visit { count: stored.count, last: stored.last, cursor };
+            self.visits.entry(path)
+                .and_modify(|existing| if existing.last < visit.last { *existing = visit })
+                .or_insert(visit);
+        }
+        self.history_store = Some(store);
+        if self.query.is_empty() {
+            let _ = self.update_matches();
+        }
+        Ok(())
+    }
+
+    /// Write the visits to the history store, if there is one
+    fn save_history(&self) -> Result<()> {
+        let Some(store) = &self.history_store else { return Ok(()) };
+        let cwd = std::env::current_dir().unwrap_or_default();
+        let mut files: Vec<StoredVisit> = self.visits.iter().map(|(path, visit)| StoredVisit {
+            path: cwd.join(path),
+            count: visit.count,
+            last: visit.last,
+            line: visit.cursor.map(|(line, _)| line),
+            column: visit.cursor.map(|(_, column)| column),
+        }).collect();
+        files.sort_by(|a, b| b.last.cmp(&a.last).then_with(|| a.path.cmp(&b.path)));
+        if let Some(dir) = store.parent() {
+            fs::create_dir_all(dir).with_context(|| format!("Failed to create {:?}", dir))?;
+        }
+        let text = toml::to_string(&StoredHistory { files })?;
+        fs::write(store, text).with_context(|| format!("Failed to write {:?}", store))
+    }
+
+    /// Remember where the cursor was in a file that's being left
+    pub fn remember_cursor(&mut self, file_path: &str, line: usize, column: usize) {
+        if let Some(visit) = self.visits.get_mut(file_path) {
+            if visit.cursor != Some((line, column)) {
+                visit.cursor = Some((line, column));
+                let _ = self.save_history();
+            }
         }
     }
+
+    /// Where the cursor was when a file was last left
+    pub fn cursor(&self, file_path: &str) -> Option<(usize, usize)> {
+        self.visits.get(file_path).and_then(|visit| visit.cursor)
+    }
+
+    /// Files opened before that still exist, the most recently opened first
+    pub fn recent_files(&self) -> Vec<RecentFile> {
+        let mut files: Vec<RecentFile> = self.visits.iter()
+            .filter(|(path, _)| Path::new(path).exists())
+            .map(|(path, visit)| RecentFile { path: path.clone(), last_opened: visit.last, count: visit.count })
+            .collect();
+        files.sort_by(|a, b| b.last_opened.cmp(&a.last_opened).then_with(|| a.path.cmp(&b.path)));
+        files
+    }
     
-    /// Add a file to the recent files list
+    /// Record that a file was opened, for frecency ranking
     pub fn add_recent_file(&mut self, file_path: &str) {
-        // Remove the file if it's already in the list to avoid duplicates
-        self.recent_files.retain(|path| path != file_path);
+        let now = unix_now();
+        let visit = self.visits.entry(file_path.to_string()).or_insert(Visit { count: 0, last: now, cursor: None });
+        visit.count += 1;
+        visit.last = now;
+
+        // Forget the least relevant files once the history gets long
+        if self.visits.len() > MAX_VISITS {
+            if let Some(stalest) = self.visits.iter()
+                .min_by_key(|(_, visit)| (visit.frecency(now), visit.last))
+                .map(|(path, _)| path.clone())
+            {
+                self.visits.remove(&stalest);
+            }
+        }
+        let _ = self.save_history();
         
-        // Add the file to the front of the list (most recent)
-        self.recent_files.push_front(file_path.to_string());
+        // Update matches if we're showing recent files (empty query)
+        if self.query.is_empty() {
+            let _ = self.update_matches();
+        }
+    }
+
+    /// Update the file list and visit history after a file was renamed
+    pub fn rename_file(&mut self, old_path: &str, new_path: &str) {
+        if let Some(visit) = self.visits.remove(old_path) {
+            self.visits.insert(new_path.to_string(), visit);
+            let _ = self.save_history();
+        }
         
-        // Keep only the most recent MAX_RECENT_FILES
-        while self.recent_files.len() > MAX_RECENT_FILES {
-            self.recent_files.pop_back();
+        if let Some(index) = self.files.iter().position(|file| file == old_path) {
+            self.files[index] = new_path.to_string();
+            self.files.sort();
         }
         
-        // Update matches if we're showing recent files (empty query)
         if self.query.is_empty() {
             let _ = self.update_matches();
         }
     }
 
+    /// Forget a file that was deleted
+    pub fn remove_file(&mut self, file_path: &str) {
+        if self.visits.remove(file_path).is_some() {
+            let _ = self.save_history();
+        }
+        self.files.retain(|file| file != file_path);
+        if self.query.is_empty() {
+            let _ = self.update_matches();
+        }
+    }
+
+    /// Whether a file is in the listed directory; any file is without a root
+    fn is_under_root(&self, file_path: &str) -> bool {
+        let Some(root) = &self.root else { return true };
+        match std::env::current_dir().and_then(|dir| dir.canonicalize()) {
+            Ok(current_dir) => current_dir.join(file_path).starts_with(root),
+            Err(_) => true,
+        }
+    }
+
+    fn frecency(&self, file_path: &str, now: u64) -> i64 {
+        self.visits.get(file_path).map_or(0, |visit| visit.frecency(now))
+    }
+
+    /// List `root` instead of the current directory, or the current
+    /// directory again with `None`; takes effect on the next refresh
+    pub fn set_root(&mut self, root: Option<PathBuf>) {
+        self.root = root;
+    }
+
+    /// The directory listed, when it isn't the current directory
+    pub fn root(&self) -> Option<&Path> {
+        self.root.as_deref()
+    }
+
+    pub fn scope(&self) -> FinderScope {
+        self.scope
+    }
+
+    pub fn set_scope(&mut self, scope: FinderScope) {
+        self.scope = scope;
+    }
+
+    /// The directory listed, with paths shown relative to the current
+    /// directory when it's under it and absolute otherwise
+    fn scan_root(&self) -> Result<(PathBuf, Option<PathBuf>)> {
+        let current_dir = std::env::current_dir()
+            .and_then(|dir| dir.canonicalize())
+            .context("Failed to get current directory")?;
+        let Some(root) = &self.root else { return Ok((current_dir, None)) };
+        let prefix = match root.strip_prefix(&current_dir) {
+            Ok(relative) if relative.as_os_str().is_empty() => None,
+            Ok(relative) => Some(relative.to_path_buf()),
+            Err(_) => Some(root.clone()),
+        };
+        Ok((root.clone(), prefix))
+    }
+
     pub fn refresh(&mut self) -> Result<()> {
         self.query.clear();
         self.files.clear();
         self.matches.clear();
         self.selected_index = 0;
+        self.pending_scan = None;
 
-        // Get current directory
-        let current_dir = std::env::current_dir()
-            .context("Failed to get current directory")?;
-
-        // Scan for files (ignoring .git and other common ignore patterns)
-        for entry in WalkDir::new(&current_dir)
-            .follow_links(false)
-            .into_iter()
-            .filter_entry(|e| {
-                !e.path()
-                    .components()
-                    .any(|c| {
-                        let c = c.as_os_str().to_string_lossy();
-                        c == ".git" || c == "target" || c.starts_with('.')
-                    })
-            })
-            .filter_map(|e| e.ok())
-            .filter(|e| e.path().is_file())
-        {
-            if let Some(path) = entry.path().strip_prefix(&current_dir).ok() {
-                if let Some(path_str) = path.to_str() {
-                    self.files.push(path_str.to_string());
-                }
-            }
-        }
-
-        // Sort files alphabetically for initial display
-        self.files.sort();
+        let (root, prefix) = self.scan_root()?;
+        self.files = scan_files(&root, prefix.as_deref(), self.include_hidden);
         
         // Initialize matches with all files when query is empty
-        self.matches = self.files.iter()
-            .map(|f| (f.clone(), 0))
-            .collect();
+        self.update_matches()?;
 
         Ok(())
     }
 
+    /// Scan the current directory on a background thread
+    ///
+    /// Unlike `refresh`, this returns immediately and keeps the current query;
+    /// the results are picked up by `poll_background_refresh`.
+    pub fn start_background_refresh(&mut self) -> Result<()> {
+        let (root, prefix) = self.scan_root()?;
+
+        let include_hidden = self.include_hidden;
+        let (sender, receiver) = mpsc::channel();
+        std::thread::spawn(move || {
+            let _ = sender.send(scan_files(&root, prefix.as_deref(), include_hidden));
+        });
+        self.pending_scan = Some(receiver);
+
+        Ok(())
+    }
+
+    /// Install the results of a finished background scan
+    ///
+    /// Returns true if the file list changed.
+    pub fn poll_background_refresh(&mut self) -> bool {
+        let files = match self.pending_scan.as_ref().map(|receiver| receiver.try_recv()) {
+            Some(Ok(files)) => files,
+            Some(Err(TryRecvError::Disconnected)) => {
+                self.pending_scan = None;
+                return false;
+            }
+            Some(Err(TryRecvError::Empty)) | None => return false,
+        };
+
+        self.pending_scan = None;
+        self.files = files;
+        let _ = self.update_matches();
+        true
+    }
+
+    /// Whether a background scan is still running
+    pub fn is_indexing(&self) -> bool {
+        self.pending_scan.is_some()
+    }
+
+    /// Whether hidden and gitignored files are listed
+    pub fn include_hidden(&self) -> bool {
+        self.include_hidden
+    }
+
+    /// Switch between listing only regular files and also listing hidden
+    /// and gitignored ones, rescanning in the background
+    pub fn toggle_hidden(&mut self) -> Result<()> {
+        self.include_hidden = !self.include_hidden;
+        self.start_background_refresh()
+    }
+
+    /// Path of a new file named by the query, under the listed directory
+    ///
+    /// Spaces are dropped, since they separate the query's terms, like the
+    /// one after a directory that was narrowed to.
+    pub fn new_file_path(&self) -> Option<PathBuf> {
+        let name: String = self.query.split_whitespace().collect();
+        if name.is_empty() || name.ends_with('/') {
+            return None;
+        }
+        let (_, prefix) = self.scan_root().ok()?;
+        Some(prefix.map_or_else(|| PathBuf::from(&name), |prefix| prefix.join(&name)))
+    }
+
+    /// Narrow the listing to the contents of a directory from the results
+    pub fn enter_directory(&mut self, directory: &str) {
+        self.query = format!("{}/ ", directory.trim_end_matches('/'));
+        let _ = self.update_matches();
+    }
+
     pub fn update_matches(&mut self) -> Result<()> {
         self.selected_index = 0;
         self.matches.clear();
+        let now = unix_now();
         
         if self.query.is_empty() {
-            // If query is empty, show recent files first, then all files
+            // If query is empty, show the most frecent files first, then all files
+            let mut recent: Vec<(&String, i64)> = self.visits.keys()
+                // Skip files that no longer exist or are outside the root
+                .filter(|path| Path::new(path).exists() && self.is_under_root(path))
+                .map(|path| (path, self.frecency(path, now)))
+                .collect();
+            recent.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+            recent.truncate(MAX_RECENT_FILES);
             
-            // First, add recent files
-            for recent_file in &self.recent_files {
-                // Skip files that no longer exist
-                if Path::new(recent_file).exists() {
-                    // Give recent files a high score for sorting
-                    self.matches.push((recent_file.clone(), 1000));
-                }
+            for (path, frecency) in &recent {
+                // Give recent files a high score for sorting
+                self.matches.push(FileMatch { path: (*path).clone(), score: 1000 + frecency, positions: Vec::new() });
             }
             
             // Then add regular files that aren't in the recent list
             // Use a higher score for files in the current directory (shorter paths)
             for file in &self.files {
-                if !self.recent_files.contains(file) {
+                if !recent.iter().any(|(path, _)| *path == file) {
                     // Score inversely proportional to path length to prioritize files in current dir
                     let base_score = 500 - file.len().min(500);
-                    self.matches.push((file.clone(), base_score as i64));
+                    self.matches.push(FileMatch { path: file.clone(), score: base_score as i64, positions: Vec::new() });
                 }
             }
+        } else {
+            // A leading `dir/ ` limits the search to that directory
+            let (scope, pattern) = split_scope(&self.query);
+            let scope_chars = scope.chars().count();
             
-            // Sort by score
-            self.matches.sort_by(|a, b| b.1.cmp(&a.1));
-            
-            return Ok(());
-        }
-
-        // Filter files based on fuzzy matching
-        for file in &self.files {
-            if let Some(score) = self.matcher.fuzzy_match(file, &self.query) {
-                // Boost score for recent files
-                let boosted_score = if self.recent_files.contains(file) {
-                    score + 1000 // Substantially boost recent files
+            for file in &self.files {
+                let Some(relative) = file.strip_prefix(scope).filter(|rest| !rest.is_empty()) else { continue };
+                
+                // Filter files with fzf-style fuzzy matching, keeping the
+                // matched positions for highlighting
+                let (score, mut positions) = if pattern.is_empty() {
+                    (500 - relative.len().min(500) as i64, Vec::new())
                 } else {
-                    score
+                    match self.matcher.fuzzy_indices(relative, pattern) {
+                        Some((score, positions)) => (score, positions.iter().map(|p| p + scope_chars).collect()),
+                        None => continue,
+                    }
                 };
+                positions.splice(0..0, 0..scope_chars);
                 
-                self.matches.push((file.clone(), boosted_score));
+                // Boost frequently and recently opened files, without
+                // letting them bury a much better match
+                let boost = (self.frecency(file, now) * 4).min(200);
+                self.matches.push(FileMatch { path: file.clone(), score: score + boost, positions });
             }
         }
 
-        // Sort by match score, higher scores first
-        self.matches.sort_by(|a, b| b.1.cmp(&a.1));
+        // Sort by score, higher scores first, preferring shorter paths on ties
+        self.matches.sort_by(|a, b| b.score.cmp(&a.score).then_with(|| a.path.len().cmp(&b.path.len())));
         
-        // Limit to 100 results for performance
-        if self.matches.len() > 100 {
-            self.matches.truncate(100);
+        // Limit the results of a query for performance
+        if !self.query.is_empty() {
+            self.matches.truncate(MAX_MATCHES);
         }
 
         Ok(())
     }
 
     pub fn get_selected(&self) -> Option<String> {
-        self.matches.get(self.selected_index).map(|(path, _)| path.clone())
+        self.matches.get(self.selected_index).map(|file_match| file_match.path.clone())
     }
 
     pub fn add_char(&mut self, c: char) {
@@ -178,7 +519,7 @@ impl FileFinder {
         &self.query
     }
 
-    pub fn matches(&self) -> &[(String, i64)] {
+    pub fn matches(&self) -> &[FileMatch] {
         &self.matches
     }
 
@@ -187,6 +528,62 @@ impl FileFinder {
     }
 }
 
+impl Default for FileFinder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Split a `dir/ pattern` query into the directory prefix and the pattern
+fn split_scope(query: &str) -> (&str, &str) {
+    match query.split_once(' ') {
+        Some((scope, pattern)) if scope.ends_with('/') => (scope, pattern.trim()),
+        _ => ("", query),
+    }
+}
+
+/// Collect the files and directories under a directory, relative to it or
+/// joined to `prefix`, and sorted; directories end with `/`
+///
+/// Unless `include_hidden` is set, hidden and gitignored entries and
+/// `target` directories are skipped. `.git` is always skipped.
+fn scan_files(root: &Path, prefix: Option<&Path>, include_hidden: bool) -> Vec<String> {
+    let mut files = Vec::new();
+
+    let walker = WalkBuilder::new(root)
+        .follow_links(false)
+        .standard_filters(!include_hidden)
+        .filter_entry(move |e| {
+            // Only look at names below the root, which may itself live in a
+            // hidden directory; skipping a directory prunes everything in it
+            if e.depth() == 0 {
+                return true;
+            }
+            let name = e.file_name().to_string_lossy();
+            name != ".git" && (include_hidden || name != "target")
+        })
+        .build();
+
+    for entry in walker.filter_map(|e| e.ok()) {
+        let is_dir = entry.file_type().is_some_and(|file_type| file_type.is_dir());
+        if let Ok(path) = entry.path().strip_prefix(root) {
+            let path = match prefix {
+                Some(prefix) if !path.as_os_str().is_empty() => prefix.join(path),
+                _ => path.to_path_buf(),
+            };
+            match path.to_str() {
+                Some("") | None => {},
+                Some(path_str) if is_dir => files.push(format!("{}/", path_str)),
+                Some(path_str) => files.push(path_str.to_string()),
+            }
+        }
+    }
+
+    // Sort files alphabetically for initial display
+    files.sort();
+    files
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -217,7 +614,7 @@ mod tests {
         
         // Manually add some test files
         finder.files = vec!["file1.rs".to_string(), "file2.rs".to_string(), "file3.rs".to_string()];
-        finder.matches = finder.files.iter().map(|f| (f.clone(), 0)).collect();
+        finder.update_matches().unwrap();
         
         assert_eq!(finder.selected_index(), 0);
         
@@ -259,8 +656,124 @@ mod tests {
 
         // just a comment      
         // Should match files with "ed" in them
-        let matches: Vec<&String> = finder.matches().iter().map(|(path, _)| path).collect();
+        let matches: Vec<&String> = finder.matches().iter().map(|file_match| &file_match.path).collect();
         assert!(matches.contains(&&"src/editor/mod.rs".to_string()));
         assert!(matches.contains(&&"src/editor/buffer.rs".to_string()));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_background_refresh_keeps_query() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("src")).unwrap();
+        std::fs::create_dir_all(dir.path().join(".git")).unwrap();
+        std::fs::write(dir.path().join("src/lib.rs"), "").unwrap();
+        std::fs::write(dir.path().join("Cargo.toml"), "").unwrap();
+        std::fs::write(dir.path().join(".git/HEAD"), "").unwrap();
+
+        assert_eq!(scan_files(dir.path(), None, false), vec!["Cargo.toml", "src/", "src/lib.rs"]);
+        // A root elsewhere is listed with its paths joined to it
+        assert_eq!(scan_files(&dir.path().join("src"), Some(Path::new("/repo/src")), false), vec!["/repo/src/lib.rs"]);
+
+        let mut finder = FileFinder::new();
+        finder.add_char('l');
+        let (sender, receiver) = mpsc::channel();
+        finder.pending_scan = Some(receiver);
+        assert!(finder.is_indexing());
+        assert!(!finder.poll_background_refresh());
+
+        sender.send(scan_files(dir.path(), None, false)).unwrap();
+        assert!(finder.poll_background_refresh());
+        assert!(!finder.is_indexing());
+        assert_eq!(finder.query(), "l");
+        assert_eq!(finder.matches()[0].path, "src/lib.rs");
+    }
+
+    #[test]
+    fn test_frecency_ranking_and_match_positions() {
+        let mut finder = FileFinder::new();
+        finder.files = vec![
+            "src/editor/mod.rs".to_string(),
+            "src/ui/mod.rs".to_string(),
+            "src/config/mod.rs".to_string(),
+        ];
+        let now = unix_now();
+        // Opened often but long ago vs. opened once just now
+        finder.visits.insert("src/config/mod.rs".to_string(), Visit { count: 3, last: now - 30 * 24 * 60 * 60, cursor: None });
+        finder.visits.insert("src/ui/mod.rs".to_string(), Visit { count: 1, last: now, cursor: None });
+
+        finder.add_char('m');
+        finder.add_char('o');
+        finder.add_char('d');
+        finder.update_matches().unwrap();
+        let order: Vec<&str> = finder.matches().iter().map(|file_match| file_match.path.as_str()).collect();
+        assert_eq!(order, vec!["src/ui/mod.rs", "src/config/mod.rs", "src/editor/mod.rs"]);
+
+        // Matched characters are reported for highlighting
+        let positions = &finder.matches()[0].positions;
+        let matched: String = positions.iter().map(|&i| "src/ui/mod.rs".chars().nth(i).unwrap()).collect();
+        assert_eq!(matched, "mod");
+
+        // Another visit moves a file up
+        finder.add_recent_file("src/editor/mod.rs");
+        finder.add_recent_file("src/editor/mod.rs");
+        finder.update_matches().unwrap();
+        assert_eq!(finder.matches()[0].path, "src/editor/mod.rs");
+    }
+
+    #[test]
+    fn test_hidden_files_and_directory_scope() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("src/ui")).unwrap();
+        std::fs::create_dir_all(dir.path().join("target")).unwrap();
+        std::fs::write(dir.path().join("src/ui/mod.rs"), "").unwrap();
+        std::fs::write(dir.path().join("src/main.rs"), "").unwrap();
+        std::fs::write(dir.path().join("main.rs"), "").unwrap();
+        std::fs::write(dir.path().join(".env"), "").unwrap();
+        std::fs::write(dir.path().join("target/out.rs"), "").unwrap();
+
+        assert!(!scan_files(dir.path(), None, false).contains(&".env".to_string()));
+        let everything = scan_files(dir.path(), None, true);
+        assert!(everything.contains(&".env".to_string()));
+        assert!(everything.contains(&"target/out.rs".to_string()));
+
+        let mut finder = FileFinder::new();
+        finder.files = scan_files(dir.path(), None, false);
+        for c in "src/ main".chars() {
+            finder.add_char(c);
+        }
+        finder.update_matches().unwrap();
+        let paths: Vec<&str> = finder.matches().iter().map(|file_match| file_match.path.as_str()).collect();
+        assert_eq!(paths, vec!["src/main.rs"]);
+        assert_eq!(finder.matches()[0].positions, (0..8).collect::<Vec<_>>());
+
+        // Selecting a directory lists its contents
+        finder.enter_directory("src/ui/");
+        assert_eq!(finder.query(), "src/ui/ ");
+        assert_eq!(finder.get_selected().as_deref(), Some("src/ui/mod.rs"));
+    }
+
+    #[test]
+    fn test_recent_files_persist_with_their_cursors() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("notes.txt");
+        let file = file.to_str().unwrap();
+        std::fs::write(file, "").unwrap();
+        let store = dir.path().join("config/recent.toml");
+
+        let mut finder = FileFinder::new();
+        finder.load_history(store.clone()).unwrap();
+        finder.add_recent_file(file);
+        finder.add_recent_file(&format!("{}/gone.txt", dir.path().display()));
+        finder.remember_cursor(file, 12, 4);
+
+        let mut next_session = FileFinder::new();
+        next_session.load_history(store.clone()).unwrap();
+        assert_eq!(next_session.cursor(file), Some((12, 4)));
+        let recent = next_session.recent_files();
+        assert_eq!(recent.len(), 1);
+        assert_eq!((recent[0].path.as_str(), recent[0].count), (file, 1));
+
+        std::fs::write(&store, "files = 3").unwrap();
+        assert!(FileFinder::new().load_history(store).is_err());
+    }
+}
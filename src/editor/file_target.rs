@@ -0,0 +1,99 @@
+use std::path::Path;
+
+/// The file name that stands for stdin, as in `git diff | zim -`
+pub const STDIN: &str = "-";
+
+/// A file named on the command line, with where to put the cursor
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileTarget {
+    pub path: String,
+    /// Zero-based line; `usize::MAX` for the last line
+    pub line: Option<usize>,
+    /// Zero-based column
+    pub column: Option<usize>,
+}
+
+impl FileTarget {
+    pub fn is_stdin(&self) -> bool {
+        self.path == STDIN
+    }
+
+    /// Read `file:line:col` or `file:line` (with an optional trailing colon,
+    /// as grep and compilers print them), unless a file by the whole name
+    /// exists
+    fn parse(arg: &str) -> Self {
+        let plain = Self { path: arg.to_string(), line: None, column: None };
+        if arg == STDIN || Path::new(arg).exists() {
+            return plain;
+        }
+
+        let trimmed = arg.strip_suffix(':').unwrap_or(arg);
+        let Some((rest, last)) = trimmed.rsplit_once(':') else {
+            return plain;
+        };
+        let Some(last) = one_based(last) else {
+            return plain;
+        };
+        match rest.rsplit_once(':') {
+            Some((path, line)) if !path.is_empty() && one_based(line).is_some() => Self {
+                path: path.to_string(),
+                line: one_based(line),
+                column: Some(last),
+            },
+            _ if !rest.is_empty() => Self { path: rest.to_string(), line: Some(last), column: None },
+            _ => plain,
+        }
+    }
+}
+
+/// A 1-based number from the command line as a 0-based index
+fn one_based(text: &str) -> Option<usize> {
+    text.parse::<usize>().ok().filter(|n| *n > 0).map(|n| n - 1)
+}
+
+/// The files to open from the command line arguments: names, `file:12:5`
+/// positions, and vim's `+42` (or `+` for the last line) before a file
+pub fn parse_args(args: &[String]) -> Vec<FileTarget> {
+    let mut targets = Vec::new();
+    let mut line = None;
+    for arg in args {
+        match arg.strip_prefix('+') {
+            Some("") => line = Some(usize::MAX),
+            Some(number) if number.parse::<usize>().is_ok() => line = Some(one_based(number).unwrap_or(0)),
+            _ => {
+                let mut target = FileTarget::parse(arg);
+                if let Some(line) = line.take() {
+                    target.line = Some(line);
+                    target.column = None;
+                }
+                targets.push(target);
+            },
+        }
+    }
+    targets
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_positions_in_file_arguments() {
+        let args: Vec<String> = ["src/lib.rs:12:5", "notes.md:3:", "+42", "README.md", "+", "log.txt", "a:b", "Cargo.toml"]
+            .iter()
+            .map(|arg| arg.to_string())
+            .collect();
+        let targets = parse_args(&args);
+        let found: Vec<(&str, Option<usize>, Option<usize>)> = targets.iter()
+            .map(|target| (target.path.as_str(), target.line, target.column))
+            .collect();
+        assert_eq!(found, vec![
+            ("src/lib.rs", Some(11), Some(4)),
+            ("notes.md", Some(2), None),
+            ("README.md", Some(41), None),
+            ("log.txt", Some(usize::MAX), None),
+            ("a:b", None, None),
+            ("Cargo.toml", None, None),
+        ]);
+    }
+}
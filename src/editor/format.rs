@@ -0,0 +1,75 @@
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// Look up the formatter configured for a file, keyed by file extension
+pub fn formatter_for_path<'a>(formatters: &'a HashMap<String, String>, path: &str) -> Option<&'a str> {
+    let extension = Path::new(path).extension()?.to_str()?;
+    formatters.get(extension).map(|command| command.as_str())
+}
+
+/// Run an external formatter on some text
+///
+/// The text is passed on stdin and the formatted result is read from
+/// stdout, which is how rustfmt, prettier, black and friends behave when
+/// no file argument is given. A non-zero exit status is reported as an
+/// error carrying the formatter's stderr.
+pub fn run_formatter(command: &str, input: &str, working_dir: Option<&Path>) -> Result<String> {
+    let mut process = Command::new("sh");
+    process
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+
+    if let Some(dir) = working_dir {
+        process.current_dir(dir);
+    }
+
+    let mut child = process
+        .spawn()
+        .with_context(|| format!("Failed to start formatter: {}", command))?;
+
+    // Feed stdin from a separate thread so large outputs can't deadlock the pipes
+    let mut stdin = child.stdin.take().context("Failed to open formatter stdin")?;
+    let input = input.to_string();
+    let writer = std::thread::spawn(move || stdin.write_all(input.as_bytes()));
+
+    let output = child.wait_with_output()
+        .with_context(|| format!("Failed to run formatter: {}", command))?;
+    let _ = writer.join();
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let first_line = stderr.lines().find(|line| !line.trim().is_empty()).unwrap_or("unknown error");
+        return Err(anyhow::anyhow!("{} failed: {}", command, first_line));
+    }
+
+    String::from_utf8(output.stdout).context("Formatter produced invalid UTF-8")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_formatter_for_path() {
+        let mut formatters = HashMap::new();
+        formatters.insert("rs".to_string(), "rustfmt".to_string());
+
+        assert_eq!(formatter_for_path(&formatters, "src/main.rs"), Some("rustfmt"));
+        assert_eq!(formatter_for_path(&formatters, "notes.txt"), None);
+        assert_eq!(formatter_for_path(&formatters, "Makefile"), None);
+    }
+
+    #[test]
+    fn test_run_formatter_uses_stdin_and_stdout() {
+        let output = run_formatter("tr a-z A-Z", "hello\nworld\n", None).unwrap();
+        assert_eq!(output, "HELLO\nWORLD\n");
+
+        assert!(run_formatter("exit 3", "text", None).is_err());
+    }
+}
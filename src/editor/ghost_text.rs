@@ -0,0 +1,197 @@
+use super::ai;
+use super::ids::BufferId;
+use crate::config::AiConfig;
+use anyhow::Result;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Marks the cursor in the text sent with the prompt
+const CURSOR: &str = "<CURSOR>";
+/// Lines sent from before and after the cursor
+const LINES_BEFORE: usize = 60;
+const LINES_AFTER: usize = 20;
+/// Longest suggestion kept, in lines
+const MAX_LINES: usize = 8;
+
+/// Where a suggestion goes: the buffer and cursor position it was asked for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Anchor {
+    pub buffer: BufferId,
+    pub x: usize,
+    pub y: usize,
+}
+
+/// AI suggestions shown as ghost text after the cursor in insert mode
+///
+/// Once typing pauses, the text around the cursor goes to the AI command
+/// on a worker thread. Typing again cancels a request that's still
+/// running, and a reply for a position the cursor has left is dropped.
+#[derive(Default)]
+pub struct GhostText {
+    /// When the text last changed, while waiting for typing to pause
+    changed_at: Option<Instant>,
+    job: Option<Job>,
+    suggestion: Option<(Anchor, String)>,
+}
+
+/// A request running on a worker thread
+struct Job {
+    anchor: Anchor,
+    receiver: Receiver<Result<String>>,
+    cancel: Arc<AtomicBool>,
+}
+
+impl GhostText {
+    /// A key was pressed: hide the suggestion, drop the request for the old
+    /// text and wait for typing to pause again
+    pub fn typed(&mut self) {
+        self.dismiss();
+        self.changed_at = Some(Instant::now());
+    }
+
+    /// Hide the suggestion and stop waiting for one
+    pub fn dismiss(&mut self) {
+        if let Some(job) = self.job.take() {
+            job.cancel.store(true, Ordering::Relaxed);
+        }
+        self.suggestion = None;
+        self.changed_at = None;
+    }
+
+    /// Whether typing has paused for `delay`, so a suggestion can be asked for
+    pub fn is_due(&self, delay: Duration) -> bool {
+        self.changed_at.is_some_and(|changed| changed.elapsed() >= delay)
+    }
+
+    /// Whether a suggestion may show up soon
+    pub fn is_waiting(&self) -> bool {
+        self.changed_at.is_some() || self.job.is_some()
+    }
+
+    /// Ask the AI command for what comes at `anchor`
+    pub fn request(&mut self, anchor: Anchor, config: AiConfig, command: String, prompt: String, working_dir: Option<PathBuf>, env: Vec<(String, String)>) {
+        self.dismiss();
+        let cancel = Arc::new(AtomicBool::new(false));
+        let (sender, receiver) = mpsc::channel();
+        let cancelled = cancel.clone();
+        std::thread::spawn(move || {
+            let reply = ai::complete_unless_cancelled(&config, &command, &prompt, working_dir.as_deref(), &env, &cancelled);
+            let _ = sender.send(reply);
+        });
+        self.job = Some(Job { anchor, receiver, cancel });
+    }
+
+    /// Pick up a finished request, whose suggestion shows if the cursor is
+    /// still at `anchor`, returning its error if it failed
+    pub fn poll(&mut self, anchor: Anchor) -> Result<()> {
+        let Some(job) = &self.job else { return Ok(()) };
+        let reply = match job.receiver.try_recv() {
+            Ok(reply) => reply,
+            Err(TryRecvError::Empty) => return Ok(()),
+            Err(TryRecvError::Disconnected) => {
+                self.job = None;
+                return Ok(());
+            },
+        };
+        let asked_at = job.anchor;
+        self.job = None;
+        let reply = reply?;
+        if asked_at == anchor {
+            self.suggestion = suggestion_from_reply(&reply).map(|text| (anchor, text));
+        }
+        Ok(())
+    }
+
+    /// The suggestion for the cursor at `anchor`
+    pub fn suggestion(&self, anchor: Anchor) -> Option<&str> {
+        self.suggestion.as_ref()
+            .filter(|(at, _)| *at == anchor)
+            .map(|(_, text)| text.as_str())
+    }
+
+    /// Take the suggestion for the cursor at `anchor` to insert it
+    pub fn accept(&mut self, anchor: Anchor) -> Option<String> {
+        self.suggestion.take_if(|(at, _)| *at == anchor).map(|(_, text)| text)
+    }
+}
+
+/// The prompt asking for what goes at byte column `x` of line `y`, with the
+/// lines around it
+pub fn prompt(lines: &[String], x: usize, y: usize) -> String {
+    let line = lines.get(y).map(String::as_str).unwrap_or("");
+    let x = x.min(line.len());
+    let start = y.saturating_sub(LINES_BEFORE);
+    let end = (y + 1 + LINES_AFTER).min(lines.len());
+
+    let mut context = String::new();
+    for before in lines.get(start..y).unwrap_or(&[]) {
+        context.push_str(before);
+        context.push('\n');
+    }
+    context.push_str(&line[..x]);
+    context.push_str(CURSOR);
+    context.push_str(&line[x..]);
+    for after in lines.get(y + 1..end).unwrap_or(&[]) {
+        context.push('\n');
+        context.push_str(after);
+    }
+
+    format!(
+        "Continue the code at {}. Reply with only the text to insert there, without \
+         explanations or code fences, or with nothing if there's no good continuation.\n\n{}",
+        CURSOR, context
+    )
+}
+
+/// The text of a reply worth showing, without code fences or trailing
+/// whitespace and at most `MAX_LINES` long
+fn suggestion_from_reply(reply: &str) -> Option<String> {
+    let lines: Vec<&str> = reply.lines()
+        .filter(|line| !line.trim_start().starts_with("```"))
+        .take(MAX_LINES)
+        .collect();
+    let text = lines.join("\n").trim_end().to_string();
+    (!text.trim().is_empty()).then_some(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_suggestions_only_show_where_they_were_asked_for() {
+        let lines = vec!["fn add(a: i32, b: i32) -> i32 {".to_string(), "    a".to_string(), "}".to_string()];
+        let prompt = prompt(&lines, 5, 1);
+        assert!(prompt.ends_with("fn add(a: i32, b: i32) -> i32 {\n    a<CURSOR>\n}"), "{}", prompt);
+
+        assert_eq!(suggestion_from_reply("```rust\n + b\n```\n").as_deref(), Some(" + b"));
+        assert_eq!(suggestion_from_reply("\n  \n"), None);
+
+        let config = AiConfig::default();
+        let buffer = BufferId::next();
+        let anchor = Anchor { buffer, x: 5, y: 1 };
+        let mut ghost = GhostText::default();
+        ghost.request(anchor, config.clone(), "echo ' + b'".to_string(), prompt.clone(), None, Vec::new());
+        while ghost.is_waiting() {
+            ghost.poll(anchor).unwrap();
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(ghost.suggestion(anchor), Some(" + b"));
+        assert_eq!(ghost.suggestion(Anchor { x: 6, ..anchor }), None);
+        assert_eq!(ghost.accept(anchor).as_deref(), Some(" + b"));
+        assert_eq!(ghost.suggestion(anchor), None);
+
+        // A reply for where the cursor was is dropped
+        ghost.request(anchor, config, "echo ' + b'".to_string(), prompt, None, Vec::new());
+        let moved = Anchor { x: 6, ..anchor };
+        while ghost.is_waiting() {
+            ghost.poll(moved).unwrap();
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(ghost.suggestion(moved), None);
+        assert_eq!(ghost.suggestion(anchor), None);
+    }
+}
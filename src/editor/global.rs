@@ -0,0 +1,98 @@
+use anyhow::{anyhow, Result};
+use regex::Regex;
+
+/// A parsed `:g/pattern/command` or `:v/pattern/command`
+pub struct Global {
+    pattern: Regex,
+    /// Run the command on lines that don't match (`:v` and `:g!`)
+    invert: bool,
+    /// The ex command to run on each line, `p` when none was given
+    pub command: String,
+}
+
+impl Global {
+    /// Parse a global command, or None if `cmd` isn't one
+    ///
+    /// The pattern can be delimited by any character that isn't a letter,
+    /// digit, space, `\` or `"`, and the delimiter can be escaped in it.
+    pub fn parse(cmd: &str) -> Option<Result<Self>> {
+        let (invert, text) = ["g!", "global!", "v", "vglobal"].iter()
+            .map(|name| (true, name))
+            .chain(["g", "global"].iter().map(|name| (false, name)))
+            .find_map(|(invert, name)| cmd.strip_prefix(name).filter(|text| starts_with_delimiter(text)).map(|text| (invert, text)))?;
+        Some(Self::parse_rest(text, invert))
+    }
+
+    fn parse_rest(text: &str, invert: bool) -> Result<Self> {
+        let delimiter = text.chars().next().unwrap_or('/');
+        let mut pattern = String::new();
+        let mut chars = text[delimiter.len_utf8()..].char_indices();
+        let mut command = "";
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '\\' => match chars.next() {
+                    Some((_, next)) if next == delimiter => pattern.push(next),
+                    Some((_, next)) => {
+                        pattern.push('\\');
+                        pattern.push(next);
+                    },
+                    None => pattern.push('\\'),
+                },
+                c if c == delimiter => {
+                    command = &text[delimiter.len_utf8() + i + c.len_utf8()..];
+                    break;
+                },
+                c => pattern.push(c),
+            }
+        }
+        if pattern.is_empty() {
+            return Err(anyhow!("Empty search pattern"));
+        }
+        let pattern = Regex::new(&pattern).map_err(|e| anyhow!("Invalid pattern: {}", e))?;
+        let command = match command.trim() {
+            "" => "p".to_string(),
+            command => command.to_string(),
+        };
+        Ok(Self { pattern, invert, command })
+    }
+
+    pub fn pattern(&self) -> &str {
+        self.pattern.as_str()
+    }
+
+    /// Whether the command runs on `line`
+    pub fn selects(&self, line: &str) -> bool {
+        self.pattern.is_match(line) != self.invert
+    }
+}
+
+fn starts_with_delimiter(text: &str) -> bool {
+    text.chars().next().is_some_and(|c| !c.is_alphanumeric() && !c.is_whitespace() && c != '\\' && c != '"' && c != '!')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_global_commands_parse() -> Result<()> {
+        let global = Global::parse("g/ERROR/d").unwrap()?;
+        assert_eq!((global.pattern(), global.command.as_str()), ("ERROR", "d"));
+        assert!(global.selects("ERROR: disk full") && !global.selects("INFO"));
+
+        // The command keeps its own delimiters, and an escaped one is part of the pattern
+        let global = Global::parse(r"g#a\#b#s/x/y/g").unwrap()?;
+        assert_eq!((global.pattern(), global.command.as_str()), ("a#b", "s/x/y/g"));
+
+        let inverted = Global::parse("v/^#/normal i;").unwrap()?;
+        assert_eq!(inverted.command, "normal i;");
+        assert!(inverted.selects("x = 1") && !inverted.selects("# comment"));
+        assert!(Global::parse("g!/x/").unwrap()?.selects("y"));
+        assert_eq!(Global::parse("global/x").unwrap()?.command, "p");
+
+        assert!(Global::parse("git status").is_none());
+        assert!(Global::parse("vsplit").is_none());
+        assert!(Global::parse("g//d").unwrap().is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,138 @@
+use super::token_search::TokenSearchResult;
+use super::workspace_edit::{LineEdit, WorkspaceEdit};
+use anyhow::{anyhow, Result};
+use std::collections::HashMap;
+use std::path::Path;
+
+/// A tab listing token search results as `path:line:text`, where editing
+/// the text and running `:apply` changes those lines in their files
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GrepEdit {
+    /// What was searched for, shown in the preview title
+    pub query: String,
+    /// Text of each listed line when the tab was made, by path and 1-based
+    /// line number
+    original: HashMap<(String, usize), String>,
+}
+
+impl GrepEdit {
+    /// The edit for a set of results, with the tab's lines
+    pub fn new(query: &str, results: &[TokenSearchResult]) -> (Self, Vec<String>) {
+        let mut original = HashMap::new();
+        let mut lines = Vec::new();
+        for result in results {
+            let key = (result.file_path.clone(), result.line_number);
+            if original.contains_key(&key) {
+                continue;
+            }
+            lines.push(format!("{}:{}:{}", result.file_path, result.line_number, result.line_content));
+            original.insert(key, result.line_content.clone());
+        }
+        (Self { query: query.to_string(), original }, lines)
+    }
+
+    /// Name of the tab, which isn't a file
+    pub fn tab_name(&self) -> String {
+        format!("[grep] {}", self.query)
+    }
+
+    /// The edit writing back every listed line whose text was changed
+    ///
+    /// Lines that were deleted from the tab, or whose `path:line:` prefix
+    /// was changed, are left alone. `read_lines` supplies file contents so
+    /// unsaved buffers are used; a line that changed there since the search
+    /// is an error rather than being overwritten, and one that already has
+    /// the new text is skipped, so `:apply` can be run again.
+    pub fn edit(&self, lines: &[String], read_lines: &dyn Fn(&Path) -> Option<Vec<String>>) -> Result<WorkspaceEdit> {
+        let mut edit = WorkspaceEdit::new(&format!("Apply edited results for \"{}\"", self.query));
+        let mut files: HashMap<&str, Vec<String>> = HashMap::new();
+        for line in lines {
+            let Some((path, line_number, text)) = self.split_line(line) else { continue };
+            let original = &self.original[&(path.to_string(), line_number)];
+            if text == original {
+                continue;
+            }
+            if !files.contains_key(path) {
+                let content = read_lines(Path::new(path)).ok_or_else(|| anyhow!("Failed to read {}", path))?;
+                files.insert(path, content);
+            }
+            let current = files[path].get(line_number - 1).map(String::as_str).unwrap_or("");
+            // Already applied
+            if current.trim_end() == text {
+                continue;
+            }
+            if current.trim_end() != original {
+                return Err(anyhow!("{}:{} changed since the search", path, line_number));
+            }
+            edit.add_edit(path, LineEdit::replace(line_number - 1, current, text));
+        }
+        Ok(edit)
+    }
+
+    /// Split a tab line into a listed path, line number and its text
+    fn split_line<'a>(&self, line: &'a str) -> Option<(&'a str, usize, &'a str)> {
+        // Paths may contain colons, so try each `:number:` in turn
+        let mut start = 0;
+        while let Some(offset) = line[start..].find(':') {
+            let path_end = start + offset;
+            let rest = &line[path_end + 1..];
+            if let Some((number, text)) = rest.split_once(':') {
+                if let Ok(line_number) = number.parse::<usize>() {
+                    let path = &line[..path_end];
+                    if self.original.contains_key(&(path.to_string(), line_number)) {
+                        return Some((path, line_number, text));
+                    }
+                }
+            }
+            start = path_end + 1;
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(path: &str, line_number: usize, line: &str) -> TokenSearchResult {
+        TokenSearchResult {
+            file_path: path.to_string(),
+            line_number,
+            column: 0,
+            line_content: line.to_string(),
+            matched_text: String::new(),
+        }
+    }
+
+    #[test]
+    fn test_only_changed_lines_are_written_back() {
+        let results = vec![
+            result("src/a.rs", 2, "let total = 1;"),
+            result("src/a.rs", 5, "total += 1;"),
+            result("c:dir/b.rs", 1, "use total;"),
+        ];
+        let (grep, mut lines) = GrepEdit::new("total", &results);
+        assert_eq!(lines, vec!["src/a.rs:2:let total = 1;", "src/a.rs:5:total += 1;", "c:dir/b.rs:1:use total;"]);
+
+        lines[0] = "src/a.rs:2:let sum = 1;".to_string();
+        lines[2] = "c:dir/b.rs:1:use sum;".to_string();
+        lines.remove(1);
+        lines.push("not a result".to_string());
+        let files = |path: &Path| match path.to_str() {
+            Some("src/a.rs") => Some(vec!["".to_string(), "let total = 1;  ".to_string()]),
+            Some("c:dir/b.rs") => Some(vec!["use total;".to_string()]),
+            _ => None,
+        };
+        let edit = grep.edit(&lines, &files).unwrap();
+        assert_eq!(edit.edit_count(), 2);
+        assert_eq!(edit.files[0].path, "src/a.rs");
+        assert_eq!(edit.files[0].edits, vec![LineEdit::replace(1, "let total = 1;  ", "let sum = 1;")]);
+        assert_eq!(edit.files[1].edits, vec![LineEdit::replace(0, "use total;", "use sum;")]);
+
+        // A line changed since the search isn't overwritten
+        let changed = |_: &Path| Some(vec!["".to_string(), "let count = 1;".to_string()]);
+        assert!(grep.edit(&lines[..1], &changed).is_err());
+        let applied = |_: &Path| Some(vec!["".to_string(), "let sum = 1;".to_string()]);
+        assert!(grep.edit(&lines[..1], &applied).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,416 @@
+use crate::config::{Config, KeyBinding, KeyBindings};
+use std::collections::HashMap;
+
+/// A key that runs an entry's command, and whether the user's config
+/// changed it from the default
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HelpKey {
+    pub label: String,
+    pub custom: bool,
+}
+
+/// One line of the help page: every key for a command, and what it does
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HelpEntry {
+    pub keys: Vec<HelpKey>,
+    pub description: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HelpSection {
+    pub title: String,
+    pub entries: Vec<HelpEntry>,
+}
+
+/// Where the help page is scrolled to, and what it's narrowed to with `/`
+#[derive(Debug, Clone, Default)]
+pub struct HelpPage {
+    pub scroll: usize,
+    pub query: String,
+    /// Whether keys go to the query rather than move around the page
+    pub searching: bool,
+    /// Lines shown at once, as of the last time the page was drawn
+    pub height: usize,
+}
+
+/// What the commands key bindings and sequences can run do, in the order
+/// the help page lists them
+const DESCRIPTIONS: &[(&str, &str)] = &[
+    ("insert_mode", "Enter Insert mode"),
+    ("normal_mode", "Return to Normal mode"),
+    ("command_mode", "Enter Command mode"),
+    ("move_left", "Move left"),
+    ("move_down", "Move down"),
+    ("move_up", "Move up"),
+    ("move_right", "Move right"),
+    ("move_word_forward", "Move a word forward"),
+    ("move_word_backward", "Move a word backward"),
+    ("move_to_line_start", "Move to start of line"),
+    ("move_to_line_end", "Move to end of line"),
+    ("move_to_file_start", "Move to top of file"),
+    ("move_to_file_end", "Move to bottom of file"),
+    ("move_paragraph_forward", "Move to the blank line after the paragraph"),
+    ("move_paragraph_backward", "Move to the blank line before the paragraph"),
+    ("move_sentence_forward", "Move to the next sentence"),
+    ("move_sentence_backward", "Move to the start of the sentence"),
+    ("page_up", "Page up"),
+    ("page_down", "Page down"),
+    ("scroll_left", "Scroll long lines a column left (with :set nowrap)"),
+    ("scroll_right", "Scroll long lines a column right"),
+    ("scroll_half_left", "Scroll long lines half a screen left"),
+    ("scroll_half_right", "Scroll long lines half a screen right"),
+    ("match_bracket", "Jump to the matching bracket"),
+    ("expand_selection", "Grow the selection to the enclosing code"),
+    ("shrink_selection", "Shrink the selection back"),
+    ("find_file", "Open file (finder; Alt+n creates the typed path)"),
+    ("token_search", "Search for code tokens across files"),
+    ("save_file", "Save current file"),
+    ("reload_file", "Reload file from disk"),
+    ("quit", "Quit editor (asks about unsaved changes)"),
+    ("save_and_quit", "Save and quit"),
+    ("new_tab", "New tab"),
+    ("close_tab", "Close current tab"),
+    ("next_tab", "Next tab"),
+    ("prev_tab", "Previous tab"),
+    ("delete_char", "Delete character at cursor and enter insert mode"),
+    ("delete_char_forward", "Delete the character after the cursor"),
+    ("delete_line", "Delete current line"),
+    ("open_line_below", "Open a line below"),
+    ("open_line_above", "Open a line above"),
+    ("undo", "Undo last action"),
+    ("redo", "Redo previously undone action"),
+    ("undo_older", "Go to the previous state, across undo branches (:undotree lists them)"),
+    ("undo_newer", "Go to the next state, across undo branches"),
+    ("join_lines", "Join lines (the selected ones in Visual mode)"),
+    ("duplicate_lines", "Duplicate the line or selection"),
+    ("move_lines_down", "Move the line or selection down"),
+    ("move_lines_up", "Move the line or selection up"),
+    ("increment_number", "Add the count (1) to the number at or after the cursor"),
+    ("decrement_number", "Subtract the count (1) from the number at or after the cursor"),
+    ("increment_sequence", "In Visual mode, number the selected lines' numbers 1, 2, 3..."),
+    ("decrement_sequence", "In Visual mode, count the selected lines' numbers down"),
+    ("lowercase", "Lowercase over a motion (guu: the line)"),
+    ("uppercase", "Uppercase over a motion"),
+    ("toggle_case", "Toggle case over a motion"),
+    ("reflow", "Reflow over a motion to text_width, keeping comment prefixes"),
+    ("copy", "Copy the selection or line"),
+    ("paste", "Paste clipboard content"),
+    ("paste_indented", "Paste lines at the cursor line's indentation"),
+    ("paste_indented_before", "Paste lines above, at the cursor line's indentation"),
+    ("paste_primary", "Paste from the primary (mouse) selection"),
+    ("paste_primary_before", "Paste from the primary selection before the cursor"),
+    ("yank_primary", "Copy to the primary (mouse) selection"),
+    ("kill_line", "Cut to the end of the line"),
+    ("kill_word", "Cut to the end of the word"),
+    ("set_mark", "Set the mark, starting a region"),
+    ("kill_region", "Cut the region"),
+    ("copy_region", "Copy the region"),
+    ("yank", "Paste the last cut or copy"),
+    ("cancel", "Cancel"),
+    ("run_cargo_check", "Run cargo check (or the compiler preset) and show diagnostics"),
+    ("run_cargo_clippy", "Run cargo clippy and show diagnostics"),
+    ("show_help", "Show this help page"),
+    ("snake_game", "Play snake"),
+    ("select", "Open the selected entry"),
+    ("next", "Select the next entry"),
+    ("previous", "Select the previous entry"),
+    ("preview", "Peek at the selected entry without opening it"),
+    ("toggle_hidden", "Show or hide hidden and ignored files"),
+    ("cycle_scope", "Search the working directory, the project or the file's directory"),
+    ("create_file", "Create the typed path"),
+    ("toggle_regex", "Toggle regex search"),
+    ("toggle_case_sensitive", "Toggle case sensitive search"),
+    ("toggle_whole_word", "Toggle whole word search"),
+    ("edit_glob", "Limit the search to files matching a glob"),
+    ("replace", "Replace the matches"),
+    ("edit_results", "Edit the results in a tab, then :apply writes changed lines back"),
+    ("command_palette", "Search every command by name and run it"),
+];
+
+/// Commands of the file finder, token search and other panels rather than
+/// Normal mode
+const PANEL_COMMANDS: [&str; 15] = [
+    "normal_mode", "cancel", "select", "next", "previous", "preview", "toggle_hidden", "cycle_scope",
+    "create_file", "toggle_regex", "toggle_case_sensitive", "toggle_whole_word", "edit_glob", "replace",
+    "edit_results",
+];
+
+/// Normal mode keys zim handles itself, which can't be rebound
+const NORMAL_KEYS: &[(&str, &str)] = &[
+    (":", "Enter Command mode"),
+    ("v / V", "Enter Visual / Visual Line mode"),
+    ("d", "Delete: dd line, dw word, d$ to end, d0 to start, d} d{ d) d( over a paragraph or sentence, dip/dap paragraph, dt) df\" to a character"),
+    ("c", "Change: cw word, c$ to end, c0 to start, cc line, c} cip ct) ... as d (the selection in Visual mode)"),
+    ("f / F <char>", "Jump to the next / previous <char> on the line; t / T stop just before it"),
+    ("; / ,", "Repeat the last f, F, t or T / repeat it the other way"),
+    ("y", "Yank (copy): yy line, yw word, y$ to end, y} yip yt) ... as d"),
+    ("p", "Paste clipboard content"),
+    ("r / R", "Replace the character under the cursor / type over the text until Esc"),
+    ("~", "Toggle the case under the cursor"),
+    ("m<a-z> / '<a-z>", "Mark the cursor line / jump to a mark"),
+    ("ZZ", "Save and quit"),
+    ("w → a", "Save all files in all tabs (a in write confirm mode)"),
+    ("K", "Show the cursor line's diagnostics in a popup"),
+    ("Ctrl+e", "Open diagnostics panel (s sorts by location, severity or message)"),
+    ("Tab", "Peek at the selected result without opening it (Enter opens)"),
+    ("n / p", "Next / previous diagnostic"),
+    ("Alt+2 Enter", "Open the selected result in tab 2 (2 Enter in diagnostics)"),
+    ("Mouse", "Rest on a diagnostic dot or a tab for its full message or path"),
+    ("Click", "Click the right border to jump to a function or section ticked there"),
+    ("j/k, Ctrl+d/u, g/G", "Scroll files over 50MB and binary files, opened read-only and paged"),
+];
+
+const VISUAL_KEYS: &[(&str, &str)] = &[
+    ("h, j, k, l", "Extend selection"),
+    ("Esc", "Return to normal mode"),
+    ("y", "Yank (copy) selected text"),
+    ("d", "Delete selected text"),
+    ("ip / ap", "Select the paragraph (with the blank lines after it), so vipy yanks it"),
+    ("u / U / ~", "Lowercase / uppercase / toggle case of the selection"),
+    (":narrow", "Edit the selected lines in their own tab; :w writes them back"),
+];
+
+const INSERT_KEYS: &[(&str, &str)] = &[
+    ("Backspace", "Delete character or join with previous line"),
+    ("Ctrl+x Ctrl+f", "Complete a file path (Ctrl+n/Ctrl+p cycle)"),
+    ("Tab", "Accept the AI suggestion shown after the cursor ([ai] inline_completion)"),
+];
+
+/// Ex commands, typed after `:`
+const EX_COMMANDS: &[(&str, &str)] = &[
+    (":q!", "Force quit (discard changes, also :qa!)"),
+    (":cq", "Quit with an error status, aborting e.g. a git commit"),
+    (":w!!", "Write the file through sudo (:sudowrite)"),
+    (":wa", "Write all modified buffers (:wqa/:xa also quits)"),
+    (":saveas <path>", "Save to a new file and edit it there"),
+    (":rename <path>", "Move the current file on disk (.rs files offer module path updates)"),
+    (":Move <path>, :Rename <name>", "Move the current file, into a directory or beside itself"),
+    (":Delete", "Move the current file to the trash and close its tabs"),
+    (":Explore [dir]", "Browse a directory: Enter opens, t in a new tab, - goes up, s sorts"),
+    (":Mkdir [dir]", "Create a directory"),
+    (":reveal", "Show the file in the system file manager"),
+    (":copypath", "Copy the file's path (rel: relative, name: just the name)"),
+    (":recent", "Reopen a recent file where you left it"),
+    (":dashboard", "Show the start dashboard of recent projects and files"),
+    (":goto N", "Jump to byte offset N (Nc for a character offset)"),
+    (":s/pat/rep/[gi]", "Replace on the cursor line (:%s for the whole buffer)"),
+    (":10,20d", "Ranges (%, ., $, 'a, +N) for d, y, s, sort, w file, w >> file, !cmd and narrow"),
+    (":g/re/cmd", "Run cmd (d, s, w >>, normal <keys>...) on lines matching re; :v on the others"),
+    (":sort[!] [u][n][i]", "Sort the buffer or a range ('<,'>, 10,20), reversed with !"),
+    (":trim", "Remove trailing whitespace (trim_on_save = true does it on every save)"),
+    (":fmt", "Format the buffer with the configured formatter"),
+    (":imports", "Sort, group and merge Rust use statements"),
+    (":fix", "Apply the compiler's suggested fix on the cursor line (f in diagnostics)"),
+    (":doc", "Insert a doc comment for the function at the cursor"),
+    (":teststub", "Add a test stub for the function at the cursor"),
+    (":compiler", "Show or pick the compiler preset for Ctrl+d"),
+    (":run", "Run the current file, streaming its output ([runners] per file type)"),
+    (":build", "Run cargo build in a panel; Enter on a file:line opens it (:cw reopens)"),
+    (":!cmd", "Run a shell command and capture its output"),
+    (":!cmd &", "Run a shell command in the background (:jobs lists, restarts and kills jobs)"),
+    (":env", "List variables for commands (:env set NAME=value, :env unset NAME)"),
+    (":git ...", "Run a git command and capture its output"),
+    (":copen", "Show output of the last cargo/shell command"),
+    (":messages", "Review past messages and errors"),
+    (":config reload", "Re-read config.toml and apply it"),
+    (":trust / :untrust", "Allow or stop cargo, compilers and formatters in this workspace"),
+    (":lcd dir / :pwd", "Set or show the tab's working directory for the finder, search and cargo"),
+];
+
+const SETTINGS: &[(&str, &str)] = &[
+    (":set name=value", "Change tab_size, wrap, line_numbers, line_number_mode or theme"),
+    (":set offset", "Show the byte offset in the status line"),
+    (":set modeline", "Let files opened afterwards set tabstop, expandtab, wrap and filetype in a vim modeline"),
+    (":set relativenumber", "Relative line numbers, absolute on the cursor line"),
+    (":set list", "Show tabs, trailing and non-breaking spaces (listchars=tab:>,trail:-)"),
+    (":set cursorline / cursorcolumn / colorcolumn=80,100", "Highlight the cursor line, column and rulers"),
+    (":set nounsavedmarkers", "Hide the gutter bars on lines that differ from the file on disk"),
+    (":set fileformat=dos / fileencoding=latin1", "Line endings and encoding to save in"),
+    (":set noeol / eol, nobomb / bomb", "Drop or add the final newline and byte order mark ([noeol], [bom])"),
+    (":set noautopairs", "Stop closing brackets and quotes while typing"),
+    (":set soft", "Arrows, Ctrl+S/Q/C/V like other editors, with hints for the vim keys"),
+    (":set max_fps=30", "Draw at most 30 frames a second, for slow connections (0 for no cap)"),
+    (":set aioffline", "Send no AI requests (:set noaioffline to reconnect)"),
+    (":set autoread-follow", "Keep reading appended lines (tail -f)"),
+    ("keymap_preset = \"emacs\"", "Modeless editing with Emacs keys (C-x C-s, C-k, C-y...)"),
+];
+
+/// The help page for the keys `config` sets up, by mode, with every key
+/// for a command on the same line
+pub fn sections(config: &Config) -> Vec<HelpSection> {
+    let defaults = KeyBindings::default();
+    let bindings = &config.key_bindings;
+    let leader = leader_label(&config.leader);
+    let mut insert = binding_keys(&bindings.insert_mode, &defaults.insert_mode);
+    insert.extend(config.insert_sequences().unwrap_or_default().into_iter().map(|(sequence, command)| {
+        let custom = config.insert_keymap.contains_key(&sequence);
+        (command, HelpKey { label: sequence.replace("<leader>", &leader), custom })
+    }));
+
+    vec![
+        section("Normal mode", normal_keys(config), NORMAL_KEYS),
+        section("Visual mode", Vec::new(), VISUAL_KEYS),
+        section("Insert mode", insert, INSERT_KEYS),
+        section("Command mode", binding_keys(&bindings.command_mode, &defaults.command_mode), &[]),
+        section("Commands", Vec::new(), EX_COMMANDS),
+        section("Settings", Vec::new(), SETTINGS),
+        section("File finder", binding_keys(&bindings.file_finder_mode, &defaults.file_finder_mode), &[]),
+        section("Token search", binding_keys(&bindings.token_search_mode, &defaults.token_search_mode), &[]),
+        section("Help page", binding_keys(&bindings.help_mode, &defaults.help_mode), &[("j/k, g/G", "Scroll"), ("/", "Narrow the page to lines matching what's typed"), ("q", "Close help")]),
+    ]
+}
+
+/// The Normal mode keys for each command, from the key bindings and the
+/// key sequences, which run either a command name or a `:` command line
+pub fn normal_keys(config: &Config) -> Vec<(String, HelpKey)> {
+    let default_keymap = Config::default().keymap;
+    let leader = leader_label(&config.leader);
+    let mut keys = binding_keys(&config.key_bindings.normal_mode, &KeyBindings::default().normal_mode);
+    keys.extend(config.normal_sequences().into_iter().map(|(sequence, command)| {
+        let custom = config.keymap.get(&sequence).is_some_and(|mapped| *mapped == command)
+            && default_keymap.get(&sequence) != Some(&command);
+        (command, HelpKey { label: sequence.replace("<leader>", &leader), custom })
+    }));
+    keys
+}
+
+/// The Normal mode commands key bindings can run, in the order the help
+/// page lists them
+pub fn normal_commands() -> impl Iterator<Item = &'static str> {
+    DESCRIPTIONS.iter().map(|(name, _)| *name).filter(|name| !PANEL_COMMANDS.contains(name))
+}
+
+/// The `:` commands the help page lists, with what they do
+pub fn ex_commands() -> impl Iterator<Item = (&'static str, &'static str)> {
+    EX_COMMANDS.iter().copied()
+}
+
+/// The sections with only the entries mentioning `query`, ignoring case,
+/// and without the sections left empty
+pub fn filter(sections: Vec<HelpSection>, query: &str) -> Vec<HelpSection> {
+    let query = query.to_lowercase();
+    sections.into_iter().filter_map(|mut section| {
+        section.entries.retain(|entry| {
+            entry.description.to_lowercase().contains(&query)
+                || entry.keys.iter().any(|key| key.label.to_lowercase().contains(&query))
+        });
+        (!section.entries.is_empty()).then_some(section)
+    }).collect()
+}
+
+/// The keys bound to each command in one mode's bindings
+fn binding_keys(bindings: &HashMap<String, KeyBinding>, defaults: &HashMap<String, KeyBinding>) -> Vec<(String, HelpKey)> {
+    bindings.iter().map(|(command, binding)| {
+        let custom = defaults.get(command) != Some(binding);
+        (command.clone(), HelpKey { label: binding_label(binding), custom })
+    }).collect()
+}
+
+/// A section of the keys for each command, in the order of `DESCRIPTIONS`
+/// and then by name, followed by the keys zim handles itself
+fn section(title: &str, keys: Vec<(String, HelpKey)>, builtin: &[(&str, &str)]) -> HelpSection {
+    let mut commands: Vec<(String, Vec<HelpKey>)> = Vec::new();
+    for (command, key) in keys {
+        match commands.iter_mut().find(|(existing, _)| *existing == command) {
+            Some((_, keys)) => keys.push(key),
+            None => commands.push((command, vec![key])),
+        }
+    }
+    let order = |command: &str| DESCRIPTIONS.iter().position(|(name, _)| *name == command).unwrap_or(DESCRIPTIONS.len());
+    commands.sort_by(|a, b| order(&a.0).cmp(&order(&b.0)).then_with(|| a.0.cmp(&b.0)));
+
+    let mut entries: Vec<HelpEntry> = commands.into_iter().map(|(command, mut keys)| {
+        // Single keys before sequences, like Ctrl+o before <space>ff
+        keys.sort_by(|a, b| (a.label.starts_with('<'), a.label.len(), &a.label).cmp(&(b.label.starts_with('<'), b.label.len(), &b.label)));
+        HelpEntry { keys, description: describe(&command) }
+    }).collect();
+    entries.extend(builtin.iter().map(|(keys, description)| HelpEntry {
+        keys: vec![HelpKey { label: keys.to_string(), custom: false }],
+        description: description.to_string(),
+    }));
+    HelpSection { title: title.to_string(), entries }
+}
+
+/// What a command does, for commands without a description spelled out
+/// going by its name
+pub fn describe(command: &str) -> String {
+    if let Some((_, description)) = DESCRIPTIONS.iter().find(|(name, _)| *name == command) {
+        return description.to_string();
+    }
+    if let Some(number) = command.strip_prefix("goto_tab_") {
+        return format!("Switch to tab {}", number);
+    }
+    if command.starts_with(':') {
+        return format!("Run {}", command);
+    }
+    let words = command.replace('_', " ");
+    let mut chars = words.chars();
+    chars.next().map(|first| first.to_uppercase().chain(chars).collect()).unwrap_or_default()
+}
+
+/// A key binding as it's written in the help, like `Ctrl+o` or `F5`
+pub fn binding_label(binding: &KeyBinding) -> String {
+    let key = match binding.key.as_str() {
+        key if key.chars().count() == 1 => key.to_string(),
+        key if key.starts_with('f') && key[1..].parse::<u8>().is_ok() => key.to_uppercase(),
+        "pageup" => "PageUp".to_string(),
+        "pagedown" => "PageDown".to_string(),
+        key => {
+            let mut chars = key.chars();
+            chars.next().map(|first| first.to_uppercase().chain(chars).collect()).unwrap_or_default()
+        },
+    };
+    let mut label = String::new();
+    for modifier in ["ctrl", "alt", "shift"] {
+        if binding.modifiers.iter().any(|held| held == modifier) {
+            label.push_str(&modifier[..1].to_uppercase());
+            label.push_str(&modifier[1..]);
+            label.push('+');
+        }
+    }
+    label + &key
+}
+
+/// The leader key as sequences are written, like `<space>` or `\`
+fn leader_label(leader: &str) -> String {
+    if leader.chars().count() == 1 || leader.starts_with('<') {
+        leader.to_string()
+    } else {
+        format!("<{}>", leader)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry<'a>(sections: &'a [HelpSection], title: &str, description: &str) -> &'a HelpEntry {
+        let section = sections.iter().find(|section| section.title == title).unwrap();
+        section.entries.iter().find(|entry| entry.description == description).unwrap()
+    }
+
+    #[test]
+    fn test_help_lists_the_configured_keys() {
+        let mut config = Config::default();
+        config.key_bindings.normal_mode.insert("save_file".to_string(), KeyBinding::new("s").with_modifier("ctrl"));
+        config.keymap.insert("<leader>sv".to_string(), ":w".to_string());
+        let sections = sections(&config);
+
+        let labels = |entry: &HelpEntry| entry.keys.iter().map(|key| (key.label.clone(), key.custom)).collect::<Vec<_>>();
+        let find = entry(&sections, "Normal mode", "Open file (finder; Alt+n creates the typed path)");
+        assert_eq!(labels(find), [("Ctrl+o".to_string(), false), ("<space>ff".to_string(), false)]);
+        assert_eq!(labels(entry(&sections, "Normal mode", "Save current file")), [("Ctrl+s".to_string(), true)]);
+        let write = entry(&sections, "Normal mode", "Run :w");
+        assert!(write.keys.contains(&HelpKey { label: "<space>w".to_string(), custom: false }));
+        assert!(write.keys.contains(&HelpKey { label: "<space>sv".to_string(), custom: true }));
+        assert_eq!(labels(entry(&sections, "Normal mode", "Switch to tab 12")), [("F12".to_string(), false)]);
+        // Keys handled outside the bindings are listed too
+        let panel = entry(&sections, "Normal mode", "Open diagnostics panel (s sorts by location, severity or message)");
+        assert_eq!(labels(panel), [("Ctrl+e".to_string(), false)]);
+
+        let narrowed = filter(sections, "<SPACE>SV");
+        assert_eq!(narrowed.len(), 1);
+        assert_eq!(narrowed[0].entries.len(), 1);
+        assert_eq!(narrowed[0].entries[0].description, "Run :w");
+    }
+}
@@ -0,0 +1,577 @@
+use super::ids::BufferId;
+use super::syntax::{HighlightedLine, SyntaxHighlighter};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::sync::Arc;
+use syntect::highlighting::{HighlightIterator, HighlightState, Highlighter, Style, Theme};
+use syntect::parsing::{ParseState, ScopeStack, SyntaxReference, SyntaxSet};
+
+/// Lines highlighted beyond the end of the view, so scrolling down a little
+/// doesn't have to wait
+const LOOKAHEAD_LINES: usize = 500;
+
+/// Lines the background highlighter finishes before sending them back
+const CHUNK_LINES: usize = 200;
+
+/// Highlighted lines of one buffer, kept in step with its edits
+///
+/// Highlighting happens on the background highlighter, which keeps its own
+/// copy of the text. When the buffer changes only the changed lines are sent
+/// over; lines after them are shifted along with their highlights, and are
+/// only redone if the change affects them (like opening a block comment).
+pub struct BufferHighlights {
+    /// Identifies the buffer to the background highlighter
+    id: BufferId,
+    /// Syntax the highlights are for
+    syntax_name: Option<String>,
+    /// Hash of each line's text when the buffer was last compared
+    hashes: Vec<u64>,
+    /// Highlight of each line; `None` for lines that changed until the
+    /// background highlighter has redone them
+    lines: Vec<Option<HighlightedLine>>,
+    /// The buffer may have changed since it was last compared
+    stale: bool,
+    /// Bumped on every change, so results for older text are dropped
+    generation: u64,
+    /// First line the background highlighter still has to (re)do
+    dirty_from: Option<usize>,
+    /// The background highlighter is working on this buffer
+    pending: bool,
+}
+
+impl BufferHighlights {
+    pub fn new(id: BufferId) -> Self {
+        Self {
+            id,
+            syntax_name: None,
+            hashes: Vec::new(),
+            lines: Vec::new(),
+            stale: true,
+            generation: 0,
+            dirty_from: None,
+            pending: false,
+        }
+    }
+
+    pub fn id(&self) -> BufferId {
+        self.id
+    }
+
+    /// Drop every highlight so the buffer is highlighted again from the
+    /// start, e.g. in a new theme
+    pub fn reset(&mut self) {
+        self.syntax_name = None;
+        self.hashes.clear();
+        self.lines.clear();
+        self.stale = true;
+    }
+
+    /// Note that the buffer may have changed; it's compared line by line
+    /// the next time highlights are needed
+    pub fn mark_stale(&mut self) {
+        self.stale = true;
+    }
+
+    /// Whether the background highlighter is working on this buffer
+    pub fn is_pending(&self) -> bool {
+        self.pending
+    }
+
+    /// The highlight of a line, if there is one
+    ///
+    /// Lines after a change keep their previous highlight until the
+    /// background highlighter gets to them.
+    pub fn line(&self, index: usize) -> Option<&HighlightedLine> {
+        self.lines.get(index).and_then(Option::as_ref)
+    }
+
+    /// Catch up with the buffer's text, keeping the highlights of every
+    /// line that didn't change, and ask the background highlighter for the
+    /// lines up to `bottom` that need (re)doing
+    pub fn update(
+        &mut self,
+        worker: &mut HighlightWorker,
+        text: &[String],
+        bottom: usize,
+        syntax: Option<&Arc<SyntaxReference>>,
+        highlighter: &SyntaxHighlighter,
+    ) {
+        let end = bottom + LOOKAHEAD_LINES;
+        let syntax_name = syntax.map(|syntax| syntax.name.clone());
+
+        if syntax_name != self.syntax_name {
+            self.syntax_name = syntax_name;
+            self.hashes = text.iter().map(|line| hash_line(line)).collect();
+            self.lines = vec![None; text.len()];
+            self.stale = false;
+            self.generation += 1;
+            self.dirty_from = None;
+            self.pending = false;
+
+            match syntax {
+                Some(syntax) => {
+                    self.dirty_from = Some(0);
+                    self.pending = true;
+                    worker.send(Message::Reset {
+                        id: self.id,
+                        generation: self.generation,
+                        text: text.to_vec(),
+                        syntax: syntax.clone(),
+                        syntax_set: highlighter.syntax_set().clone(),
+                        theme: Arc::new(highlighter.theme().clone()),
+                        end,
+                    });
+                },
+                None => worker.close(self),
+            }
+            return;
+        }
+        if self.syntax_name.is_none() {
+            return;
+        }
+
+        if self.stale {
+            self.stale = false;
+            if let Some((start, removed, inserted)) = self.splice(text) {
+                self.dirty_from = Some(self.dirty_from.map_or(start, |dirty| dirty.min(start)));
+                self.pending = true;
+                worker.send(Message::Edit {
+                    id: self.id,
+                    generation: self.generation,
+                    start,
+                    removed,
+                    text: text[start..start + inserted].to_vec(),
+                    end,
+                });
+                return;
+            }
+        }
+
+        if !self.pending && self.dirty_from.is_some_and(|dirty| dirty < bottom.min(text.len())) {
+            self.pending = true;
+            worker.send(Message::Highlight { id: self.id, generation: self.generation, end });
+        }
+    }
+
+    /// Compare the text with the last version seen and replace the lines
+    /// that changed, returning the first changed line and how many lines
+    /// were removed and inserted there
+    fn splice(&mut self, text: &[String]) -> Option<(usize, usize, usize)> {
+        let hashes: Vec<u64> = text.iter().map(|line| hash_line(line)).collect();
+        let prefix = hashes.iter().zip(&self.hashes).take_while(|(new, old)| new == old).count();
+        let max_suffix = hashes.len().min(self.hashes.len()) - prefix;
+        let suffix = hashes.iter().rev().zip(self.hashes.iter().rev())
+            .take(max_suffix)
+            .take_while(|(new, old)| new == old)
+            .count();
+        if prefix == hashes.len() && prefix == self.hashes.len() {
+            return None;
+        }
+
+        let removed = self.hashes.len() - prefix - suffix;
+        let inserted = hashes.len() - prefix - suffix;
+        self.lines.splice(prefix..prefix + removed, std::iter::repeat_with(|| None).take(inserted));
+        self.hashes = hashes;
+        self.generation += 1;
+        Some((prefix, removed, inserted))
+    }
+
+    /// Take in lines finished by the background highlighter, unless the
+    /// buffer changed since
+    pub fn apply(&mut self, chunk: HighlightChunk) {
+        if chunk.id != self.id || chunk.generation != self.generation {
+            return;
+        }
+        for (index, line) in chunk.lines {
+            if let Some(slot) = self.lines.get_mut(index) {
+                *slot = Some(line);
+            }
+        }
+        self.dirty_from = chunk.dirty_from;
+        if chunk.done {
+            self.pending = false;
+        }
+    }
+}
+
+fn hash_line(line: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    line.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Work for the background highlighter
+enum Message {
+    /// Start over with a buffer's full text
+    Reset {
+        id: BufferId,
+        generation: u64,
+        text: Vec<String>,
+        syntax: Arc<SyntaxReference>,
+        syntax_set: Arc<SyntaxSet>,
+        theme: Arc<Theme>,
+        end: usize,
+    },
+    /// Replace `removed` lines at `start` with `text`
+    Edit {
+        id: BufferId,
+        generation: u64,
+        start: usize,
+        removed: usize,
+        text: Vec<String>,
+        end: usize,
+    },
+    /// Highlight the lines that need it up to `end`
+    Highlight { id: BufferId, generation: u64, end: usize },
+    /// Forget a buffer
+    Close { id: BufferId },
+    /// Forget every buffer but these
+    Retain { ids: Vec<BufferId> },
+}
+
+/// Lines finished by the background highlighter
+pub struct HighlightChunk {
+    pub id: BufferId,
+    generation: u64,
+    lines: Vec<(usize, HighlightedLine)>,
+    /// First line still to do afterwards
+    dirty_from: Option<usize>,
+    /// Nothing more to do up to the requested line
+    done: bool,
+}
+
+/// Highlights lines on a background thread, so opening a large file or an
+/// edit that changes how everything after it is highlighted doesn't hold
+/// up typing
+#[derive(Default)]
+pub struct HighlightWorker {
+    channel: Option<(Sender<Message>, Receiver<HighlightChunk>)>,
+}
+
+impl HighlightWorker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queue work, starting the thread the first time
+    fn send(&mut self, message: Message) {
+        let (messages, _) = self.channel.get_or_insert_with(|| {
+            let (message_sender, message_receiver) = mpsc::channel();
+            let (chunk_sender, chunk_receiver) = mpsc::channel();
+            std::thread::spawn(move || run_worker(message_receiver, chunk_sender));
+            (message_sender, chunk_receiver)
+        });
+        let _ = messages.send(message);
+    }
+
+    /// Forget a closed buffer's text
+    pub fn close(&mut self, highlights: &BufferHighlights) {
+        if self.channel.is_some() {
+            self.send(Message::Close { id: highlights.id });
+        }
+    }
+
+    /// Forget the text of every buffer but the given ones, in case one
+    /// went away without being closed
+    pub fn retain<'a>(&mut self, live: impl IntoIterator<Item = &'a BufferHighlights>) {
+        if self.channel.is_some() {
+            let ids = live.into_iter().map(|highlights| highlights.id).collect();
+            self.send(Message::Retain { ids });
+        }
+    }
+
+    /// Lines finished since the last call
+    pub fn poll(&self) -> Vec<HighlightChunk> {
+        match &self.channel {
+            Some((_, chunks)) => chunks.try_iter().collect(),
+            None => Vec::new(),
+        }
+    }
+}
+
+/// Parser and highlighter state at the start of a line
+type LineState = (ParseState, HighlightState);
+
+/// The background highlighter's copy of a buffer
+struct WorkerBuffer {
+    generation: u64,
+    syntax: Arc<SyntaxReference>,
+    syntax_set: Arc<SyntaxSet>,
+    theme: Arc<Theme>,
+    text: Vec<String>,
+    /// State at the start of each line, as of the last time it was reached
+    states: Vec<Option<LineState>>,
+    /// Lines whose highlight is up to date
+    done: Vec<bool>,
+    /// Highlight up to this line
+    end: usize,
+}
+
+impl WorkerBuffer {
+    fn first_dirty(&self) -> Option<usize> {
+        self.done.iter().position(|done| !done)
+    }
+
+    fn has_work(&self) -> bool {
+        self.first_dirty().is_some_and(|dirty| dirty < self.end)
+    }
+
+    /// Highlight up to `CHUNK_LINES` lines from the first one that needs it
+    ///
+    /// Each line starts from the state the previous one ended in; when that
+    /// differs from before, the next line is redone too.
+    fn work(&mut self, id: BufferId) -> HighlightChunk {
+        let highlighter = Highlighter::new(&self.theme);
+        let mut lines = Vec::new();
+
+        if let Some(start) = self.first_dirty() {
+            let stop = self.text.len().min(self.end).min(start + CHUNK_LINES);
+            for index in start..stop {
+                if self.done[index] {
+                    continue;
+                }
+                let state = match &self.states[index] {
+                    Some(state) => state.clone(),
+                    None if index == 0 => (
+                        ParseState::new(&self.syntax),
+                        HighlightState::new(&highlighter, ScopeStack::new()),
+                    ),
+                    // The line before hasn't been reached yet
+                    None => break,
+                };
+                let (line, end_state) = highlight_line(&self.text[index], state, &self.syntax_set, &highlighter);
+                lines.push((index, line));
+                self.done[index] = true;
+
+                if let Some(next_state) = self.states.get_mut(index + 1) {
+                    if next_state.as_ref() != Some(&end_state) {
+                        *next_state = Some(end_state);
+                        self.done[index + 1] = false;
+                    }
+                }
+            }
+        }
+
+        HighlightChunk {
+            id,
+            generation: self.generation,
+            lines,
+            dirty_from: self.first_dirty(),
+            done: !self.has_work(),
+        }
+    }
+}
+
+/// Highlight one line starting from `state`, returning the state at its end
+fn highlight_line(text: &str, state: LineState, syntax_set: &SyntaxSet, highlighter: &Highlighter) -> (HighlightedLine, LineState) {
+    let (mut parse_state, mut highlight_state) = state;
+    // The syntax definitions expect each line to end with a newline
+    let line = format!("{}\n", text);
+
+    let ranges = match parse_state.parse_line(&line, syntax_set) {
+        Ok(ops) => HighlightIterator::new(&mut highlight_state, &ops, &line, highlighter)
+            .map(|(style, text)| (style, text.trim_end_matches('\n').to_string()))
+            .filter(|(_, text)| !text.is_empty())
+            .collect(),
+        Err(_) => vec![(Style::default(), text.to_string())],
+    };
+
+    (HighlightedLine { ranges }, (parse_state, highlight_state))
+}
+
+fn run_worker(messages: Receiver<Message>, chunks: Sender<HighlightChunk>) {
+    let mut buffers: HashMap<BufferId, WorkerBuffer> = HashMap::new();
+
+    loop {
+        // Take in everything queued, waiting for more only when idle
+        let idle = !buffers.values().any(WorkerBuffer::has_work);
+        let first = if idle {
+            match messages.recv() {
+                Ok(message) => Some(message),
+                Err(_) => return,
+            }
+        } else {
+            match messages.try_recv() {
+                Ok(message) => Some(message),
+                Err(TryRecvError::Empty) => None,
+                Err(TryRecvError::Disconnected) => return,
+            }
+        };
+        for message in first.into_iter().chain(messages.try_iter()) {
+            receive(&mut buffers, message, &chunks);
+        }
+
+        // Work a chunk at a time so new edits are picked up quickly
+        let Some((&id, buffer)) = buffers.iter_mut().find(|(_, buffer)| buffer.has_work()) else { continue };
+        if chunks.send(buffer.work(id)).is_err() {
+            return;
+        }
+    }
+}
+
+fn receive(buffers: &mut HashMap<BufferId, WorkerBuffer>, message: Message, chunks: &Sender<HighlightChunk>) {
+    match message {
+        Message::Reset { id, generation, text, syntax, syntax_set, theme, end } => {
+            let mut buffer = WorkerBuffer {
+                generation,
+                syntax,
+                syntax_set,
+                theme,
+                states: vec![None; text.len()],
+                done: vec![false; text.len()],
+                text,
+                end,
+            };
+            // An empty buffer has nothing to do, which the editor has to hear
+            if !buffer.has_work() {
+                let _ = chunks.send(buffer.work(id));
+            }
+            buffers.insert(id, buffer);
+        },
+        Message::Edit { id, generation, start, removed, text, end } => {
+            let Some(buffer) = buffers.get_mut(&id) else { return };
+            let inserted = text.len();
+            // The state at the start of the first changed line still holds
+            let start_state = buffer.states.get(start).cloned().flatten();
+            buffer.text.splice(start..start + removed, text);
+            buffer.states.splice(start..start + removed, std::iter::repeat_with(|| None).take(inserted));
+            buffer.done.splice(start..start + removed, std::iter::repeat_n(false, inserted));
+            if let Some(state) = buffer.states.get_mut(start) {
+                *state = start_state;
+            }
+            // After deleted lines, the next line follows different text
+            if inserted == 0 {
+                if let Some(done) = buffer.done.get_mut(start) {
+                    *done = false;
+                }
+            }
+            buffer.generation = generation;
+            buffer.end = end;
+            if !buffer.has_work() {
+                let _ = chunks.send(buffer.work(id));
+            }
+        },
+        Message::Highlight { id, generation, end } => {
+            if let Some(buffer) = buffers.get_mut(&id) {
+                buffer.generation = generation;
+                buffer.end = end;
+                if !buffer.has_work() {
+                    let _ = chunks.send(buffer.work(id));
+                }
+            }
+        },
+        Message::Close { id } => {
+            buffers.remove(&id);
+        },
+        Message::Retain { ids } => {
+            buffers.retain(|id, _| ids.contains(id));
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Fixture {
+        highlighter: SyntaxHighlighter,
+        syntax: Arc<SyntaxReference>,
+        worker: HighlightWorker,
+    }
+
+    impl Fixture {
+        fn new() -> Self {
+            let highlighter = SyntaxHighlighter::new();
+            let syntax = highlighter.determine_syntax(Some("main.rs"), "").unwrap();
+            Self { highlighter, syntax, worker: HighlightWorker::new() }
+        }
+
+        fn send(&mut self, highlights: &mut BufferHighlights, text: &[String], bottom: usize) {
+            highlights.update(&mut self.worker, text, bottom, Some(&self.syntax), &self.highlighter);
+        }
+
+        /// Update until the background highlighter has caught up
+        fn update(&mut self, highlights: &mut BufferHighlights, text: &[String], bottom: usize) {
+            self.send(highlights, text, bottom);
+            while highlights.is_pending() {
+                for chunk in self.worker.poll() {
+                    highlights.apply(chunk);
+                }
+                std::thread::sleep(std::time::Duration::from_millis(2));
+                self.send(highlights, text, bottom);
+            }
+        }
+    }
+
+    fn lines(text: &str) -> Vec<String> {
+        text.lines().map(str::to_string).collect()
+    }
+
+    fn style_of(highlights: &BufferHighlights, index: usize) -> Style {
+        highlights.line(index).unwrap().ranges[0].0
+    }
+
+    #[test]
+    fn test_state_carries_across_lines_and_edits() {
+        let mut fixture = Fixture::new();
+        let mut text = lines("let a = 1;\nlet b = 2;\nlet c = 3;\nlet d = 4;");
+        let mut highlights = BufferHighlights::new(BufferId::next());
+        fixture.update(&mut highlights, &text, 10);
+        let code_style = style_of(&highlights, 3);
+
+        // Opening a block comment changes everything after it
+        text[1] = "/* let b = 2;".to_string();
+        highlights.mark_stale();
+        fixture.update(&mut highlights, &text, 10);
+        let comment_style = style_of(&highlights, 3);
+        assert_ne!(comment_style, code_style);
+
+        // Inserting a line only redoes that line; the ones after are shifted
+        text.insert(0, "// header".to_string());
+        highlights.mark_stale();
+        fixture.send(&mut highlights, &text, 10);
+        assert!(highlights.line(0).is_none());
+        assert_eq!(style_of(&highlights, 4), comment_style);
+        fixture.update(&mut highlights, &text, 10);
+        assert!(highlights.line(0).is_some());
+
+        // Removing the comment opener puts the code style back
+        text.remove(2);
+        highlights.mark_stale();
+        fixture.update(&mut highlights, &text, 10);
+        assert_eq!(style_of(&highlights, 3), code_style);
+    }
+
+    #[test]
+    fn test_forgotten_buffers_are_not_highlighted() {
+        let mut fixture = Fixture::new();
+        let text: Vec<String> = (0..1000).map(|i| format!("let x{} = {};", i, i)).collect();
+        let (mut kept, mut orphan) = (BufferHighlights::new(BufferId::next()), BufferHighlights::new(BufferId::next()));
+        fixture.update(&mut kept, &text, 10);
+        fixture.update(&mut orphan, &text, 10);
+
+        fixture.worker.retain([&kept]);
+        fixture.send(&mut orphan, &text, 900);
+        fixture.update(&mut kept, &text, 900);
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        assert!(fixture.worker.poll().iter().all(|chunk| chunk.id == kept.id()));
+        assert!(kept.line(999).is_some());
+    }
+
+    #[test]
+    fn test_highlighting_stops_past_the_view() {
+        let mut fixture = Fixture::new();
+        let text: Vec<String> = (0..1000).map(|i| format!("let x{} = \"{}\";", i, i)).collect();
+        let mut highlights = BufferHighlights::new(BufferId::next());
+        fixture.update(&mut highlights, &text, 100);
+        assert!(highlights.line(100 + LOOKAHEAD_LINES - 1).is_some());
+        assert!(highlights.line(100 + LOOKAHEAD_LINES).is_none());
+
+        // Scrolling down picks up where it left off
+        fixture.update(&mut highlights, &text, 900);
+        let last: String = highlights.line(999).unwrap().ranges.iter().map(|(_, text)| text.as_str()).collect();
+        assert_eq!(last, text[999]);
+    }
+}
@@ -1,4 +1,7 @@
 use super::cursor::Cursor;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::time::{Duration, Instant};
 
 /// Represents a single undoable editor action
 #[derive(Debug, Clone)]
@@ -55,6 +58,11 @@ pub enum ActionType {
         position: (usize, usize),  // (line, column)
         deleted_text: String,
     },
+    ReplaceInLine {
+        position: (usize, usize),  // (line, column)
+        old_text: String,
+        new_text: String,
+    },
 }
 
 #[derive(Debug, Clone)]
@@ -64,57 +72,141 @@ pub struct EditorAction {
     pub cursor_after: Cursor,
 }
 
-/// History manager for tracking editor changes
+impl EditorAction {
+    /// A short description of the change, for the undo tree panel
+    pub fn summary(&self) -> String {
+        let short = |text: &str| {
+            let text = text.lines().next().unwrap_or("");
+            if text.chars().count() > 20 {
+                format!("{}...", text.chars().take(20).collect::<String>())
+            } else {
+                text.to_string()
+            }
+        };
+        let change = match &self.action_type {
+            ActionType::InsertChar { c, .. } => format!("insert {:?}", c),
+            ActionType::DeleteChar { c, .. } => format!("delete {:?}", c),
+            ActionType::InsertNewline { .. } => "split line".to_string(),
+            ActionType::DeleteLine { content, .. } => format!("delete line \"{}\"", short(content)),
+            ActionType::JoinLines { .. } => "join lines".to_string(),
+            ActionType::ReplaceSelection { old_text, new_text, .. } => {
+                format!("replace \"{}\" with \"{}\"", short(old_text), short(new_text))
+            },
+            ActionType::SetContent { .. } => "change the whole buffer".to_string(),
+            ActionType::OpenLineBelow { .. } | ActionType::OpenLineAbove { .. } => "open line".to_string(),
+            ActionType::DeleteWord { deleted_text, .. }
+            | ActionType::DeleteToEndOfLine { deleted_text, .. }
+            | ActionType::DeleteToStartOfLine { deleted_text, .. } => format!("delete \"{}\"", short(deleted_text)),
+            ActionType::ReplaceInLine { old_text, new_text, .. } => {
+                format!("replace \"{}\" with \"{}\"", short(old_text), short(new_text))
+            },
+        };
+        format!("{} on line {}", change, self.cursor_before.y + 1)
+    }
+}
+
+/// One state in the undo tree, reached from its parent by an action
+#[derive(Debug, Clone)]
+struct Node {
+    /// The change that led here; the root, the oldest state kept, has none
+    action: Option<EditorAction>,
+    parent: usize,
+    /// Oldest first
+    children: Vec<usize>,
+    /// The child redo goes to: the newest, or the one last undone
+    redo: Option<usize>,
+    /// When the change was made, counting changes from 1; 0 for the root
+    seq: usize,
+    made_at: Instant,
+}
+
+impl Node {
+    fn root() -> Self {
+        Self { action: None, parent: 0, children: Vec::new(), redo: None, seq: 0, made_at: Instant::now() }
+    }
+}
+
+/// A state in the undo tree as shown in the `:undotree` panel
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UndoTreeRow {
+    /// Node id to pass to `Buffer::undo_to`
+    pub node: usize,
+    /// Branch lines, with `*` marking the state's own branch
+    pub graph: String,
+    pub seq: usize,
+    pub summary: String,
+    pub age: String,
+    pub current: bool,
+}
+
+/// Undo history as a tree, like vim's
+///
+/// Undoing and then making a change starts a new branch instead of
+/// throwing the undone changes away. `u` and `Ctrl+r` move along the
+/// current branch, `g-` and `g+` through every state in the order they were
+/// made, and `:undotree` jumps to any of them.
 pub struct History {
-    actions: Vec<EditorAction>,
-    current_index: usize,
+    /// Node 0 is the root
+    nodes: Vec<Node>,
+    /// Node whose state the buffer is in
+    current: usize,
+    next_seq: usize,
     max_history: usize,
     /// Flag to indicate if we're currently in an undo operation
     in_undo_or_redo: bool,
+    /// The lines and cursor from before an edit that is recorded as one
+    /// step once it's done, like a change or replace mode
+    group: Option<(Vec<String>, Cursor)>,
 }
 
 impl History {
     pub fn new() -> Self {
         Self {
-            actions: Vec::new(),
-            current_index: 0,
+            nodes: vec![Node::root()],
+            current: 0,
+            next_seq: 1,
             max_history: 1000, // Configurable limit
             in_undo_or_redo: false,
+            group: None,
         }
     }
 
-    /// Add an action to the history
+    /// Add an action to the history, as a new branch if changes were undone
     pub fn push(&mut self, action: EditorAction) {
-        // Don't record actions that happen during an undo/redo operation
-        if self.in_undo_or_redo {
+        // Don't record actions that happen during an undo/redo operation,
+        // or one at a time while they're grouped
+        if self.in_undo_or_redo || self.group.is_some() {
             return;
         }
 
-        // If we're not at the end of the history, truncate the future actions
-        if self.current_index < self.actions.len() {
-            self.actions.truncate(self.current_index);
-        }
-
-        // Add the new action
-        self.actions.push(action);
-        self.current_index = self.actions.len();
+        let index = self.nodes.len();
+        self.nodes.push(Node {
+            action: Some(action),
+            parent: self.current,
+            children: Vec::new(),
+            redo: None,
+            seq: self.next_seq,
+            made_at: Instant::now(),
+        });
+        self.next_seq += 1;
+        let parent = &mut self.nodes[self.current];
+        parent.children.push(index);
+        parent.redo = Some(index);
+        self.current = index;
 
-        // Trim history if it exceeds the maximum size
-        if self.actions.len() > self.max_history {
-            let remove_count = self.actions.len() - self.max_history;
-            self.actions.drain(0..remove_count);
-            self.current_index -= remove_count;
+        if self.nodes.len() > self.max_history + 1 {
+            self.trim();
         }
     }
 
     /// Can we undo?
     pub fn can_undo(&self) -> bool {
-        self.current_index > 0
+        self.current != 0
     }
 
     /// Can we redo?
     pub fn can_redo(&self) -> bool {
-        self.current_index < self.actions.len()
+        self.nodes[self.current].redo.is_some()
     }
 
     /// Get the action to undo
@@ -123,8 +215,11 @@ impl History {
             return None;
         }
 
-        self.current_index -= 1;
-        let action = self.actions[self.current_index].clone();
+        let node = &self.nodes[self.current];
+        let action = node.action.clone()?;
+        let (undone, parent) = (self.current, node.parent);
+        self.nodes[parent].redo = Some(undone);
+        self.current = parent;
         Some(action)
     }
 
@@ -134,15 +229,36 @@ impl History {
             return None;
         }
 
-        let action = self.actions[self.current_index].clone();
-        self.current_index += 1;
-        Some(action)
+        let child = self.nodes[self.current].redo?;
+        self.current = child;
+        self.nodes[child].action.clone()
     }
 
     /// Clear all history
     pub fn clear(&mut self) {
-        self.actions.clear();
-        self.current_index = 0;
+        self.nodes = vec![Node::root()];
+        self.current = 0;
+        self.group = None;
+    }
+
+    /// Start recording edits as a single step, from `lines` as they are now
+    pub fn begin_group(&mut self, lines: &[String], cursor: Cursor) {
+        if self.group.is_none() && !self.in_undo_or_redo {
+            self.group = Some((lines.to_vec(), cursor));
+        }
+    }
+
+    /// Record everything since `begin_group` as one step, if it changed
+    /// anything
+    pub fn end_group(&mut self, lines: &[String], cursor: Cursor) {
+        let Some((old_lines, cursor_before)) = self.group.take() else { return };
+        if old_lines != lines {
+            self.push(EditorAction {
+                action_type: ActionType::SetContent { old_lines, new_lines: lines.to_vec() },
+                cursor_before,
+                cursor_after: cursor,
+            });
+        }
     }
     
     /// Mark that we're entering an undo/redo operation
@@ -154,6 +270,196 @@ impl History {
     pub fn end_undo_or_redo(&mut self) {
         self.in_undo_or_redo = false;
     }
+
+    /// The node of the buffer's current state
+    pub fn current(&self) -> usize {
+        self.current
+    }
+
+    /// The state made just before the current one, on any branch (`g-`)
+    pub fn older(&self) -> Option<usize> {
+        let seq = self.nodes[self.current].seq;
+        (0..self.nodes.len())
+            .filter(|&node| self.nodes[node].seq < seq)
+            .max_by_key(|&node| self.nodes[node].seq)
+    }
+
+    /// The state made just after the current one, on any branch (`g+`)
+    pub fn newer(&self) -> Option<usize> {
+        let seq = self.nodes[self.current].seq;
+        (0..self.nodes.len())
+            .filter(|&node| self.nodes[node].seq > seq)
+            .min_by_key(|&node| self.nodes[node].seq)
+    }
+
+    /// How many undos it takes to get from the current state back to
+    /// where the branch leading to `target` splits off
+    pub fn undos_to(&self, target: usize) -> usize {
+        if target >= self.nodes.len() {
+            return 0;
+        }
+        let mut on_target_path = vec![false; self.nodes.len()];
+        let mut node = target;
+        on_target_path[node] = true;
+        while node != 0 {
+            node = self.nodes[node].parent;
+            on_target_path[node] = true;
+        }
+
+        let mut undos = 0;
+        let mut node = self.current;
+        while !on_target_path[node] {
+            node = self.nodes[node].parent;
+            undos += 1;
+        }
+        undos
+    }
+
+    /// Point redo down the branch leading to `target`
+    pub fn follow(&mut self, target: usize) {
+        let mut node = target.min(self.nodes.len() - 1);
+        while node != 0 {
+            let parent = self.nodes[node].parent;
+            self.nodes[parent].redo = Some(node);
+            node = parent;
+        }
+    }
+
+    /// Every state, newest first, with branch lines like a git log graph
+    pub fn tree_rows(&self) -> Vec<UndoTreeRow> {
+        // Each branch gets a column: a node's oldest child continues its
+        // column and newer children start new ones to the right
+        let mut column = vec![0; self.nodes.len()];
+        let mut columns = 1;
+        let mut stack = vec![0];
+        while let Some(node) = stack.pop() {
+            for (i, &child) in self.nodes[node].children.iter().enumerate() {
+                column[child] = if i == 0 {
+                    column[node]
+                } else {
+                    columns += 1;
+                    columns - 1
+                };
+                stack.push(child);
+            }
+        }
+        // A column is drawn from the state its branch split off at up to
+        // its newest state
+        let mut spans = vec![(usize::MAX, 0); columns];
+        for (node, data) in self.nodes.iter().enumerate() {
+            let span = &mut spans[column[node]];
+            let start = if node == 0 || column[data.parent] == column[node] { data.seq } else { self.nodes[data.parent].seq };
+            span.0 = span.0.min(start);
+            span.1 = span.1.max(data.seq);
+        }
+
+        let mut order: Vec<usize> = (0..self.nodes.len()).collect();
+        order.sort_by_key(|&node| std::cmp::Reverse(self.nodes[node].seq));
+        order.into_iter().map(|node| {
+            let data = &self.nodes[node];
+            let graph: String = (0..columns).map(|c| {
+                if c == column[node] {
+                    '*'
+                } else if spans[c].0 < data.seq && data.seq < spans[c].1 {
+                    '|'
+                } else {
+                    ' '
+                }
+            }).collect();
+            UndoTreeRow {
+                node,
+                graph,
+                seq: data.seq,
+                summary: data.action.as_ref().map_or_else(|| "original".to_string(), EditorAction::summary),
+                age: age(data.made_at.elapsed()),
+                current: node == self.current,
+            }
+        }).collect()
+    }
+
+    /// Forget the oldest states once there are more than `max_history`
+    ///
+    /// Abandoned branches go first, oldest leaf first; after that the
+    /// oldest states on the current branch are folded into the root.
+    fn trim(&mut self) {
+        let count = self.nodes.len();
+        let mut excess = count - 1 - self.max_history;
+
+        let mut on_path = vec![false; count];
+        let mut node = self.current;
+        loop {
+            on_path[node] = true;
+            if node == 0 {
+                break;
+            }
+            node = self.nodes[node].parent;
+        }
+
+        let mut removed = vec![false; count];
+        let mut child_count: Vec<usize> = self.nodes.iter().map(|node| node.children.len()).collect();
+        let mut leaves: BinaryHeap<Reverse<(usize, usize)>> = (0..count)
+            .filter(|&node| child_count[node] == 0 && !on_path[node])
+            .map(|node| Reverse((self.nodes[node].seq, node)))
+            .collect();
+        while excess > 0 {
+            let Some(Reverse((_, leaf))) = leaves.pop() else { break };
+            removed[leaf] = true;
+            excess -= 1;
+            let parent = self.nodes[leaf].parent;
+            child_count[parent] -= 1;
+            if child_count[parent] == 0 && !on_path[parent] {
+                leaves.push(Reverse((self.nodes[parent].seq, parent)));
+            }
+        }
+
+        // Only the current branch is left; its oldest change becomes the root
+        let mut root = 0;
+        while excess > 0 && root != self.current {
+            removed[root] = true;
+            root = self.nodes[root].children.iter().copied()
+                .find(|&child| !removed[child])
+                .unwrap_or(self.current);
+            excess -= 1;
+        }
+
+        // Renumber the nodes that are left, which keeps parents before
+        // their children and `root` first
+        let mut new_index = vec![usize::MAX; count];
+        let mut kept = 0;
+        for node in 0..count {
+            if !removed[node] {
+                new_index[node] = kept;
+                kept += 1;
+            }
+        }
+        let nodes = std::mem::take(&mut self.nodes);
+        self.nodes = nodes.into_iter().enumerate()
+            .filter(|(node, _)| !removed[*node])
+            .map(|(node, mut data)| {
+                if node == root {
+                    data.action = None;
+                    data.parent = 0;
+                } else {
+                    data.parent = new_index[data.parent];
+                }
+                data.children = data.children.iter().filter(|&&child| !removed[child]).map(|&child| new_index[child]).collect();
+                data.redo = data.redo.filter(|&child| !removed[child]).map(|child| new_index[child]);
+                data
+            })
+            .collect();
+        self.current = new_index[self.current];
+    }
+}
+
+/// How long ago something happened, like `5s ago` or `2h ago`
+pub fn age(elapsed: Duration) -> String {
+    let seconds = elapsed.as_secs();
+    match seconds {
+        0..=59 => format!("{}s ago", seconds),
+        60..=3599 => format!("{}m ago", seconds / 60),
+        3600..=86399 => format!("{}h ago", seconds / 3600),
+        _ => format!("{}d ago", seconds / 86400),
+    }
 }
 
 #[cfg(test)]
@@ -174,7 +480,7 @@ mod tests {
         
         assert!(history.can_undo());
         assert!(!history.can_redo());
-        assert_eq!(history.current_index, 1);
+        assert_eq!(history.current(), 1);
     }
     
     #[test]
@@ -228,36 +534,87 @@ mod tests {
         assert!(!history.can_redo());
     }
     
+    fn insert(x: usize, c: char) -> EditorAction {
+        EditorAction {
+            action_type: ActionType::InsertChar { x, y: 0, c },
+            cursor_before: Cursor { x, y: 0 },
+            cursor_after: Cursor { x: x + 1, y: 0 },
+        }
+    }
+
+    fn inserted(action: Option<EditorAction>) -> char {
+        match action.map(|action| action.action_type) {
+            Some(ActionType::InsertChar { c, .. }) => c,
+            other => panic!("Unexpected action {:?}", other),
+        }
+    }
+
     #[test]
-    fn test_history_truncation() {
+    fn test_undone_changes_stay_on_a_branch() {
         let mut history = History::new();
         
         // Add three actions
         for i in 0..3 {
-            let action = EditorAction {
-                action_type: ActionType::InsertChar { x: i, y: 0, c: (b'a' + i as u8) as char },
-                cursor_before: Cursor { x: i, y: 0 },
-                cursor_after: Cursor { x: i + 1, y: 0 },
-            };
-            history.push(action);
+            history.push(insert(i, (b'a' + i as u8) as char));
         }
         
         // Undo back to the first action
         history.undo_action();
         history.undo_action();
+        assert_eq!(history.current(), 1);
         
-        assert_eq!(history.current_index, 1);
-        
-        // Add a new action, which should truncate the future
-        let new_action = EditorAction {
-            action_type: ActionType::InsertChar { x: 1, y: 0, c: 'x' },
-            cursor_before: Cursor { x: 1, y: 0 },
-            cursor_after: Cursor { x: 2, y: 0 },
-        };
-        history.push(new_action);
-        
-        // Now we should have only 2 actions
-        assert_eq!(history.actions.len(), 2);
+        // A new action starts a branch, and redo follows it
+        history.push(insert(1, 'x'));
         assert!(!history.can_redo());
+        assert_eq!(history.current(), 4);
+        history.undo_action();
+        assert_eq!(inserted(history.redo_action()), 'x');
+
+        // g- and g+ go through the states in the order they were made
+        assert_eq!(history.older(), Some(3));
+        assert_eq!(history.undos_to(3), 1);
+        history.undo_action();
+        history.follow(3);
+        assert_eq!(inserted(history.redo_action()), 'b');
+        assert_eq!(inserted(history.redo_action()), 'c');
+        assert_eq!(history.current(), 3);
+        assert_eq!(history.newer(), Some(4));
+
+        let rows = history.tree_rows();
+        let graph: Vec<(&str, usize, bool)> = rows.iter().map(|row| (row.graph.as_str(), row.seq, row.current)).collect();
+        assert_eq!(graph, vec![
+            (" *", 4, false),
+            ("*|", 3, true),
+            ("*|", 2, false),
+            ("* ", 1, false),
+            ("* ", 0, false),
+        ]);
+        assert_eq!(rows[0].summary, "insert 'x' on line 1");
+        assert_eq!(rows[4].summary, "original");
+    }
+
+    #[test]
+    fn test_old_branches_are_forgotten_first() {
+        let mut history = History::new();
+        history.max_history = 3;
+        history.push(insert(0, 'a'));
+        history.push(insert(1, 'b'));
+        history.undo_action();
+        history.push(insert(1, 'c'));
+        history.push(insert(2, 'd'));
+        // The abandoned 'b' went first
+        assert_eq!(history.nodes.len(), 4);
+        assert!(history.tree_rows().iter().all(|row| row.seq != 2));
+
+        // Then the oldest change on the current branch
+        history.push(insert(3, 'e'));
+        assert_eq!(history.nodes.len(), 4);
+        let seqs: Vec<usize> = history.tree_rows().iter().map(|row| row.seq).collect();
+        assert_eq!(seqs, vec![5, 4, 3, 1]);
+        let mut undos = 0;
+        while history.undo_action().is_some() {
+            undos += 1;
+        }
+        assert_eq!(undos, 3);
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,94 @@
+use std::time::{Duration, Instant};
+
+/// How long the mouse has to rest on something before its tooltip shows
+pub const HOVER_DELAY: Duration = Duration::from_millis(500);
+
+/// A run of screen cells on one row with a tooltip, recorded while rendering
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HoverTarget {
+    pub x: u16,
+    pub y: u16,
+    pub width: u16,
+    pub text: String,
+}
+
+impl HoverTarget {
+    fn contains(&self, column: u16, row: u16) -> bool {
+        row == self.y && column >= self.x && column < self.x.saturating_add(self.width)
+    }
+}
+
+/// Where the mouse rests, for showing the tooltip of what's under it
+#[derive(Debug, Default)]
+pub struct Hover {
+    /// Mouse column and row, and when it got there
+    position: Option<(u16, u16, Instant)>,
+    targets: Vec<HoverTarget>,
+}
+
+impl Hover {
+    pub fn moved(&mut self, column: u16, row: u16) {
+        if !matches!(self.position, Some((x, y, _)) if (x, y) == (column, row)) {
+            self.position = Some((column, row, Instant::now()));
+        }
+    }
+
+    /// Hide the tooltip until the mouse moves again
+    pub fn clear(&mut self) {
+        self.position = None;
+    }
+
+    /// Forget the targets of the previous frame before recording new ones
+    pub fn clear_targets(&mut self) {
+        self.targets.clear();
+    }
+
+    pub fn add_targets(&mut self, targets: impl IntoIterator<Item = HoverTarget>) {
+        self.targets.extend(targets);
+    }
+
+    fn target_at(&self, column: u16, row: u16) -> Option<&HoverTarget> {
+        self.targets.iter().find(|target| target.contains(column, row))
+    }
+
+    /// The target under the mouse, once the mouse has rested on it for
+    /// `delay`, with the mouse position
+    pub fn tooltip(&self, delay: Duration) -> Option<(&HoverTarget, u16, u16)> {
+        let (column, row, since) = self.position?;
+        if since.elapsed() < delay {
+            return None;
+        }
+        Some((self.target_at(column, row)?, column, row))
+    }
+
+    /// Whether the mouse is on a target whose tooltip isn't showing yet
+    pub fn is_waiting(&self, delay: Duration) -> bool {
+        self.position.is_some_and(|(column, row, since)| {
+            since.elapsed() < delay && self.target_at(column, row).is_some()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tooltip_for_the_target_under_the_mouse() {
+        let mut hover = Hover::default();
+        hover.add_targets([HoverTarget { x: 10, y: 2, width: 5, text: "src/main.rs".to_string() }]);
+        assert!(hover.tooltip(Duration::ZERO).is_none());
+
+        hover.moved(12, 2);
+        assert!(hover.is_waiting(Duration::from_secs(60)));
+        assert!(hover.tooltip(Duration::from_secs(60)).is_none());
+        let (target, column, row) = hover.tooltip(Duration::ZERO).unwrap();
+        assert_eq!((target.text.as_str(), column, row), ("src/main.rs", 12, 2));
+
+        hover.moved(15, 2);
+        assert!(hover.tooltip(Duration::ZERO).is_none());
+        hover.moved(10, 2);
+        hover.clear();
+        assert!(hover.tooltip(Duration::ZERO).is_none());
+    }
+}
@@ -0,0 +1,127 @@
+use super::word_diff;
+use similar::{DiffTag, TextDiff};
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+
+/// A run of changed lines between the saved file and the buffer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hunk {
+    /// Lines of the saved file it replaces
+    pub old: Range<usize>,
+    /// Lines of the buffer it consists of; empty for a deletion
+    pub new: Range<usize>,
+}
+
+impl Hunk {
+    /// Whether the cursor on buffer line `line` is on this hunk; a
+    /// deletion sits on the line after it
+    pub fn contains(&self, line: usize) -> bool {
+        self.new.contains(&line) || (self.new.is_empty() && self.new.start == line)
+    }
+}
+
+/// The changes a save would write, with the hunks chosen to be left out of
+/// it, like staging part of a file in git
+#[derive(Debug, Default)]
+pub struct WriteReview {
+    /// Changed words of modified lines, by buffer line
+    pub words: HashMap<usize, Vec<Range<usize>>>,
+    pub hunks: Vec<Hunk>,
+    /// Indexes into `hunks` that stay in the buffer only
+    pub excluded: HashSet<usize>,
+}
+
+impl WriteReview {
+    pub fn new(saved: &str, content: &str) -> Self {
+        Self {
+            words: word_diff::changed_lines(saved, content),
+            hunks: hunks(saved, content),
+            excluded: HashSet::new(),
+        }
+    }
+
+    /// The index of the hunk on buffer line `line`
+    pub fn hunk_at(&self, line: usize) -> Option<usize> {
+        self.hunks.iter().position(|hunk| hunk.contains(line))
+    }
+
+    /// Whether buffer line `line` is in a hunk left out of the save
+    pub fn is_excluded(&self, line: usize) -> bool {
+        self.excluded.iter().any(|&index| self.hunks[index].new.contains(&line))
+    }
+
+    /// Leave the hunk out of the save, or put it back in
+    pub fn toggle(&mut self, index: usize) {
+        if !self.excluded.remove(&index) {
+            self.excluded.insert(index);
+        }
+    }
+}
+
+/// The changed hunks of `new` against `old`, by lines
+pub fn hunks(old: &str, new: &str) -> Vec<Hunk> {
+    let mut hunks: Vec<Hunk> = Vec::new();
+    for op in TextDiff::from_lines(old, new).ops() {
+        if op.tag() == DiffTag::Equal {
+            continue;
+        }
+        // A deletion next to an insertion is one change
+        match hunks.last_mut() {
+            Some(last) if last.old.end == op.old_range().start && last.new.end == op.new_range().start => {
+                last.old.end = op.old_range().end;
+                last.new.end = op.new_range().end;
+            },
+            _ => hunks.push(Hunk { old: op.old_range(), new: op.new_range() }),
+        }
+    }
+    hunks
+}
+
+/// `new` with the `excluded` hunks turned back into what `old` had there
+pub fn apply_except(old: &str, new: &str, hunks: &[Hunk], excluded: &HashSet<usize>) -> String {
+    let old_lines: Vec<&str> = old.split_inclusive('\n').collect();
+    let new_lines: Vec<&str> = new.split_inclusive('\n').collect();
+    let mut text = String::with_capacity(new.len());
+    let mut old_line = 0;
+    for (index, hunk) in hunks.iter().enumerate() {
+        text.extend(old_lines[old_line..hunk.old.start].iter().copied());
+        if excluded.contains(&index) {
+            text.extend(old_lines[hunk.old.clone()].iter().copied());
+        } else {
+            text.extend(new_lines[hunk.new.clone()].iter().copied());
+        }
+        old_line = hunk.old.end;
+    }
+    text.extend(old_lines[old_line..].iter().copied());
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_excluded_hunks_keep_the_saved_text() {
+        let old = "fn main() {\n    run();\n}\n\nfn run() {}\n";
+        let new = "fn main() {\n    dbg!(1);\n    run();\n}\n\nfn run() { fixed() }\n";
+        let hunks = hunks(old, new);
+        assert_eq!(hunks, vec![
+            Hunk { old: 1..1, new: 1..2 },
+            Hunk { old: 4..5, new: 5..6 },
+        ]);
+
+        let mut review = WriteReview::new(old, new);
+        assert_eq!(review.hunk_at(1), Some(0));
+        assert_eq!(review.hunk_at(2), None);
+        review.toggle(0);
+        assert!(review.is_excluded(1));
+        assert_eq!(apply_except(old, new, &review.hunks, &review.excluded),
+            "fn main() {\n    run();\n}\n\nfn run() { fixed() }\n");
+
+        review.toggle(0);
+        assert_eq!(apply_except(old, new, &review.hunks, &review.excluded), new);
+        review.toggle(1);
+        assert_eq!(apply_except(old, new, &review.hunks, &review.excluded),
+            "fn main() {\n    dbg!(1);\n    run();\n}\n\nfn run() {}\n");
+    }
+}
@@ -0,0 +1,14 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+static NEXT_BUFFER_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Identifies a buffer for as long as the editor runs, unlike the index of
+/// its tab, which changes as tabs are opened and closed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct BufferId(u64);
+
+impl BufferId {
+    pub fn next() -> Self {
+        Self(NEXT_BUFFER_ID.fetch_add(1, Ordering::Relaxed))
+    }
+}
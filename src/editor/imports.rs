@@ -0,0 +1,455 @@
+use anyhow::Result;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// How far `use` statements are merged, named after rustfmt's
+/// `imports_granularity` option
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportGranularity {
+    /// One `use` per crate: `use std::{fs, io::Read};`
+    Crate,
+    /// One `use` per module: `use std::io::{Read, Write};`
+    Module,
+    /// One `use` per imported item
+    Item,
+}
+
+impl ImportGranularity {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "crate" => Some(ImportGranularity::Crate),
+            "module" => Some(ImportGranularity::Module),
+            "item" => Some(ImportGranularity::Item),
+            _ => None,
+        }
+    }
+}
+
+/// Which paragraph of the import block a path belongs in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Group {
+    Std,
+    External,
+    Local,
+}
+
+/// One imported item, after brace groups are expanded
+#[derive(Debug, Clone)]
+struct Import {
+    visibility: String,
+    path: Vec<String>,
+    alias: Option<String>,
+    glob: bool,
+}
+
+/// A tree of imported paths sharing prefixes, rendered back into nested braces
+#[derive(Debug, Default)]
+struct Node {
+    children: BTreeMap<String, Node>,
+    /// The path up to this node is itself imported
+    imported: bool,
+    aliases: BTreeSet<String>,
+    glob: bool,
+}
+
+impl Node {
+    fn insert(&mut self, import: &Import) {
+        let mut node = self;
+        for segment in &import.path {
+            node = node.children.entry(segment.clone()).or_default();
+        }
+        if import.glob {
+            node.glob = true;
+        } else if let Some(alias) = &import.alias {
+            node.aliases.insert(alias.clone());
+        } else {
+            node.imported = true;
+        }
+    }
+
+    /// The entries of this node's brace list
+    fn render_items(&self) -> Vec<String> {
+        let mut items = Vec::new();
+        for (name, child) in &self.children {
+            let has_children = !child.children.is_empty() || child.glob;
+            if child.imported && !has_children {
+                items.push(name.clone());
+            }
+            for alias in &child.aliases {
+                items.push(format!("{} as {}", name, alias));
+            }
+            if has_children {
+                let mut nested = Vec::new();
+                if child.imported {
+                    nested.push("self".to_string());
+                }
+                nested.extend(child.render_items());
+                if child.glob {
+                    nested.push("*".to_string());
+                }
+
+                if nested.len() == 1 {
+                    items.push(format!("{}::{}", name, nested[0]));
+                } else {
+                    items.push(format!("{}::{{{}}}", name, nested.join(", ")));
+                }
+            }
+        }
+        items
+    }
+}
+
+/// Sort, group and merge the `use` statements at the top of a Rust file
+///
+/// The block starts at the first top-level `use` and runs over following
+/// `use` statements and blank lines. It's rewritten as std, external and
+/// crate-local paragraphs (re-exports last), with duplicates removed and
+/// paths merged according to `granularity`. Statements with attributes,
+/// like `#[cfg(test)]`, aren't merged; they keep their attributes and go
+/// last, in their order. Returns the new lines of the whole file, or `None`
+/// when there are no imports to organize.
+pub fn organize_imports(lines: &[String], granularity: ImportGranularity) -> Result<Option<Vec<String>>> {
+    let Some(mut start) = lines.iter().position(|line| is_use_start(line)) else {
+        return Ok(None);
+    };
+    while start > 0 && is_attribute(&lines[start - 1]) {
+        start -= 1;
+    }
+
+    // Collect complete statements, which may span several lines
+    let mut statements = Vec::new();
+    let mut attributed = Vec::new();
+    let mut end = start;
+    let mut index = start;
+    while index < lines.len() {
+        let line = &lines[index];
+        if line.trim().is_empty() {
+            index += 1;
+            continue;
+        }
+        let first = index;
+        while lines.get(index).is_some_and(|line| is_attribute(line)) {
+            index += 1;
+        }
+        if !lines.get(index).is_some_and(|line| is_use_start(line)) {
+            break;
+        }
+
+        let mut statement = String::new();
+        loop {
+            let Some(line) = lines.get(index) else {
+                return Err(anyhow::anyhow!("Unterminated use statement on line {}", start + 1));
+            };
+            if line.contains("//") || line.contains("/*") {
+                return Err(anyhow::anyhow!("Comment inside use statement on line {}", index + 1));
+            }
+            statement.push_str(line.trim());
+            statement.push(' ');
+            index += 1;
+            if line.trim_end().ends_with(';') {
+                break;
+            }
+        }
+        if is_attribute(&lines[first]) {
+            attributed.push(&lines[first..index]);
+        } else {
+            statements.push(statement);
+        }
+        end = index;
+    }
+
+    let local_modules: BTreeSet<String> = lines.iter()
+        .filter_map(|line| declared_module(line))
+        .collect();
+
+    let mut imports = Vec::new();
+    for statement in &statements {
+        imports.extend(parse_statement(statement)?);
+    }
+
+    // Bucket by (re-export, group, visibility) so each paragraph is stable
+    let mut paragraphs: BTreeMap<(bool, Group, String), Vec<Import>> = BTreeMap::new();
+    for import in imports {
+        let group = classify(&import.path[0], &local_modules);
+        let key = (!import.visibility.is_empty(), group, import.visibility.clone());
+        paragraphs.entry(key).or_default().push(import);
+    }
+
+    let mut block: Vec<String> = Vec::new();
+    for ((_, _, visibility), imports) in &paragraphs {
+        if !block.is_empty() {
+            block.push(String::new());
+        }
+
+        // Imports that end up in the same `use` statement
+        let mut statements: BTreeMap<Vec<String>, Node> = BTreeMap::new();
+        for import in imports {
+            let key = match granularity {
+                ImportGranularity::Crate => import.path[..1].to_vec(),
+                ImportGranularity::Module => module_of(import),
+                ImportGranularity::Item => {
+                    let mut key = import.path.clone();
+                    key.extend(import.alias.iter().cloned());
+                    key.extend(import.glob.then(|| "*".to_string()));
+                    key
+                },
+            };
+            statements.entry(key).or_default().insert(import);
+        }
+
+        let mut rendered: Vec<String> = statements.values()
+            .flat_map(|node| node.render_items())
+            .map(|item| format!("{}use {};", visibility, item))
+            .collect();
+        rendered.dedup();
+        block.extend(rendered);
+    }
+    for statement in attributed {
+        if !block.is_empty() {
+            block.push(String::new());
+        }
+        block.extend_from_slice(statement);
+    }
+
+    let mut result = lines[..start].to_vec();
+    result.extend(block);
+    result.extend_from_slice(&lines[end..]);
+    Ok(Some(result))
+}
+
+/// Whether a line begins a top-level `use` statement
+fn is_use_start(line: &str) -> bool {
+    let Some(rest) = visibility_prefix(line) else { return false };
+    rest.starts_with("use ")
+}
+
+/// Whether a line is an outer attribute like `#[cfg(test)]`
+fn is_attribute(line: &str) -> bool {
+    line.starts_with("#[")
+}
+
+/// Strip an optional `pub` / `pub(...)` from the start of an unindented line
+fn visibility_prefix(line: &str) -> Option<&str> {
+    if line.starts_with(char::is_whitespace) {
+        return None;
+    }
+    if let Some(rest) = line.strip_prefix("pub(") {
+        let close = rest.find(')')?;
+        return Some(rest[close + 1..].trim_start());
+    }
+    Some(line.strip_prefix("pub ").map(str::trim_start).unwrap_or(line))
+}
+
+/// Name of a `mod name;` or `mod name {` declared at the top level
+fn declared_module(line: &str) -> Option<String> {
+    let rest = visibility_prefix(line)?.strip_prefix("mod ")?;
+    let name: String = rest.chars().take_while(|c| c.is_alphanumeric() || *c == '_').collect();
+    (!name.is_empty()).then_some(name)
+}
+
+fn classify(first_segment: &str, local_modules: &BTreeSet<String>) -> Group {
+    match first_segment {
+        "std" | "core" | "alloc" | "proc_macro" | "test" => Group::Std,
+        "crate" | "self" | "super" => Group::Local,
+        name if local_modules.contains(name) => Group::Local,
+        _ => Group::External,
+    }
+}
+
+/// The module an import is taken from; a glob imports from its own path
+fn module_of(import: &Import) -> Vec<String> {
+    if import.glob || import.path.len() == 1 {
+        import.path.clone()
+    } else {
+        import.path[..import.path.len() - 1].to_vec()
+    }
+}
+
+/// Split a `use` statement into the items it imports
+fn parse_statement(statement: &str) -> Result<Vec<Import>> {
+    let statement = statement.trim().trim_end_matches(';');
+    let (visibility, tree) = match statement.find("use ") {
+        Some(position) => (&statement[..position], &statement[position + 4..]),
+        None => return Err(anyhow::anyhow!("Not a use statement: {}", statement)),
+    };
+    let visibility = match visibility.trim() {
+        "" => String::new(),
+        vis => format!("{} ", vis.replace(' ', "")),
+    };
+
+    let tokens = tokenize(tree);
+    let mut parser = Parser { tokens, position: 0, imports: Vec::new(), visibility };
+    parser.parse_tree(Vec::new())?;
+    if parser.position != parser.tokens.len() {
+        return Err(anyhow::anyhow!("Could not parse: use {}", tree.trim()));
+    }
+    Ok(parser.imports)
+}
+
+fn tokenize(text: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            ':' if chars.peek() == Some(&':') => {
+                chars.next();
+                tokens.push("::".to_string());
+            },
+            '{' | '}' | ',' | '*' => tokens.push(c.to_string()),
+            c if c.is_whitespace() => {},
+            _ => {
+                let mut word = c.to_string();
+                while let Some(&next) = chars.peek() {
+                    if next.is_alphanumeric() || next == '_' || next == '#' {
+                        word.push(next);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                tokens.push(word);
+            },
+        }
+    }
+    tokens
+}
+
+struct Parser {
+    tokens: Vec<String>,
+    position: usize,
+    imports: Vec<Import>,
+    visibility: String,
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<String> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens.get(self.position).map(String::as_str)
+    }
+
+    fn push(&mut self, mut path: Vec<String>, alias: Option<String>, glob: bool) {
+        // `foo::{self}` imports `foo` itself
+        if path.len() > 1 && path.last().is_some_and(|segment| segment == "self") {
+            path.pop();
+        }
+        self.imports.push(Import { visibility: self.visibility.clone(), path, alias, glob });
+    }
+
+    fn parse_tree(&mut self, mut prefix: Vec<String>) -> Result<()> {
+        loop {
+            match self.next().as_deref() {
+                Some("{") => {
+                    while self.peek() != Some("}") {
+                        self.parse_tree(prefix.clone())?;
+                        match self.peek() {
+                            Some(",") => { self.next(); },
+                            Some("}") => {},
+                            _ => return Err(anyhow::anyhow!("Expected , or }} in use list")),
+                        }
+                    }
+                    self.next();
+                    return Ok(());
+                },
+                Some("*") if !prefix.is_empty() => {
+                    self.push(prefix, None, true);
+                    return Ok(());
+                },
+                Some(segment) if segment.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '#') => {
+                    prefix.push(segment.to_string());
+                    match self.peek() {
+                        Some("::") => { self.next(); },
+                        Some("as") => {
+                            self.next();
+                            let alias = self.next().ok_or_else(|| anyhow::anyhow!("Missing alias after as"))?;
+                            self.push(prefix, Some(alias), false);
+                            return Ok(());
+                        },
+                        _ => {
+                            self.push(prefix, None, false);
+                            return Ok(());
+                        },
+                    }
+                },
+                other => return Err(anyhow::anyhow!("Unexpected {} in use statement", other.unwrap_or("end"))),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(text: &str) -> Vec<String> {
+        text.lines().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn test_organize_by_crate() {
+        let source = lines("\
+//! Crate docs
+mod editor;
+
+use crate::editor::Mode;
+use std::io::Write;
+use anyhow::{Result, Context};
+use std::{fs, io};
+use editor::Editor;
+use std::io::Write;
+pub use crate::editor::Buffer;
+use std::collections::{HashMap as Map, HashSet};
+
+fn main() {}");
+
+        let organized = organize_imports(&source, ImportGranularity::Crate).unwrap().unwrap();
+        assert_eq!(organized, lines("\
+//! Crate docs
+mod editor;
+
+use std::{collections::{HashMap as Map, HashSet}, fs, io::{self, Write}};
+
+use anyhow::{Context, Result};
+
+use crate::editor::Mode;
+use editor::Editor;
+
+pub use crate::editor::Buffer;
+
+fn main() {}"));
+
+        // Organizing again changes nothing
+        assert_eq!(organize_imports(&organized, ImportGranularity::Crate).unwrap().unwrap(), organized);
+    }
+
+    #[test]
+    fn test_module_and_item_granularity() {
+        let source = lines("use std::{fs, io::{self, Read, Write}};\nuse std::fs::File;\n\nfn f() {}");
+
+        let by_module = organize_imports(&source, ImportGranularity::Module).unwrap().unwrap();
+        assert_eq!(by_module, lines("use std::{fs, io};\nuse std::fs::File;\nuse std::io::{Read, Write};\n\nfn f() {}"));
+
+        let by_item = organize_imports(&source, ImportGranularity::Item).unwrap().unwrap();
+        assert_eq!(by_item.len(), 7);
+        assert_eq!(by_item[3], "use std::io::Read;");
+
+        assert!(organize_imports(&lines("fn f() {}"), ImportGranularity::Crate).unwrap().is_none());
+        assert!(organize_imports(&lines("use a::{b;"), ImportGranularity::Crate).is_err());
+    }
+
+    #[test]
+    fn test_attributes_stay_on_their_use() {
+        let source = lines("#[cfg(test)]\nuse std::io;\nuse anyhow::Result;\n#[cfg(unix)]\n#[allow(unused)]\nuse std::os::unix::fs::PermissionsExt;\nuse std::fs;\n\nfn f() {}");
+        let organized = organize_imports(&source, ImportGranularity::Crate).unwrap().unwrap();
+        assert_eq!(organized, lines("use std::fs;\n\nuse anyhow::Result;\n\n#[cfg(test)]\nuse std::io;\n\n#[cfg(unix)]\n#[allow(unused)]\nuse std::os::unix::fs::PermissionsExt;\n\nfn f() {}"));
+        assert_eq!(organize_imports(&organized, ImportGranularity::Crate).unwrap().unwrap(), organized);
+
+        // An attribute on something else ends the block
+        let source = lines("use b::x;\nuse a::y;\n#[derive(Debug)]\nstruct S;");
+        let organized = organize_imports(&source, ImportGranularity::Crate).unwrap().unwrap();
+        assert_eq!(organized, lines("use a::y;\nuse b::x;\n#[derive(Debug)]\nstruct S;"));
+    }
+}
@@ -0,0 +1,74 @@
+use regex::Regex;
+
+/// Where `delta` changes the first number that ends after column `x` of
+/// `line`, the one under the cursor or else the next one, like vim's Ctrl+a
+///
+/// Decimal numbers can be negative; `0x` hex and `0b` binary numbers are
+/// unsigned and wrap around. Leading zeros and the case of hex digits are
+/// kept. Returns the byte range of the number and what replaces it, or None
+/// if there's no number to change.
+pub fn increment(line: &str, x: usize, delta: i64) -> Option<(usize, usize, String)> {
+    let pattern = Regex::new(r"0[xX][0-9a-fA-F]+|0[bB][01]+|-?[0-9]+").expect("valid regex");
+    let found = pattern.find_iter(line).find(|number| number.end() > x)?;
+    let mut start = found.start();
+    // A minus after a word is a dash or a subtraction, not a sign
+    if found.as_str().starts_with('-') && line[..start].chars().next_back().is_some_and(|c| c.is_alphanumeric() || c == '_') {
+        start += 1;
+    }
+    let number = &line[start..found.end()];
+    Some((start, found.end(), add(number, delta)?))
+}
+
+fn add(number: &str, delta: i64) -> Option<String> {
+    let (prefix, digits) = number.split_at(number.len().min(2));
+    match prefix {
+        "0x" | "0X" => {
+            let value = u64::from_str_radix(digits, 16).ok()?.wrapping_add_signed(delta);
+            let width = digits.len();
+            Some(match digits.chars().any(|c| c.is_ascii_uppercase()) {
+                true => format!("{}{:0width$X}", prefix, value),
+                false => format!("{}{:0width$x}", prefix, value),
+            })
+        },
+        "0b" | "0B" => {
+            let value = u64::from_str_radix(digits, 2).ok()?.wrapping_add_signed(delta);
+            Some(format!("{}{:0width$b}", prefix, value, width = digits.len()))
+        },
+        _ => {
+            let value = number.parse::<i64>().ok()?.checked_add(delta)?;
+            // Zero padding keeps its width, like `007` to `008`
+            let digits = number.trim_start_matches('-');
+            if digits.len() > 1 && digits.starts_with('0') {
+                let sign = if value < 0 { "-" } else { "" };
+                return Some(format!("{}{:0width$}", sign, value.unsigned_abs(), width = digits.len()));
+            }
+            Some(value.to_string())
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn changed(line: &str, x: usize, delta: i64) -> Option<String> {
+        let (start, end, number) = increment(line, x, delta)?;
+        Some(format!("{}{}{}", &line[..start], number, &line[end..]))
+    }
+
+    #[test]
+    fn test_numbers_under_or_after_the_cursor_change() {
+        assert_eq!(changed("let x = 41;", 0, 1).as_deref(), Some("let x = 42;"));
+        assert_eq!(changed("a 9 b 9", 3, 1).as_deref(), Some("a 9 b 10"));
+        assert_eq!(changed("x = 1", 0, -3).as_deref(), Some("x = -2"));
+        assert_eq!(changed("x = -1", 0, 5).as_deref(), Some("x = 4"));
+        assert_eq!(changed("item-3", 0, 1).as_deref(), Some("item-4"));
+        assert_eq!(changed("v007", 0, 1).as_deref(), Some("v008"));
+        assert_eq!(changed("0xff", 0, 1).as_deref(), Some("0x100"));
+        assert_eq!(changed("0x00FE", 2, 1).as_deref(), Some("0x00FF"));
+        assert_eq!(changed("0x0", 0, -1).as_deref(), Some("0xffffffffffffffff"));
+        assert_eq!(changed("mask 0b0111", 0, 1).as_deref(), Some("mask 0b1000"));
+        assert_eq!(changed("no numbers", 0, 1), None);
+        assert_eq!(changed("1 then text", 2, 1), None);
+    }
+}
@@ -0,0 +1,29 @@
+use anyhow::Result;
+use crossterm::event::{self, Event};
+use std::time::Duration;
+
+/// Longest stretch of queued input handled before the screen is redrawn
+///
+/// Held keys repeat faster than frames can be drawn. Handling everything
+/// already queued before drawing again keeps the cursor from running on
+/// after the key is released, and caps redraws at about 60 a second.
+pub const FRAME_BUDGET: Duration = Duration::from_millis(16);
+
+/// Where input events come from
+pub trait EventSource {
+    /// The next event, if one arrives within `timeout`
+    fn next(&mut self, timeout: Duration) -> Result<Option<Event>>;
+}
+
+/// Events read from the terminal
+pub struct TerminalEvents;
+
+impl EventSource for TerminalEvents {
+    fn next(&mut self, timeout: Duration) -> Result<Option<Event>> {
+        if event::poll(timeout)? {
+            Ok(Some(event::read()?))
+        } else {
+            Ok(None)
+        }
+    }
+}
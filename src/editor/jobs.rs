@@ -0,0 +1,306 @@
+use anyhow::{anyhow, Context, Result};
+use std::collections::VecDeque;
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Most output lines kept for a job; older ones are dropped
+const MAX_OUTPUT_LINES: usize = 10_000;
+
+/// Where a background job is in its life
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobStatus {
+    Running,
+    /// Exited by itself, with its status code unless a signal ended it
+    Exited(Option<i32>),
+    Killed,
+}
+
+impl JobStatus {
+    pub fn label(self) -> String {
+        match self {
+            JobStatus::Running => "running".to_string(),
+            JobStatus::Exited(Some(0)) => "done".to_string(),
+            JobStatus::Exited(Some(code)) => format!("exit {}", code),
+            JobStatus::Exited(None) => "signalled".to_string(),
+            JobStatus::Killed => "killed".to_string(),
+        }
+    }
+}
+
+/// A shell command started with `:!command &`, running while editing goes on
+pub struct Job {
+    pub id: usize,
+    pub command: String,
+    pub dir: PathBuf,
+    /// Variables set for it on top of zim's environment
+    pub env: Vec<(String, String)>,
+    pub status: JobStatus,
+    started: Instant,
+    finished: Option<Instant>,
+    /// Lines of stdout and stderr as they arrive, filled by reader threads
+    output: Arc<Mutex<VecDeque<String>>>,
+    /// Lines read so far, counting those dropped past `MAX_OUTPUT_LINES`
+    received: Arc<AtomicUsize>,
+    /// The lines read and whole seconds run as of the last `take_changed`
+    shown: (usize, u64),
+    child: Option<Child>,
+}
+
+impl Job {
+    /// How long it's been running, or ran for
+    pub fn runtime(&self) -> Duration {
+        self.finished.unwrap_or_else(Instant::now) - self.started
+    }
+
+    /// Everything it printed so far
+    pub fn output(&self) -> Vec<String> {
+        self.output.lock().map(|lines| lines.iter().cloned().collect()).unwrap_or_default()
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.status == JobStatus::Running
+    }
+}
+
+/// Background jobs, listed by `:jobs` until the editor quits, which kills
+/// any still running
+#[derive(Default)]
+pub struct Jobs {
+    jobs: Vec<Job>,
+    next_id: usize,
+}
+
+impl Jobs {
+    /// Start `command` through the shell in `dir` with the variables in
+    /// `env`, returning its id
+    pub fn spawn(&mut self, command: &str, dir: &Path, env: &[(String, String)]) -> Result<usize> {
+        self.next_id += 1;
+        let job = start(self.next_id, command, dir, env)?;
+        self.jobs.push(job);
+        Ok(self.next_id)
+    }
+
+    pub fn list(&self) -> &[Job] {
+        &self.jobs
+    }
+
+    pub fn get(&self, id: usize) -> Option<&Job> {
+        self.jobs.iter().find(|job| job.id == id)
+    }
+
+    pub fn running(&self) -> usize {
+        self.jobs.iter().filter(|job| job.is_running()).count()
+    }
+
+    /// Whether anything shown about the jobs changed since the last call:
+    /// one printed a line, or a running one's runtime reached a new second
+    pub fn take_changed(&mut self) -> bool {
+        let mut changed = false;
+        for job in &mut self.jobs {
+            let seconds = if job.is_running() { job.runtime().as_secs() } else { job.shown.1 };
+            let now = (job.received.load(Ordering::Relaxed), seconds);
+            if now != job.shown {
+                job.shown = now;
+                changed = true;
+            }
+        }
+        changed
+    }
+
+    /// Note jobs that exited since the last poll, returning their ids
+    pub fn poll(&mut self) -> Vec<usize> {
+        let mut finished = Vec::new();
+        for job in &mut self.jobs {
+            let Some(child) = &mut job.child else { continue };
+            if let Ok(Some(status)) = child.try_wait() {
+                job.status = JobStatus::Exited(status.code());
+                job.finished = Some(Instant::now());
+                job.child = None;
+                finished.push(job.id);
+            }
+        }
+        finished
+    }
+
+    pub fn kill(&mut self, id: usize) -> Result<()> {
+        let job = self.jobs.iter_mut().find(|job| job.id == id).ok_or_else(|| anyhow!("No job {}", id))?;
+        let Some(mut child) = job.child.take() else {
+            return Err(anyhow!("Job {} isn't running", id));
+        };
+        kill_group(&mut child);
+        job.status = JobStatus::Killed;
+        job.finished = Some(Instant::now());
+        Ok(())
+    }
+
+    /// Run a job's command again in its place, with the same environment,
+    /// killing it first if it's still running
+    pub fn restart(&mut self, id: usize) -> Result<()> {
+        let index = self.jobs.iter().position(|job| job.id == id).ok_or_else(|| anyhow!("No job {}", id))?;
+        if self.jobs[index].is_running() {
+            self.kill(id)?;
+        }
+        let job = &self.jobs[index];
+        let (command, dir, env) = (job.command.clone(), job.dir.clone(), job.env.clone());
+        self.jobs[index] = start(id, &command, &dir, &env)?;
+        Ok(())
+    }
+}
+
+impl Drop for Jobs {
+    fn drop(&mut self) {
+        for job in &mut self.jobs {
+            if let Some(mut child) = job.child.take() {
+                kill_group(&mut child);
+            }
+        }
+    }
+}
+
+fn start(id: usize, command: &str, dir: &Path, env: &[(String, String)]) -> Result<Job> {
+    let mut shell = Command::new("sh");
+    shell.arg("-c")
+        .arg(command)
+        .current_dir(dir)
+        .envs(env.iter().map(|(name, value)| (name, value)))
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    // In a process group of its own, so whatever it starts is killed with it
+    #[cfg(unix)]
+    std::os::unix::process::CommandExt::process_group(&mut shell, 0);
+    let mut child = shell.spawn().with_context(|| format!("Failed to start {}", command))?;
+
+    let output = Arc::new(Mutex::new(VecDeque::new()));
+    let received = Arc::new(AtomicUsize::new(0));
+    if let Some(stdout) = child.stdout.take() {
+        collect_lines(stdout, output.clone(), received.clone());
+    }
+    if let Some(stderr) = child.stderr.take() {
+        collect_lines(stderr, output.clone(), received.clone());
+    }
+    Ok(Job {
+        id,
+        command: command.to_string(),
+        dir: dir.to_path_buf(),
+        env: env.to_vec(),
+        status: JobStatus::Running,
+        started: Instant::now(),
+        finished: None,
+        output,
+        received,
+        shown: (0, 0),
+        child: Some(child),
+    })
+}
+
+/// Kill a job's shell and every process in its group, like the servers or
+/// watchers it started
+fn kill_group(child: &mut Child) {
+    #[cfg(unix)]
+    let _ = Command::new("kill")
+        .args(["-s", "KILL", "--", &format!("-{}", child.id())])
+        .stderr(Stdio::null())
+        .status();
+    let _ = child.kill();
+    let _ = child.wait();
+}
+
+/// Append each line read from `pipe` to `output` on a thread of its own,
+/// counting them in `received`
+fn collect_lines(pipe: impl Read + Send + 'static, output: Arc<Mutex<VecDeque<String>>>, received: Arc<AtomicUsize>) {
+    std::thread::spawn(move || {
+        for line in BufReader::new(pipe).lines() {
+            let Ok(line) = line else { break };
+            let Ok(mut lines) = output.lock() else { break };
+            if lines.len() >= MAX_OUTPUT_LINES {
+                lines.pop_front();
+            }
+            lines.push_back(line);
+            received.fetch_add(1, Ordering::Relaxed);
+        }
+    });
+}
+
+/// A duration as `1h02m`, `3m05s` or `12s`
+pub fn format_runtime(runtime: Duration) -> String {
+    let seconds = runtime.as_secs();
+    match seconds {
+        0..=59 => format!("{}s", seconds),
+        60..=3599 => format!("{}m{:02}s", seconds / 60, seconds % 60),
+        _ => format!("{}h{:02}m", seconds / 3600, seconds / 60 % 60),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wait_for(jobs: &mut Jobs, id: usize) {
+        let started = Instant::now();
+        while jobs.get(id).unwrap().is_running() && started.elapsed() < Duration::from_secs(10) {
+            jobs.poll();
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn test_jobs_run_in_the_background_and_can_be_killed_or_restarted() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut jobs = Jobs::default();
+
+        let env = vec![("GREETING".to_string(), "hi".to_string())];
+        let echo = jobs.spawn("pwd; echo $GREETING; echo oops >&2; exit 3", dir.path(), &env)?;
+        wait_for(&mut jobs, echo);
+        let job = jobs.get(echo).unwrap();
+        assert_eq!(job.status, JobStatus::Exited(Some(3)));
+        assert_eq!(job.status.label(), "exit 3");
+        // Readers may still be finishing after the exit is seen
+        std::thread::sleep(Duration::from_millis(50));
+        let mut output = job.output();
+        output.sort();
+        assert_eq!(output.len(), 3);
+        // It runs in the directory it was given
+        let pwd = dir.path().canonicalize()?.to_string_lossy().to_string();
+        assert_eq!(output, [pwd.as_str(), "hi", "oops"]);
+        // What it printed is news once
+        assert!(jobs.take_changed());
+        assert!(!jobs.take_changed());
+
+        let sleeper = jobs.spawn("sleep 30", dir.path(), &[])?;
+        assert_eq!(jobs.running(), 1);
+        jobs.kill(sleeper)?;
+        assert_eq!(jobs.get(sleeper).unwrap().status, JobStatus::Killed);
+        assert!(jobs.kill(sleeper).is_err());
+
+        jobs.restart(echo)?;
+        assert_eq!(jobs.list().len(), 2);
+        wait_for(&mut jobs, echo);
+        assert_eq!(jobs.get(echo).unwrap().status, JobStatus::Exited(Some(3)));
+        assert!(jobs.get(echo).unwrap().output().contains(&"hi".to_string()));
+
+        // Killing a job stops what its shell started too
+        let server = jobs.spawn("sleep 30 & echo $!; wait", dir.path(), &[])?;
+        let started = Instant::now();
+        while jobs.get(server).unwrap().output().is_empty() && started.elapsed() < Duration::from_secs(10) {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        let pid = jobs.get(server).unwrap().output()[0].clone();
+        jobs.kill(server)?;
+        let alive = || std::fs::read_to_string(format!("/proc/{}/stat", pid)).is_ok_and(|stat| !stat.contains(") Z "));
+        let started = Instant::now();
+        while alive() && started.elapsed() < Duration::from_secs(5) {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert!(!alive());
+
+        assert_eq!(format_runtime(Duration::from_secs(7)), "7s");
+        assert_eq!(format_runtime(Duration::from_secs(185)), "3m05s");
+        assert_eq!(format_runtime(Duration::from_secs(3720)), "1h02m");
+        Ok(())
+    }
+}
@@ -0,0 +1,318 @@
+use anyhow::{anyhow, Result};
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// One key press in a mapped sequence, ignoring Shift on characters since
+/// the character's case already says whether it was held
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct KeyStroke {
+    code: KeyCode,
+    ctrl: bool,
+    alt: bool,
+}
+
+impl KeyStroke {
+    fn from_event(event: &KeyEvent) -> Self {
+        Self {
+            code: event.code,
+            ctrl: event.modifiers.contains(KeyModifiers::CONTROL),
+            alt: event.modifiers.contains(KeyModifiers::ALT),
+        }
+    }
+
+    /// The key press this stands for, with Shift held for capital letters
+    /// the way terminals report them
+    pub fn to_event(self) -> KeyEvent {
+        let mut modifiers = KeyModifiers::NONE;
+        modifiers.set(KeyModifiers::CONTROL, self.ctrl);
+        modifiers.set(KeyModifiers::ALT, self.alt);
+        modifiers.set(KeyModifiers::SHIFT, matches!(self.code, KeyCode::Char(c) if c.is_uppercase()));
+        KeyEvent::new(self.code, modifiers)
+    }
+
+    /// The key in the notation used to write sequences, like `f`, `<space>`
+    /// or `<C-s>`
+    pub fn label(&self) -> String {
+        let name = match self.code {
+            KeyCode::Char(' ') => "space".to_string(),
+            KeyCode::Char('<') => "lt".to_string(),
+            KeyCode::Char('>') if self.ctrl || self.alt => "gt".to_string(),
+            KeyCode::Char(c) if !self.ctrl && !self.alt => return c.to_string(),
+            KeyCode::Char(c) => c.to_string(),
+            KeyCode::Enter => "cr".to_string(),
+            KeyCode::Esc => "esc".to_string(),
+            KeyCode::Tab => "tab".to_string(),
+            KeyCode::Backspace => "bs".to_string(),
+            KeyCode::F(n) => format!("F{}", n),
+            other => format!("{:?}", other).to_lowercase(),
+        };
+        let prefix = match (self.ctrl, self.alt) {
+            (true, true) => "C-A-",
+            (true, false) => "C-",
+            (false, true) => "A-",
+            (false, false) => "",
+        };
+        format!("<{}{}>", prefix, name)
+    }
+}
+
+/// Parse a key sequence like `<leader>ff`, `gd` or `<C-w>v`
+pub fn parse_sequence(text: &str, leader: &[KeyStroke]) -> Result<Vec<KeyStroke>> {
+    let mut strokes = Vec::new();
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        if c == '<' {
+            if let Some(end) = rest.find('>') {
+                let name = &rest[1..end];
+                if name.eq_ignore_ascii_case("leader") {
+                    strokes.extend_from_slice(leader);
+                } else {
+                    strokes.push(parse_special(name).ok_or_else(|| anyhow!("Unknown key <{}> in \"{}\"", name, text))?);
+                }
+                rest = &rest[end + 1..];
+                continue;
+            }
+        }
+        strokes.push(KeyStroke { code: KeyCode::Char(c), ctrl: false, alt: false });
+        rest = &rest[c.len_utf8()..];
+    }
+    if strokes.is_empty() {
+        return Err(anyhow!("Empty key sequence"));
+    }
+    Ok(strokes)
+}
+
+/// A key written inside angle brackets, like `space`, `C-s` or `F5`
+fn parse_special(name: &str) -> Option<KeyStroke> {
+    let mut stroke = KeyStroke { code: KeyCode::Null, ctrl: false, alt: false };
+    let mut key = name;
+    loop {
+        let lower = key.to_ascii_lowercase();
+        if lower.starts_with("c-") && key.len() > 2 {
+            stroke.ctrl = true;
+        } else if (lower.starts_with("a-") || lower.starts_with("m-")) && key.len() > 2 {
+            stroke.alt = true;
+        } else {
+            break;
+        }
+        key = &key[2..];
+    }
+
+    stroke.code = match key.to_ascii_lowercase().as_str() {
+        "space" => KeyCode::Char(' '),
+        "lt" => KeyCode::Char('<'),
+        "gt" => KeyCode::Char('>'),
+        "cr" | "enter" | "return" => KeyCode::Enter,
+        "esc" => KeyCode::Esc,
+        "tab" => KeyCode::Tab,
+        "bs" | "backspace" => KeyCode::Backspace,
+        "up" => KeyCode::Up,
+        "down" => KeyCode::Down,
+        "left" => KeyCode::Left,
+        "right" => KeyCode::Right,
+        "home" => KeyCode::Home,
+        "end" => KeyCode::End,
+        lower if lower.starts_with('f') && lower.len() > 1 => KeyCode::F(lower[1..].parse().ok()?),
+        _ => {
+            let mut chars = key.chars();
+            let c = chars.next()?;
+            if chars.next().is_some() {
+                return None;
+            }
+            // Ctrl combinations arrive as lowercase letters
+            KeyCode::Char(if stroke.ctrl { c.to_ascii_lowercase() } else { c })
+        },
+    };
+    Some(stroke)
+}
+
+#[derive(Debug, Default)]
+struct Node {
+    action: Option<String>,
+    children: HashMap<KeyStroke, Node>,
+}
+
+/// What to do with the keys fed to a [`Keymap`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeymapStep {
+    /// Run a command bound to a completed sequence
+    Action(String),
+    /// Handle the key as if there were no keymap
+    Key(KeyEvent),
+}
+
+/// Key sequences stored in a trie, matched a key at a time
+#[derive(Debug, Default)]
+pub struct Keymap {
+    root: Node,
+    pending: Vec<KeyEvent>,
+    pending_since: Option<Instant>,
+}
+
+impl Keymap {
+    /// Build a keymap from sequences mapped to commands, returning it with
+    /// the errors for any sequences that couldn't be parsed
+    pub fn new(sequences: &HashMap<String, String>, leader: &str) -> (Self, Vec<String>) {
+        let mut keymap = Keymap::default();
+        let mut errors = Vec::new();
+        // The leader is a single key, written as a character or a name
+        // with or without angle brackets, like "\\", "space" or "<space>"
+        let parsed = if leader.chars().count() == 1 || leader.starts_with('<') {
+            parse_sequence(leader, &[]).ok()
+        } else {
+            parse_special(leader).map(|stroke| vec![stroke])
+        };
+        let leader = parsed.unwrap_or_else(|| {
+            errors.push(format!("Invalid leader key: {}", leader));
+            vec![KeyStroke { code: KeyCode::Char(' '), ctrl: false, alt: false }]
+        });
+
+        let mut sorted: Vec<_> = sequences.iter().collect();
+        sorted.sort();
+        for (sequence, action) in sorted {
+            match parse_sequence(sequence, &leader) {
+                Ok(strokes) => {
+                    let mut node = &mut keymap.root;
+                    for stroke in strokes {
+                        node = node.children.entry(stroke).or_default();
+                    }
+                    node.action = Some(action.clone());
+                },
+                Err(e) => errors.push(format!("Invalid key sequence \"{}\": {}", sequence, e)),
+            }
+        }
+        (keymap, errors)
+    }
+
+    fn node(&self, keys: &[KeyEvent]) -> Option<&Node> {
+        keys.iter().try_fold(&self.root, |node, key| node.children.get(&KeyStroke::from_event(key)))
+    }
+
+    /// Feed a key press, returning what to do now; nothing while the key
+    /// continues a sequence that might still be completed
+    pub fn feed(&mut self, key: KeyEvent) -> Vec<KeymapStep> {
+        self.pending.push(key);
+        match self.node(&self.pending) {
+            Some(node) if !node.children.is_empty() => {
+                self.pending_since = Some(Instant::now());
+                Vec::new()
+            },
+            Some(node) => {
+                let action = node.action.clone().unwrap_or_default();
+                self.pending.clear();
+                self.pending_since = None;
+                vec![KeymapStep::Action(action)]
+            },
+            None => {
+                self.pending.pop();
+                if self.pending.is_empty() {
+                    return vec![KeymapStep::Key(key)];
+                }
+                let mut steps = self.flush();
+                steps.extend(self.feed(key));
+                steps
+            },
+        }
+    }
+
+    /// Give up waiting for the rest of a sequence: run the command bound to
+    /// the keys typed so far, or hand them back unmapped
+    pub fn flush(&mut self) -> Vec<KeymapStep> {
+        let action = self.node(&self.pending).and_then(|node| node.action.clone());
+        let pending = std::mem::take(&mut self.pending);
+        self.pending_since = None;
+        match action {
+            Some(action) => vec![KeymapStep::Action(action)],
+            None => pending.into_iter().map(KeymapStep::Key).collect(),
+        }
+    }
+
+    /// Whether a key starts one of the sequences
+    pub fn starts_sequence(&self, key: &KeyEvent) -> bool {
+        self.root.children.contains_key(&KeyStroke::from_event(key))
+    }
+
+    pub fn is_pending(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    /// Whether a started sequence has waited longer than `timeout`
+    pub fn timed_out(&self, timeout: Duration) -> bool {
+        self.pending_since.is_some_and(|since| since.elapsed() >= timeout)
+    }
+
+    /// The keys typed so far, as written in a sequence
+    pub fn pending_keys(&self) -> String {
+        self.pending.iter().map(|key| KeyStroke::from_event(key).label()).collect()
+    }
+
+    /// Keys that can follow the ones typed so far, with the command each
+    /// runs, or "+N" for the number of sequences a prefix leads to
+    pub fn options(&self) -> Vec<(String, String)> {
+        let Some(node) = self.node(&self.pending).filter(|_| self.is_pending()) else { return Vec::new() };
+        let mut options: Vec<(String, String)> = node.children.iter()
+            .map(|(stroke, child)| {
+                let description = match (&child.action, child.children.is_empty()) {
+                    (Some(action), true) => action.clone(),
+                    _ => format!("+{}", count_actions(child)),
+                };
+                (stroke.label(), description)
+            })
+            .collect();
+        options.sort();
+        options
+    }
+}
+
+fn count_actions(node: &Node) -> usize {
+    usize::from(node.action.is_some()) + node.children.values().map(count_actions).sum::<usize>()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(c: char) -> KeyEvent {
+        KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE)
+    }
+
+    fn keymap(sequences: &[(&str, &str)]) -> Keymap {
+        let sequences = sequences.iter().map(|(keys, action)| (keys.to_string(), action.to_string())).collect();
+        let (keymap, errors) = Keymap::new(&sequences, "<space>");
+        assert!(errors.is_empty(), "{:?}", errors);
+        keymap
+    }
+
+    #[test]
+    fn test_parse_sequence() {
+        let leader = parse_sequence(",", &[]).unwrap();
+        let strokes = parse_sequence("<leader>f<C-s><space><A-gt>", &leader).unwrap();
+        let labels: Vec<String> = strokes.iter().map(KeyStroke::label).collect();
+        assert_eq!(labels, [",", "f", "<C-s>", "<space>", "<A-gt>"]);
+        assert!(parse_sequence("<nope>", &leader).is_err());
+    }
+
+    #[test]
+    fn test_matches_sequences_and_replays_the_rest() {
+        let mut keymap = keymap(&[("<leader>ff", "find_file"), ("<leader>fs", ":w"), ("gd", "definition"), ("g", "top")]);
+
+        assert_eq!(keymap.feed(key(' ')), []);
+        assert_eq!(keymap.feed(key('f')), []);
+        assert_eq!(keymap.pending_keys(), "<space>f");
+        assert_eq!(keymap.options(), [("f".to_string(), "find_file".to_string()), ("s".to_string(), ":w".to_string())]);
+        assert_eq!(keymap.feed(key('f')), [KeymapStep::Action("find_file".to_string())]);
+        assert!(!keymap.is_pending());
+
+        // An unmapped key hands back the prefix and itself
+        keymap.feed(key(' '));
+        assert_eq!(keymap.feed(key('j')), [KeymapStep::Key(key(' ')), KeymapStep::Key(key('j'))]);
+
+        // A prefix with its own command runs it when the sequence ends early
+        keymap.feed(key('g'));
+        assert_eq!(keymap.feed(key('x')), [KeymapStep::Action("top".to_string()), KeymapStep::Key(key('x'))]);
+        keymap.feed(key('g'));
+        assert!(keymap.timed_out(Duration::ZERO));
+        assert_eq!(keymap.flush(), [KeymapStep::Action("top".to_string())]);
+    }
+}
@@ -0,0 +1,288 @@
+use super::compiler;
+use super::diagnostics::{Diagnostic, DiagnosticCollection, DiagnosticSeverity, TextSpan};
+use super::ids::BufferId;
+use super::runner::quote;
+use crate::config::LinterConfig;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::time::Instant;
+
+/// Extensions a `[linters.<name>]` entry covers when it doesn't list its
+/// own: the usual ones for a language name, or the name itself
+fn default_extensions(name: &str) -> Vec<&str> {
+    match name {
+        "python" => vec!["py", "pyi"],
+        "javascript" => vec!["js", "jsx", "mjs", "cjs"],
+        "typescript" => vec!["ts", "tsx", "mts", "cts"],
+        "go" => vec!["go"],
+        "ruby" => vec!["rb"],
+        "shell" => vec!["sh", "bash"],
+        "c" => vec!["c", "h"],
+        "cpp" => vec!["cc", "cpp", "cxx", "hpp"],
+        name => vec![name],
+    }
+}
+
+/// The linter configured for the file at `path`, with its name
+pub fn linter_for_path<'a>(linters: &'a HashMap<String, LinterConfig>, path: &str) -> Option<(&'a str, &'a LinterConfig)> {
+    let extension = Path::new(path).extension()?.to_str()?;
+    let mut names: Vec<&String> = linters.keys().collect();
+    // Pick the same one every time when two cover an extension
+    names.sort();
+    names.into_iter().find_map(|name| {
+        let linter = &linters[name];
+        let covers = match &linter.extensions {
+            extensions if extensions.is_empty() => default_extensions(name).contains(&extension),
+            extensions => extensions.iter().any(|covered| covered.trim_start_matches('.') == extension),
+        };
+        covers.then_some((name.as_str(), linter))
+    })
+}
+
+/// The shell command that lints `file`: `{file}` in the configured command
+/// stands for its path, which is added at the end when it isn't used
+pub fn command_line(linter: &LinterConfig, file: &str) -> String {
+    if linter.command.contains("{file}") {
+        linter.command.replace("{file}", &quote(file))
+    } else {
+        format!("{} {}", linter.command, quote(file))
+    }
+}
+
+/// A linter running in the background on a buffer that was saved, so a
+/// slow one doesn't hold up writing
+pub struct Run {
+    pub buffer: BufferId,
+    pub name: String,
+    pub linter: LinterConfig,
+    pub root: PathBuf,
+    pub file: String,
+    pub command_line: String,
+    pub started: Instant,
+    receiver: Receiver<std::io::Result<Output>>,
+}
+
+impl Run {
+    /// Start `command_line` in `root` with `env` on a thread of its own
+    pub fn start(buffer: BufferId, name: String, linter: LinterConfig, root: PathBuf, file: String, env: Vec<(String, String)>) -> Self {
+        let command_line = command_line(&linter, &file);
+        let (sender, receiver) = mpsc::channel();
+        let (command, dir) = (command_line.clone(), root.clone());
+        std::thread::spawn(move || {
+            let _ = sender.send(Command::new("sh").arg("-c").arg(&command).current_dir(&dir).envs(env).output());
+        });
+        Run { buffer, name, linter, root, file, command_line, started: Instant::now(), receiver }
+    }
+
+    /// The linter's output once it has finished, or None while it's running
+    pub fn poll(&self) -> Option<std::io::Result<Output>> {
+        match self.receiver.try_recv() {
+            Ok(output) => Some(output),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => Some(Err(std::io::Error::other("the linter stopped"))),
+        }
+    }
+}
+
+/// Parse a linter's output into diagnostics for the current file
+///
+/// With `format = "json"` (or no format, when the output looks like JSON)
+/// any object with a message and a line is a diagnostic, which covers the
+/// JSON reports of ruff, eslint, golangci-lint, shellcheck and most others.
+/// Otherwise the output is read as `file:line:col: message` lines.
+pub fn parse_output(linter: &LinterConfig, output: &str, root: &Path, current_file: &str) -> DiagnosticCollection {
+    let json = match linter.format.as_deref() {
+        Some(format) => format == "json",
+        None => output.trim_start().starts_with(['[', '{']),
+    };
+    if !json {
+        // Lints without a severity are warnings, not errors as with compilers
+        return compiler::parse_lines(output, root, current_file, DiagnosticSeverity::Warning);
+    }
+
+    let mut diagnostics = DiagnosticCollection::new();
+    let Ok(current) = Path::new(current_file).canonicalize() else { return diagnostics };
+    // Some linters print one JSON object per line instead of one document
+    let values: Vec<Value> = match serde_json::from_str(output.trim()) {
+        Ok(value) => vec![value],
+        Err(_) => output.lines().filter_map(|line| serde_json::from_str(line).ok()).collect(),
+    };
+    let mut found = Vec::new();
+    for value in &values {
+        collect(value, None, &mut found);
+    }
+    for (file, diagnostic) in found {
+        let same_file = match file {
+            Some(file) => root.join(&file).canonicalize().is_ok_and(|path| path == current),
+            // A report without file names is about the file it was given
+            None => true,
+        };
+        if same_file {
+            diagnostics.add_diagnostic(diagnostic);
+        }
+    }
+    diagnostics
+}
+
+const FILE_KEYS: [&str; 6] = ["filename", "filePath", "file", "path", "Filename", "file_name"];
+const MESSAGE_KEYS: [&str; 5] = ["message", "text", "Text", "msg", "description"];
+const LINE_KEYS: [&str; 4] = ["line", "row", "Line", "lineNumber"];
+const COLUMN_KEYS: [&str; 4] = ["column", "col", "Column", "character"];
+const END_LINE_KEYS: [&str; 3] = ["endLine", "end_line", "EndLine"];
+const END_COLUMN_KEYS: [&str; 3] = ["endColumn", "end_column", "EndColumn"];
+/// Objects a position can be nested in, like ruff's `location`
+const START_KEYS: [&str; 5] = ["location", "start", "Pos", "pos", "position"];
+const END_KEYS: [&str; 3] = ["end_location", "end", "End"];
+const SEVERITY_KEYS: [&str; 5] = ["severity", "level", "Severity", "type", "kind"];
+const CODE_KEYS: [&str; 6] = ["code", "ruleId", "rule", "symbol", "FromLinter", "check_name"];
+
+/// Walk a JSON report, collecting each diagnostic with the file it's in,
+/// which may be given by an enclosing object as in eslint's reports
+fn collect(value: &Value, file: Option<&str>, found: &mut Vec<(Option<String>, Diagnostic)>) {
+    match value {
+        Value::Array(values) => {
+            for value in values {
+                collect(value, file, found);
+            }
+        },
+        Value::Object(object) => {
+            let start = START_KEYS.iter().find_map(|key| object.get(*key)).filter(|start| start.is_object());
+            let file = string(value, &FILE_KEYS)
+                .or_else(|| start.and_then(|start| string(start, &FILE_KEYS)))
+                .or(file);
+            if let Some(diagnostic) = diagnostic(value, start) {
+                let diagnostic = match file {
+                    Some(file) => diagnostic.with_file_path(file),
+                    None => diagnostic,
+                };
+                found.push((file.map(str::to_string), diagnostic));
+                return;
+            }
+            for value in object.values() {
+                collect(value, file, found);
+            }
+        },
+        _ => {},
+    }
+}
+
+/// The diagnostic a JSON object describes, if it has a message and a line
+fn diagnostic(object: &Value, start: Option<&Value>) -> Option<Diagnostic> {
+    let message = string(object, &MESSAGE_KEYS)?;
+    // The position is in the object itself, or in one nested in it
+    let position = [Some(object), start].into_iter().flatten()
+        .find(|position| number(position, &LINE_KEYS).is_some())?;
+    let line = number(position, &LINE_KEYS)?.max(1);
+    let column = number(position, &COLUMN_KEYS).unwrap_or(1).max(1);
+    let end = END_KEYS.iter().find_map(|key| object.get(*key));
+    let (end_line, end_column) = match end {
+        Some(end) => (number(end, &LINE_KEYS), number(end, &COLUMN_KEYS)),
+        None => (number(object, &END_LINE_KEYS), number(object, &END_COLUMN_KEYS)),
+    };
+    let end_column = match end_column {
+        Some(end_column) if end_line.unwrap_or(line) == line && end_column > column => end_column - 1,
+        _ => column,
+    };
+
+    let message = match string(object, &CODE_KEYS) {
+        Some(code) => format!("{} ({})", message, code),
+        None => message.to_string(),
+    };
+    Some(Diagnostic::new(&message, severity(object), TextSpan::new(line - 1, column - 1, end_column))
+        .with_original_line(line))
+}
+
+/// The severity a linter gave, as a word or as eslint's 1 and 2; lints
+/// are warnings unless they say otherwise
+fn severity(object: &Value) -> DiagnosticSeverity {
+    let Some(value) = SEVERITY_KEYS.iter().find_map(|key| object.get(*key)) else { return DiagnosticSeverity::Warning };
+    if let Some(level) = value.as_u64() {
+        return if level >= 2 { DiagnosticSeverity::Error } else { DiagnosticSeverity::Warning };
+    }
+    let word = value.as_str().unwrap_or_default().to_lowercase();
+    if word.contains("error") || word.contains("fatal") {
+        DiagnosticSeverity::Error
+    } else if word.contains("info") || word.contains("note") || word.contains("convention") || word.contains("refactor") {
+        DiagnosticSeverity::Information
+    } else if word.contains("hint") || word.contains("style") {
+        DiagnosticSeverity::Hint
+    } else {
+        DiagnosticSeverity::Warning
+    }
+}
+
+fn string<'a>(object: &'a Value, keys: &[&str]) -> Option<&'a str> {
+    keys.iter().find_map(|key| object.get(*key)?.as_str())
+}
+
+/// A number given as one, or as a string of digits
+fn number(object: &Value, keys: &[&str]) -> Option<usize> {
+    keys.iter().find_map(|key| match object.get(*key)? {
+        Value::Number(number) => number.as_u64().map(|number| number as usize),
+        Value::String(text) => text.parse().ok(),
+        _ => None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn linter(command: &str, format: Option<&str>) -> LinterConfig {
+        LinterConfig { command: command.to_string(), extensions: Vec::new(), format: format.map(str::to_string) }
+    }
+
+    #[test]
+    fn test_linters_are_picked_by_extension() {
+        let mut linters = HashMap::new();
+        linters.insert("python".to_string(), linter("ruff check --output-format json", None));
+        linters.insert("lua".to_string(), LinterConfig { extensions: vec![".luau".to_string()], ..linter("selene", None) });
+        assert_eq!(linter_for_path(&linters, "app/main.py").map(|(name, _)| name), Some("python"));
+        assert_eq!(linter_for_path(&linters, "init.luau").map(|(name, _)| name), Some("lua"));
+        assert!(linter_for_path(&linters, "init.lua").is_none());
+
+        let (_, python) = linter_for_path(&linters, "main.py").unwrap();
+        assert_eq!(command_line(python, "my app/main.py"), "ruff check --output-format json 'my app/main.py'");
+        assert_eq!(command_line(&linter("eslint -f json {file} --quiet", None), "a.js"), "eslint -f json a.js --quiet");
+    }
+
+    #[test]
+    fn test_json_and_line_reports_parse() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("main.py"), "import os\n").unwrap();
+        fs::write(dir.path().join("other.py"), "").unwrap();
+        let current = dir.path().join("main.py");
+        let current = current.to_str().unwrap();
+
+        let ruff = r#"[
+            {"code": "F401", "filename": "main.py", "message": "`os` imported but unused",
+             "location": {"row": 1, "column": 8}, "end_location": {"row": 1, "column": 10}},
+            {"code": "E501", "filename": "other.py", "message": "Line too long",
+             "location": {"row": 1, "column": 1}, "end_location": {"row": 1, "column": 90}}
+        ]"#;
+        let diagnostics = parse_output(&linter("ruff", None), ruff, dir.path(), current);
+        let all = diagnostics.get_all_diagnostics();
+        assert_eq!(all.len(), 1);
+        assert_eq!(all[0].message, "`os` imported but unused (F401)");
+        assert_eq!(all[0].severity, DiagnosticSeverity::Warning);
+        assert_eq!((all[0].span.line, all[0].span.start_column, all[0].span.end_column), (0, 7, 9));
+
+        // eslint gives the file once for all of its messages
+        let eslint = r#"[{"filePath": "main.py", "messages": [
+            {"ruleId": "no-undef", "severity": 2, "message": "'x' is not defined.", "line": 1, "column": 1}
+        ]}]"#;
+        let diagnostics = parse_output(&linter("eslint", Some("json")), eslint, dir.path(), current);
+        let all = diagnostics.get_all_diagnostics();
+        assert_eq!((all[0].message.as_str(), all[0].severity.clone()), ("'x' is not defined. (no-undef)", DiagnosticSeverity::Error));
+
+        let lines = "main.py:1:1: error: bad import\nmain.py:1:8: unused import\nother.py:3:1: trailing space\n";
+        let diagnostics = parse_output(&linter("flake8", Some("lines")), lines, dir.path(), current);
+        let severities: Vec<_> = diagnostics.get_all_diagnostics().iter().map(|d| d.severity.clone()).collect();
+        assert_eq!(severities.len(), 2);
+        assert!(severities.contains(&DiagnosticSeverity::Error) && severities.contains(&DiagnosticSeverity::Warning));
+    }
+}
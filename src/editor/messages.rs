@@ -0,0 +1,155 @@
+use std::collections::VecDeque;
+
+/// Name of the scratch tab that shows the message log (`:messages`)
+pub const MESSAGES_BUFFER_NAME: &str = "[messages]";
+
+/// Name of the scratch tab that shows the last command output (`:copen`)
+pub const OUTPUT_BUFFER_NAME: &str = "[output]";
+
+/// Log of messages produced by ex commands, cargo runs and shell commands
+///
+/// Messages are kept around so they can be reviewed (and yanked) later
+/// from a read-only buffer instead of disappearing from the status line.
+pub struct MessageLog {
+    entries: VecDeque<String>,
+    max_entries: usize,
+    /// Full output of the last external command that was run
+    last_output: Option<CommandOutput>,
+    /// Values like API keys that are never kept, in messages or output
+    secrets: Vec<String>,
+}
+
+/// Captured output of an external command
+#[derive(Debug, Clone)]
+pub struct CommandOutput {
+    /// The command line that produced this output
+    pub command: String,
+    /// Combined stdout and stderr, split into lines
+    pub lines: Vec<String>,
+    /// Whether the command exited successfully
+    pub success: bool,
+}
+
+impl CommandOutput {
+    pub fn new(command: &str, stdout: &str, stderr: &str, success: bool) -> Self {
+        let lines = stdout
+            .lines()
+            .chain(stderr.lines())
+            .map(|line| line.to_string())
+            .collect();
+
+        Self {
+            command: command.to_string(),
+            lines,
+            success,
+        }
+    }
+}
+
+impl MessageLog {
+    pub fn new() -> Self {
+        Self {
+            entries: VecDeque::new(),
+            max_entries: 1000,
+            last_output: None,
+            secrets: Vec::new(),
+        }
+    }
+
+    /// Hide `secret` wherever it would appear from now on
+    pub fn add_secret(&mut self, secret: &str) {
+        // Very short values would blank out ordinary text
+        if secret.len() >= 4 && !self.secrets.iter().any(|known| known == secret) {
+            self.secrets.push(secret.to_string());
+        }
+    }
+
+    /// The text with every known secret replaced by `[redacted]`
+    pub fn redact(&self, text: &str) -> String {
+        self.secrets.iter().fold(text.to_string(), |text, secret| text.replace(secret.as_str(), "[redacted]"))
+    }
+
+    /// Append a message, dropping the oldest one if the log is full
+    pub fn push(&mut self, message: &str) {
+        // Multi-line messages are stored line by line so they render cleanly
+        for line in message.lines() {
+            self.entries.push_back(self.redact(line));
+        }
+
+        while self.entries.len() > self.max_entries {
+            self.entries.pop_front();
+        }
+    }
+
+    /// Get all messages, oldest first
+    pub fn entries(&self) -> impl Iterator<Item = &String> {
+        self.entries.iter()
+    }
+
+    /// Get the most recent message
+    pub fn last(&self) -> Option<&String> {
+        self.entries.back()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Remember the output of an external command for `:copen`
+    pub fn set_last_output(&mut self, mut output: CommandOutput) {
+        output.command = self.redact(&output.command);
+        for line in &mut output.lines {
+            *line = self.redact(line);
+        }
+        self.last_output = Some(output);
+    }
+
+    /// Get the output of the last external command, if any
+    pub fn last_output(&self) -> Option<&CommandOutput> {
+        self.last_output.as_ref()
+    }
+}
+
+impl Default for MessageLog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_push_splits_lines_and_caps_size() {
+        let mut log = MessageLog::new();
+        log.max_entries = 3;
+
+        log.push("first");
+        log.push("second\nthird");
+        assert_eq!(log.entries().count(), 3);
+        assert_eq!(log.last().unwrap(), "third");
+
+        log.push("fourth");
+        let entries: Vec<&String> = log.entries().collect();
+        assert_eq!(entries, vec!["second", "third", "fourth"]);
+    }
+
+    #[test]
+    fn test_secrets_are_redacted() {
+        let mut log = MessageLog::new();
+        log.add_secret("sk-12345");
+        log.add_secret("ab");
+        log.push("AI command failed: bad key sk-12345");
+        log.set_last_output(CommandOutput::new("env", "KEY=sk-12345\nTAB=ab\n", "", true));
+        assert_eq!(log.last().unwrap(), "AI command failed: bad key [redacted]");
+        assert_eq!(log.last_output().unwrap().lines, vec!["KEY=[redacted]", "TAB=ab"]);
+    }
+
+    #[test]
+    fn test_command_output_combines_streams() {
+        let output = CommandOutput::new("git status", "line 1\nline 2\n", "warning\n", false);
+        assert_eq!(output.lines, vec!["line 1", "line 2", "warning"]);
+        assert!(!output.success);
+    }
+}
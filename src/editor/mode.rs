@@ -21,12 +21,70 @@ pub enum Mode {
     FilenamePrompt,
     /// Reload confirmation mode (for confirming file reload)
     ReloadConfirm,
+    /// Overwrite confirmation mode (for confirming a save-as or rename onto an existing file)
+    OverwriteConfirm,
+    /// Delete confirmation mode (for confirming `:Delete` moving the buffer's file to the trash)
+    DeleteConfirm,
+    /// Trust confirmation mode (for allowing a workspace's commands to run)
+    TrustConfirm,
+    /// Quit confirmation mode (for saving or discarding unsaved changes before quitting or closing a tab)
+    QuitConfirm,
     /// Visual mode (for character-based selections)
     Visual,
     /// Visual Line mode (for line-based selections)
     VisualLine,
     /// Delete mode (for composable delete operations)
     Delete,
+    /// Change mode (for composable change operations, which delete and then insert)
+    Change,
+    /// Replace char mode (for replacing the character under the cursor with the next one typed, `r`)
+    ReplaceChar,
+    /// Find char mode (for the character to find on the line, `f`, `F`, `t`, `T`)
+    FindChar,
+    /// Replace mode (for typing over existing text, `R`)
+    Replace,
+    /// Set mark mode (for naming the mark to put on the cursor line, `m`)
+    SetMark,
+    /// Go to mark mode (for naming the mark to jump to, `'`)
+    GoToMark,
+    /// Operator mode (for the motion after a case, reflow or yank operator, `gu`, `gU`, `g~`, `gq`, `y`)
+    Operator,
+    /// Workspace edit preview mode (for reviewing a multi-file edit before applying it)
+    WorkspaceEditPreview,
+    /// Undo tree panel (for moving to any earlier or undone state, `:undotree`)
+    UndoTree,
+    /// Background jobs panel (for watching, restarting or killing `:!command &` jobs, `:jobs`)
+    Jobs,
+    /// Build output panel (for following a `:build` or `:run` and jumping to the locations in its output)
+    BuildOutput,
+    /// Command palette (for finding any command by name and running it, Ctrl+p)
+    CommandPalette,
+    /// Start dashboard (for picking a recent project or file, or an action, `:dashboard`)
+    Dashboard,
+    /// Directory listing (for browsing a directory and opening its files, `zim dir`, `:Explore`)
+    DirectoryListing,
+    /// Recent files picker (for reopening a file from an earlier session where it was left, `:recent`)
+    RecentFiles,
     /// Snake game mode (easter egg)
     Snake,
-}
\ No newline at end of file
+}
+/// The terminal cursor's shape, which shows the mode at a glance as in vim
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CursorShape {
+    Block,
+    Bar,
+    Underline,
+}
+
+impl Mode {
+    /// A bar where typing inserts text, an underline where it replaces it,
+    /// and a block everywhere else
+    pub fn cursor_shape(self) -> CursorShape {
+        match self {
+            Mode::Insert | Mode::Command | Mode::FilenamePrompt | Mode::FileFinder
+                | Mode::TokenSearch | Mode::CommandPalette => CursorShape::Bar,
+            Mode::Replace | Mode::ReplaceChar => CursorShape::Underline,
+            _ => CursorShape::Block,
+        }
+    }
+}
@@ -0,0 +1,114 @@
+/// How many lines at the start and at the end of a file are searched for
+/// modelines, as in vim
+pub const MODELINE_LINES: usize = 5;
+
+/// Options a file sets for itself in a vim modeline, like
+/// `# vim: set ts=2 sw=2 et:`, which override the config for its buffer
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Modeline {
+    pub tab_size: Option<usize>,
+    pub expand_tab: Option<bool>,
+    pub wrap: Option<bool>,
+    pub filetype: Option<String>,
+}
+
+impl Modeline {
+    /// The options set by the modelines in the first and last lines of a
+    /// file, later ones winning, or None when there are none
+    pub fn find(lines: &[String]) -> Option<Self> {
+        let tail = lines.len().saturating_sub(MODELINE_LINES).max(MODELINE_LINES.min(lines.len()));
+        let mut found = Modeline::default();
+        for line in lines[..MODELINE_LINES.min(lines.len())].iter().chain(&lines[tail..]) {
+            if let Some(modeline) = Self::parse(line) {
+                found.merge(modeline);
+            }
+        }
+        (found != Modeline::default()).then_some(found)
+    }
+
+    /// The options set by a modeline in `line`, in either of vim's forms:
+    /// `vim: ts=2 sw=2:` or `vim: set ts=2 sw=2:` followed by other text
+    ///
+    /// Options zim doesn't have are ignored, so only `tabstop`,
+    /// `shiftwidth`, `expandtab`, `wrap` and `filetype` (and their short
+    /// names) do anything.
+    pub fn parse(line: &str) -> Option<Self> {
+        let rest = ["vim:", "Vim:", "vi:", "ex:"].iter().find_map(|marker| {
+            line.match_indices(marker)
+                // The marker starts the line or follows a blank; ex: has to follow one
+                .find(|(at, _)| (*at == 0 && *marker != "ex:") || line[..*at].ends_with([' ', '\t']))
+                .map(|(at, _)| &line[at + marker.len()..])
+        })?;
+
+        let rest = rest.trim_start();
+        let options: Vec<&str> = match rest.strip_prefix("set ").or_else(|| rest.strip_prefix("se ")) {
+            // Up to the closing colon, anything after it being the comment's own
+            Some(rest) => rest.split(':').next().unwrap_or_default().split_whitespace().collect(),
+            None => rest.split([':', ' ', '\t']).filter(|option| !option.is_empty()).collect(),
+        };
+
+        let mut modeline = Modeline::default();
+        let mut shift_width = None;
+        for option in options {
+            let (name, value) = match option.split_once('=') {
+                Some((name, value)) => (name, Some(value)),
+                None => (option, None),
+            };
+            match (name, value) {
+                ("ts" | "tabstop", Some(value)) => modeline.tab_size = value.parse().ok().filter(|size| *size > 0),
+                // zim indents by tab_size, which is what shiftwidth is for in vim
+                ("sw" | "shiftwidth", Some(value)) => shift_width = value.parse().ok().filter(|size| *size > 0),
+                ("et" | "expandtab", None) => modeline.expand_tab = Some(true),
+                ("noet" | "noexpandtab", None) => modeline.expand_tab = Some(false),
+                ("wrap", None) => modeline.wrap = Some(true),
+                ("nowrap", None) => modeline.wrap = Some(false),
+                ("ft" | "filetype" | "syn" | "syntax", Some(value)) if !value.is_empty() => {
+                    modeline.filetype = Some(value.to_string());
+                },
+                _ => {},
+            }
+        }
+        modeline.tab_size = shift_width.or(modeline.tab_size);
+        Some(modeline)
+    }
+
+    fn merge(&mut self, other: Modeline) {
+        self.tab_size = other.tab_size.or(self.tab_size);
+        self.expand_tab = other.expand_tab.or(self.expand_tab);
+        self.wrap = other.wrap.or(self.wrap);
+        self.filetype = other.filetype.or(self.filetype.take());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_modelines_parse_in_both_forms() {
+        let modeline = Modeline::parse("# vim: set ts=8 sw=2 et ft=python: more comment").unwrap();
+        assert_eq!(modeline, Modeline {
+            tab_size: Some(2),
+            expand_tab: Some(true),
+            wrap: None,
+            filetype: Some("python".to_string()),
+        });
+        let modeline = Modeline::parse("// vi:noet:ts=4 nowrap").unwrap();
+        assert_eq!((modeline.tab_size, modeline.expand_tab, modeline.wrap), (Some(4), Some(false), Some(false)));
+        assert_eq!(Modeline::parse("vim: tw=72").unwrap(), Modeline::default());
+
+        assert!(Modeline::parse("novim:ts=2").is_none());
+        assert!(Modeline::parse("ex: ts=2").is_none());
+        assert!(Modeline::parse("let navi: ts=2").is_none());
+        assert!(Modeline::parse("set ts=2").is_none());
+
+        // Only the first and last lines count, the later ones winning
+        let mut lines: Vec<String> = (0..20).map(|i| format!("line {}", i)).collect();
+        lines[1] = "# vim: ts=2 et".to_string();
+        lines[10] = "# vim: nowrap".to_string();
+        lines[19] = "# vim: set ts=3:".to_string();
+        let modeline = Modeline::find(&lines).unwrap();
+        assert_eq!((modeline.tab_size, modeline.expand_tab, modeline.wrap), (Some(3), Some(true), None));
+        assert!(Modeline::find(&lines[2..19]).is_none());
+    }
+}
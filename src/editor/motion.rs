@@ -0,0 +1,228 @@
+/// Whether a line separates paragraphs: empty or only whitespace
+fn is_blank(line: &str) -> bool {
+    line.trim().is_empty()
+}
+
+/// Where a `{ } ( )` motion repeated `count` times goes from `position`
+/// (line, byte), or None for any other key
+pub fn target(lines: &[String], motion: char, mut position: (usize, usize), count: usize) -> Option<(usize, usize)> {
+    for _ in 0..count.max(1) {
+        let next = match motion {
+            '}' => paragraph_forward(lines, position.0),
+            '{' => paragraph_backward(lines, position.0),
+            ')' => sentence_forward(lines, position),
+            '(' => sentence_backward(lines, position),
+            _ => return None,
+        };
+        if next == position {
+            break;
+        }
+        position = next;
+    }
+    Some(position)
+}
+
+/// Where `f`, `F`, `t` or `T` (`kind`) looking for `target` lands on
+/// `line` from byte `x`: on its `count`th match after (`f`) or before
+/// (`F`) the cursor, or beside it (`t`, `T`). `repeat`ed by `;` or `,`,
+/// `t` and `T` skip a match right next to the cursor so they don't stay put.
+pub fn find_char(line: &str, x: usize, kind: char, target: char, count: usize, repeat: bool) -> Option<usize> {
+    let till = matches!(kind, 't' | 'T');
+    let skip = usize::from(till && repeat);
+    let nth = count.max(1) - 1;
+    match kind {
+        'f' | 't' => {
+            let (i, _) = line.char_indices().filter(|(i, _)| *i > x).skip(skip).filter(|(_, c)| *c == target).nth(nth)?;
+            Some(if till { line[..i].char_indices().next_back().map_or(i, |(before, _)| before) } else { i })
+        },
+        'F' | 'T' => {
+            let (i, c) = line.get(..x).unwrap_or(line).char_indices().rev().skip(skip).filter(|(_, c)| *c == target).nth(nth)?;
+            Some(if till { i + c.len_utf8() } else { i })
+        },
+        _ => None,
+    }
+}
+
+/// Where `}` goes from line `y`: the blank line after the paragraph, or
+/// the end of the last line
+pub fn paragraph_forward(lines: &[String], y: usize) -> (usize, usize) {
+    let mut i = y;
+    while i < lines.len() && is_blank(&lines[i]) {
+        i += 1;
+    }
+    while i < lines.len() && !is_blank(&lines[i]) {
+        i += 1;
+    }
+    if i < lines.len() {
+        return (i, 0);
+    }
+    let last = lines.len().saturating_sub(1);
+    (last, lines.get(last).map_or(0, String::len))
+}
+
+/// Where `{` goes from line `y`: the blank line before the paragraph, or
+/// the start of the first line
+pub fn paragraph_backward(lines: &[String], y: usize) -> (usize, usize) {
+    let mut i = y.min(lines.len().saturating_sub(1));
+    while i > 0 && is_blank(&lines[i]) {
+        i -= 1;
+    }
+    while i > 0 && !is_blank(&lines[i]) {
+        i -= 1;
+    }
+    (i, 0)
+}
+
+/// Where `)` goes: the start of the next sentence, or the end of the last
+/// line
+pub fn sentence_forward(lines: &[String], position: (usize, usize)) -> (usize, usize) {
+    let mut from = position.0.min(lines.len().saturating_sub(1));
+    while from > 0 && !is_blank(&lines[from - 1]) {
+        from -= 1;
+    }
+    let to = (paragraph_forward(lines, position.0).0 + 1).min(lines.len());
+    sentence_starts(lines, from, to).into_iter().find(|start| *start > position).unwrap_or_else(|| {
+        let last = lines.len().saturating_sub(1);
+        (last, lines.get(last).map_or(0, String::len))
+    })
+}
+
+/// Where `(` goes: the start of this sentence, or of the one before when
+/// already at the start
+pub fn sentence_backward(lines: &[String], position: (usize, usize)) -> (usize, usize) {
+    let mut from = position.0;
+    loop {
+        from = paragraph_backward(lines, from).0;
+        let starts = sentence_starts(lines, from, (position.0 + 1).min(lines.len()));
+        if let Some(start) = starts.into_iter().rev().find(|start| *start < position) {
+            return start;
+        }
+        if from == 0 {
+            return (0, 0);
+        }
+    }
+}
+
+/// Where sentences start on lines `from` to `to` (exclusive), with `from`
+/// the start of a paragraph or a blank line before one
+///
+/// A sentence ends at `.`, `!` or `?`, maybe followed by closing brackets
+/// or quotes, then a space or the end of the line. The first of a run of
+/// blank lines counts as a sentence too, as in Vim.
+fn sentence_starts(lines: &[String], from: usize, to: usize) -> Vec<(usize, usize)> {
+    let mut starts = Vec::new();
+    let mut after_blank = from > 0 && lines.get(from - 1).is_some_and(|line| is_blank(line));
+    let (mut at_start, mut ended) = (true, false);
+    for (y, line) in lines.iter().enumerate().take(to).skip(from) {
+        if is_blank(line) {
+            if !after_blank {
+                starts.push((y, 0));
+            }
+            (at_start, ended, after_blank) = (true, false, true);
+            continue;
+        }
+        after_blank = false;
+        for (x, c) in line.char_indices() {
+            if c.is_whitespace() {
+                at_start |= ended;
+                ended = false;
+                continue;
+            }
+            if at_start {
+                starts.push((y, x));
+                at_start = false;
+            }
+            ended = matches!(c, '.' | '!' | '?') || (ended && matches!(c, ')' | ']' | '"' | '\''));
+        }
+        // The end of a line is a space too
+        at_start |= ended;
+        ended = false;
+    }
+    starts
+}
+
+/// The first and last lines of the paragraph text object at line `y`:
+/// `ip` is the paragraph, or the run of blank lines, the cursor is on;
+/// `ap` (`around`) adds the blank lines after it, or else those before
+pub fn paragraph_object(lines: &[String], y: usize, around: bool) -> (usize, usize) {
+    let last = lines.len().saturating_sub(1);
+    let y = y.min(last);
+    let blank = |i: usize| lines.get(i).is_some_and(|line| is_blank(line));
+    let kind = blank(y);
+    let (mut start, mut end) = (y, y);
+    while start > 0 && blank(start - 1) == kind {
+        start -= 1;
+    }
+    while end < last && blank(end + 1) == kind {
+        end += 1;
+    }
+    if !around {
+        return (start, end);
+    }
+    if end < last {
+        end += 1;
+        while end < last && blank(end + 1) != kind {
+            end += 1;
+        }
+    } else if !kind {
+        while start > 0 && blank(start - 1) {
+            start -= 1;
+        }
+    }
+    (start, end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_paragraph_and_sentence_motions() {
+        let lines: Vec<String> = [
+            "First one. Second two",
+            "goes (on.)  Third?",
+            "",
+            "",
+            "Next \"paragraph.\" Here",
+        ].iter().map(|line| line.to_string()).collect();
+
+        assert_eq!(paragraph_forward(&lines, 0), (2, 0));
+        assert_eq!(target(&lines, '}', (0, 0), 2), Some((4, 22)));
+        assert_eq!(paragraph_backward(&lines, 4), (3, 0));
+        assert_eq!(target(&lines, '{', (4, 5), 2), Some((0, 0)));
+
+        assert_eq!(sentence_forward(&lines, (0, 0)), (0, 11));
+        assert_eq!(sentence_forward(&lines, (0, 11)), (1, 12));
+        assert_eq!(sentence_forward(&lines, (1, 12)), (2, 0));
+        assert_eq!(sentence_forward(&lines, (2, 0)), (4, 0));
+        assert_eq!(sentence_forward(&lines, (4, 0)), (4, 18));
+        assert_eq!(sentence_backward(&lines, (1, 3)), (0, 11));
+        assert_eq!(sentence_backward(&lines, (0, 11)), (0, 0));
+        assert_eq!(sentence_backward(&lines, (4, 0)), (2, 0));
+        assert_eq!(target(&lines, '(', (4, 0), 2), Some((1, 12)));
+        assert_eq!(target(&lines, 'x', (0, 0), 1), None);
+
+        assert_eq!(paragraph_object(&lines, 1, false), (0, 1));
+        assert_eq!(paragraph_object(&lines, 1, true), (0, 3));
+        assert_eq!(paragraph_object(&lines, 3, false), (2, 3));
+        assert_eq!(paragraph_object(&lines, 2, true), (2, 4));
+        // The last paragraph takes the blank lines before it instead
+        assert_eq!(paragraph_object(&lines, 4, true), (2, 4));
+    }
+
+    #[test]
+    fn test_find_char_on_the_line() {
+        let line = "call(a, b), (c, é)";
+        assert_eq!(find_char(line, 0, 'f', ',', 1, false), Some(6));
+        assert_eq!(find_char(line, 0, 'f', ',', 2, false), Some(10));
+        assert_eq!(find_char(line, 0, 't', ')', 1, false), Some(8));
+        // Repeating t from beside its match goes on to the next one
+        assert_eq!(find_char(line, 8, 't', ')', 1, false), Some(8));
+        assert_eq!(find_char(line, 8, 't', ')', 1, true), Some(16));
+        assert_eq!(find_char(line, 19, 'F', '(', 1, false), Some(12));
+        assert_eq!(find_char(line, 19, 'T', 'é', 1, false), Some(18));
+        assert_eq!(find_char(line, 19, 'T', ',', 1, false), Some(15));
+        assert_eq!(find_char(line, 0, 'f', 'z', 1, false), None);
+        assert_eq!(find_char(line, 19, 'F', '(', 3, false), None);
+    }
+}
@@ -0,0 +1,98 @@
+use super::ids::BufferId;
+use anyhow::{anyhow, Result};
+
+/// A tab holding a range of lines from another buffer, written back into
+/// that buffer when saved or closed (`:narrow`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Narrowing {
+    /// The buffer the lines came from, found by id so it can be renamed or
+    /// its tab moved in the meantime
+    pub source_id: BufferId,
+    /// File path (or tab name) of the buffer the lines came from
+    pub source: String,
+    /// First line of the region in the source buffer
+    pub start: usize,
+    /// The region's lines as of the last write-back, used to find it again
+    /// if lines above it were added or removed since
+    original: Vec<String>,
+}
+
+impl Narrowing {
+    pub fn new(source_id: BufferId, source: &str, start: usize, lines: &[String]) -> Self {
+        Self {
+            source_id,
+            source: source.to_string(),
+            start,
+            original: lines.to_vec(),
+        }
+    }
+
+    /// Name shown for the narrowed tab, like `src/main.rs:10-24`
+    pub fn tab_name(&self) -> String {
+        format!("{}:{}-{} (narrowed)", self.source, self.start + 1, self.start + self.original.len().max(1))
+    }
+
+    /// The source buffer's lines with the region replaced by `lines`
+    ///
+    /// Fails if the region was changed in the source buffer in the meantime,
+    /// rather than overwriting someone else's edits.
+    pub fn write_back(&mut self, source: &[String], lines: &[String]) -> Result<Vec<String>> {
+        let start = self.locate(source)
+            .ok_or_else(|| anyhow!("The narrowed lines were changed in {}, not writing back", self.source))?;
+
+        let mut result = source.to_vec();
+        result.splice(start..start + self.original.len(), lines.iter().cloned());
+        self.start = start;
+        self.original = lines.to_vec();
+        Ok(result)
+    }
+
+    /// Where the region is in the source: at its old position, or else its
+    /// only occurrence
+    fn locate(&self, source: &[String]) -> Option<usize> {
+        let len = self.original.len();
+        if source.get(self.start..self.start + len) == Some(self.original.as_slice()) {
+            return Some(self.start);
+        }
+        if len == 0 {
+            return None;
+        }
+        let mut matches = source.windows(len)
+            .enumerate()
+            .filter(|(_, window)| *window == self.original.as_slice())
+            .map(|(index, _)| index);
+        match (matches.next(), matches.next()) {
+            (Some(index), None) => Some(index),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(text: &str) -> Vec<String> {
+        text.lines().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn test_write_back_follows_the_region() {
+        let source = lines("a\nb\nc\nd");
+        let mut narrowing = Narrowing::new(BufferId::next(), "file.txt", 1, &source[1..3]);
+        assert_eq!(narrowing.tab_name(), "file.txt:2-3 (narrowed)");
+
+        let written = narrowing.write_back(&source, &lines("B\nx\nC")).unwrap();
+        assert_eq!(written, lines("a\nB\nx\nC\nd"));
+
+        // A line added above the region in the source moves it down
+        let source = lines("new\na\nB\nx\nC\nd");
+        let written = narrowing.write_back(&source, &lines("only")).unwrap();
+        assert_eq!(written, lines("new\na\nonly\nd"));
+        assert_eq!(narrowing.start, 2);
+
+        // Edits to the region itself aren't overwritten
+        let source = lines("new\na\nchanged\nd");
+        assert!(narrowing.write_back(&source, &lines("again")).is_err());
+    }
+}
@@ -0,0 +1,88 @@
+use crate::config::NotifyConfig;
+use anyhow::Result;
+use std::time::{Duration, Instant};
+
+/// How long the status line stays flashed
+const FLASH_DURATION: Duration = Duration::from_millis(400);
+
+/// Kinds of background job, each with its own `[notify]` setting
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobKind {
+    Build,
+    Shell,
+    Ai,
+    Search,
+}
+
+/// Signals for jobs that finished, waiting to be shown
+#[derive(Debug, Default)]
+pub struct Notifier {
+    bell: bool,
+    flash_until: Option<Instant>,
+}
+
+impl Notifier {
+    /// Signal that a job of `kind` finished after `elapsed`, as configured
+    pub fn job_finished(&mut self, config: &NotifyConfig, kind: JobKind, elapsed: Duration) -> Result<()> {
+        let setting = match kind {
+            JobKind::Build => &config.build,
+            JobKind::Shell => &config.shell,
+            JobKind::Ai => &config.ai,
+            JobKind::Search => &config.search,
+        };
+        let (bell, flash) = match setting.as_str() {
+            "none" => (false, false),
+            "bell" => (true, false),
+            "flash" => (false, true),
+            "both" => (true, true),
+            _ => return Err(anyhow::anyhow!("Unknown notify setting: {} (use none, bell, flash or both)", setting)),
+        };
+        if elapsed < Duration::from_secs(config.after_seconds) {
+            return Ok(());
+        }
+        self.bell |= bell;
+        if flash {
+            self.flash_until = Some(Instant::now() + FLASH_DURATION);
+        }
+        Ok(())
+    }
+
+    /// Whether the bell should ring, once per signal
+    pub fn take_bell(&mut self) -> bool {
+        std::mem::take(&mut self.bell)
+    }
+
+    pub fn is_flashing(&self) -> bool {
+        self.flash_until.is_some_and(|until| Instant::now() < until)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_only_long_jobs_are_signalled_as_configured() {
+        let config = NotifyConfig {
+            build: "both".to_string(),
+            search: "flash".to_string(),
+            ai: "loud".to_string(),
+            after_seconds: 2,
+            ..NotifyConfig::default()
+        };
+        let mut notifier = Notifier::default();
+
+        notifier.job_finished(&config, JobKind::Build, Duration::from_secs(1)).unwrap();
+        notifier.job_finished(&config, JobKind::Shell, Duration::from_secs(60)).unwrap();
+        assert!(!notifier.take_bell() && !notifier.is_flashing());
+
+        notifier.job_finished(&config, JobKind::Search, Duration::from_secs(3)).unwrap();
+        assert!(!notifier.take_bell() && notifier.is_flashing());
+
+        notifier.job_finished(&config, JobKind::Build, Duration::from_secs(3)).unwrap();
+        assert!(notifier.take_bell());
+        assert!(!notifier.take_bell());
+
+        assert!(notifier.job_finished(&config, JobKind::Ai, Duration::from_secs(3)).is_err());
+    }
+}
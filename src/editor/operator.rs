@@ -0,0 +1,164 @@
+/// An operator waiting for the motion it works over: `gu`, `gU`, `g~`, `gq`
+/// or `y`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextOperator {
+    Lowercase,
+    Uppercase,
+    ToggleCase,
+    /// Re-wrap lines to `text_width`
+    Reflow,
+    /// Copy the text to the clipboard
+    Yank,
+}
+
+impl TextOperator {
+    /// The key that works on the whole line when typed after the operator,
+    /// like the last `u` of `guu`
+    pub fn line_key(self) -> char {
+        match self {
+            TextOperator::Lowercase => 'u',
+            TextOperator::Uppercase => 'U',
+            TextOperator::ToggleCase => '~',
+            TextOperator::Reflow => 'q',
+            TextOperator::Yank => 'y',
+        }
+    }
+
+    /// How the status line names the operator while it waits
+    pub fn name(self) -> &'static str {
+        match self {
+            TextOperator::Lowercase => "LOWERCASE",
+            TextOperator::Uppercase => "UPPERCASE",
+            TextOperator::ToggleCase => "TOGGLE CASE",
+            TextOperator::Reflow => "REFLOW",
+            TextOperator::Yank => "YANK",
+        }
+    }
+
+    /// `text` in the operator's case; reflowing and yanking don't change case
+    pub fn change_case(self, text: &str) -> String {
+        match self {
+            TextOperator::Lowercase => text.to_lowercase(),
+            TextOperator::Uppercase => text.to_uppercase(),
+            TextOperator::ToggleCase => text.chars().flat_map(toggle_case).collect(),
+            TextOperator::Reflow | TextOperator::Yank => text.to_string(),
+        }
+    }
+}
+
+fn toggle_case(c: char) -> Vec<char> {
+    if c.is_lowercase() {
+        c.to_uppercase().collect()
+    } else if c.is_uppercase() {
+        c.to_lowercase().collect()
+    } else {
+        vec![c]
+    }
+}
+
+/// Where the word at column `x` ends, the way `dw` deletes it: a run of
+/// word characters, of other symbols, or of whitespace
+pub fn word_end(line: &str, x: usize) -> usize {
+    let is_word = |c: char| c.is_alphanumeric() || c == '_';
+    let class = |c: char| if c.is_whitespace() { 0 } else if is_word(c) { 1 } else { 2 };
+    let Some(first) = line.get(x..).and_then(|rest| rest.chars().next()) else { return x };
+    line[x..].char_indices()
+        .find(|(_, c)| class(*c) != class(first))
+        .map_or(line.len(), |(i, _)| x + i)
+}
+
+/// Comment markers a reflowed paragraph keeps at the start of each line,
+/// longest first
+const COMMENT_MARKERS: [&str; 8] = ["///", "//!", "//", "#", "--", ";", "*", ">"];
+
+/// The indentation and comment marker at the start of `line`, with the
+/// space after the marker
+fn line_prefix(line: &str) -> &str {
+    let indent = line.len() - line.trim_start().len();
+    let rest = &line[indent..];
+    let Some(marker) = COMMENT_MARKERS.iter().find(|marker| rest.starts_with(**marker)) else {
+        return &line[..indent];
+    };
+    let space = usize::from(rest[marker.len()..].starts_with(' '));
+    &line[..indent + marker.len() + space]
+}
+
+/// Re-wrap `lines` to at most `width` columns, like `gq`
+///
+/// Every line gets the first line's indentation and comment marker, so a
+/// doc comment stays a doc comment. Blank lines (or bare comment markers)
+/// separate paragraphs and are kept. A word longer than the width gets a
+/// line to itself.
+pub fn reflow(lines: &[String], width: usize) -> Vec<String> {
+    let Some(first) = lines.first() else { return Vec::new() };
+    let prefix = line_prefix(first);
+    let marker = prefix.trim();
+    let mut reflowed = Vec::new();
+    let mut words = Vec::new();
+    for line in lines {
+        let text = line.trim_start();
+        let text = text.strip_prefix(marker).filter(|_| !marker.is_empty()).unwrap_or(text);
+        if text.trim().is_empty() {
+            wrap(&words, prefix, width, &mut reflowed);
+            words.clear();
+            reflowed.push(prefix.trim_end().to_string());
+        } else {
+            words.extend(text.split_whitespace());
+        }
+    }
+    wrap(&words, prefix, width, &mut reflowed);
+    reflowed
+}
+
+fn wrap(words: &[&str], prefix: &str, width: usize, lines: &mut Vec<String>) {
+    let mut line = prefix.to_string();
+    for word in words {
+        let empty = line.len() == prefix.len();
+        if !empty && line.chars().count() + 1 + word.chars().count() > width {
+            lines.push(std::mem::replace(&mut line, prefix.to_string()));
+        } else if !empty {
+            line.push(' ');
+        }
+        line.push_str(word);
+    }
+    if line.len() > prefix.len() {
+        lines.push(line);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(text: &[&str]) -> Vec<String> {
+        text.iter().map(|line| line.to_string()).collect()
+    }
+
+    #[test]
+    fn test_reflow_keeps_comment_markers_and_paragraphs() {
+        let comment = lines(&[
+            "    /// Re-wrap the lines of a paragraph so that none of them is longer",
+            "    /// than the text width.",
+            "    ///",
+            "    /// Second paragraph.",
+        ]);
+        assert_eq!(reflow(&comment, 40), [
+            "    /// Re-wrap the lines of a paragraph",
+            "    /// so that none of them is longer",
+            "    /// than the text width.",
+            "    ///",
+            "    /// Second paragraph.",
+        ]);
+        assert_eq!(reflow(&lines(&["one two", "three", "", "four"]), 80), ["one two three", "", "four"]);
+        assert_eq!(reflow(&lines(&["# a supercalifragilistic word"]), 10), ["# a", "# supercalifragilistic", "# word"]);
+    }
+
+    #[test]
+    fn test_case_changes_and_word_ends() {
+        assert_eq!(TextOperator::ToggleCase.change_case("Hello, Straße"), "hELLO, sTRASSE");
+        assert_eq!(TextOperator::Uppercase.change_case("snake_case"), "SNAKE_CASE");
+        assert_eq!(word_end("let foo_bar = 1;", 4), 11);
+        assert_eq!(word_end("a == b", 2), 4);
+        assert_eq!(word_end("abc", 3), 3);
+    }
+}
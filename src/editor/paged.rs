@@ -0,0 +1,213 @@
+use anyhow::{Context, Result};
+use encoding_rs::{UTF_16BE, UTF_16LE};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom};
+
+/// Files bigger than this are paged from disk instead of loaded
+pub const LARGE_FILE_BYTES: u64 = 50 * 1024 * 1024;
+/// How much of the start of a file is checked for NUL bytes
+const BINARY_CHECK_BYTES: u64 = 8192;
+/// Lines between the offsets remembered for a large text file
+const INDEX_STEP: usize = 1024;
+/// Bytes shown on each row of a hex dump
+const HEX_ROW_BYTES: usize = 16;
+/// Longest part of a line read for display
+const MAX_LINE_BYTES: u64 = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PagedKind {
+    /// Text too large to load, shown line by line
+    Large,
+    /// Binary content, shown as a hex dump
+    Binary,
+}
+
+/// A read-only view of a large or binary file that reads only the rows on
+/// screen, without syntax highlighting or diagnostics
+pub struct PagedFile {
+    path: String,
+    pub kind: PagedKind,
+    pub size: u64,
+    /// Offset of every `INDEX_STEP`th line of a large text file
+    checkpoints: Vec<u64>,
+    line_count: usize,
+    /// First row shown
+    pub top: usize,
+}
+
+impl PagedFile {
+    /// Open `path` paged if it's binary or bigger than `limit` bytes, or
+    /// None if it should be loaded as usual
+    pub fn open(path: &str, limit: u64) -> Result<Option<Self>> {
+        let mut file = File::open(path).with_context(|| format!("Failed to read file: {}", path))?;
+        let size = file.metadata()?.len();
+        let mut head = Vec::new();
+        file.by_ref().take(BINARY_CHECK_BYTES).read_to_end(&mut head)?;
+
+        // UTF-16 text is full of NULs but has a byte order mark
+        let utf16 = matches!(encoding_rs::Encoding::for_bom(&head), Some((encoding, _)) if encoding == UTF_16LE || encoding == UTF_16BE);
+        let kind = if head.contains(&0) && !utf16 {
+            PagedKind::Binary
+        } else if size > limit {
+            PagedKind::Large
+        } else {
+            return Ok(None);
+        };
+
+        let mut paged = Self {
+            path: path.to_string(),
+            kind,
+            size,
+            checkpoints: vec![0],
+            line_count: 0,
+            top: 0,
+        };
+        if kind == PagedKind::Large {
+            file.seek(SeekFrom::Start(0))?;
+            paged.index_lines(file)?;
+        }
+        Ok(Some(paged))
+    }
+
+    /// Count the lines in one pass, remembering where every
+    /// `INDEX_STEP`th one starts
+    fn index_lines(&mut self, file: File) -> Result<()> {
+        let mut reader = BufReader::with_capacity(1 << 16, file);
+        let mut offset = 0u64;
+        let mut breaks = 0usize;
+        loop {
+            let chunk = reader.fill_buf()?;
+            if chunk.is_empty() {
+                break;
+            }
+            for (i, _) in chunk.iter().enumerate().filter(|(_, byte)| **byte == b'\n') {
+                breaks += 1;
+                if breaks.is_multiple_of(INDEX_STEP) {
+                    self.checkpoints.push(offset + i as u64 + 1);
+                }
+            }
+            let len = chunk.len();
+            offset += len as u64;
+            reader.consume(len);
+        }
+        // Like a loaded buffer, a final newline starts an empty last line
+        self.line_count = breaks + 1;
+        Ok(())
+    }
+
+    /// Lines of text, or hex dump rows
+    pub fn row_count(&self) -> usize {
+        match self.kind {
+            PagedKind::Large => self.line_count,
+            PagedKind::Binary => (self.size as usize).div_ceil(HEX_ROW_BYTES).max(1),
+        }
+    }
+
+    /// Move the first row shown, keeping a screen of `height` rows filled
+    pub fn scroll(&mut self, amount: isize, height: usize) {
+        let last_top = self.row_count().saturating_sub(height);
+        self.top = self.top.saturating_add_signed(amount).min(last_top);
+    }
+
+    /// Show the first row, or with `end` the last screen of `height` rows
+    pub fn jump(&mut self, end: bool, height: usize) {
+        self.top = if end { self.row_count().saturating_sub(height) } else { 0 };
+    }
+
+    /// Read up to `count` rows starting at `first` from disk
+    pub fn rows(&self, first: usize, count: usize) -> Result<Vec<String>> {
+        let mut file = File::open(&self.path).with_context(|| format!("Failed to read file: {}", self.path))?;
+        match self.kind {
+            PagedKind::Binary => {
+                let offset = (first * HEX_ROW_BYTES) as u64;
+                file.seek(SeekFrom::Start(offset))?;
+                let mut bytes = Vec::new();
+                file.take((count * HEX_ROW_BYTES) as u64).read_to_end(&mut bytes)?;
+                Ok(bytes.chunks(HEX_ROW_BYTES)
+                    .enumerate()
+                    .map(|(i, row)| hex_row(offset + (i * HEX_ROW_BYTES) as u64, row))
+                    .collect())
+            },
+            PagedKind::Large => {
+                let Some(checkpoint) = self.checkpoints.get(first / INDEX_STEP) else {
+                    return Ok(Vec::new());
+                };
+                file.seek(SeekFrom::Start(*checkpoint))?;
+                let mut reader = BufReader::new(file);
+                for _ in 0..first % INDEX_STEP {
+                    reader.skip_until(b'\n')?;
+                }
+
+                let count = count.min(self.line_count.saturating_sub(first));
+                let mut rows = Vec::with_capacity(count);
+                for _ in 0..count {
+                    // Very long lines are cut off rather than read whole
+                    let mut line = Vec::new();
+                    reader.by_ref().take(MAX_LINE_BYTES).read_until(b'\n', &mut line)?;
+                    if line.last() == Some(&b'\n') {
+                        line.pop();
+                    } else {
+                        reader.skip_until(b'\n')?;
+                    }
+                    if line.last() == Some(&b'\r') {
+                        line.pop();
+                    }
+                    rows.push(String::from_utf8_lossy(&line).into_owned());
+                }
+                Ok(rows)
+            },
+        }
+    }
+}
+
+/// A hex dump row: offset, bytes in hex and the printable ones as text
+fn hex_row(offset: u64, bytes: &[u8]) -> String {
+    let mut row = format!("{:08x} ", offset);
+    for i in 0..HEX_ROW_BYTES {
+        if i % 8 == 0 {
+            row.push(' ');
+        }
+        match bytes.get(i) {
+            Some(byte) => row.push_str(&format!("{:02x} ", byte)),
+            None => row.push_str("   "),
+        }
+    }
+    let text: String = bytes.iter()
+        .map(|&byte| if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '.' })
+        .collect();
+    row.push_str(&format!(" |{}|", text));
+    row
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_large_and_binary_files_are_paged() {
+        let dir = tempfile::tempdir().unwrap();
+        let text = dir.path().join("big.log");
+        let lines: Vec<String> = (0..3000).map(|i| format!("line {}", i)).collect();
+        std::fs::write(&text, lines.join("\r\n")).unwrap();
+        let text = text.to_str().unwrap();
+        assert!(PagedFile::open(text, LARGE_FILE_BYTES).unwrap().is_none());
+
+        let mut paged = PagedFile::open(text, 1000).unwrap().unwrap();
+        assert_eq!((paged.kind, paged.row_count()), (PagedKind::Large, 3000));
+        assert_eq!(paged.rows(2047, 3).unwrap(), vec!["line 2047", "line 2048", "line 2049"]);
+        assert_eq!(paged.rows(2999, 5).unwrap(), vec!["line 2999"]);
+        paged.scroll(5000, 40);
+        assert_eq!(paged.top, 2960);
+        paged.scroll(-10, 40);
+        assert_eq!(paged.top, 2950);
+
+        let binary = dir.path().join("data.bin");
+        std::fs::write(&binary, b"\x7fELF\0\x01\x02abcdefghijklmnop").unwrap();
+        let paged = PagedFile::open(binary.to_str().unwrap(), LARGE_FILE_BYTES).unwrap().unwrap();
+        assert_eq!((paged.kind, paged.row_count()), (PagedKind::Binary, 2));
+        assert_eq!(paged.rows(0, 10).unwrap(), vec![
+            "00000000  7f 45 4c 46 00 01 02 61  62 63 64 65 66 67 68 69  |.ELF...abcdefghi|",
+            "00000010  6a 6b 6c 6d 6e 6f 70                              |jklmnop|",
+        ]);
+    }
+}
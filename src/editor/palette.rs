@@ -0,0 +1,190 @@
+use super::help;
+use crate::config::Config;
+use fuzzy_matcher::{skim::SkimMatcherV2, FuzzyMatcher};
+
+/// A command the palette can run, by the name it's listed under
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PaletteItem {
+    pub name: String,
+    /// A key binding command name like `find_file`, or a `:` command line
+    pub action: String,
+    /// The keys bound to it, to learn for next time
+    pub keys: Vec<String>,
+    /// Whether the `:` command takes arguments, so picking it only starts
+    /// the command line
+    pub needs_arguments: bool,
+}
+
+/// A fuzzy-searched list of every command, with its keys (Ctrl+p)
+#[derive(Default)]
+pub struct Palette {
+    items: Vec<PaletteItem>,
+    query: String,
+    /// Indices into `items` with the positions of `name` that matched
+    matches: Vec<(usize, Vec<usize>)>,
+    selected: usize,
+    matcher: SkimMatcherV2,
+}
+
+impl Palette {
+    /// List the commands for the key bindings and keymap in `config`, with
+    /// an empty query
+    pub fn open(&mut self, config: &Config) {
+        self.items = items(config);
+        self.query.clear();
+        self.update_matches();
+    }
+
+    pub fn query(&self) -> &str {
+        &self.query
+    }
+
+    pub fn add_char(&mut self, c: char) {
+        self.query.push(c);
+        self.update_matches();
+    }
+
+    pub fn remove_char(&mut self) {
+        self.query.pop();
+        self.update_matches();
+    }
+
+    /// The matching items with the positions of their names to highlight,
+    /// best first
+    pub fn matches(&self) -> impl Iterator<Item = (&PaletteItem, &[usize])> {
+        self.matches.iter().map(|(index, positions)| (&self.items[*index], positions.as_slice()))
+    }
+
+    pub fn selected_index(&self) -> usize {
+        self.selected
+    }
+
+    pub fn selected(&self) -> Option<&PaletteItem> {
+        self.matches.get(self.selected).map(|(index, _)| &self.items[*index])
+    }
+
+    pub fn select_next(&mut self) {
+        if self.selected + 1 < self.matches.len() {
+            self.selected += 1;
+        }
+    }
+
+    pub fn select_previous(&mut self) {
+        self.selected = self.selected.saturating_sub(1);
+    }
+
+    fn update_matches(&mut self) {
+        self.selected = 0;
+        self.matches.clear();
+        let query = self.query.trim();
+        if query.is_empty() {
+            self.matches = (0..self.items.len()).map(|index| (index, Vec::new())).collect();
+            return;
+        }
+        let mut scored: Vec<(i64, usize, Vec<usize>)> = self.items.iter().enumerate().filter_map(|(index, item)| {
+            // The name is what's shown highlighted; the action and keys
+            // match too, so `find_file` or `C-o` finds it
+            match self.matcher.fuzzy_indices(&item.name, query) {
+                Some((score, positions)) => Some((score, index, positions)),
+                None => std::iter::once(item.action.as_str()).chain(item.keys.iter().map(String::as_str))
+                    .filter_map(|text| self.matcher.fuzzy_match(text, query))
+                    .max()
+                    .map(|score| (score, index, Vec::new())),
+            }
+        }).collect();
+        scored.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(&b.1)));
+        self.matches = scored.into_iter().map(|(_, index, positions)| (index, positions)).collect();
+    }
+}
+
+/// Every Normal mode command, then the `:` commands, then whatever else the
+/// user's keymap runs, each with the keys bound to it
+fn items(config: &Config) -> Vec<PaletteItem> {
+    let bound = help::normal_keys(config);
+    let keys_for = |action: &str| -> Vec<String> {
+        let mut keys: Vec<String> = bound.iter().filter(|(command, _)| command == action).map(|(_, key)| key.label.clone()).collect();
+        keys.sort_by(|a, b| (a.starts_with('<'), a.len(), a).cmp(&(b.starts_with('<'), b.len(), b)));
+        keys
+    };
+
+    let mut items: Vec<PaletteItem> = help::normal_commands().map(|command| PaletteItem {
+        name: help::describe(command),
+        action: command.to_string(),
+        keys: keys_for(command),
+        needs_arguments: false,
+    }).collect();
+    // A usage like `:trust / :untrust` lists two commands
+    let usages = help::ex_commands().flat_map(|(usage, description)| usage.split(" / ").map(move |usage| (usage, description)));
+    for (usage, description) in usages {
+        // Only commands that start with a name can be run from here, not
+        // ranges, patterns or `:!`
+        let Some(rest) = usage.strip_prefix(':').filter(|rest| rest.starts_with(char::is_alphabetic)) else { continue };
+        let name: String = rest.chars().take_while(|c| c.is_alphabetic() || *c == '!').collect();
+        if !matches!(rest[name.len()..].chars().next(), None | Some(' ' | ',' | '[')) {
+            continue;
+        }
+        let action = format!(":{}", name);
+        items.push(PaletteItem {
+            name: description.to_string(),
+            keys: keys_for(&action),
+            needs_arguments: usage.len() > action.len(),
+            action,
+        });
+    }
+    let mut others: Vec<PaletteItem> = Vec::new();
+    for (command, _) in &bound {
+        let listed = items.iter().chain(&others).any(|item| item.action == *command);
+        if !listed && !command.starts_with("goto_tab_") {
+            others.push(PaletteItem {
+                name: help::describe(command),
+                action: command.clone(),
+                keys: keys_for(command),
+                needs_arguments: false,
+            });
+        }
+    }
+    // They come from a HashMap, so give them a stable order
+    others.sort_by(|a, b| a.name.cmp(&b.name));
+    items.extend(others);
+    items
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_commands_are_found_by_name_with_their_keys() {
+        let mut config = Config::default();
+        config.keymap.insert("<leader>gb".to_string(), ":git blame".to_string());
+        let mut palette = Palette::default();
+        palette.open(&config);
+
+        for c in "opn file".chars() {
+            palette.add_char(c);
+        }
+        let item = palette.selected().unwrap();
+        assert_eq!(item.action, "find_file");
+        assert_eq!(item.keys, ["Ctrl+o", "<space>ff"]);
+        let (_, positions) = palette.matches().next().unwrap();
+        assert_eq!(positions[0], 0);
+
+        // `:` commands with arguments only start the command line
+        palette.open(&config);
+        for c in "saveas".chars() {
+            palette.add_char(c);
+        }
+        let item = palette.selected().unwrap();
+        assert_eq!((item.action.as_str(), item.needs_arguments), (":saveas", true));
+        let find = |action: &str| items(&config).into_iter().find(|item| item.action == action).unwrap();
+        assert!(!find(":trust").needs_arguments && !find(":untrust").needs_arguments);
+        assert!(find(":lcd").needs_arguments && !find(":pwd").needs_arguments);
+
+        palette.open(&config);
+        for c in "git blame".chars() {
+            palette.add_char(c);
+        }
+        assert_eq!(palette.selected().unwrap().keys, ["<space>gb"]);
+        assert!(palette.matches().all(|(item, _)| !item.action.starts_with("goto_tab_")));
+    }
+}
@@ -0,0 +1,135 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// An insert-mode completion of the file path before the cursor
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathCompletion {
+    /// Line being completed
+    pub y: usize,
+    /// Column where the completed file name starts, after the path's last `/`
+    pub start: usize,
+    /// Entries of the path's directory that start with the typed name;
+    /// directories end in `/`
+    pub candidates: Vec<String>,
+    pub selected: usize,
+}
+
+impl PathCompletion {
+    /// Complete the path that ends at column `x`, resolving relative paths
+    /// against `base`
+    ///
+    /// Returns `None` when nothing in the directory matches.
+    pub fn start(line: &str, x: usize, y: usize, base: &Path) -> Option<Self> {
+        let path_start = line[..x].rfind(is_path_boundary).map_or(0, |index| index + 1);
+        let typed = &line[path_start..x];
+        let (dir, name) = match typed.rfind('/') {
+            Some(slash) => (&typed[..=slash], &typed[slash + 1..]),
+            None => ("", typed),
+        };
+
+        let candidates = candidates(&resolve(dir, base), name);
+        if candidates.is_empty() {
+            return None;
+        }
+        Some(PathCompletion {
+            y,
+            start: path_start + dir.len(),
+            candidates,
+            selected: 0,
+        })
+    }
+
+    pub fn current(&self) -> &str {
+        &self.candidates[self.selected]
+    }
+
+    /// Select the next candidate, or the previous one, wrapping around
+    pub fn cycle(&mut self, forward: bool) {
+        let count = self.candidates.len();
+        self.selected = if forward {
+            (self.selected + 1) % count
+        } else {
+            (self.selected + count - 1) % count
+        };
+    }
+}
+
+/// Characters that can't be part of a path being typed, like the quotes of
+/// an include or the parenthesis of a Markdown link
+fn is_path_boundary(ch: char) -> bool {
+    ch.is_whitespace() || matches!(ch, '"' | '\'' | '`' | '(' | ')' | '[' | ']' | '<' | '>' | '{' | '}' | ',' | '=' | ';')
+}
+
+fn resolve(dir: &str, base: &Path) -> PathBuf {
+    if let Some(rest) = dir.strip_prefix("~/") {
+        if let Some(home) = dirs::home_dir() {
+            return home.join(rest);
+        }
+    }
+    if dir.starts_with('/') {
+        PathBuf::from(dir)
+    } else {
+        base.join(dir)
+    }
+}
+
+/// Sorted entries of `dir` starting with `name`; hidden entries only when
+/// `name` starts with a dot
+fn candidates(dir: &Path, name: &str) -> Vec<String> {
+    let Ok(entries) = fs::read_dir(dir) else { return Vec::new() };
+    let mut candidates: Vec<String> = entries.filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let file_name = entry.file_name().to_string_lossy().to_string();
+            if !file_name.starts_with(name) || (file_name.starts_with('.') && !name.starts_with('.')) {
+                return None;
+            }
+            let is_dir = entry.path().is_dir();
+            Some(if is_dir { format!("{}/", file_name) } else { file_name })
+        })
+        .collect();
+    candidates.sort();
+    candidates
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_completes_entries_of_the_typed_directory() {
+        let dir = tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("docs/guides")).unwrap();
+        fs::write(dir.path().join("docs/getting-started.md"), "").unwrap();
+        fs::write(dir.path().join("docs/.hidden"), "").unwrap();
+
+        let line = "See [the guide](docs/g";
+        let completion = PathCompletion::start(line, line.len(), 3, dir.path()).unwrap();
+        assert_eq!(completion.start, 21);
+        assert_eq!(completion.candidates, ["getting-started.md", "guides/"]);
+
+        let line = "#include \"docs/";
+        let completion = PathCompletion::start(line, line.len(), 0, dir.path()).unwrap();
+        assert_eq!(completion.candidates, ["getting-started.md", "guides/"]);
+
+        let line = "docs/.";
+        let completion = PathCompletion::start(line, line.len(), 0, dir.path()).unwrap();
+        assert_eq!(completion.candidates, [".hidden"]);
+
+        assert_eq!(PathCompletion::start("docs/x", 6, 0, dir.path()), None);
+    }
+
+    #[test]
+    fn test_cycle_wraps_around() {
+        let mut completion = PathCompletion {
+            y: 0,
+            start: 0,
+            candidates: vec!["a".to_string(), "b".to_string()],
+            selected: 0,
+        };
+        completion.cycle(false);
+        assert_eq!(completion.current(), "b");
+        completion.cycle(true);
+        assert_eq!(completion.current(), "a");
+    }
+}
@@ -0,0 +1,70 @@
+use super::HighlightedLine;
+use std::ops::Range;
+
+/// Lines loaded on each side of the peeked location
+const CONTEXT_LINES: usize = 200;
+
+/// A read-only look at a location from the token search, diagnostics or
+/// file finder panel, shown over the panel without opening a tab
+pub struct Peek {
+    pub path: String,
+    /// Lines around the location, starting at `first_line`
+    pub lines: Vec<HighlightedLine>,
+    pub first_line: usize,
+    /// Line of the location, in the whole file
+    pub line: usize,
+    /// Lines scrolled away from the location with PageUp and PageDown
+    scroll: isize,
+}
+
+impl Peek {
+    pub fn new(path: &str, lines: Vec<HighlightedLine>, first_line: usize, line: usize) -> Self {
+        Self {
+            path: path.to_string(),
+            lines,
+            first_line,
+            line,
+            scroll: 0,
+        }
+    }
+
+    /// Which of a file's lines to load to peek at `line`
+    pub fn window(line: usize, line_count: usize) -> Range<usize> {
+        let line = line.min(line_count.saturating_sub(1));
+        line.saturating_sub(CONTEXT_LINES)..(line + CONTEXT_LINES + 1).min(line_count)
+    }
+
+    /// First line shown in a viewer `height` rows tall: the location a third
+    /// of the way down, moved by scrolling, without running past the
+    /// loaded lines
+    pub fn top_line(&self, height: usize) -> usize {
+        let last_top = (self.first_line + self.lines.len()).saturating_sub(height).max(self.first_line);
+        let top = self.line as isize - (height / 3) as isize + self.scroll;
+        (top.max(self.first_line as isize) as usize).min(last_top)
+    }
+
+    pub fn scroll(&mut self, amount: isize) {
+        let limit = self.lines.len() as isize;
+        self.scroll = (self.scroll + amount).clamp(-limit, limit);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_window_and_scrolling_stay_within_the_file() {
+        assert_eq!(Peek::window(5, 10), 0..10);
+        assert_eq!(Peek::window(1000, 2000), 800..1201);
+        assert_eq!(Peek::window(50, 0), 0..0);
+
+        let lines = vec![HighlightedLine { ranges: Vec::new() }; 100];
+        let mut peek = Peek::new("src/main.rs", lines, 0, 40);
+        assert_eq!(peek.top_line(30), 30);
+        peek.scroll(-1000);
+        assert_eq!(peek.top_line(30), 0);
+        peek.scroll(1000);
+        assert_eq!(peek.top_line(30), 70);
+    }
+}
@@ -0,0 +1,129 @@
+use anyhow::{Context, Result};
+use std::io::{ErrorKind, Write};
+use std::process::{Command, Stdio};
+
+/// Shown when the `*` register falls back to the clipboard
+pub const UNAVAILABLE: &str = "No primary selection here (it needs wl-clipboard, xclip or xsel); used the clipboard";
+
+/// Commands that read and write the primary selection
+struct Tool {
+    read: &'static [&'static str],
+    write: &'static [&'static str],
+}
+
+const WL_CLIPBOARD: Tool = Tool {
+    read: &["wl-paste", "--primary", "--no-newline"],
+    write: &["wl-copy", "--primary"],
+};
+const XCLIP: Tool = Tool {
+    read: &["xclip", "-o", "-selection", "primary"],
+    write: &["xclip", "-i", "-selection", "primary"],
+};
+const XSEL: Tool = Tool {
+    read: &["xsel", "--primary", "--output"],
+    write: &["xsel", "--primary", "--input"],
+};
+
+/// Tools to try for the display servers in use, best first. Only X11 and
+/// Wayland have a primary selection.
+fn tools(wayland: bool, x11: bool) -> Vec<&'static Tool> {
+    let mut tools = Vec::new();
+    if !cfg!(unix) || cfg!(target_os = "macos") {
+        return tools;
+    }
+    if wayland {
+        tools.push(&WL_CLIPBOARD);
+    }
+    if x11 {
+        tools.extend([&XCLIP, &XSEL]);
+    }
+    tools
+}
+
+fn available_tools() -> Vec<&'static Tool> {
+    tools(std::env::var_os("WAYLAND_DISPLAY").is_some(), std::env::var_os("DISPLAY").is_some())
+}
+
+/// Read the primary selection (what was last selected with the mouse), or
+/// None if there's no primary selection or no tool to read it with
+pub fn read() -> Result<Option<String>> {
+    read_with(available_tools().iter().map(|tool| tool.read))
+}
+
+/// The output of the first of `commands` that's installed and succeeds,
+/// None if none is installed
+fn read_with<'a>(commands: impl IntoIterator<Item = &'a [&'a str]>) -> Result<Option<String>> {
+    let mut failed = false;
+    for command in commands {
+        let output = match Command::new(command[0]).args(&command[1..]).stdin(Stdio::null()).output() {
+            Err(e) if e.kind() == ErrorKind::NotFound => continue,
+            result => result.with_context(|| format!("Failed to run {}", command[0]))?,
+        };
+        // The next tool may still work, as one for another display server
+        // fails here
+        if !output.status.success() {
+            failed = true;
+            continue;
+        }
+        return Ok(Some(String::from_utf8_lossy(&output.stdout).replace("\r\n", "\n")));
+    }
+    // Some tools report an empty selection as a failure
+    Ok(failed.then(String::new))
+}
+
+/// Set the primary selection, returning false if there's no primary
+/// selection or no tool to set it with
+pub fn write(text: &str) -> Result<bool> {
+    write_with(available_tools().iter().map(|tool| tool.write), text)
+}
+
+/// Pipe `text` into the first of `commands` that's installed, returning
+/// false if none is
+pub fn write_with<'a>(commands: impl IntoIterator<Item = &'a [&'a str]>, text: &str) -> Result<bool> {
+    for command in commands {
+        // The tools leave a process in the background to serve the
+        // selection. Its output goes nowhere rather than to a pipe, so
+        // waiting only takes until the text is handed over
+        let mut child = match Command::new(command[0]).args(&command[1..])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+        {
+            Err(e) if e.kind() == ErrorKind::NotFound => continue,
+            result => result.with_context(|| format!("Failed to run {}", command[0]))?,
+        };
+        child.stdin.take().context("Failed to open stdin")?.write_all(text.as_bytes())?;
+        let status = child.wait()?;
+        if !status.success() {
+            return Err(anyhow::anyhow!("{} failed", command[0]));
+        }
+        return Ok(true);
+    }
+    Ok(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tools_for_display_servers() {
+        let names = |tools: Vec<&Tool>| tools.iter().map(|tool| tool.read[0]).collect::<Vec<_>>();
+        if cfg!(target_os = "linux") {
+            assert_eq!(names(tools(true, true)), ["wl-paste", "xclip", "xsel"]);
+            assert_eq!(names(tools(false, true)), ["xclip", "xsel"]);
+        }
+        assert!(tools(false, false).is_empty());
+    }
+
+    #[test]
+    fn test_read_tries_the_next_tool_when_one_fails() -> Result<()> {
+        let missing: &[&str] = &["zim-no-such-clipboard-tool"];
+        let (fails, works): (&[&str], &[&str]) = (&["false"], &["printf", "text"]);
+        assert_eq!(read_with([missing, fails, works])?.as_deref(), Some("text"));
+        assert_eq!(read_with([fails])?.as_deref(), Some(""));
+        assert_eq!(read_with([missing])?, None);
+        Ok(())
+    }
+}
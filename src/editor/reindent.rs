@@ -0,0 +1,59 @@
+/// Leading spaces and tabs of a line
+pub fn indentation(line: &str) -> &str {
+    &line[..line.len() - line.trim_start_matches([' ', '\t']).len()]
+}
+
+/// Move a block of whole lines to a new indentation, keeping the lines'
+/// indentation relative to each other. The block's least indented line
+/// ends up at `indent`, and blank lines are left empty.
+pub fn reindent(text: &str, indent: &str) -> String {
+    let common = text.lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| indentation(line).len())
+        .min()
+        .unwrap_or(0);
+
+    let mut result = String::new();
+    for line in text.split_inclusive('\n') {
+        let (content, newline) = match line.strip_suffix('\n') {
+            Some(content) => (content, "\n"),
+            None => (line, ""),
+        };
+        if !content.trim().is_empty() {
+            result.push_str(indent);
+            result.push_str(&content[common..]);
+        }
+        result.push_str(newline);
+    }
+    result
+}
+
+/// How many spaces Backspace at byte column `x` should delete: when only
+/// spaces come before the cursor, back to the previous indent level, like
+/// vim's `softtabstop`
+pub fn indent_backspace_width(line: &str, x: usize, tab_size: usize) -> Option<usize> {
+    let before = line.get(..x)?;
+    if x == 0 || tab_size < 2 || before.bytes().any(|b| b != b' ') {
+        return None;
+    }
+    Some((x - 1) % tab_size + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reindent_keeps_relative_indentation() {
+        let block = "        if ok {\n            run();\n\n        }\n";
+        assert_eq!(reindent(block, "    "), "    if ok {\n        run();\n\n    }\n");
+        assert_eq!(reindent("\tx\n\t\ty\n", ""), "x\n\ty\n");
+        assert_eq!(indentation("  \tlet x;"), "  \t");
+
+        assert_eq!(indent_backspace_width("        run();", 8, 4), Some(4));
+        assert_eq!(indent_backspace_width("      run();", 6, 4), Some(2));
+        assert_eq!(indent_backspace_width("    run();", 5, 4), None);
+        assert_eq!(indent_backspace_width("\t    run();", 5, 4), None);
+        assert_eq!(indent_backspace_width("    run();", 4, 1), None);
+    }
+}
@@ -0,0 +1,146 @@
+use anyhow::{anyhow, Context, Result};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// How `:run` runs a file: a shell command and where it runs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RunCommand {
+    pub command: String,
+    pub dir: PathBuf,
+}
+
+/// Interpreters used by file type when `[runners]` doesn't name one;
+/// `{file}` stands for the file's path
+fn builtin(extension: &str) -> Option<&'static str> {
+    match extension {
+        "py" => Some("python3 {file}"),
+        "js" | "mjs" | "cjs" => Some("node {file}"),
+        "sh" | "bash" => Some("bash {file}"),
+        _ => None,
+    }
+}
+
+/// The command that runs the file at `path`, which should be absolute
+///
+/// A runner configured for the file's extension comes first. Rust files
+/// run with `cargo run --bin` in their package when they're one of its
+/// binaries; other files run with the interpreter for their type, from
+/// their own directory.
+pub fn run_command(path: &Path, runners: &HashMap<String, String>) -> Result<RunCommand> {
+    let extension = path.extension().and_then(|extension| extension.to_str()).unwrap_or("");
+    let dir = path.parent().map(Path::to_path_buf).unwrap_or_else(|| PathBuf::from("."));
+    let template = match runners.get(extension) {
+        Some(template) => template.as_str(),
+        None if extension == "rs" => return cargo_run(path),
+        None => builtin(extension).ok_or_else(|| match extension {
+            "" => anyhow!("No runner for files without an extension (add one under [runners])"),
+            _ => anyhow!("No runner for .{} files (add one under [runners])", extension),
+        })?,
+    };
+    let command = template
+        .replace("{file}", &quote(&path.to_string_lossy()))
+        .replace("{dir}", &quote(&dir.to_string_lossy()));
+    Ok(RunCommand { command, dir })
+}
+
+/// `cargo run --bin` for the binary target whose main file is `path`
+fn cargo_run(path: &Path) -> Result<RunCommand> {
+    let root = path.ancestors()
+        .skip(1)
+        .find(|dir| dir.join("Cargo.toml").is_file())
+        .with_context(|| format!("{} isn't in a cargo package", path.display()))?;
+    let manifest_path = root.join("Cargo.toml");
+    let text = fs::read_to_string(&manifest_path)
+        .with_context(|| format!("Failed to read {}", manifest_path.display()))?;
+    let manifest: toml::Value = toml::from_str(&text)
+        .map_err(|e| anyhow!("Invalid {}: {}", manifest_path.display(), e))?;
+    let relative = path.strip_prefix(root).unwrap_or(path);
+
+    let bin = binary_for(&manifest, relative)
+        .ok_or_else(|| anyhow!("{} isn't the main file of a binary in {}", relative.display(), manifest_path.display()))?;
+    Ok(RunCommand { command: format!("cargo run --bin {}", quote(&bin)), dir: root.to_path_buf() })
+}
+
+/// The name of the binary target built from `relative`, a path inside the
+/// package, using the `[[bin]]` entries and cargo's default layout
+fn binary_for(manifest: &toml::Value, relative: &Path) -> Option<String> {
+    let package = manifest.get("package")?.get("name")?.as_str()?;
+    // Where cargo looks for a binary that has no `path`
+    let default_paths = |name: &str| {
+        let mut paths = vec![PathBuf::from(format!("src/bin/{}.rs", name)), PathBuf::from(format!("src/bin/{}/main.rs", name))];
+        if name == package {
+            paths.push(PathBuf::from("src/main.rs"));
+        }
+        paths
+    };
+
+    let bins = manifest.get("bin").and_then(|bins| bins.as_array()).cloned().unwrap_or_default();
+    for bin in &bins {
+        let Some(name) = bin.get("name").and_then(|name| name.as_str()) else { continue };
+        let matches = match bin.get("path").and_then(|path| path.as_str()) {
+            Some(bin_path) => Path::new(bin_path) == relative,
+            None => default_paths(name).iter().any(|path| path == relative),
+        };
+        if matches {
+            return Some(name.to_string());
+        }
+    }
+
+    let autobins = manifest.get("package")?.get("autobins").and_then(|autobins| autobins.as_bool()).unwrap_or(true);
+    if !autobins {
+        return None;
+    }
+    if relative == Path::new("src/main.rs") {
+        return Some(package.to_string());
+    }
+    let mut components = relative.iter().map(|part| part.to_str());
+    match (components.next(), components.next(), components.next(), components.next()) {
+        (Some(Some("src")), Some(Some("bin")), Some(Some(file)), None) => file.strip_suffix(".rs").map(str::to_string),
+        (Some(Some("src")), Some(Some("bin")), Some(Some(dir)), Some(Some("main.rs"))) if components.next().is_none() => Some(dir.to_string()),
+        _ => None,
+    }
+}
+
+/// `text` quoted for the shell, unless it's plain enough not to need it
+pub fn quote(text: &str) -> String {
+    if !text.is_empty() && text.chars().all(|c| c.is_ascii_alphanumeric() || "_-./".contains(c)) {
+        text.to_string()
+    } else {
+        format!("'{}'", text.replace('\'', "'\\''"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_files_run_with_their_interpreter_or_cargo_bin() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let root = dir.path().canonicalize()?;
+        fs::create_dir_all(root.join("src/bin/tool"))?;
+        fs::write(root.join("Cargo.toml"), "[package]\nname = \"app\"\n\n[[bin]]\nname = \"extra\"\npath = \"tools/extra.rs\"\n")?;
+        let mut runners = HashMap::new();
+
+        let script = root.join("my script.py");
+        let run = run_command(&script, &runners)?;
+        assert_eq!(run.command, format!("python3 '{}'", script.display()));
+        assert_eq!(run.dir, root);
+
+        let cargo = |path: &str| run_command(&root.join(path), &HashMap::new()).map(|run| run.command);
+        assert_eq!(cargo("src/main.rs")?, "cargo run --bin app");
+        assert_eq!(cargo("src/bin/other.rs")?, "cargo run --bin other");
+        assert_eq!(cargo("src/bin/tool/main.rs")?, "cargo run --bin tool");
+        assert_eq!(cargo("tools/extra.rs")?, "cargo run --bin extra");
+        assert_eq!(run_command(&root.join("src/main.rs"), &HashMap::new())?.dir, root);
+        assert!(cargo("src/lib.rs").is_err());
+
+        runners.insert("py".to_string(), "uv run {file}".to_string());
+        runners.insert("rs".to_string(), "rust-script {file}".to_string());
+        assert_eq!(run_command(&root.join("a.py"), &runners)?.command, format!("uv run {}", quote(&root.join("a.py").to_string_lossy())));
+        assert_eq!(run_command(&root.join("src/lib.rs"), &runners)?.command, format!("rust-script {}", quote(&root.join("src/lib.rs").to_string_lossy())));
+        assert!(run_command(&root.join("notes.txt"), &runners).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,328 @@
+use super::workspace_edit::{LineEdit, WorkspaceEdit};
+use regex::Regex;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+/// Find the `src` directory of the crate a file belongs to
+pub fn find_crate_src(file: &Path) -> Option<PathBuf> {
+    file.ancestors()
+        .skip(1)
+        .find(|dir| dir.join("Cargo.toml").exists())
+        .map(|dir| dir.join("src"))
+        .filter(|src| file.starts_with(src))
+}
+
+/// The module path of a source file inside `src`, e.g. `src/a/b.rs` and
+/// `src/a/b/mod.rs` are both `a::b`. The crate root is an empty path.
+pub fn module_path(src: &Path, file: &Path) -> Option<Vec<String>> {
+    if file.extension()? != "rs" {
+        return None;
+    }
+
+    let relative = file.strip_prefix(src).ok()?.with_extension("");
+    let mut segments: Vec<String> = relative
+        .components()
+        .map(|component| component.as_os_str().to_str().map(str::to_string))
+        .collect::<Option<_>>()?;
+
+    if segments == ["lib"] || segments == ["main"] {
+        return Some(Vec::new());
+    }
+    if segments.last().is_some_and(|last| last == "mod") {
+        segments.pop();
+    }
+    Some(segments)
+}
+
+/// The file that declares the child modules of `module`
+fn module_file(src: &Path, module: &[String], read_lines: &dyn Fn(&Path) -> Option<Vec<String>>, child: &str) -> Option<PathBuf> {
+    let candidates = if module.is_empty() {
+        vec![src.join("lib.rs"), src.join("main.rs")]
+    } else {
+        let dir = src.join(module.join("/"));
+        vec![dir.with_extension("rs"), dir.join("mod.rs")]
+    };
+
+    // Prefer the candidate that already declares the child (a crate can have
+    // both lib.rs and main.rs), then the first one that exists
+    let declares_child = |path: &PathBuf| {
+        read_lines(path).is_some_and(|lines| lines.iter().any(|line| parse_mod_declaration(line, child).is_some()))
+    };
+    candidates.iter().find(|path| declares_child(path))
+        .or_else(|| candidates.iter().find(|path| path.exists()))
+        .cloned()
+}
+
+/// Where a new `mod` declaration goes in a module file's lines, after the
+/// last one or at the top, and the visibility that one has
+fn declaration_position(lines: &[String]) -> (usize, String) {
+    let mod_line = Regex::new(r"^\s*((?:pub(?:\([^)]*\))?\s+)?)mod\s+\w+\s*;").expect("valid regex");
+    lines.iter()
+        .enumerate()
+        .rev()
+        .find_map(|(index, line)| mod_line.captures(line).map(|captures| (index + 1, captures[1].to_string())))
+        .unwrap_or((0, String::new()))
+}
+
+/// Build the edits that declare a newly created source file as a module:
+/// `mod name;` in its parent module, and a `#[cfg(test)] mod tests;` with
+/// a `tests.rs` next to it, which start deselected so they're opt-in
+///
+/// Returns `None` when the file isn't a module of a crate or is already
+/// declared. `read_lines` supplies file contents so unsaved buffers are used.
+pub fn new_module_edit(file: &Path, read_lines: &dyn Fn(&Path) -> Option<Vec<String>>) -> Option<WorkspaceEdit> {
+    let src = find_crate_src(file)?;
+    let module = module_path(&src, file)?;
+    let (name, parent) = module.split_last()?;
+    let parent_file = module_file(&src, parent, read_lines, name)?;
+    let parent_lines = read_lines(&parent_file)?;
+    if parent_lines.iter().any(|line| parse_mod_declaration(line, name).is_some()) {
+        return None;
+    }
+
+    let mut edit = WorkspaceEdit::new(&format!("Declare module {}", module.join("::")));
+    let (position, visibility) = declaration_position(&parent_lines);
+    edit.add_edit(&parent_file.to_string_lossy(), LineEdit::insert(position, &format!("{}mod {};", visibility, name)));
+    if name == "tests" {
+        return Some(edit);
+    }
+
+    let lines = read_lines(file).unwrap_or_default();
+    let blank = lines.iter().all(|line| line.trim().is_empty());
+    let mut declaration = vec!["#[cfg(test)]", "mod tests;"];
+    if !blank {
+        declaration.insert(0, "");
+    }
+    let at = if blank { 0 } else { lines.len() };
+    let tests_file = src.join(module.join("/")).join("tests.rs");
+    let tests = ["use super::*;".to_string(), String::new(), "#[test]".to_string(), format!("fn test_{}() {{", name), "}".to_string()];
+    let test_edits = declaration.iter()
+        .map(|line| (file.to_path_buf(), LineEdit::insert(at, line)))
+        .chain(tests.iter().map(|line| (tests_file.clone(), LineEdit::insert(0, line))));
+    for (path, mut line_edit) in test_edits {
+        line_edit.selected = false;
+        edit.add_edit(&path.to_string_lossy(), line_edit);
+    }
+    Some(edit)
+}
+
+/// If the line is `mod name;` (with any visibility), return the text before `mod`
+fn parse_mod_declaration(line: &str, name: &str) -> Option<String> {
+    let pattern = Regex::new(&format!(
+        r"^(\s*(?:pub(?:\([^)]*\))?\s+)?)mod\s+{}\s*;",
+        regex::escape(name)
+    )).ok()?;
+    pattern.captures(line).map(|captures| captures[1].to_string())
+}
+
+/// Build the edits that keep a crate compiling after a source file moves
+/// from `old_file` to `new_file`
+///
+/// The `mod` declaration is renamed, or moved to the new parent module, and
+/// `crate::`, `self::`, `super::` and 2018-style relative `use` paths that
+/// named the old module are rewritten. Returns `None` when the files aren't
+/// modules of the same crate or nothing refers to the old path.
+/// `read_lines` supplies file contents so unsaved buffers are used.
+pub fn module_rename_edit(
+    old_file: &Path,
+    new_file: &Path,
+    read_lines: &dyn Fn(&Path) -> Option<Vec<String>>,
+) -> Option<WorkspaceEdit> {
+    let src = find_crate_src(new_file)?;
+    let old_module = module_path(&src, old_file)?;
+    let new_module = module_path(&src, new_file)?;
+    if old_module.is_empty() || new_module.is_empty() || old_module == new_module {
+        return None;
+    }
+
+    let (old_name, old_parent) = old_module.split_last()?;
+    let (new_name, new_parent) = new_module.split_last()?;
+
+    let mut edit = WorkspaceEdit::new(&format!(
+        "Update module paths: {} -> {}",
+        old_module.join("::"),
+        new_module.join("::")
+    ));
+    let mut declaration_lines: HashSet<(PathBuf, usize)> = HashSet::new();
+
+    // Declaration in the parent module
+    if let Some(old_parent_file) = module_file(&src, old_parent, read_lines, old_name) {
+        let lines = read_lines(&old_parent_file).unwrap_or_default();
+        let declaration = lines.iter().enumerate()
+            .find_map(|(index, line)| parse_mod_declaration(line, old_name).map(|prefix| (index, prefix)));
+
+        if let Some((index, prefix)) = declaration {
+            let old_line = &lines[index];
+            let path = old_parent_file.to_string_lossy().to_string();
+            declaration_lines.insert((old_parent_file.clone(), index));
+
+            if old_parent == new_parent {
+                let renamed = old_line.replacen(&format!("mod {}", old_name), &format!("mod {}", new_name), 1);
+                let renamed = if renamed == *old_line {
+                    format!("{}mod {};", prefix, new_name)
+                } else {
+                    renamed
+                };
+                edit.add_edit(&path, LineEdit::replace(index, old_line, &renamed));
+            } else {
+                edit.add_edit(&path, LineEdit::delete(index, old_line));
+
+                if let Some(new_parent_file) = module_file(&src, new_parent, read_lines, new_name) {
+                    let new_lines = read_lines(&new_parent_file).unwrap_or_default();
+                    let already_declared = new_lines.iter().any(|line| parse_mod_declaration(line, new_name).is_some());
+                    if !already_declared {
+                        let (position, _) = declaration_position(&new_lines);
+                        let visibility = prefix.trim_start();
+                        edit.add_edit(
+                            &new_parent_file.to_string_lossy(),
+                            LineEdit::insert(position, &format!("{}mod {};", visibility, new_name)),
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    // Paths that refer to the old module
+    let new_absolute = format!("crate::{}", new_module.join("::"));
+    let crate_pattern = Regex::new(&format!(r"\bcrate::{}\b", regex::escape(&old_module.join("::")))).ok()?;
+    let self_pattern = Regex::new(&format!(r"\bself::{}\b", regex::escape(old_name))).ok()?;
+    let super_pattern = Regex::new(&format!(r"\bsuper::{}\b", regex::escape(old_name))).ok()?;
+    let use_pattern = Regex::new(&format!(r"^(\s*(?:pub(?:\([^)]*\))?\s+)?use\s+){}::", regex::escape(old_name))).ok()?;
+    let same_parent = old_parent == new_parent;
+
+    let files = WalkDir::new(&src)
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_file() && entry.path().extension().is_some_and(|ext| ext == "rs"));
+
+    for entry in files {
+        let file = entry.path();
+        let Some(module) = module_path(&src, file) else { continue };
+        let Some(lines) = read_lines(file) else { continue };
+        // The moved file's own relative paths are left alone
+        if file == new_file {
+            continue;
+        }
+
+        let in_old_parent = module == old_parent;
+        let in_old_sibling = module.split_last().is_some_and(|(_, parent)| parent == old_parent);
+        let path = file.to_string_lossy().to_string();
+
+        for (index, line) in lines.iter().enumerate() {
+            if declaration_lines.contains(&(file.to_path_buf(), index)) {
+                continue;
+            }
+
+            let mut updated = crate_pattern.replace_all(line, new_absolute.as_str()).into_owned();
+            if in_old_parent {
+                let replacement = if same_parent { format!("self::{}", new_name) } else { new_absolute.clone() };
+                updated = self_pattern.replace_all(&updated, replacement.as_str()).into_owned();
+
+                let replacement = if same_parent { format!("${{1}}{}::", new_name) } else { format!("${{1}}{}::", new_absolute) };
+                updated = use_pattern.replace(&updated, replacement.as_str()).into_owned();
+            }
+            if in_old_sibling {
+                let replacement = if same_parent { format!("super::{}", new_name) } else { new_absolute.clone() };
+                updated = super_pattern.replace_all(&updated, replacement.as_str()).into_owned();
+            }
+
+            if updated != *line {
+                edit.add_edit(&path, LineEdit::replace(index, line, &updated));
+            }
+        }
+    }
+
+    if edit.is_empty() {
+        None
+    } else {
+        Some(edit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn read(path: &Path) -> Option<Vec<String>> {
+        fs::read_to_string(path).ok().map(|content| content.lines().map(str::to_string).collect())
+    }
+
+    #[test]
+    fn test_module_path() {
+        let src = Path::new("/p/src");
+        assert_eq!(module_path(src, Path::new("/p/src/lib.rs")), Some(vec![]));
+        assert_eq!(module_path(src, Path::new("/p/src/a/mod.rs")), Some(vec!["a".to_string()]));
+        assert_eq!(module_path(src, Path::new("/p/src/a/b.rs")), Some(vec!["a".to_string(), "b".to_string()]));
+        assert_eq!(module_path(src, Path::new("/p/README.md")), None);
+    }
+
+    #[test]
+    fn test_rename_within_parent() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        fs::write(root.join("Cargo.toml"), "[package]\nname = \"demo\"\n").unwrap();
+        fs::create_dir_all(root.join("src/net")).unwrap();
+        fs::write(root.join("src/main.rs"), "mod net;\nuse crate::net::client::Client;\n\nfn main() {}\n").unwrap();
+        fs::write(root.join("src/net/mod.rs"), "pub mod client;\npub use self::client::Client;\n").unwrap();
+        fs::write(root.join("src/net/server.rs"), "use super::client::Client;\nuse crate::net::clientele::X;\n").unwrap();
+        fs::write(root.join("src/net/http.rs"), "pub struct Client;\n").unwrap();
+
+        let edit = module_rename_edit(&root.join("src/net/client.rs"), &root.join("src/net/http.rs"), &read).unwrap();
+        assert_eq!(edit.edit_count(), 4);
+
+        let file = |name: &str| edit.files.iter().find(|file| file.path.ends_with(name)).unwrap();
+        assert_eq!(file("net/mod.rs").edits[0].new.as_deref(), Some("pub mod http;"));
+        assert_eq!(file("net/mod.rs").edits[1].new.as_deref(), Some("pub use self::http::Client;"));
+        assert_eq!(file("main.rs").edits[0].new.as_deref(), Some("use crate::net::http::Client;"));
+        // Only whole path segments are rewritten
+        assert_eq!(file("server.rs").edits.len(), 1);
+        assert_eq!(file("server.rs").edits[0].new.as_deref(), Some("use super::http::Client;"));
+    }
+
+    #[test]
+    fn test_move_to_other_parent() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        fs::write(root.join("Cargo.toml"), "[package]\nname = \"demo\"\n").unwrap();
+        fs::create_dir_all(root.join("src/util")).unwrap();
+        fs::write(root.join("src/lib.rs"), "pub mod util;\npub(crate) mod parse;\nuse parse::Parser;\n").unwrap();
+        fs::write(root.join("src/util/mod.rs"), "mod strings;\n\npub fn helper() {}\n").unwrap();
+        fs::write(root.join("src/util/parse.rs"), "pub struct Parser;\n").unwrap();
+
+        let edit = module_rename_edit(&root.join("src/parse.rs"), &root.join("src/util/parse.rs"), &read).unwrap();
+        let lib = edit.files.iter().find(|file| file.path.ends_with("lib.rs")).unwrap();
+        assert_eq!(lib.edits[0], LineEdit::delete(1, "pub(crate) mod parse;"));
+        assert_eq!(lib.edits[1].new.as_deref(), Some("use crate::util::parse::Parser;"));
+
+        let util = edit.files.iter().find(|file| file.path.ends_with("util/mod.rs")).unwrap();
+        assert_eq!(util.edits, vec![LineEdit::insert(1, "pub(crate) mod parse;")]);
+    }
+
+    #[test]
+    fn test_new_module_is_declared_with_optional_tests() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        fs::write(root.join("Cargo.toml"), "[package]\nname = \"demo\"\n").unwrap();
+        fs::create_dir_all(root.join("src/net")).unwrap();
+        fs::write(root.join("src/main.rs"), "mod net;\n\nfn main() {}\n").unwrap();
+        fs::write(root.join("src/net/mod.rs"), "pub mod client;\n\npub fn connect() {}\n").unwrap();
+        fs::write(root.join("src/net/server.rs"), "").unwrap();
+
+        let edit = new_module_edit(&root.join("src/net/server.rs"), &read).unwrap();
+        assert_eq!(edit.title, "Declare module net::server");
+        let file = |name: &str| edit.files.iter().find(|file| file.path.ends_with(name)).unwrap();
+        assert_eq!(file("net/mod.rs").edits, vec![LineEdit::insert(1, "pub mod server;")]);
+        let server = &file("net/server.rs").edits;
+        assert_eq!(server.iter().map(|edit| edit.new.as_deref().unwrap()).collect::<Vec<_>>(), vec!["#[cfg(test)]", "mod tests;"]);
+        assert!(server.iter().all(|edit| !edit.selected));
+        assert_eq!(file("net/server/tests.rs").edits.len(), 5);
+
+        // Already declared
+        assert!(new_module_edit(&root.join("src/net/client.rs"), &read).is_none());
+        // Not in a crate's sources
+        assert!(new_module_edit(&root.join("build.rs"), &read).is_none());
+    }
+}
@@ -0,0 +1,171 @@
+use anyhow::{Context, Result};
+use std::fs::{self, File, Metadata, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// How files are written to disk, from the config
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WriteOptions {
+    /// Write a temporary file next to the target and rename it over the
+    /// target, so a crash mid-write can't leave it cut short
+    pub atomic: bool,
+    /// Copy the file as it was to a backup before writing it
+    pub backup: bool,
+    /// Where backups go; next to the file when unset
+    pub backup_dir: Option<PathBuf>,
+}
+
+/// Write a file and wait for it to reach the disk, so a program waiting on
+/// zim, like git for a commit message, reads what was saved
+///
+/// Symlinks are written through. An atomic write keeps the file's
+/// permissions and owner; when it can't (the owner can't be kept, the file
+/// has other hard links, or the directory doesn't allow new files), the
+/// file is written in place instead.
+pub fn write_file(path: &Path, bytes: &[u8], options: &WriteOptions) -> Result<()> {
+    let target = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let existing = fs::metadata(&target).ok();
+
+    if options.backup && existing.is_some() {
+        let backup = backup_path(&target, options.backup_dir.as_deref());
+        if let Some(dir) = backup.parent() {
+            fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+        fs::copy(&target, &backup)
+            .with_context(|| format!("Failed to back up {} to {}", path.display(), backup.display()))?;
+    }
+
+    if options.atomic && existing.as_ref().is_none_or(has_no_other_links) {
+        let temp = temp_path(&target);
+        // A directory zim can't create files in may still have files it can write
+        if let Ok(file) = OpenOptions::new().write(true).create_new(true).open(&temp) {
+            match replace(file, &temp, &target, bytes, existing.as_ref()) {
+                Ok(true) => return Ok(()),
+                Ok(false) => {
+                    let _ = fs::remove_file(&temp);
+                },
+                Err(e) => {
+                    let _ = fs::remove_file(&temp);
+                    return Err(e.context(format!("Failed to write {}", path.display())));
+                },
+            }
+        }
+    }
+
+    let mut file = File::create(&target).with_context(|| format!("Failed to write {}", path.display()))?;
+    file.write_all(bytes).with_context(|| format!("Failed to write {}", path.display()))?;
+    file.sync_all().with_context(|| format!("Failed to flush {} to disk", path.display()))?;
+    Ok(())
+}
+
+/// Fill the temporary file and rename it over `target`, returning false
+/// without touching `target` if its owner can't be kept
+fn replace(mut file: File, temp: &Path, target: &Path, bytes: &[u8], existing: Option<&Metadata>) -> Result<bool> {
+    if let Some(metadata) = existing {
+        if !keep_owner(temp, metadata) {
+            return Ok(false);
+        }
+        fs::set_permissions(temp, metadata.permissions())?;
+    }
+    file.write_all(bytes)?;
+    file.sync_all()?;
+    fs::rename(temp, target)?;
+    // The rename itself is only durable once the directory is flushed
+    if let Some(dir) = target.parent().and_then(|dir| File::open(dir).ok()) {
+        let _ = dir.sync_all();
+    }
+    Ok(true)
+}
+
+#[cfg(unix)]
+fn keep_owner(temp: &Path, metadata: &Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    std::os::unix::fs::chown(temp, Some(metadata.uid()), Some(metadata.gid())).is_ok()
+}
+
+#[cfg(not(unix))]
+fn keep_owner(_temp: &Path, _metadata: &Metadata) -> bool {
+    true
+}
+
+/// Renaming over a file with other hard links would split it from them
+#[cfg(unix)]
+fn has_no_other_links(metadata: &Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    metadata.nlink() <= 1
+}
+
+#[cfg(not(unix))]
+fn has_no_other_links(_metadata: &Metadata) -> bool {
+    true
+}
+
+/// A hidden file next to `target` for writing it atomically
+fn temp_path(target: &Path) -> PathBuf {
+    let name = target.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+    target.with_file_name(format!(".{}.zim-{}.tmp", name, std::process::id()))
+}
+
+/// `file~` next to the file, or in `dir` named after the file's whole path
+/// with `%` for `/`, so files with the same name don't share a backup
+pub fn backup_path(target: &Path, dir: Option<&Path>) -> PathBuf {
+    match dir {
+        None => {
+            let name = target.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+            target.with_file_name(format!("{}~", name))
+        },
+        Some(dir) => dir.join(format!("{}~", target.to_string_lossy().replace(['/', '\\'], "%"))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_atomic_saves_keep_permissions_links_and_a_backup() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let root = dir.path().canonicalize()?;
+        let path = root.join("notes.txt");
+        let atomic = WriteOptions { atomic: true, backup: true, backup_dir: None };
+
+        // A new file has nothing to back up
+        write_file(&path, b"one\n", &atomic)?;
+        assert_eq!(fs::read_to_string(&path)?, "one\n");
+        assert!(!root.join("notes.txt~").exists());
+
+        write_file(&path, b"two\n", &atomic)?;
+        assert_eq!(fs::read_to_string(&path)?, "two\n");
+        assert_eq!(fs::read_to_string(root.join("notes.txt~"))?, "one\n");
+        let names: Vec<String> = fs::read_dir(&root)?.map(|entry| entry.unwrap().file_name().to_string_lossy().to_string()).collect();
+        assert_eq!(names.len(), 2, "temporary file left behind: {:?}", names);
+
+        let backups = root.join("backups");
+        let elsewhere = WriteOptions { backup_dir: Some(backups.clone()), ..atomic.clone() };
+        write_file(&path, b"three\n", &elsewhere)?;
+        let backup = backup_path(&path, Some(&backups));
+        assert!(backup.starts_with(&backups) && backup.to_string_lossy().ends_with("%notes.txt~"));
+        assert_eq!(fs::read_to_string(backup)?, "two\n");
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o640))?;
+            write_file(&path, b"four\n", &atomic)?;
+            assert_eq!(fs::metadata(&path)?.permissions().mode() & 0o777, 0o640);
+
+            // Symlinks stay links, and hard links keep sharing the file
+            let link = root.join("link.txt");
+            std::os::unix::fs::symlink(&path, &link)?;
+            write_file(&link, b"five\n", &atomic)?;
+            assert!(fs::symlink_metadata(&link)?.file_type().is_symlink());
+            assert_eq!(fs::read_to_string(&path)?, "five\n");
+
+            let hard = root.join("hard.txt");
+            fs::hard_link(&path, &hard)?;
+            write_file(&path, b"six\n", &atomic)?;
+            assert_eq!(fs::read_to_string(&hard)?, "six\n");
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,150 @@
+/// The overview ruler on the editor's right border: where the view is in
+/// the file, and ticks at its functions, types and section headers that
+/// can be clicked to jump there
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Scrollbar {
+    /// Screen column and first row of the ruler
+    pub x: u16,
+    pub y: u16,
+    pub height: u16,
+    pub line_count: usize,
+    /// Lines that start a function, type or section, in order
+    pub ticks: Vec<usize>,
+}
+
+/// How one row of the ruler is drawn
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Row {
+    /// Part of the view's position in the file
+    pub thumb: bool,
+    /// A function, type or section starts in the lines this row stands for
+    pub tick: bool,
+}
+
+impl Scrollbar {
+    /// Lines each row stands for; files shorter than the ruler get a row
+    /// per line, lined up with the text
+    fn scale(&self) -> usize {
+        self.line_count.max(self.height as usize)
+    }
+
+    fn row_of(&self, line: usize) -> usize {
+        line * self.height as usize / self.scale().max(1)
+    }
+
+    /// The rows from top to bottom, with the view showing `visible` lines
+    /// from `top_line`; the thumb is left out when the whole file fits
+    pub fn rows(&self, top_line: usize, visible: usize) -> Vec<Row> {
+        let height = self.height as usize;
+        let mut rows = vec![Row { thumb: false, tick: false }; height];
+        if self.line_count > visible {
+            let first = self.row_of(top_line).min(height.saturating_sub(1));
+            let last = self.row_of((top_line + visible).min(self.line_count)).max(first + 1).min(height);
+            for row in &mut rows[first..last] {
+                row.thumb = true;
+            }
+        }
+        for &line in &self.ticks {
+            if let Some(row) = rows.get_mut(self.row_of(line)) {
+                row.tick = true;
+            }
+        }
+        rows
+    }
+
+    /// Lines with a tick on screen row `row`
+    pub fn ticks_on(&self, row: u16) -> impl Iterator<Item = usize> + '_ {
+        let row = row.checked_sub(self.y).map(usize::from);
+        self.ticks.iter().copied().filter(move |&line| Some(self.row_of(line)) == row)
+    }
+
+    /// The line a click at `column`, `row` jumps to: the first tick on that
+    /// row, or else the part of the file the row stands for
+    pub fn line_at(&self, column: u16, row: u16) -> Option<usize> {
+        if column != self.x || row < self.y || row >= self.y + self.height || self.line_count == 0 {
+            return None;
+        }
+        if let Some(line) = self.ticks_on(row).next() {
+            return Some(line);
+        }
+        let line = usize::from(row - self.y) * self.scale() / self.height as usize;
+        Some(line.min(self.line_count - 1))
+    }
+}
+
+/// Lines that start a function, impl, type or module, or a section: a
+/// Markdown heading, or a comment like `// MARK: ...`, `#region` or
+/// `// ---- Name ----`
+pub fn structure_lines(lines: &[String], markdown: bool) -> Vec<usize> {
+    lines.iter()
+        .enumerate()
+        .filter(|(_, line)| if markdown { is_heading(line) } else { starts_item(line) || is_section_comment(line) })
+        .map(|(index, _)| index)
+        .collect()
+}
+
+fn is_heading(line: &str) -> bool {
+    let hashes = line.len() - line.trim_start_matches('#').len();
+    (1..=6).contains(&hashes) && line[hashes..].starts_with(' ')
+}
+
+/// Keywords that open an item, after any visibility and qualifiers
+const ITEM_KEYWORDS: [&str; 11] = ["fn", "impl", "trait", "struct", "enum", "mod", "macro_rules!", "def", "class", "function", "func"];
+const QUALIFIERS: [&str; 9] = ["pub", "async", "unsafe", "const", "extern", "export", "default", "static", "abstract"];
+
+fn starts_item(line: &str) -> bool {
+    let mut words = line.split_whitespace().peekable();
+    while let Some(word) = words.peek() {
+        let bare = word.split('(').next().unwrap_or(word);
+        if QUALIFIERS.contains(&bare) || word.starts_with('"') {
+            words.next();
+        } else {
+            break;
+        }
+    }
+    let (Some(keyword), Some(name)) = (words.next(), words.next()) else { return false };
+    // `impl<T> Trait for X` puts the generics on the keyword
+    let keyword = keyword.split('<').next().unwrap_or(keyword);
+    ITEM_KEYWORDS.contains(&keyword) && name.starts_with(|c: char| c.is_alphabetic() || c == '_' || c == '<' || c == '(')
+}
+
+fn is_section_comment(line: &str) -> bool {
+    let trimmed = line.trim_start();
+    let Some(body) = ["//", "#", "--", "/*"].iter().find_map(|marker| trimmed.strip_prefix(marker)) else { return false };
+    let body = body.trim_start();
+    body.starts_with("MARK:") || body.starts_with("region") || body.starts_with("===") || body.starts_with("---")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ticks_mark_items_and_clicks_jump_to_them() {
+        let lines: Vec<String> = [
+            "use std::fs;",
+            "// ---- Parsing ----",
+            "pub(crate) async fn parse(text: &str) {",
+            "    let f = |x| x;",
+            "}",
+            "impl<T> Parser for Wrapper<T> {",
+            "    fn next(&mut self) {}",
+            "}",
+            "extern \"C\" fn callback() {}",
+            "let function = 1;",
+        ].iter().map(|line| line.to_string()).collect();
+        assert_eq!(structure_lines(&lines, false), [1, 2, 5, 6, 8]);
+        let markdown: Vec<String> = ["# Title", "text", "#hashtag", "## Usage"].iter().map(|line| line.to_string()).collect();
+        assert_eq!(structure_lines(&markdown, true), [0, 3]);
+
+        // 100 lines on 10 rows, showing lines 50 to 59
+        let scrollbar = Scrollbar { x: 79, y: 1, height: 10, line_count: 100, ticks: vec![5, 42, 47] };
+        let rows = scrollbar.rows(50, 10);
+        assert_eq!(rows.iter().map(|row| row.thumb).collect::<Vec<_>>(), [false, false, false, false, false, true, false, false, false, false]);
+        assert_eq!(rows.iter().map(|row| row.tick).collect::<Vec<_>>(), [true, false, false, false, true, false, false, false, false, false]);
+        assert_eq!(scrollbar.line_at(79, 5), Some(42));
+        assert_eq!(scrollbar.line_at(79, 8), Some(70));
+        assert_eq!(scrollbar.line_at(78, 5), None);
+        assert_eq!(scrollbar.line_at(79, 11), None);
+    }
+}
@@ -0,0 +1,93 @@
+use anyhow::{anyhow, Result};
+use std::cmp::Ordering;
+
+/// How `:sort` orders lines, from its `!` and flags
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SortOptions {
+    /// Largest first (`:sort!`)
+    pub reverse: bool,
+    /// Keep only the first of equal lines (`u`)
+    pub unique: bool,
+    /// Compare the first number on each line (`n`)
+    pub numeric: bool,
+    /// Compare without case (`i`)
+    pub ignore_case: bool,
+}
+
+impl SortOptions {
+    /// Parse what follows `sort`: an optional `!`, then flags like `u`,
+    /// `n` and `i`, which may be separated by spaces
+    pub fn parse(args: &str) -> Result<Self> {
+        let (reverse, flags) = match args.strip_prefix('!') {
+            Some(flags) => (true, flags),
+            None => (false, args),
+        };
+        let mut options = SortOptions { reverse, ..Self::default() };
+        for flag in flags.chars().filter(|c| !c.is_whitespace()) {
+            match flag {
+                'u' => options.unique = true,
+                'n' => options.numeric = true,
+                'i' => options.ignore_case = true,
+                _ => return Err(anyhow!("Unknown sort flag: {}", flag)),
+            }
+        }
+        Ok(options)
+    }
+
+    fn compare(&self, a: &str, b: &str) -> Ordering {
+        if self.numeric {
+            // Lines without a number go first, in the order they were in
+            return first_number(a).cmp(&first_number(b));
+        }
+        if self.ignore_case {
+            return a.to_lowercase().cmp(&b.to_lowercase());
+        }
+        a.cmp(b)
+    }
+}
+
+/// `lines` sorted the way `:sort` does it; the sort is stable, so equal
+/// lines keep their order (and `u` keeps the first of them)
+pub fn sort_lines(lines: &[String], options: SortOptions) -> Vec<String> {
+    let mut sorted = lines.to_vec();
+    sorted.sort_by(|a, b| options.compare(a, b));
+    if options.unique {
+        sorted.dedup_by(|b, a| options.compare(a, b) == Ordering::Equal);
+    }
+    if options.reverse {
+        sorted.reverse();
+    }
+    sorted
+}
+
+/// The first decimal number on the line, with a `-` right before it
+fn first_number(line: &str) -> Option<i128> {
+    let start = line.find(|c: char| c.is_ascii_digit())?;
+    let digits = line[start..].find(|c: char| !c.is_ascii_digit()).map_or(&line[start..], |end| &line[start..start + end]);
+    // Too many digits to hold saturate rather than sorting as no number
+    let value = digits.parse::<i128>().unwrap_or(i128::MAX);
+    Some(if line[..start].ends_with('-') { -value } else { value })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(text: &[&str]) -> Vec<String> {
+        text.iter().map(|line| line.to_string()).collect()
+    }
+
+    #[test]
+    fn test_sort_flags() -> Result<()> {
+        let names = lines(&["pear", "Apple", "fig", "apple", "fig"]);
+        assert_eq!(sort_lines(&names, SortOptions::parse("")?), ["Apple", "apple", "fig", "fig", "pear"]);
+        assert_eq!(sort_lines(&names, SortOptions::parse("! u")?), ["pear", "fig", "apple", "Apple"]);
+        assert_eq!(sort_lines(&names, SortOptions::parse("ui")?), ["Apple", "fig", "pear"]);
+
+        let numbers = lines(&["item 10", "item 9", "none", "x -3", "item 10 again"]);
+        assert_eq!(sort_lines(&numbers, SortOptions::parse("n")?), ["none", "x -3", "item 9", "item 10", "item 10 again"]);
+        assert_eq!(sort_lines(&numbers, SortOptions::parse("nu")?), ["none", "x -3", "item 9", "item 10"]);
+        assert!(SortOptions::parse("x").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,147 @@
+use anyhow::{anyhow, Result};
+use regex::{Regex, RegexBuilder};
+
+/// A parsed `:s/pattern/replacement/flags` command
+pub struct Substitute {
+    pattern: Regex,
+    /// Replacement in the regex crate's syntax
+    replacement: String,
+    /// Replace every match on a line rather than the first (`g`)
+    global: bool,
+}
+
+impl Substitute {
+    /// Parse what follows the `s`: a delimiter (usually `/`), the pattern,
+    /// the replacement and flags. Like vim, `\1` and `&` in the replacement
+    /// refer to groups and the whole match, and `\r` splits the line.
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut chars = text.chars();
+        let delimiter = chars.next()
+            .filter(|c| !c.is_alphanumeric() && !c.is_whitespace() && *c != '\\' && *c != '"')
+            .ok_or_else(|| anyhow!("Usage: s/pattern/replacement/[gi]"))?;
+        let parts = split_unescaped(chars.as_str(), delimiter);
+        let (pattern, replacement, flags) = match parts.as_slice() {
+            [pattern] => (pattern.as_str(), "", ""),
+            [pattern, replacement] => (pattern.as_str(), replacement.as_str(), ""),
+            [pattern, replacement, flags] => (pattern.as_str(), replacement.as_str(), flags.as_str()),
+            _ => return Err(anyhow!("Trailing characters: {}", text)),
+        };
+        if pattern.is_empty() {
+            return Err(anyhow!("Empty search pattern"));
+        }
+
+        let mut global = false;
+        let mut ignore_case = false;
+        for flag in flags.chars() {
+            match flag {
+                'g' => global = true,
+                'i' => ignore_case = true,
+                'I' => ignore_case = false,
+                _ => return Err(anyhow!("Unknown substitute flag: {}", flag)),
+            }
+        }
+
+        let pattern = RegexBuilder::new(pattern)
+            .case_insensitive(ignore_case)
+            .build()
+            .map_err(|e| anyhow!("Invalid pattern: {}", e))?;
+        Ok(Self { pattern, replacement: convert_replacement(replacement), global })
+    }
+
+    pub fn pattern(&self) -> &str {
+        self.pattern.as_str()
+    }
+
+    /// The line with its matches replaced, or None if nothing matched
+    pub fn apply(&self, line: &str) -> Option<String> {
+        if !self.pattern.is_match(line) {
+            return None;
+        }
+        let limit = if self.global { 0 } else { 1 };
+        Some(self.pattern.replacen(line, limit, self.replacement.as_str()).into_owned())
+    }
+}
+
+/// Recognize `s/...` (with any delimiter), returning the text after the
+/// `s`; a range before it, like the `%` of `%s/...`, is split off first
+pub fn split_command(cmd: &str) -> Option<&str> {
+    let text = cmd.strip_prefix('s')?;
+    let delimiter = text.chars().next()?;
+    (!delimiter.is_alphanumeric() && !delimiter.is_whitespace()).then_some(text)
+}
+
+/// Split on `delimiter`, except where it's escaped; an escaped delimiter
+/// stands for itself, and other escapes are kept for the regex
+fn split_unescaped(text: &str, delimiter: char) -> Vec<String> {
+    let mut parts = vec![String::new()];
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        let part = parts.last_mut().unwrap();
+        match c {
+            '\\' => match chars.next() {
+                Some(next) if next == delimiter => part.push(next),
+                Some(next) => {
+                    part.push('\\');
+                    part.push(next);
+                },
+                None => part.push('\\'),
+            },
+            c if c == delimiter => parts.push(String::new()),
+            c => part.push(c),
+        }
+    }
+    parts
+}
+
+/// Turn vim's `\1`, `&`, `\&` and `\r` into the regex crate's replacement
+/// syntax, escaping its `$`
+fn convert_replacement(text: &str) -> String {
+    let mut converted = String::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some(digit @ '0'..='9') => converted.push_str(&format!("${{{}}}", digit)),
+                Some('r') | Some('n') => converted.push('\n'),
+                Some('t') => converted.push('\t'),
+                Some('$') => converted.push_str("$$"),
+                Some(other) => converted.push(other),
+                None => converted.push('\\'),
+            },
+            '&' => converted.push_str("${0}"),
+            '$' => converted.push_str("$$"),
+            c => converted.push(c),
+        }
+    }
+    converted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_substitute_like_vim() {
+        let first = Substitute::parse("/o/0/").unwrap();
+        assert_eq!(first.apply("foo boo").as_deref(), Some("f0o boo"));
+        assert_eq!(first.apply("bar"), None);
+
+        let all = Substitute::parse("/o/0/g").unwrap();
+        assert_eq!(all.apply("foo boo").as_deref(), Some("f00 b00"));
+
+        let groups = Substitute::parse(r"#(\w+)=(\w+)#\2=\1 [&] \& $x#").unwrap();
+        assert_eq!(groups.apply("a=b").as_deref(), Some("b=a [a=b] & $x"));
+
+        let paths = Substitute::parse(r"/src\/old/src\/new/i").unwrap();
+        assert_eq!(paths.apply("SRC/OLD/lib.rs").as_deref(), Some("src/new/lib.rs"));
+
+        assert!(Substitute::parse("/(/x/").is_err());
+        assert!(Substitute::parse("/a/b/z").is_err());
+        assert!(Substitute::parse("//b/").is_err());
+
+        assert_eq!(split_command("s/a/b/g"), Some("/a/b/g"));
+        assert_eq!(split_command("s#a#b#"), Some("#a#b#"));
+        assert_eq!(split_command("set number"), None);
+        assert_eq!(split_command("sav x"), None);
+    }
+}
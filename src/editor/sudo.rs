@@ -0,0 +1,77 @@
+use super::ids::BufferId;
+use anyhow::{anyhow, Context, Result};
+use std::io::{ErrorKind, Read, Write};
+use std::process::{Command, Stdio};
+
+/// A write `:w!!` prepared, waiting for the terminal to be handed over so
+/// sudo can ask for the password itself; zim never sees it
+#[derive(Debug, Clone)]
+pub struct SudoWrite {
+    pub buffer: BufferId,
+    pub path: String,
+    pub bytes: Vec<u8>,
+}
+
+/// Programs that run `tee` as root, tried in order: sudo, then polkit's
+/// pkexec where sudo isn't installed
+const ELEVATE: [&str; 2] = ["sudo", "pkexec"];
+
+/// Write `bytes` to `path` as root with `tee`, which writes the file in
+/// place, so its owner and permissions stay as they were
+pub fn write(path: &str, bytes: &[u8]) -> Result<()> {
+    for program in ELEVATE {
+        match run(&[program], path, bytes) {
+            Err(e) if e.downcast_ref::<std::io::Error>().is_some_and(|e| e.kind() == ErrorKind::NotFound) => continue,
+            result => return result,
+        }
+    }
+    Err(anyhow!("Neither sudo nor pkexec is installed"))
+}
+
+/// Pipe `bytes` into `tee -- path` run through `prefix`
+fn run(prefix: &[&str], path: &str, bytes: &[u8]) -> Result<()> {
+    let mut child = Command::new(prefix[0])
+        .args(&prefix[1..])
+        .args(["tee", "--", path])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    // A refused password closes the pipe before everything is written
+    let written = child.stdin.take().map_or(Ok(()), |mut stdin| stdin.write_all(bytes));
+    let mut errors = String::new();
+    if let Some(mut stderr) = child.stderr.take() {
+        let _ = stderr.read_to_string(&mut errors);
+    }
+    let status = child.wait().with_context(|| format!("Failed to run {}", prefix[0]))?;
+    if !status.success() {
+        let errors = errors.trim();
+        return Err(match errors.is_empty() {
+            true => anyhow!("{} tee failed ({})", prefix[0], status),
+            false => anyhow!("{} tee failed: {}", prefix[0], errors),
+        });
+    }
+    written.with_context(|| format!("Failed to write {}", path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tee_writes_the_file_and_reports_failures() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("hosts");
+        let path = path.to_str().unwrap();
+
+        // `env` runs tee as the same user, standing in for sudo
+        run(&["env"], path, b"127.0.0.1 localhost\n")?;
+        assert_eq!(std::fs::read_to_string(path)?, "127.0.0.1 localhost\n");
+
+        let missing = dir.path().join("missing/hosts");
+        let error = run(&["env"], missing.to_str().unwrap(), b"text").unwrap_err();
+        assert!(error.to_string().starts_with("env tee failed: tee: "), "{}", error);
+        Ok(())
+    }
+}
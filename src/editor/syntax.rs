@@ -1,16 +1,20 @@
 use anyhow::Result;
 use std::path::Path;
 use syntect::easy::HighlightLines;
-use syntect::highlighting::{ThemeSet, Style};
+use syntect::highlighting::{ThemeSet, Style, Theme};
 use syntect::parsing::{SyntaxSet, SyntaxReference};
 use syntect::util::LinesWithEndings;
+use std::cell::OnceCell;
 use std::sync::Arc;
 use std::hash::{Hash, Hasher};
 
 /// Manages syntax highlighting
+///
+/// Syntax definitions and themes take a noticeable moment to load, so they
+/// are only loaded the first time a file actually needs highlighting.
 pub struct SyntaxHighlighter {
-    syntax_set: SyntaxSet,
-    theme_set: ThemeSet,
+    syntax_set: OnceCell<Arc<SyntaxSet>>,
+    theme_set: OnceCell<ThemeSet>,
     current_theme: String,
 }
 
@@ -56,20 +60,26 @@ impl Hash for HighlightedLine {
 impl SyntaxHighlighter {
     /// Create a new syntax highlighter with default settings
     pub fn new() -> Self {
-        // Load syntax definitions and themes
-        let syntax_set = SyntaxSet::load_defaults_newlines();
-        let theme_set = ThemeSet::load_defaults();
-        
         Self {
-            syntax_set,
-            theme_set,
+            syntax_set: OnceCell::new(),
+            theme_set: OnceCell::new(),
             current_theme: "Solarized (dark)".to_string(), // Default theme
         }
     }
+
+    /// Syntax definitions, loaded on first use
+    pub fn syntax_set(&self) -> &Arc<SyntaxSet> {
+        self.syntax_set.get_or_init(|| Arc::new(SyntaxSet::load_defaults_newlines()))
+    }
+
+    /// Themes, loaded on first use
+    fn theme_set(&self) -> &ThemeSet {
+        self.theme_set.get_or_init(ThemeSet::load_defaults)
+    }
     
     /// Set the current theme
     pub fn set_theme(&mut self, theme_name: &str) -> Result<()> {
-        if self.theme_set.themes.contains_key(theme_name) {
+        if self.theme_set().themes.contains_key(theme_name) {
             self.current_theme = theme_name.to_string();
             Ok(())
         } else {
@@ -79,13 +89,18 @@ impl SyntaxHighlighter {
     
     /// List available themes
     pub fn list_themes(&self) -> Vec<String> {
-        self.theme_set.themes.keys().cloned().collect()
+        self.theme_set().themes.keys().cloned().collect()
     }
     
     /// Get the current theme
     pub fn current_theme(&self) -> &str {
         &self.current_theme
     }
+
+    /// The current theme's colors
+    pub fn theme(&self) -> &Theme {
+        &self.theme_set().themes[&self.current_theme]
+    }
     
     /// Determine the syntax to use based on file extension or first line
     pub fn determine_syntax(&self, file_path: Option<&str>, first_line: &str) -> Option<Arc<SyntaxReference>> {
@@ -95,7 +110,7 @@ impl SyntaxHighlighter {
             if let Some(extension) = path.extension() {
                 if let Some(extension_str) = extension.to_str() {
                     // Try to find syntax by extension
-                    if let Some(syntax) = self.syntax_set.find_syntax_by_extension(extension_str) {
+                    if let Some(syntax) = self.syntax_set().find_syntax_by_extension(extension_str) {
                         return Some(Arc::new(syntax.clone()));
                     }
                 }
@@ -103,7 +118,7 @@ impl SyntaxHighlighter {
             
             // Try by filename (use path string matching)
             let filename = path.file_name().and_then(|f| f.to_str()).unwrap_or("");
-            for syntax in self.syntax_set.syntaxes() {
+            for syntax in self.syntax_set().syntaxes() {
                 // Check file extensions
                 for ext in &syntax.file_extensions {
                     if filename.ends_with(ext) {
@@ -114,17 +129,23 @@ impl SyntaxHighlighter {
         }
         
         // Try first line detection as fallback
-        if let Some(syntax) = self.syntax_set.find_syntax_by_first_line(first_line) {
+        if let Some(syntax) = self.syntax_set().find_syntax_by_first_line(first_line) {
             return Some(Arc::new(syntax.clone()));
         }
         
         None
     }
     
+    /// The syntax a filetype like `python` or `sh` stands for, by its name
+    /// or one of its extensions
+    pub fn syntax_for_filetype(&self, filetype: &str) -> Option<Arc<SyntaxReference>> {
+        self.syntax_set().find_syntax_by_token(filetype).map(|syntax| Arc::new(syntax.clone()))
+    }
+    
     /// Highlight a portion of text with the given syntax
     pub fn highlight_text(&self, text: &str, syntax: Arc<SyntaxReference>) -> Vec<HighlightedLine> {
         // Get the current theme
-        let theme = &self.theme_set.themes[&self.current_theme];
+        let theme = &self.theme_set().themes[&self.current_theme];
         
         // Create a highlighter
         let mut highlighter = HighlightLines::new(&syntax, theme);
@@ -133,7 +154,7 @@ impl SyntaxHighlighter {
         let mut result = Vec::new();
         
         for line in LinesWithEndings::from(text) {
-            match highlighter.highlight_line(line, &self.syntax_set) {
+            match highlighter.highlight_line(line, self.syntax_set()) {
                 Ok(ranges) => {
                     // Convert &str to String in ranges
                     let string_ranges = ranges.into_iter()
@@ -157,7 +178,7 @@ impl SyntaxHighlighter {
     
     /// Get a list of supported languages
     pub fn supported_languages(&self) -> Vec<String> {
-        self.syntax_set.syntaxes().iter()
+        self.syntax_set().syntaxes().iter()
             .map(|syntax| syntax.name.clone())
             .collect()
     }
@@ -167,4 +188,18 @@ impl Default for SyntaxHighlighter {
     fn default() -> Self {
         Self::new()
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_syntax_definitions_load_lazily() {
+        let highlighter = SyntaxHighlighter::new();
+        assert!(highlighter.syntax_set.get().is_none());
+
+        let syntax = highlighter.determine_syntax(Some("main.rs"), "fn main() {}");
+        assert_eq!(syntax.unwrap().name, "Rust");
+        assert!(highlighter.syntax_set.get().is_some());
+    }
+}
@@ -0,0 +1,164 @@
+use super::doc_comment::FunctionSignature;
+use anyhow::Result;
+
+const INDENT: &str = "    ";
+
+/// A test stub added to a buffer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TestStub {
+    /// The buffer's lines with the stub added
+    pub lines: Vec<String>,
+    /// Name of the new test function
+    pub name: String,
+    /// The empty line inside the test's body
+    pub body_line: usize,
+}
+
+/// Add a test stub for a function to the file's `#[cfg(test)] mod tests`
+/// block, creating the block at the end of the file if there isn't one
+///
+/// The test is named `test_<function>`, with a number added if that name is
+/// already taken.
+pub fn add_test_stub(lines: &[String], signature: &FunctionSignature) -> Result<TestStub> {
+    if lines.iter().any(|line| line.trim() == "mod tests;") {
+        return Err(anyhow::anyhow!("Tests live in a separate file"));
+    }
+
+    let name = unique_test_name(lines, &signature.name);
+    let stub = |indent: &str| vec![
+        format!("{}#[test]", indent),
+        format!("{}fn {}() {{", indent, name),
+        format!("{}{}", indent, INDENT),
+        format!("{}}}", indent),
+    ];
+
+    let mut result = lines.to_vec();
+    let body_line = match find_tests_module(lines) {
+        Some((start, end)) => {
+            let indent = format!("{}{}", leading_whitespace(&lines[start]), INDENT);
+            // Separate from the previous item unless the block is empty
+            let mut added = Vec::new();
+            if end > start + 1 && !lines[end - 1].trim().is_empty() {
+                added.push(String::new());
+            }
+            let body_line = end + added.len() + 2;
+            added.extend(stub(&indent));
+            result.splice(end..end, added);
+            body_line
+        },
+        None => {
+            while result.last().is_some_and(|line| line.trim().is_empty()) {
+                result.pop();
+            }
+            if !result.is_empty() {
+                result.push(String::new());
+            }
+            result.push("#[cfg(test)]".to_string());
+            result.push("mod tests {".to_string());
+            result.push(format!("{}use super::*;", INDENT));
+            result.push(String::new());
+            let body_line = result.len() + 2;
+            result.extend(stub(INDENT));
+            result.push("}".to_string());
+            body_line
+        },
+    };
+
+    Ok(TestStub { lines: result, name, body_line })
+}
+
+/// The `mod tests {` line following `#[cfg(test)]` and the line with its
+/// closing brace
+fn find_tests_module(lines: &[String]) -> Option<(usize, usize)> {
+    let start = lines.iter().enumerate().position(|(index, line)| {
+        line.trim_start().starts_with("mod tests")
+            && line.trim_end().ends_with('{')
+            && index > 0
+            && lines[..index].iter().rev()
+                .map(|line| line.trim())
+                .take_while(|line| line.starts_with("#["))
+                .any(|line| line == "#[cfg(test)]")
+    })?;
+
+    let mut depth = 0i32;
+    for (index, line) in lines.iter().enumerate().skip(start) {
+        depth += line.matches('{').count() as i32 - line.matches('}').count() as i32;
+        if depth <= 0 {
+            return Some((start, index));
+        }
+    }
+    None
+}
+
+fn unique_test_name(lines: &[String], function: &str) -> String {
+    let taken = |name: &str| {
+        let declaration = format!("fn {}(", name);
+        lines.iter().any(|line| line.contains(&declaration))
+    };
+
+    let base = format!("test_{}", function);
+    if !taken(&base) {
+        return base;
+    }
+    (2..).map(|n| format!("{}_{}", base, n))
+        .find(|name| !taken(name))
+        .unwrap_or(base)
+}
+
+fn leading_whitespace(line: &str) -> &str {
+    &line[..line.len() - line.trim_start().len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::editor::doc_comment::find_function;
+
+    fn lines(text: &str) -> Vec<String> {
+        text.lines().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn test_creates_tests_module() {
+        let source = lines("pub fn add(a: i32, b: i32) -> i32 {\n    a + b\n}\n\n");
+        let signature = find_function(&source, 1).unwrap();
+        let stub = add_test_stub(&source, &signature).unwrap();
+        assert_eq!(stub.lines, lines("\
+pub fn add(a: i32, b: i32) -> i32 {
+    a + b
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add() {
+        
+    }
+}"));
+        assert_eq!(stub.body_line, 10);
+    }
+
+    #[test]
+    fn test_appends_to_existing_module() {
+        let source = lines("\
+fn add() {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add() {
+        assert!(true);
+    }
+}");
+        let signature = find_function(&source, 0).unwrap();
+        let stub = add_test_stub(&source, &signature).unwrap();
+        assert_eq!(stub.name, "test_add_2");
+        assert_eq!(stub.lines[10..], ["", "    #[test]", "    fn test_add_2() {", "        ", "    }", "}"]);
+        assert_eq!(stub.body_line, 13);
+        assert_eq!(stub.lines[stub.body_line], "        ");
+    }
+}
@@ -0,0 +1,741 @@
+use super::workspace_edit::{LineEdit, WorkspaceEdit};
+use anyhow::{Context, Result};
+use grep::matcher::Matcher;
+use grep_regex::RegexMatcher;
+use grep_searcher::sinks::UTF8;
+use grep_searcher::Searcher;
+use ignore::overrides::{Override, OverrideBuilder};
+use ignore::WalkBuilder;
+use regex::Regex;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Queries shorter than this don't start a search
+pub const MIN_QUERY_LEN: usize = 3;
+
+/// How long typing has to pause before a search starts
+const DEBOUNCE: Duration = Duration::from_millis(150);
+
+/// Results are loaded a page at a time; the next page is fetched when the
+/// selection gets close to the end of what's loaded
+const PAGE_SIZE: usize = 500;
+
+/// Structure for token search functionality
+///
+/// Searching runs on a worker thread that streams results back over a
+/// channel, so typing stays responsive in large projects. New results are
+/// only ever appended, which keeps the selection where it is while they
+/// arrive.
+pub struct TokenSearch {
+    pub query: String,
+    pub results: Vec<TokenSearchResult>,
+    pub selected_index: usize,
+    /// Most results a single query may load (`token_search_max_results`)
+    pub max_results: usize,
+    /// Whether the last search stopped at `max_results`
+    pub truncated: bool,
+    /// Treat the query as a regular expression instead of literal text (Alt+r)
+    pub regex: bool,
+    /// Match case exactly (Alt+c)
+    pub case_sensitive: bool,
+    /// Only match whole words (Alt+w)
+    pub whole_word: bool,
+    /// Space-separated globs limiting which files are searched; a leading
+    /// `!` excludes, e.g. `*.rs !target/**`
+    pub glob: String,
+    /// Typing edits the glob instead of the query (Alt+g switches)
+    pub editing_glob: bool,
+    /// Why the current query can't be searched, e.g. an invalid regex
+    pub error: Option<String>,
+    /// Text that replaces every match in a project-wide replace
+    pub replacement: String,
+    /// Typing edits the replacement and Enter previews the replace (Ctrl+r switches)
+    pub replacing: bool,
+    /// Directory searched, the current directory by default
+    root: Option<PathBuf>,
+    /// Directory result paths are shown relative to, the root by default;
+    /// results outside it get absolute paths
+    base: Option<PathBuf>,
+    /// When the query last changed, while waiting for typing to pause
+    query_changed_at: Option<Instant>,
+    job: Option<SearchJob>,
+    /// Results of the previous query stay visible until the new one reports
+    stale_results: bool,
+    /// Result to keep selected when a refined query finds it again
+    restore_selection: Option<(String, usize)>,
+    /// Files the last search has looked through so far
+    files_searched: usize,
+}
+
+/// Represents a token search result
+#[derive(Clone)]
+pub struct TokenSearchResult {
+    pub file_path: String,
+    pub line_number: usize,
+    pub column: usize,
+    pub line_content: String,
+    pub matched_text: String,
+}
+
+/// A search running on a worker thread
+struct SearchJob {
+    receiver: Receiver<SearchMessage>,
+    cancel: Arc<AtomicBool>,
+    /// Number of results the worker may find before it waits for more to be requested
+    demand: Arc<AtomicUsize>,
+    /// Files the worker has searched, for showing progress
+    files_searched: Arc<AtomicUsize>,
+}
+
+enum SearchMessage {
+    Results(Vec<TokenSearchResult>),
+    Done { truncated: bool },
+}
+
+impl Drop for SearchJob {
+    fn drop(&mut self) {
+        self.cancel.store(true, Ordering::Relaxed);
+    }
+}
+
+impl TokenSearch {
+    pub fn new() -> Self {
+        Self::with_max_results(10_000)
+    }
+
+    pub fn with_max_results(max_results: usize) -> Self {
+        Self {
+            query: String::new(),
+            results: Vec::new(),
+            selected_index: 0,
+            max_results,
+            truncated: false,
+            regex: false,
+            case_sensitive: false,
+            whole_word: false,
+            glob: String::new(),
+            editing_glob: false,
+            error: None,
+            replacement: String::new(),
+            replacing: false,
+            root: None,
+            base: None,
+            query_changed_at: None,
+            job: None,
+            stale_results: false,
+            restore_selection: None,
+            files_searched: 0,
+        }
+    }
+
+    /// Add a character to the search query (or the glob or replacement
+    /// while editing them)
+    pub fn add_char(&mut self, c: char) {
+        if self.editing_glob {
+            self.glob.push(c);
+        } else if self.replacing {
+            self.replacement.push(c);
+            return;
+        } else {
+            self.query.push(c);
+        }
+        self.query_changed_at = Some(Instant::now());
+    }
+
+    /// Remove the last character from the search query (or the glob or
+    /// replacement while editing them)
+    pub fn remove_char(&mut self) {
+        if self.editing_glob {
+            self.glob.pop();
+        } else if self.replacing {
+            self.replacement.pop();
+            return;
+        } else {
+            self.query.pop();
+        }
+        self.query_changed_at = Some(Instant::now());
+    }
+
+    pub fn toggle_regex(&mut self) {
+        self.regex = !self.regex;
+        self.query_changed_at = Some(Instant::now());
+    }
+
+    pub fn toggle_case_sensitive(&mut self) {
+        self.case_sensitive = !self.case_sensitive;
+        self.query_changed_at = Some(Instant::now());
+    }
+
+    pub fn toggle_whole_word(&mut self) {
+        self.whole_word = !self.whole_word;
+        self.query_changed_at = Some(Instant::now());
+    }
+
+    /// Switch typing between the query and the file glob
+    pub fn toggle_glob_editing(&mut self) {
+        self.editing_glob = !self.editing_glob;
+        self.replacing = false;
+    }
+
+    /// Switch typing between the query and the replacement text
+    pub fn toggle_replacing(&mut self) {
+        self.replacing = !self.replacing;
+        self.editing_glob = false;
+    }
+
+    /// Move to the next search result
+    pub fn next(&mut self) {
+        if !self.results.is_empty() {
+            self.selected_index = (self.selected_index + 1) % self.results.len();
+        }
+        self.restore_selection = None;
+        self.request_more_if_needed();
+    }
+
+    /// Move to the previous search result
+    pub fn previous(&mut self) {
+        if !self.results.is_empty() {
+            self.selected_index = if self.selected_index == 0 {
+                self.results.len() - 1
+            } else {
+                self.selected_index - 1
+            };
+        }
+        self.restore_selection = None;
+        self.request_more_if_needed();
+    }
+
+    /// How many files the last search has looked through so far
+    pub fn files_searched(&self) -> usize {
+        self.files_searched
+    }
+
+    /// Whether more results may still come for the query, because the
+    /// search is running or stopped at the limit
+    pub fn has_more(&self) -> bool {
+        self.job.is_some() || self.truncated
+    }
+
+    /// "match 12 of 87" for the selected result, with a `+` on the total
+    /// while more may come
+    pub fn match_status(&self) -> Option<String> {
+        if self.results.is_empty() {
+            return None;
+        }
+        let more = if self.has_more() { "+" } else { "" };
+        Some(format!("match {} of {}{}", self.selected_index + 1, self.results.len(), more))
+    }
+
+    /// How many files the loaded results are in
+    pub fn file_count(&self) -> usize {
+        // Each file's results arrive together, so they're next to each other
+        self.results.windows(2).filter(|pair| pair[0].file_path != pair[1].file_path).count() + usize::from(!self.results.is_empty())
+    }
+
+    /// The selected result's place among its file's results, and how many
+    /// that file has
+    pub fn position_in_file(&self) -> Option<(usize, usize)> {
+        let file = &self.get_selected()?.file_path;
+        let first = self.results[..self.selected_index].iter().rev().take_while(|result| &result.file_path == file).count();
+        let after = self.results[self.selected_index + 1..].iter().take_while(|result| &result.file_path == file).count();
+        Some((first + 1, first + 1 + after))
+    }
+
+    /// Get the currently selected result
+    pub fn get_selected(&self) -> Option<&TokenSearchResult> {
+        self.results.get(self.selected_index)
+    }
+
+    /// Get a clone of the currently selected result
+    pub fn get_selected_cloned(&self) -> Option<TokenSearchResult> {
+        self.results.get(self.selected_index).cloned()
+    }
+
+    /// Whether a search is running or about to start
+    pub fn is_searching(&self) -> bool {
+        self.job.is_some() || self.query_changed_at.is_some()
+    }
+
+    /// Start a search once typing has paused and pick up streamed results
+    ///
+    /// Returns true if the results changed.
+    pub fn poll(&mut self) -> bool {
+        if self.query_changed_at.is_some_and(|changed| changed.elapsed() >= DEBOUNCE) {
+            self.start_search();
+        }
+
+        let mut changed = false;
+        if let Some(job) = &self.job {
+            let files_searched = job.files_searched.load(Ordering::Relaxed);
+            changed = files_searched != self.files_searched;
+            self.files_searched = files_searched;
+        }
+        while let Some(job) = &self.job {
+            let message = match job.receiver.try_recv() {
+                Ok(message) => message,
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    self.job = None;
+                    break;
+                }
+            };
+
+            if self.stale_results {
+                self.results.clear();
+                self.selected_index = 0;
+                self.stale_results = false;
+            }
+            changed = true;
+
+            match message {
+                SearchMessage::Results(batch) => {
+                    if let Some((file, line)) = &self.restore_selection {
+                        if let Some(offset) = batch.iter().position(|r| &r.file_path == file && r.line_number == *line) {
+                            self.selected_index = self.results.len() + offset;
+                            self.restore_selection = None;
+                        }
+                    }
+                    self.results.extend(batch);
+                },
+                SearchMessage::Done { truncated } => {
+                    self.truncated = truncated;
+                    self.files_searched = job.files_searched.load(Ordering::Relaxed);
+                    self.job = None;
+                    self.restore_selection = None;
+                },
+            }
+        }
+
+        changed
+    }
+
+    /// Search `root`, e.g. the tab's working directory, keeping result
+    /// paths relative to the current directory so they can be opened
+    pub fn set_root(&mut self, root: PathBuf) {
+        self.base = std::env::current_dir().and_then(|dir| dir.canonicalize()).ok();
+        self.root = Some(root);
+    }
+
+    /// Start searching for the current query right away, cancelling any
+    /// search that is still running
+    pub fn start_search(&mut self) {
+        self.query_changed_at = None;
+        self.job = None;
+        self.truncated = false;
+        self.error = None;
+
+        let root = match self.root.clone().or_else(|| std::env::current_dir().ok()) {
+            Some(root) => root,
+            None => return,
+        };
+        let options = self.build_matcher()
+            .and_then(|matcher| Ok((matcher, self.build_overrides(&root)?)));
+        let (matcher, overrides) = match options {
+            Ok(options) if self.query.len() >= MIN_QUERY_LEN => options,
+            result => {
+                if let Err(e) = result {
+                    self.error = Some(format!("{:#}", e));
+                }
+                self.results.clear();
+                self.selected_index = 0;
+                self.stale_results = false;
+                return;
+            }
+        };
+
+        // Keep showing the old results until the new ones come in, and try
+        // to keep the same result selected
+        self.restore_selection = self.get_selected()
+            .map(|result| (result.file_path.clone(), result.line_number));
+        self.stale_results = true;
+
+        let (sender, receiver) = mpsc::channel();
+        let cancel = Arc::new(AtomicBool::new(false));
+        let demand = Arc::new(AtomicUsize::new(PAGE_SIZE.min(self.max_results)));
+        let files_searched = Arc::new(AtomicUsize::new(0));
+        self.files_searched = 0;
+        let worker = SearchWorker {
+            matcher,
+            overrides,
+            base: self.base.clone().unwrap_or_else(|| root.clone()),
+            root,
+            max_results: self.max_results,
+            sender,
+            cancel: cancel.clone(),
+            demand: demand.clone(),
+            files_searched: files_searched.clone(),
+        };
+        std::thread::spawn(move || worker.run());
+
+        self.job = Some(SearchJob { receiver, cancel, demand, files_searched });
+    }
+
+    /// Build the matcher for the query and the current toggles
+    fn build_matcher(&self) -> Result<RegexMatcher> {
+        RegexMatcher::new(&self.pattern()).context("Invalid search pattern")
+    }
+
+    /// The regular expression for the query and the current toggles
+    fn pattern(&self) -> String {
+        let mut pattern = if self.regex {
+            self.query.clone()
+        } else {
+            regex::escape(&self.query)
+        };
+        if self.whole_word {
+            pattern = format!(r"\b(?:{})\b", pattern);
+        }
+        if !self.case_sensitive {
+            pattern = format!("(?i){}", pattern);
+        }
+        pattern
+    }
+
+    /// Build the edit that replaces every match in the project
+    ///
+    /// Unlike the result list this isn't limited to `max_results`. Each
+    /// changed line is one edit in the preview; in regex mode `$1` or
+    /// `${name}` in the replacement insert capture groups. `read_lines`
+    /// supplies file contents so unsaved buffers are used.
+    pub fn replace_edit(&self, read_lines: &dyn Fn(&Path) -> Option<Vec<String>>) -> Result<WorkspaceEdit> {
+        if self.query.len() < MIN_QUERY_LEN {
+            return Err(anyhow::anyhow!("Enter at least {} characters to replace", MIN_QUERY_LEN));
+        }
+
+        let root = self.root.clone()
+            .or_else(|| std::env::current_dir().ok())
+            .and_then(|root| root.canonicalize().ok())
+            .context("No directory to search")?;
+        let pattern = Regex::new(&self.pattern()).context("Invalid search pattern")?;
+        let overrides = self.build_overrides(&root)?;
+
+        let mut edit = WorkspaceEdit::new(&format!("Replace \"{}\" with \"{}\"", self.query, self.replacement));
+        let walker = WalkBuilder::new(&root).overrides(overrides).build();
+        for entry in walker.filter_map(|entry| entry.ok()) {
+            if !entry.file_type().is_some_and(|file_type| file_type.is_file()) {
+                continue;
+            }
+            let Some(lines) = read_lines(entry.path()) else { continue };
+
+            let path = entry.path().to_string_lossy();
+            for (index, line) in lines.iter().enumerate() {
+                if !pattern.is_match(line) {
+                    continue;
+                }
+                let replaced = if self.regex {
+                    pattern.replace_all(line, self.replacement.as_str())
+                } else {
+                    pattern.replace_all(line, regex::NoExpand(&self.replacement))
+                };
+                if replaced != *line {
+                    edit.add_edit(&path, LineEdit::replace(index, line, &replaced));
+                }
+            }
+        }
+
+        Ok(edit)
+    }
+
+    /// Turn the glob field into include/exclude rules relative to the root
+    fn build_overrides(&self, root: &Path) -> Result<Override> {
+        let mut builder = OverrideBuilder::new(root);
+        for glob in self.glob.split_whitespace() {
+            builder.add(glob).with_context(|| format!("Invalid glob: {}", glob))?;
+        }
+        builder.build().context("Invalid glob")
+    }
+
+    /// Ask the worker for another page once the selection nears the end of
+    /// the loaded results
+    fn request_more_if_needed(&mut self) {
+        if let Some(job) = &self.job {
+            if self.selected_index + PAGE_SIZE / 5 >= self.results.len() {
+                let wanted = (self.results.len() + PAGE_SIZE).min(self.max_results);
+                job.demand.fetch_max(wanted, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Search for the current query and wait for every result
+    pub fn search(&mut self) -> Result<()> {
+        self.start_search();
+        if let Some(job) = &self.job {
+            job.demand.store(self.max_results, Ordering::Relaxed);
+        }
+        while self.job.is_some() {
+            if !self.poll() {
+                std::thread::sleep(Duration::from_millis(5));
+            }
+        }
+        if self.stale_results {
+            self.results.clear();
+            self.stale_results = false;
+        }
+        Ok(())
+    }
+}
+
+impl Default for TokenSearch {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The part of a search that runs on the worker thread
+struct SearchWorker {
+    matcher: RegexMatcher,
+    overrides: Override,
+    root: PathBuf,
+    base: PathBuf,
+    max_results: usize,
+    sender: Sender<SearchMessage>,
+    cancel: Arc<AtomicBool>,
+    demand: Arc<AtomicUsize>,
+    files_searched: Arc<AtomicUsize>,
+}
+
+impl SearchWorker {
+    fn run(self) {
+        // An error means the receiver went away or the pattern was invalid,
+        // so there's nothing more to report
+        let truncated = self.search().unwrap_or_default();
+        let _ = self.sender.send(SearchMessage::Done { truncated });
+    }
+
+    /// Walk the project, sending each file's matches as one batch
+    ///
+    /// Returns whether the search stopped at the result limit.
+    fn search(&self) -> Result<bool> {
+        let mut searcher = Searcher::new();
+        searcher.multi_line_with_matcher(&self.matcher);
+
+        let mut found = 0;
+
+        // Walk through the files in the root directory allowed by the globs,
+        // respecting .gitignore
+        let walker = WalkBuilder::new(&self.root)
+            .overrides(self.overrides.clone())
+            .build();
+        for entry in walker {
+            if self.cancel.load(Ordering::Relaxed) {
+                return Ok(false);
+            }
+
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(_) => continue, // Skip entries with errors
+            };
+            let path = entry.path();
+            if path.is_dir() {
+                continue;
+            }
+
+            let remaining = self.max_results - found;
+            let batch = self.search_file(&mut searcher, path, remaining);
+            self.files_searched.fetch_add(1, Ordering::Relaxed);
+            found += batch.len();
+            if !batch.is_empty() {
+                self.sender.send(SearchMessage::Results(batch))?;
+            }
+
+            if found >= self.max_results {
+                return Ok(true);
+            }
+
+            // Lazy loading: pause until more results are wanted
+            while found >= self.demand.load(Ordering::Relaxed) {
+                if self.cancel.load(Ordering::Relaxed) {
+                    return Ok(false);
+                }
+                std::thread::sleep(Duration::from_millis(20));
+            }
+        }
+
+        Ok(false)
+    }
+
+    fn search_file(&self, searcher: &mut Searcher, path: &Path, limit: usize) -> Vec<TokenSearchResult> {
+        // Get relative path for display
+        let file_path = match path.strip_prefix(&self.base) {
+            Ok(rel_path) => rel_path.to_string_lossy().to_string(),
+            Err(_) => path.to_string_lossy().to_string(),
+        };
+
+        let mut results = Vec::new();
+        let _ = searcher.search_path(&self.matcher, path, UTF8(|line_number, line| {
+            // Find match column position
+            if let Some(grep_match) = self.matcher.find(line.as_bytes())? {
+                let start = grep_match.start();
+
+                // Extract matched text (a regex match may span the line break)
+                let end = std::cmp::min(grep_match.end(), line.trim_end().len()).max(start);
+                let matched_text = &line[start..end];
+
+                results.push(TokenSearchResult {
+                    file_path: file_path.clone(),
+                    line_number: line_number as usize,
+                    column: start,
+                    line_content: line.trim_end().to_string(),
+                    matched_text: matched_text.to_string(),
+                });
+            }
+
+            // Stop reading this file once the limit is reached
+            Ok(results.len() < limit)
+        }));
+
+        results
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn search_in(root: &Path, query: &str, max_results: usize) -> TokenSearch {
+        let mut search = TokenSearch::with_max_results(max_results);
+        search.root = Some(root.to_path_buf());
+        search.query = query.to_string();
+        search
+    }
+
+    #[test]
+    fn test_search_streams_results_with_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut content = String::new();
+        for i in 0..50 {
+            content.push_str(&format!("let needle_{} = {};\n", i, i));
+        }
+        fs::write(dir.path().join("a.rs"), &content).unwrap();
+        fs::write(dir.path().join("b.rs"), "no match here\n").unwrap();
+
+        let mut search = search_in(dir.path(), "NEEDLE", 1000);
+        search.search().unwrap();
+        assert_eq!(search.results.len(), 50);
+        assert!(!search.truncated);
+        assert_eq!(search.results[0].matched_text, "needle");
+        assert_eq!(search.files_searched(), 2);
+        assert_eq!(search.file_count(), 1);
+        search.next();
+        assert_eq!(search.match_status().as_deref(), Some("match 2 of 50"));
+        assert_eq!(search.position_in_file(), Some((2, 50)));
+
+        let mut search = search_in(dir.path(), "needle", 20);
+        search.search().unwrap();
+        assert_eq!(search.results.len(), 20);
+        assert!(search.truncated);
+        assert_eq!(search.match_status().as_deref(), Some("match 1 of 20+"));
+    }
+
+    #[test]
+    fn test_typing_is_debounced_and_selection_kept() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a.rs"), "fn alpha() {}\nfn alphabet() {}\nfn beta() {}\n").unwrap();
+
+        let mut search = search_in(dir.path(), "", 1000);
+        for c in "alph".chars() {
+            search.add_char(c);
+        }
+        // Nothing starts until typing pauses
+        search.poll();
+        assert!(search.job.is_none());
+        assert!(search.is_searching());
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while search.is_searching() && Instant::now() < deadline {
+            search.poll();
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(search.results.len(), 2);
+
+        // Refining the query keeps the selected result selected
+        search.next();
+        assert_eq!(search.get_selected().unwrap().line_content, "fn alphabet() {}");
+        search.add_char('a');
+        search.add_char('b');
+        search.start_search();
+        assert_eq!(search.results.len(), 2); // old results stay until new ones arrive
+        while search.is_searching() && Instant::now() < deadline {
+            search.poll();
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(search.results.len(), 1);
+        assert_eq!(search.get_selected().unwrap().line_content, "fn alphabet() {}");
+    }
+
+    #[test]
+    fn test_replace_edit_covers_every_match() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a.rs"), "let old_name = 1;\nold_name + old_name\n").unwrap();
+        fs::write(dir.path().join("b.txt"), "old_name\n").unwrap();
+        let read = |path: &Path| fs::read_to_string(path).ok().map(|text| text.lines().map(str::to_string).collect());
+
+        // Not limited by max_results, and one edit per changed line
+        let mut search = search_in(dir.path(), "old_name", 1);
+        search.glob = "*.rs".to_string();
+        search.replacement = "new_name".to_string();
+        let edit = search.replace_edit(&read).unwrap();
+        assert_eq!(edit.files.len(), 1);
+        assert_eq!(edit.files[0].edits[1].new.as_deref(), Some("new_name + new_name"));
+
+        // Regex replacements can use capture groups; literal ones can't
+        search.query = r"(\w+)_name".to_string();
+        search.replacement = "${1}Name".to_string();
+        search.regex = true;
+        assert_eq!(search.replace_edit(&read).unwrap().files[0].edits[0].new.as_deref(), Some("let oldName = 1;"));
+        search.query = "old_name".to_string();
+        search.regex = false;
+        assert_eq!(search.replace_edit(&read).unwrap().files[0].edits[0].new.as_deref(), Some("let ${1}Name = 1;"));
+    }
+
+    #[test]
+    fn test_search_toggles_and_globs() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("target")).unwrap();
+        fs::write(dir.path().join("lib.rs"), "Token token tokens\nfn tok3n() {}\n").unwrap();
+        fs::write(dir.path().join("notes.md"), "token\n").unwrap();
+        fs::write(dir.path().join("target/gen.rs"), "token\n").unwrap();
+
+        let count = |search: &mut TokenSearch| {
+            search.search().unwrap();
+            search.results.len()
+        };
+
+        let mut search = search_in(dir.path(), "token", 1000);
+        assert_eq!(count(&mut search), 3);
+
+        search.glob = "*.rs !target/**".to_string();
+        assert_eq!(count(&mut search), 1);
+
+        // Case sensitivity finds "token" on the first line after "Token"
+        search.toggle_case_sensitive();
+        search.search().unwrap();
+        assert_eq!(search.results[0].column, 6);
+
+        search.toggle_case_sensitive();
+        search.query = "tok.n".to_string();
+        assert_eq!(count(&mut search), 0);
+        search.toggle_regex();
+        assert_eq!(count(&mut search), 2);
+        assert_eq!(search.results[0].matched_text, "Token");
+
+        search.toggle_whole_word();
+        search.query = "tok.ns".to_string();
+        assert_eq!(count(&mut search), 1);
+        search.query = "tok.".to_string();
+        assert_eq!(count(&mut search), 0);
+
+        // Typing goes to the glob field while it's focused
+        search.toggle_glob_editing();
+        search.add_char('x');
+        assert_eq!(search.glob, "*.rs !target/**x");
+
+        search.query = "tok(".to_string();
+        search.search().unwrap();
+        assert!(search.error.is_some());
+    }
+}
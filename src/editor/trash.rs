@@ -0,0 +1,121 @@
+use anyhow::{anyhow, Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The system trash: `~/.Trash` on macOS, and the freedesktop.org trash
+/// (`$XDG_DATA_HOME/Trash`) elsewhere
+pub fn system_trash() -> Result<PathBuf> {
+    let home = dirs::home_dir().ok_or_else(|| anyhow!("No home directory for the trash"))?;
+    if cfg!(target_os = "macos") {
+        return Ok(home.join(".Trash"));
+    }
+    if cfg!(windows) {
+        return Err(anyhow!("No trash on this system (set trash_dir in the config)"));
+    }
+    Ok(dirs::data_dir().unwrap_or_else(|| home.join(".local/share")).join("Trash"))
+}
+
+/// Move the file at `path` to the trash at `trash`, returning where it went
+///
+/// Outside macOS the trash is laid out the freedesktop.org way, with the
+/// file in `files/` and a `.trashinfo` in `info/` recording where it came
+/// from, so file managers can put it back.
+pub fn move_to_trash(path: &Path, trash: &Path) -> Result<PathBuf> {
+    let original = path.canonicalize().with_context(|| format!("{} doesn't exist", path.display()))?;
+    let name = original.file_name().ok_or_else(|| anyhow!("Can't delete {}", original.display()))?.to_string_lossy().to_string();
+    let freedesktop = !cfg!(target_os = "macos") || trash != system_trash().unwrap_or_default();
+    let files = if freedesktop { trash.join("files") } else { trash.to_path_buf() };
+    fs::create_dir_all(&files).with_context(|| format!("Failed to create {}", files.display()))?;
+
+    // Claim a name nobody else in the trash has, the info file first as the spec asks
+    let info_dir = trash.join("info");
+    let mut number = 1;
+    let (target, info) = loop {
+        let candidate = if number == 1 { name.clone() } else { format!("{}.{}", name, number) };
+        number += 1;
+        if files.join(&candidate).exists() {
+            continue;
+        }
+        if !freedesktop {
+            break (files.join(&candidate), None);
+        }
+        fs::create_dir_all(&info_dir).with_context(|| format!("Failed to create {}", info_dir.display()))?;
+        let info = info_dir.join(format!("{}.trashinfo", candidate));
+        match fs::OpenOptions::new().write(true).create_new(true).open(&info) {
+            Ok(_) => break (files.join(&candidate), Some(info)),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e).with_context(|| format!("Failed to write {}", info.display())),
+        }
+    };
+    if let Some(info) = &info {
+        let text = format!("[Trash Info]\nPath={}\nDeletionDate={}\n", encode_path(&original), deletion_date(SystemTime::now()));
+        fs::write(info, text).with_context(|| format!("Failed to write {}", info.display()))?;
+    }
+
+    // fs::rename fails across filesystems, so fall back to copy + delete
+    let moved = fs::rename(&original, &target).or_else(|_| {
+        fs::copy(&original, &target)?;
+        fs::remove_file(&original)
+    });
+    if let Err(e) = moved {
+        if let Some(info) = &info {
+            let _ = fs::remove_file(info);
+        }
+        return Err(e).with_context(|| format!("Failed to move {} to the trash", original.display()));
+    }
+    Ok(target)
+}
+
+/// A path percent-encoded for a `.trashinfo` file
+fn encode_path(path: &Path) -> String {
+    path.to_string_lossy().bytes().map(|byte| match byte {
+        b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'/' | b'-' | b'_' | b'.' | b'~' => (byte as char).to_string(),
+        byte => format!("%{:02X}", byte),
+    }).collect()
+}
+
+/// `time` as `YYYY-MM-DDThh:mm:ss`, in UTC
+fn deletion_date(time: SystemTime) -> String {
+    let seconds = time.duration_since(UNIX_EPOCH).map(|elapsed| elapsed.as_secs()).unwrap_or(0);
+    let (days, rest) = (seconds / 86400, seconds % 86400);
+    // Days since 1970 to a civil date (Howard Hinnant's algorithm)
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}", year, month, day, rest / 3600, rest % 3600 / 60, rest % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_files_move_to_the_trash_with_their_info() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let trash = dir.path().join("Trash");
+        for contents in ["first", "second"] {
+            fs::write(dir.path().join("my notes.txt"), contents)?;
+            move_to_trash(&dir.path().join("my notes.txt"), &trash)?;
+        }
+        assert!(!dir.path().join("my notes.txt").exists());
+        assert_eq!(fs::read_to_string(trash.join("files/my notes.txt"))?, "first");
+        assert_eq!(fs::read_to_string(trash.join("files/my notes.txt.2"))?, "second");
+
+        let info = fs::read_to_string(trash.join("info/my notes.txt.2.trashinfo"))?;
+        let original = dir.path().canonicalize()?.join("my notes.txt");
+        assert!(info.starts_with("[Trash Info]\n"));
+        assert!(info.contains(&format!("Path={}\n", original.to_string_lossy().replace(' ', "%20"))));
+
+        assert!(move_to_trash(&dir.path().join("missing.txt"), &trash).is_err());
+        assert_eq!(deletion_date(UNIX_EPOCH + Duration::from_secs(1_709_210_096)), "2024-02-29T12:34:56");
+        Ok(())
+    }
+}
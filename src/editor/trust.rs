@@ -0,0 +1,124 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// What's been decided about running a workspace's commands
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trust {
+    Trusted,
+    Untrusted,
+    /// Not asked yet
+    Unknown,
+}
+
+/// Decisions kept in `trust.toml` next to the config file
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Decisions {
+    #[serde(default)]
+    trusted: Vec<PathBuf>,
+    #[serde(default)]
+    untrusted: Vec<PathBuf>,
+}
+
+/// Which workspaces may run cargo, compilers and formatters
+///
+/// Building a project runs its build scripts and macros, so a directory is
+/// asked about the first time one of those would run there, and the answer
+/// is remembered. Trusting a directory trusts everything under it. In safe
+/// mode (`--safe`) nothing runs at all.
+#[derive(Debug, Default)]
+pub struct WorkspaceTrust {
+    /// Where decisions are saved; without one every workspace is trusted,
+    /// as in tests and scripts that don't load the store
+    store: Option<PathBuf>,
+    decisions: Decisions,
+    /// No commands run, whatever was decided (`--safe`)
+    pub safe: bool,
+}
+
+impl WorkspaceTrust {
+    /// Load the decisions saved at `store`
+    pub fn load(store: PathBuf, safe: bool) -> Result<Self> {
+        let decisions = if store.exists() {
+            let text = fs::read_to_string(&store)
+                .with_context(|| format!("Failed to read {:?}", store))?;
+            toml::from_str(&text).map_err(|e| anyhow::anyhow!("Invalid {:?}: {}", store, e))?
+        } else {
+            Decisions::default()
+        };
+        Ok(Self { store: Some(store), decisions, safe })
+    }
+
+    pub fn state(&self, workspace: &Path) -> Trust {
+        if self.store.is_none() {
+            return Trust::Trusted;
+        }
+        let workspace = canonical(workspace);
+        // The closest decision wins, so a project can be excluded from a
+        // trusted parent directory
+        let closest = |dirs: &[PathBuf]| dirs.iter()
+            .filter(|dir| workspace.starts_with(dir))
+            .map(|dir| dir.components().count())
+            .max();
+        match (closest(&self.decisions.trusted), closest(&self.decisions.untrusted)) {
+            (Some(trusted), Some(untrusted)) if trusted > untrusted => Trust::Trusted,
+            (Some(_), None) => Trust::Trusted,
+            (_, Some(_)) => Trust::Untrusted,
+            (None, None) => Trust::Unknown,
+        }
+    }
+
+    /// Remember whether `workspace` is trusted
+    pub fn decide(&mut self, workspace: &Path, trusted: bool) -> Result<()> {
+        let workspace = canonical(workspace);
+        self.decisions.trusted.retain(|dir| *dir != workspace);
+        self.decisions.untrusted.retain(|dir| *dir != workspace);
+        if trusted {
+            self.decisions.trusted.push(workspace);
+        } else {
+            self.decisions.untrusted.push(workspace);
+        }
+
+        let Some(store) = &self.store else { return Ok(()) };
+        if let Some(dir) = store.parent() {
+            fs::create_dir_all(dir).with_context(|| format!("Failed to create {:?}", dir))?;
+        }
+        let text = toml::to_string_pretty(&self.decisions).context("Failed to serialize trust decisions")?;
+        fs::write(store, text).with_context(|| format!("Failed to write {:?}", store))
+    }
+}
+
+fn canonical(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trust_decisions_are_remembered() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let code = dir.path().join("code");
+        let project = code.join("project");
+        let vendored = code.join("vendored");
+        fs::create_dir_all(&project)?;
+        fs::create_dir_all(&vendored)?;
+        let store = dir.path().join("config").join("trust.toml");
+
+        let mut trust = WorkspaceTrust::load(store.clone(), false)?;
+        assert_eq!(trust.state(&project), Trust::Unknown);
+        trust.decide(&code, true)?;
+        trust.decide(&vendored, false)?;
+
+        let trust = WorkspaceTrust::load(store, false)?;
+        assert_eq!(trust.state(&project), Trust::Trusted);
+        assert_eq!(trust.state(&vendored), Trust::Untrusted);
+        assert_eq!(trust.state(dir.path()), Trust::Unknown);
+
+        // Without a store nothing is asked
+        assert_eq!(WorkspaceTrust::default().state(dir.path()), Trust::Trusted);
+        Ok(())
+    }
+}
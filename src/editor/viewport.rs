@@ -21,8 +21,14 @@ impl Viewport {
         self.height = height;
     }
 
-    pub fn ensure_cursor_visible(&mut self, cursor_y: usize, cursor_x: usize) {
-        // Vertical scrolling
+    /// Scroll so the cursor is on screen, `side_margin` columns from the
+    /// left and right edges
+    pub fn ensure_cursor_visible(&mut self, cursor_y: usize, cursor_x: usize, side_margin: usize) {
+        self.ensure_line_visible(cursor_y);
+        self.ensure_column_visible(cursor_x, side_margin);
+    }
+
+    pub fn ensure_line_visible(&mut self, cursor_y: usize) {
         if cursor_y < self.top_line {
             // Cursor is above viewport
             self.top_line = cursor_y;
@@ -30,17 +36,47 @@ impl Viewport {
             // Cursor is below viewport
             self.top_line = cursor_y - self.height + 1;
         }
+    }
 
-        // Horizontal scrolling
-        if cursor_x < self.left_column {
+    /// Scroll sideways so column `cursor_x` stays at least `margin` columns
+    /// from either edge (`sidescrolloff`)
+    pub fn ensure_column_visible(&mut self, cursor_x: usize, margin: usize) {
+        // Nothing is known about the width until the first frame is drawn
+        if self.width == 0 {
+            return;
+        }
+        let margin = self.side_margin(margin);
+        if cursor_x < self.left_column + margin {
             // Cursor is to the left of viewport
-            self.left_column = cursor_x;
-        } else if cursor_x >= self.left_column + self.width {
+            self.left_column = cursor_x.saturating_sub(margin);
+        } else if cursor_x + margin >= self.left_column + self.width {
             // Cursor is to the right of viewport
-            self.left_column = cursor_x - self.width + 1;
+            self.left_column = cursor_x + margin + 1 - self.width;
         }
     }
 
+    /// Move the view `columns` to the right, or left if negative (`zl`, `zh`,
+    /// `zL`, `zH`), returning the column the cursor has to move to so it
+    /// stays in view
+    ///
+    /// The view goes no further right than the end of the cursor line, which
+    /// is `line_len` columns long.
+    pub fn scroll_sideways(&mut self, columns: isize, cursor_x: usize, line_len: usize, margin: usize) -> usize {
+        let margin = self.side_margin(margin);
+        let last_column = line_len.saturating_sub(1);
+        let furthest = last_column.saturating_sub(margin).max(self.left_column.min(last_column));
+        self.left_column = self.left_column.saturating_add_signed(columns).min(furthest);
+
+        let first = (self.left_column + margin).min(last_column);
+        let last = (self.left_column + self.width.max(1) - 1).saturating_sub(margin).max(first);
+        cursor_x.clamp(first, last)
+    }
+
+    /// `margin`, shrunk to fit a narrow view
+    fn side_margin(&self, margin: usize) -> usize {
+        margin.min(self.width.saturating_sub(1) / 2)
+    }
+
     pub fn scroll_up(&mut self, lines: usize) {
         if self.top_line > lines {
             self.top_line -= lines;
@@ -70,6 +106,12 @@ impl Viewport {
     }
 }
 
+impl Default for Viewport {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -100,7 +142,7 @@ mod tests {
         viewport.update_dimensions(80, 24);
         
         // Cursor within viewport initially
-        viewport.ensure_cursor_visible(10, 10);
+        viewport.ensure_cursor_visible(10, 10, 0);
         assert_eq!(viewport.top_line, 0);
         assert_eq!(viewport.left_column, 0);
     }
@@ -111,7 +153,7 @@ mod tests {
         viewport.update_dimensions(10, 5);
         
         // Cursor below viewport
-        viewport.ensure_cursor_visible(10, 5);
+        viewport.ensure_cursor_visible(10, 5, 0);
         assert_eq!(viewport.top_line, 6); // 10 - 5 + 1 = 6
         assert_eq!(viewport.left_column, 0);
     }
@@ -122,7 +164,7 @@ mod tests {
         viewport.update_dimensions(10, 5);
         
         // Cursor to the right of viewport
-        viewport.ensure_cursor_visible(2, 15);
+        viewport.ensure_cursor_visible(2, 15, 0);
         assert_eq!(viewport.top_line, 0);
         assert_eq!(viewport.left_column, 6); // 15 - 10 + 1 = 6
     }
@@ -134,7 +176,7 @@ mod tests {
         viewport.top_line = 10;
         
         // Cursor above viewport
-        viewport.ensure_cursor_visible(5, 5);
+        viewport.ensure_cursor_visible(5, 5, 0);
         assert_eq!(viewport.top_line, 5);
         assert_eq!(viewport.left_column, 0);
     }
@@ -146,11 +188,37 @@ mod tests {
         viewport.left_column = 10;
         
         // Cursor to the left of viewport
-        viewport.ensure_cursor_visible(2, 5);
+        viewport.ensure_cursor_visible(2, 5, 0);
         assert_eq!(viewport.top_line, 0);
         assert_eq!(viewport.left_column, 5);
     }
 
+    #[test]
+    fn test_sideways_scrolling_keeps_a_margin() {
+        let mut viewport = Viewport::new();
+        viewport.update_dimensions(20, 5);
+
+        // The cursor pushes the view along with `margin` columns to spare
+        viewport.ensure_column_visible(17, 3);
+        assert_eq!(viewport.left_column, 1);
+        viewport.ensure_column_visible(2, 3);
+        assert_eq!(viewport.left_column, 0);
+        // A margin too wide for the view is shrunk
+        viewport.ensure_column_visible(30, 50);
+        assert_eq!(viewport.left_column, 20);
+
+        // Scrolling drags the cursor along and stops at the end of its line
+        viewport.left_column = 0;
+        assert_eq!(viewport.scroll_sideways(10, 4, 100, 3), 13);
+        assert_eq!(viewport.left_column, 10);
+        assert_eq!(viewport.scroll_sideways(100, 13, 40, 3), 39);
+        assert_eq!(viewport.left_column, 36);
+        assert_eq!(viewport.scroll_sideways(-30, 39, 40, 3), 22);
+        assert_eq!(viewport.left_column, 6);
+        assert_eq!(viewport.scroll_sideways(-30, 22, 40, 3), 16);
+        assert_eq!(viewport.left_column, 0);
+    }
+
     #[test]
     fn test_scroll_up() {
         let mut viewport = Viewport::new();
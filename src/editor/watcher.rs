@@ -0,0 +1,143 @@
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver};
+
+/// Watches the files of open buffers for changes made by other programs
+///
+/// The parent directory of each file is watched rather than the file itself,
+/// because many tools (including formatters and other editors) save by
+/// writing a new file and renaming it over the old one, which would silently
+/// end a watch on the original inode.
+pub struct FileWatcher {
+    watcher: Option<RecommendedWatcher>,
+    receiver: Receiver<notify::Result<Event>>,
+    /// Absolute paths of the files we report changes for
+    files: HashSet<PathBuf>,
+    /// Directories currently registered with the OS watcher
+    directories: HashSet<PathBuf>,
+}
+
+impl FileWatcher {
+    pub fn new() -> Self {
+        let (sender, receiver) = mpsc::channel();
+        // Watching is best effort: without it the editor still works, the
+        // user just has to press `e` to pick up external changes
+        let watcher = notify::recommended_watcher(sender).ok();
+
+        Self {
+            watcher,
+            receiver,
+            files: HashSet::new(),
+            directories: HashSet::new(),
+        }
+    }
+
+    /// Make the watched set match the given file paths
+    pub fn sync<'a>(&mut self, paths: impl Iterator<Item = &'a str>) {
+        let wanted: HashSet<PathBuf> = paths.filter_map(absolute_path).collect();
+        if wanted == self.files {
+            return;
+        }
+
+        let wanted_directories: HashSet<PathBuf> = wanted
+            .iter()
+            .filter_map(|path| path.parent().map(Path::to_path_buf))
+            .collect();
+
+        if let Some(watcher) = self.watcher.as_mut() {
+            for directory in self.directories.difference(&wanted_directories) {
+                let _ = watcher.unwatch(directory);
+            }
+            for directory in wanted_directories.difference(&self.directories) {
+                let _ = watcher.watch(directory, RecursiveMode::NonRecursive);
+            }
+        }
+
+        self.files = wanted;
+        self.directories = wanted_directories;
+    }
+
+    /// Drain pending events and return the watched files that changed
+    pub fn changed_files(&mut self) -> Vec<PathBuf> {
+        let mut changed = Vec::new();
+
+        while let Ok(event) = self.receiver.try_recv() {
+            let Ok(event) = event else { continue };
+            if !matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) {
+                continue;
+            }
+
+            for path in event.paths {
+                if self.files.contains(&path) && !changed.contains(&path) {
+                    changed.push(path);
+                }
+            }
+        }
+
+        changed
+    }
+
+    /// Whether this path is one of the watched files
+    pub fn is_watched(&self, path: &str) -> bool {
+        absolute_path(path).is_some_and(|path| self.files.contains(&path))
+    }
+}
+
+impl Default for FileWatcher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Resolve a buffer path to the absolute form the OS watcher reports
+pub fn absolute_path(path: &str) -> Option<PathBuf> {
+    if path.starts_with("untitled-") || path.starts_with('[') {
+        return None;
+    }
+
+    let path = Path::new(path);
+    // Canonicalize the directory rather than the file so paths still match
+    // while the file is briefly missing during an atomic save
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.canonicalize().ok()?,
+        _ => std::env::current_dir().ok()?.canonicalize().ok()?,
+    };
+    Some(parent.join(path.file_name()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_reports_changes_to_watched_files_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let watched = dir.path().join("watched.txt");
+        let other = dir.path().join("other.txt");
+        std::fs::write(&watched, "one\n").unwrap();
+        std::fs::write(&other, "one\n").unwrap();
+
+        let mut watcher = FileWatcher::new();
+        if watcher.watcher.is_none() {
+            // No OS watcher available in this environment
+            return;
+        }
+        watcher.sync(std::iter::once(watched.to_str().unwrap()));
+        assert!(watcher.is_watched(watched.to_str().unwrap()));
+        assert!(!watcher.is_watched(other.to_str().unwrap()));
+
+        std::fs::write(&other, "two\n").unwrap();
+        std::fs::write(&watched, "two\n").unwrap();
+
+        let expected = absolute_path(watched.to_str().unwrap()).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut changed = Vec::new();
+        while changed.is_empty() && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(20));
+            changed = watcher.changed_files();
+        }
+        assert_eq!(changed, vec![expected]);
+    }
+}
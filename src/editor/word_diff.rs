@@ -0,0 +1,86 @@
+use similar::{capture_diff_slices, Algorithm, DiffOp, DiffTag, TextDiff};
+use std::collections::HashMap;
+use std::ops::Range;
+
+/// Changed words of each line of `new` that replaced a line of `old`,
+/// as byte ranges into the new line
+///
+/// Lines that were only added have no entry, since all of them is new.
+pub fn changed_lines(old: &str, new: &str) -> HashMap<usize, Vec<Range<usize>>> {
+    let diff = TextDiff::from_lines(old, new);
+    let mut changed = HashMap::new();
+    for op in diff.ops() {
+        if let DiffOp::Replace { old_index, old_len, new_index, new_len } = *op {
+            for i in 0..old_len.min(new_len) {
+                let old_line = line_text(diff.old_slices()[old_index + i]);
+                let new_line = line_text(diff.new_slices()[new_index + i]);
+                changed.insert(new_index + i, changed_words(old_line, new_line));
+            }
+        }
+    }
+    changed
+}
+
+/// Byte ranges of `new` that aren't in `old`, a word at a time
+pub fn changed_words(old: &str, new: &str) -> Vec<Range<usize>> {
+    let old_words = words(old);
+    let new_words = words(new);
+    let old_text: Vec<&str> = old_words.iter().map(|range| &old[range.clone()]).collect();
+    let new_text: Vec<&str> = new_words.iter().map(|range| &new[range.clone()]).collect();
+
+    let mut ranges: Vec<Range<usize>> = Vec::new();
+    for op in capture_diff_slices(Algorithm::Myers, &old_text, &new_text) {
+        let added = op.new_range();
+        if op.tag() == DiffTag::Equal || added.is_empty() {
+            continue;
+        }
+        let range = new_words[added.start].start..new_words[added.end - 1].end;
+        match ranges.last_mut() {
+            Some(last) if last.end == range.start => last.end = range.end,
+            _ => ranges.push(range),
+        }
+    }
+    ranges
+}
+
+/// Split a line into runs of word characters, runs of whitespace and
+/// single punctuation characters
+fn words(line: &str) -> Vec<Range<usize>> {
+    let kind = |c: char| if c.is_alphanumeric() || c == '_' { 0 } else if c.is_whitespace() { 1 } else { 2 };
+    let mut words: Vec<Range<usize>> = Vec::new();
+    let mut last_kind = None;
+    for (i, c) in line.char_indices() {
+        let this_kind = kind(c);
+        match words.last_mut() {
+            Some(word) if last_kind == Some(this_kind) && this_kind != 2 => word.end = i + c.len_utf8(),
+            _ => words.push(i..i + c.len_utf8()),
+        }
+        last_kind = Some(this_kind);
+    }
+    words
+}
+
+fn line_text(slice: &str) -> &str {
+    slice.trim_end_matches('\n').trim_end_matches('\r')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_only_changed_words_are_marked() {
+        let old = "let total = add(a, b);\nkeep\n";
+        let new = "let sum = add(a, c);\nkeep\nadded\n";
+        let changed = changed_lines(old, new);
+        let first = &changed[&0];
+        let words: Vec<&str> = first.iter().map(|range| &new[range.clone()]).collect();
+        assert_eq!(words, vec!["sum", "c"]);
+        assert!(!changed.contains_key(&1));
+        assert!(!changed.contains_key(&2));
+
+        assert_eq!(changed_words("same line", "same line"), Vec::<Range<usize>>::new());
+        assert_eq!(changed_words("one two", "one two three"), vec![7..13]);
+        assert_eq!(changed_words("total", "count"), vec![0..5]);
+    }
+}
@@ -0,0 +1,201 @@
+use anyhow::Result;
+use std::path::Path;
+
+/// A change to one line of a file, part of a multi-file edit
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LineEdit {
+    /// 0-indexed line the edit applies to; insertions go before this line
+    pub line: usize,
+    /// Content the line must still have; `None` inserts a new line
+    pub old: Option<String>,
+    /// Replacement content; `None` deletes the line
+    pub new: Option<String>,
+    /// Whether the edit will be applied (toggled in the preview)
+    pub selected: bool,
+}
+
+impl LineEdit {
+    pub fn replace(line: usize, old: &str, new: &str) -> Self {
+        Self { line, old: Some(old.to_string()), new: Some(new.to_string()), selected: true }
+    }
+
+    pub fn insert(line: usize, new: &str) -> Self {
+        Self { line, old: None, new: Some(new.to_string()), selected: true }
+    }
+
+    pub fn delete(line: usize, old: &str) -> Self {
+        Self { line, old: Some(old.to_string()), new: None, selected: true }
+    }
+}
+
+/// The edits for a single file
+#[derive(Debug, Clone)]
+pub struct FileEdit {
+    pub path: String,
+    pub edits: Vec<LineEdit>,
+}
+
+/// A set of line edits across several files, reviewed in a preview before
+/// anything is changed
+///
+/// Used by refactorings and project-wide replace. Every edit can be
+/// deselected in the preview; applying writes each file once.
+#[derive(Debug, Clone)]
+pub struct WorkspaceEdit {
+    /// Describes what the edit does, shown as the preview title
+    pub title: String,
+    pub files: Vec<FileEdit>,
+    /// Index of the highlighted edit, counting across all files
+    pub selected_index: usize,
+}
+
+impl WorkspaceEdit {
+    pub fn new(title: &str) -> Self {
+        Self {
+            title: title.to_string(),
+            files: Vec::new(),
+            selected_index: 0,
+        }
+    }
+
+    /// Add an edit, grouping it with earlier edits to the same file
+    pub fn add_edit(&mut self, path: &str, edit: LineEdit) {
+        match self.files.iter_mut().find(|file| file.path == path) {
+            Some(file) => file.edits.push(edit),
+            None => self.files.push(FileEdit {
+                path: path.to_string(),
+                edits: vec![edit],
+            }),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.iter().all(|file| file.edits.is_empty())
+    }
+
+    /// Total number of edits across all files
+    pub fn edit_count(&self) -> usize {
+        self.files.iter().map(|file| file.edits.len()).sum()
+    }
+
+    /// Number of edits that will be applied
+    pub fn selected_count(&self) -> usize {
+        self.files.iter()
+            .flat_map(|file| file.edits.iter())
+            .filter(|edit| edit.selected)
+            .count()
+    }
+
+    pub fn next(&mut self) {
+        let count = self.edit_count();
+        if count > 0 {
+            self.selected_index = (self.selected_index + 1) % count;
+        }
+    }
+
+    pub fn previous(&mut self) {
+        let count = self.edit_count();
+        if count > 0 {
+            self.selected_index = (self.selected_index + count - 1) % count;
+        }
+    }
+
+    /// Include or exclude the highlighted edit
+    pub fn toggle_selected(&mut self) {
+        let index = self.selected_index;
+        if let Some(edit) = self.files.iter_mut().flat_map(|file| file.edits.iter_mut()).nth(index) {
+            edit.selected = !edit.selected;
+        }
+    }
+
+    /// Select every edit, or deselect them all if they're all selected
+    pub fn toggle_all(&mut self) {
+        let select = self.selected_count() < self.edit_count();
+        for edit in self.files.iter_mut().flat_map(|file| file.edits.iter_mut()) {
+            edit.selected = select;
+        }
+    }
+}
+
+/// Show a path relative to the working directory when it's inside it
+pub fn relative_path(path: &str) -> String {
+    let relative = std::env::current_dir()
+        .and_then(|cwd| cwd.canonicalize())
+        .ok()
+        .and_then(|cwd| Path::new(path).strip_prefix(cwd).ok().map(|p| p.display().to_string()));
+    relative.unwrap_or_else(|| path.to_string())
+}
+
+/// Apply the selected edits to a file's lines
+///
+/// Fails without changing anything if a line no longer has the content the
+/// edit was computed from, e.g. because the file changed after the preview
+/// was built.
+pub fn apply_to_lines(lines: &[String], edits: &[LineEdit]) -> Result<Vec<String>> {
+    let mut selected: Vec<(usize, &LineEdit)> = edits.iter().enumerate().filter(|(_, edit)| edit.selected).collect();
+    // Work bottom-up so earlier line numbers stay valid, and on a shared
+    // line change the existing line before inserting above it; lines
+    // inserted at the same place keep their order
+    selected.sort_by_key(|(index, edit)| std::cmp::Reverse((edit.line, edit.old.is_some(), *index)));
+
+    let mut result = lines.to_vec();
+    for (_, edit) in selected {
+        if let Some(old) = &edit.old {
+            if result.get(edit.line) != Some(old) {
+                return Err(anyhow::anyhow!("Line {} changed since the edit was previewed", edit.line + 1));
+            }
+        }
+
+        match (&edit.old, &edit.new) {
+            (Some(_), Some(new)) => result[edit.line] = new.clone(),
+            (Some(_), None) => {
+                result.remove(edit.line);
+            },
+            (None, Some(new)) => result.insert(edit.line.min(result.len()), new.clone()),
+            (None, None) => {},
+        }
+    }
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(text: &[&str]) -> Vec<String> {
+        text.iter().map(|line| line.to_string()).collect()
+    }
+
+    #[test]
+    fn test_apply_selected_edits() {
+        let original = lines(&["mod a;", "mod b;", "", "fn main() {}"]);
+        let mut edit = WorkspaceEdit::new("test");
+        edit.add_edit("src/main.rs", LineEdit::replace(0, "mod a;", "mod c;"));
+        edit.add_edit("src/main.rs", LineEdit::delete(1, "mod b;"));
+        edit.add_edit("src/main.rs", LineEdit::insert(2, "mod d;"));
+        assert_eq!(edit.files.len(), 1);
+        assert_eq!(edit.edit_count(), 3);
+
+        let result = apply_to_lines(&original, &edit.files[0].edits).unwrap();
+        assert_eq!(result, lines(&["mod c;", "mod d;", "", "fn main() {}"]));
+
+        // Deselected edits are skipped
+        edit.next();
+        edit.toggle_selected();
+        assert_eq!(edit.selected_count(), 2);
+        let result = apply_to_lines(&original, &edit.files[0].edits).unwrap();
+        assert_eq!(result, lines(&["mod c;", "mod b;", "mod d;", "", "fn main() {}"]));
+
+        // Lines inserted at the same place keep their order
+        let mut appended = WorkspaceEdit::new("test");
+        appended.add_edit("src/main.rs", LineEdit::insert(4, ""));
+        appended.add_edit("src/main.rs", LineEdit::insert(4, "mod e;"));
+        let result = apply_to_lines(&original, &appended.files[0].edits).unwrap();
+        assert_eq!(result, lines(&["mod a;", "mod b;", "", "fn main() {}", "", "mod e;"]));
+
+        // Stale edits are refused
+        let changed = lines(&["mod x;", "mod b;", "", "fn main() {}"]);
+        assert!(apply_to_lines(&changed, &edit.files[0].edits).is_err());
+    }
+}
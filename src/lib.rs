@@ -0,0 +1,3 @@
+pub mod editor;
+pub mod ui;
+pub mod config;
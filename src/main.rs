@@ -1,106 +1,393 @@
-mod editor;
-mod ui;
-mod config;
-
-use anyhow::Result;
+use anyhow::{Context, Result};
 use clap::Parser;
 use crossterm::{
-    event::{self, DisableMouseCapture, EnableMouseCapture, Event},
+    cursor::{SetCursorStyle, Show},
+    event::{DisableBracketedPaste, DisableMouseCapture, EnableBracketedPaste, EnableMouseCapture},
     execute,
-    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
+    style::Print,
+    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen, SetTitle},
 };
-use std::{io, time::Duration};
+use std::{io::{self, Read}, sync::atomic::{AtomicBool, Ordering}, time::{Duration, Instant}};
 use tui::{
     backend::CrosstermBackend,
     Terminal,
 };
 
-use editor::Editor;
+use zim::{config, editor, ui};
+use zim::editor::{CursorShape, Editor, TerminalEvents};
 
 /// Zim - the modern, fast, easily configurable, AI powered vim from the future
 #[derive(Parser, Debug)]
 #[clap(author, version, about)]
 struct Cli {
-    /// File to open
+    /// Files to open, each in its own tab: `file`, `file:line`,
+    /// `file:line:col`, or `+line file` (`+` alone for the last line).
+    /// `-` reads from stdin.
     #[clap(name = "FILE")]
-    file: Option<String>,
+    files: Vec<String>,
+
+    /// Report how long each startup phase took
+    #[clap(long)]
+    startuptime: bool,
+
+    /// Ex command to run once the files are open, like `-c '%s/foo/bar/g'`;
+    /// can be given more than once
+    #[clap(short = 'c', value_name = "COMMAND")]
+    commands: Vec<String>,
+
+    /// File of ex commands to run, one per line, before any `-c` ones
+    #[clap(long, value_name = "FILE")]
+    script: Option<String>,
+
+    /// Run the commands without starting the interface, then exit
+    #[clap(long)]
+    batch: bool,
+
+    /// Don't run cargo, compilers, formatters, shell or AI commands
+    #[clap(long)]
+    safe: bool,
+}
+
+impl Cli {
+    /// The commands from `--script` and then `-c`; blank lines and `"`
+    /// comments in the script are skipped
+    fn commands(&self) -> Result<Vec<String>> {
+        let mut commands = Vec::new();
+        if let Some(script) = &self.script {
+            let text = std::fs::read_to_string(script)
+                .with_context(|| format!("Failed to read script: {}", script))?;
+            commands.extend(text.lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('"'))
+                .map(str::to_string));
+        }
+        commands.extend(self.commands.iter().cloned());
+        Ok(commands)
+    }
+}
+
+/// Load which workspaces may run commands; if that fails, none may
+fn load_trust(editor: &mut Editor, safe: bool) {
+    let trust = config::Config::path()
+        .and_then(|path| editor::WorkspaceTrust::load(path.with_file_name("trust.toml"), safe));
+    match trust {
+        Ok(trust) => editor.trust = trust,
+        Err(e) => {
+            editor.trust.safe = true;
+            editor.show_message(&format!("Error: {}; running in safe mode", e));
+        },
+    }
+}
+
+/// Load the files opened in earlier sessions, which `:recent` and the
+/// file finder list; batch runs leave them alone
+fn load_recent_files(editor: &mut Editor) {
+    let loaded = config::Config::path()
+        .and_then(|path| editor.file_finder.load_history(path.with_file_name("recent.toml")));
+    if let Err(e) = loaded {
+        editor.show_message(&format!("Error: {}", e));
+    }
+}
+
+/// Open the files and run the commands on them without a terminal
+/// (`--batch`), failing at the first command that does
+///
+/// Returns the status to exit with, which `:cq` sets.
+fn run_batch(cli: &Cli, config: config::Config, stdin: Option<&[u8]>) -> Result<i32> {
+    let mut editor = Editor::new_with_config(config);
+    editor.batch = true;
+    load_trust(&mut editor, cli.safe);
+    editor.open_targets(&editor::parse_args(&cli.files), stdin)?;
+    editor.run_commands(&cli.commands()?)?;
+    Ok(editor.exit_code)
+}
+
+/// Records how long each startup phase took (`--startuptime`)
+struct StartupTimer {
+    start: Instant,
+    last: Instant,
+    phases: Vec<(String, Duration, Duration)>,
+}
+
+impl StartupTimer {
+    fn new() -> Self {
+        let now = Instant::now();
+        Self {
+            start: now,
+            last: now,
+            phases: Vec::new(),
+        }
+    }
+
+    /// Mark the end of a phase, recording its own duration and the total so far
+    fn mark(&mut self, phase: &str) {
+        let now = Instant::now();
+        self.phases.push((phase.to_string(), now - self.last, now - self.start));
+        self.last = now;
+    }
+
+    /// Format the timings like vim's --startuptime: total, self, phase
+    fn report(&self) -> Vec<String> {
+        let mut lines = vec!["   total      self: phase".to_string()];
+        for (phase, elapsed, total) in &self.phases {
+            lines.push(format!(
+                "{:>6.1}ms {:>6.1}ms: {}",
+                total.as_secs_f64() * 1000.0,
+                elapsed.as_secs_f64() * 1000.0,
+                phase
+            ));
+        }
+        lines
+    }
+}
+
+/// Terminal escapes (xterm's) to save the title on the terminal's stack at
+/// startup and put it back on exit, since it can't be read
+const SAVE_TITLE: &str = "\x1b[22;0t";
+const RESTORE_TITLE: &str = "\x1b[23;0t";
+
+/// Whether zim has the terminal in raw mode on the alternate screen, so
+/// it's given back exactly once whether zim exits or panics
+static TERMINAL_TAKEN: AtomicBool = AtomicBool::new(false);
+
+/// Take over the terminal for the interface
+fn take_terminal() -> Result<()> {
+    enable_raw_mode()?;
+    TERMINAL_TAKEN.store(true, Ordering::SeqCst);
+    // Bracketed paste sends a paste as one event instead of as keys
+    execute!(io::stdout(), EnterAlternateScreen, EnableMouseCapture, EnableBracketedPaste, Print(SAVE_TITLE))?;
+    Ok(())
+}
+
+/// Give the terminal back as it was before `take_terminal`; errors are
+/// ignored as this also runs while panicking
+fn restore_terminal() {
+    if TERMINAL_TAKEN.swap(false, Ordering::SeqCst) {
+        let _ = disable_raw_mode();
+        let _ = execute!(
+            io::stdout(),
+            LeaveAlternateScreen,
+            DisableMouseCapture,
+            DisableBracketedPaste,
+            SetCursorStyle::DefaultUserShape,
+            Print(RESTORE_TITLE),
+            Show
+        );
+    }
+}
+
+/// Restores the terminal when dropped, including while unwinding
+struct TerminalGuard;
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        restore_terminal();
+    }
+}
+
+fn cursor_style(shape: CursorShape) -> SetCursorStyle {
+    match shape {
+        CursorShape::Block => SetCursorStyle::SteadyBlock,
+        CursorShape::Bar => SetCursorStyle::SteadyBar,
+        CursorShape::Underline => SetCursorStyle::SteadyUnderScore,
+    }
 }
 
 fn run_app(
     terminal: &mut Terminal<CrosstermBackend<io::Stdout>>,
-    mut editor: Editor,
+    editor: &mut Editor,
+    timer: &mut Option<StartupTimer>,
 ) -> Result<()> {
+    let mut first_frame = true;
+    let mut indexing = false;
+    // The cursor shape and title last sent, so they're only sent on changes
+    let mut shown: Option<(CursorShape, String)> = None;
+    let mut last_frame: Option<Instant> = None;
+
     loop {
-        // Draw UI and collect any viewport updates
+        // Sudo asks for its password on the terminal, outside the interface
+        if editor.has_sudo_write() {
+            suspend_terminal(terminal)?;
+            editor.finish_sudo_write();
+            resume_terminal(terminal)?;
+            shown = None;
+            editor.request_redraw();
+        }
+
+        // Draw UI only when something changed, at most `max_fps` times a
+        // second, and collect any viewport updates
+        let frame_time = editor.config.max_fps.filter(|fps| *fps > 0).map(|fps| Duration::from_secs(1) / fps);
+        let next_frame = match (last_frame, frame_time) {
+            (Some(last), Some(frame_time)) => frame_time.saturating_sub(last.elapsed()),
+            _ => Duration::ZERO,
+        };
         let mut viewport_update = None;
-        terminal.draw(|f| {
-            viewport_update = ui::render(f, &mut editor);
-        })?;
+        if next_frame.is_zero() && editor.take_redraw() {
+            last_frame = Some(Instant::now());
+            editor::note_open_files(editor.open_files());
+            terminal.draw(|f| {
+                viewport_update = ui::render(f, editor);
+            })?;
+            let state = (editor.mode.cursor_shape(), editor.terminal_title());
+            if shown.as_ref() != Some(&state) {
+                execute!(terminal.backend_mut(), cursor_style(state.0), SetTitle(&state.1))?;
+                shown = Some(state);
+            }
+        }
+        if editor.notifier.take_bell() {
+            execute!(terminal.backend_mut(), Print('\x07'))?;
+        }
+
+        // Defer slow startup work until something is on screen
+        if first_frame {
+            first_frame = false;
+            if let Some(timer) = timer.as_mut() {
+                timer.mark("first frame drawn");
+            }
+            editor.start_deferred_tasks();
+            indexing = true;
+        }
+
+        editor.on_tick();
+        if !editor.flush_pending_keys()? {
+            return Ok(());
+        }
+
+        if indexing && !editor.file_finder.is_indexing() {
+            indexing = false;
+            if let Some(timer) = timer.as_mut() {
+                timer.mark("file index ready");
+                for line in timer.report() {
+                    editor.messages.push(&line);
+                }
+            }
+        }
         
         // Apply viewport updates if needed (safely updates viewport dimensions)
         if let Some(update) = viewport_update {
-            if let Some(tab) = editor.tabs.get_mut(editor.current_tab) {
-                tab.viewport.width = update.width;
-                tab.viewport.height = update.height;
-            }
+            ui::apply_viewport_update(editor, update);
         }
 
-        if crossterm::event::poll(Duration::from_millis(100))? {
-            match event::read()? {
-                Event::Key(key) => {
-                    // Handle key event in the editor
-                    if !editor.handle_key(key)? {
-                        // Editor returned false, which means we should quit
-                        return Ok(());
-                    }
-                },
-                Event::Mouse(mouse_event) => {
-                    // Handle mouse event in the editor if we have tabs
-                    if !editor.tabs.is_empty() {
-                        editor.handle_mouse(mouse_event)?;
-                    }
-                },
-                _ => {}
-            }
+        // Poll faster while background results are streaming in, and wake
+        // for a frame held back by the cap
+        let timeout = if next_frame.is_zero() { editor.poll_timeout() } else { editor.poll_timeout().min(next_frame) };
+        if !editor.handle_input(&mut TerminalEvents, timeout)? {
+            // Editor returned false, which means we should quit
+            return Ok(());
         }
     }
 }
 
+/// Give the terminal back to the shell while another program talks to the
+/// user directly
+fn suspend_terminal(terminal: &mut Terminal<CrosstermBackend<io::Stdout>>) -> Result<()> {
+    disable_raw_mode()?;
+    execute!(terminal.backend_mut(), LeaveAlternateScreen, DisableMouseCapture, DisableBracketedPaste, SetCursorStyle::DefaultUserShape)?;
+    terminal.show_cursor()?;
+    Ok(())
+}
+
+/// Take the terminal back after `suspend_terminal`, redrawing everything
+fn resume_terminal(terminal: &mut Terminal<CrosstermBackend<io::Stdout>>) -> Result<()> {
+    enable_raw_mode()?;
+    execute!(terminal.backend_mut(), EnterAlternateScreen, EnableMouseCapture, EnableBracketedPaste)?;
+    terminal.clear()?;
+    Ok(())
+}
+
 fn main() -> Result<()> {
     let cli = Cli::parse();
+    let mut timer = if cli.startuptime { Some(StartupTimer::new()) } else { None };
     
     // Load config
     let config = config::Config::load()?;
+    if let Some(timer) = timer.as_mut() {
+        timer.mark("config loaded");
+    }
     
-    // Setup terminal
-    enable_raw_mode()?;
-    let mut stdout = io::stdout();
-    execute!(stdout, EnterAlternateScreen, EnableMouseCapture)?;
-    let backend = CrosstermBackend::new(stdout);
+    // Read a pipe into `zim -` before taking over the terminal; keys are
+    // then read from the terminal itself rather than stdin
+    let stdin = if cli.files.iter().any(|file| file == editor::STDIN) {
+        let mut bytes = Vec::new();
+        io::stdin().read_to_end(&mut bytes)?;
+        Some(bytes)
+    } else {
+        None
+    };
+
+    if cli.batch {
+        match run_batch(&cli, config, stdin.as_deref()) {
+            Ok(0) => return Ok(()),
+            Ok(code) => std::process::exit(code),
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            },
+        }
+    }
+    let commands = cli.commands()?;
+
+    // Setup terminal, making sure a panic leaves it usable and writes a
+    // crash report next to the config
+    if let Ok(path) = config::Config::path() {
+        editor::install_panic_hook(restore_terminal, path.with_file_name("crashes"));
+    }
+    let guard = TerminalGuard;
+    take_terminal()?;
+    let backend = CrosstermBackend::new(io::stdout());
     let mut terminal = Terminal::new(backend)?;
+    if let Some(timer) = timer.as_mut() {
+        timer.mark("terminal setup");
+    }
 
     // Create editor with config
     let mut editor = Editor::new_with_config(config);
+    load_trust(&mut editor, cli.safe);
+    load_recent_files(&mut editor);
+    if let Some(timer) = timer.as_mut() {
+        timer.mark("editor created");
+    }
     
-    // Load file if provided
-    if let Some(file_path) = &cli.file {
-        editor.load_file(file_path)?;
+    // Load files if provided
+    if !cli.files.is_empty() {
+        editor.open_targets(&editor::parse_args(&cli.files), stdin.as_deref())?;
+        if let Some(timer) = timer.as_mut() {
+            timer.mark("file loaded");
+        }
     }
 
-    let res = run_app(&mut terminal, editor);
+    // Commands from -c and --script run once the files are open
+    let res = match editor.run_commands(&commands) {
+        Ok(true) => run_app(&mut terminal, &mut editor, &mut timer),
+        Ok(false) => Ok(()),
+        Err(e) => {
+            editor.show_message(&format!("Error: {}", e));
+            run_app(&mut terminal, &mut editor, &mut timer)
+        },
+    };
+
+    editor.remember_cursors();
 
     // Restore terminal
-    disable_raw_mode()?;
-    execute!(
-        terminal.backend_mut(),
-        LeaveAlternateScreen,
-        DisableMouseCapture
-    )?;
-    terminal.show_cursor()?;
+    drop(guard);
 
     if let Err(err) = res {
         println!("Error: {:?}", err);
     }
 
+    if let Some(timer) = &timer {
+        for line in timer.report() {
+            eprintln!("{}", line);
+        }
+    }
+
+    // A non-zero status from `:cq` tells the program that started zim, like
+    // git waiting on a commit message, that editing was aborted
+    let exit_code = editor.exit_code;
+    drop(editor);
+    if exit_code != 0 {
+        std::process::exit(exit_code);
+    }
+
     Ok(())
 }
\ No newline at end of file
@@ -7,19 +7,41 @@ use tui::{
     Frame,
 };
 
-use crate::editor::{Editor, Mode, HighlightedLine, Tab, GameState, Position};
-use std::collections::HashMap;
+use crate::editor::{Editor, Mode, HighlightedLine, Tab, GameState, Position, MIN_QUERY_LEN, relative_path, HoverTarget, HOVER_DELAY, Keymap, Scrollbar, structure_lines, PagedKind, JobStatus, format_runtime, PendingClose, format_age, DashboardAction, HelpEntry};
 use syntect::highlighting::Style as SyntectStyle;
+use syntect::parsing::SyntaxReference;
+use std::sync::Arc;
+
+#[cfg(any(test, feature = "snapshots"))]
+pub mod snapshot;
 
 /// Holds information about viewport dimensions that need to be updated
 pub struct ViewportUpdate {
     pub width: usize,
     pub height: usize,
+    /// First column shown of long lines, as drawn
+    pub left_column: usize,
+}
+
+/// Size the current tab's view to what the last frame drew
+pub fn apply_viewport_update(editor: &mut Editor, update: ViewportUpdate) {
+    let Some(tab) = editor.tabs.get_mut(editor.current_tab) else { return };
+    let viewport = &mut tab.viewport;
+    // The frame drawn with the old size is redrawn with the new one
+    if (viewport.width, viewport.height, viewport.left_column) != (update.width, update.height, update.left_column) {
+        viewport.width = update.width;
+        viewport.height = update.height;
+        viewport.left_column = update.left_column;
+        editor.request_redraw();
+    }
 }
 
 pub fn render<B: Backend>(f: &mut Frame<B>, editor: &mut Editor) -> Option<ViewportUpdate> {
     let size = f.size();
     let mut viewport_update = None;
+    editor.prepare_highlights();
+    editor.hover.clear_targets();
+    editor.scrollbar = None;
 
     // Create the layout with tab bar (increased height)
     let chunks = Layout::default()
@@ -28,16 +50,19 @@ pub fn render<B: Backend>(f: &mut Frame<B>, editor: &mut Editor) -> Option<Viewp
         .constraints([
             Constraint::Length(3), // Tab bar - increased for visibility
             Constraint::Min(1),    // Editor area
-            Constraint::Length(1)  // Status line
+            Constraint::Length(1), // Status line
+            Constraint::Length(u16::from(editor.config.soft_mode)), // Soft mode hints
         ].as_ref())
         .split(size);
 
     // Render the tab bar
-    render_tab_bar(f, editor, chunks[0]);
+    let tab_targets = render_tab_bar(f, editor, chunks[0]);
+    editor.hover.add_targets(tab_targets);
     
     // Render main content
     match editor.mode {
         Mode::FileFinder => {
+            editor.update_file_preview();
             render_file_finder(f, editor, chunks[1]);
         },
         Mode::TokenSearch => {
@@ -57,9 +82,54 @@ pub fn render<B: Backend>(f: &mut Frame<B>, editor: &mut Editor) -> Option<Viewp
         Mode::FilenamePrompt => {
             render_filename_prompt(f, editor, chunks[1]);
         },
-        Mode::DiagnosticsPanel => {
-            // In DiagnosticsPanel mode, show a specialized view of diagnostics
-            render_diagnostics_panel(f, editor, chunks[1]);
+        Mode::DiagnosticsPanel => match editor.config.diagnostics_height {
+            // With a height set, the panel sits below the buffer it lists
+            Some(height) => {
+                let panes = Layout::default()
+                    .direction(Direction::Vertical)
+                    .constraints([Constraint::Min(3), Constraint::Length(height)].as_ref())
+                    .split(chunks[1]);
+                viewport_update = render_editor_area(f, editor, panes[0]);
+                render_diagnostics_panel(f, editor, panes[1]);
+            },
+            None => render_diagnostics_panel(f, editor, chunks[1]),
+        },
+        Mode::WorkspaceEditPreview => {
+            render_workspace_edit_preview(f, editor, chunks[1]);
+        },
+        Mode::UndoTree => {
+            // The buffer stays visible beside the tree to show each state
+            let panes = Layout::default()
+                .direction(Direction::Horizontal)
+                .constraints([Constraint::Min(20), Constraint::Length(chunks[1].width.min(60) * 2 / 3)].as_ref())
+                .split(chunks[1]);
+            viewport_update = render_editor_area(f, editor, panes[0]);
+            render_undo_tree(f, editor, panes[1]);
+        },
+        Mode::Jobs => {
+            render_jobs_panel(f, editor, chunks[1]);
+        },
+        Mode::RecentFiles => {
+            render_recent_files_panel(f, editor, chunks[1]);
+        },
+        Mode::DirectoryListing => {
+            render_directory_listing(f, editor, chunks[1]);
+        },
+        Mode::Dashboard => {
+            render_dashboard(f, editor, chunks[1]);
+        },
+        Mode::CommandPalette => {
+            render_command_palette(f, editor, chunks[1]);
+        },
+        Mode::BuildOutput => {
+            // Under the buffer, so editing stays in view while it runs
+            let height = (chunks[1].height * 2 / 5).max(5);
+            let panes = Layout::default()
+                .direction(Direction::Vertical)
+                .constraints([Constraint::Min(3), Constraint::Length(height)].as_ref())
+                .split(chunks[1]);
+            viewport_update = render_editor_area(f, editor, panes[0]);
+            render_build_panel(f, editor, panes[1]);
         },
         Mode::Visual | Mode::VisualLine => {
             // In Visual modes, highlight the selection
@@ -78,8 +148,23 @@ pub fn render<B: Backend>(f: &mut Frame<B>, editor: &mut Editor) -> Option<Viewp
         }
     }
     
+    // A peeked location floats over the panel it was picked from
+    if editor.peek.is_some() {
+        render_peek(f, editor, chunks[1]);
+    }
+    
+    render_tooltip(f, editor, size);
+    
     // Render status line
     render_status_line(f, editor, chunks[2]);
+    if editor.config.soft_mode {
+        render_hint_bar(f, editor, chunks[3]);
+    }
+
+    // Show where a partly typed key sequence can lead
+    if let Some(keymap) = editor.pending_keymap() {
+        render_which_key(f, keymap, chunks[1]);
+    }
     
     // Helper function to create a centered rect using up certain percentage of the available rect
     fn centered_rect(percent_x: u16, percent_y: u16, r: Rect) -> Rect {
@@ -220,8 +305,9 @@ pub fn render<B: Backend>(f: &mut Frame<B>, editor: &mut Editor) -> Option<Viewp
     viewport_update
 }
 
-/// Render the tab bar
-fn render_tab_bar<B: Backend>(f: &mut Frame<B>, editor: &Editor, area: Rect) {
+/// Render the tab bar, returning tooltips with the full path of tabs whose
+/// title is shortened or cut off
+fn render_tab_bar<B: Backend>(f: &mut Frame<B>, editor: &Editor, area: Rect) -> Vec<HoverTarget> {
     // Create the tab bar block with prominent coloring
     let tab_bar_block = Block::default()
         .title(" TABS ")
@@ -234,6 +320,7 @@ fn render_tab_bar<B: Backend>(f: &mut Frame<B>, editor: &Editor, area: Rect) {
     
     // Create tab items
     let mut tab_spans = Vec::new();
+    let mut targets = Vec::new();
     
     // Always show debug info to confirm tabs are rendering
     tab_spans.push(tui::text::Span::styled(
@@ -276,10 +363,21 @@ fn render_tab_bar<B: Backend>(f: &mut Frame<B>, editor: &Editor, area: Rect) {
         };
         
         // Add tab item
-        tab_spans.push(tui::text::Span::styled(
-            format!(" {}{} ", f_key_display, filename),
-            style,
-        ));
+        let title = format!(" {}{} ", f_key_display, filename);
+        let x = inner_area.x as usize + tab_spans.iter().map(|span: &Span| span.content.chars().count()).sum::<usize>();
+        let width = title.chars().count();
+        let right = inner_area.right() as usize;
+        if let Some(path) = &tab.buffer.file_path {
+            if x < right && (filename != *path || x + width > right) {
+                targets.push(HoverTarget {
+                    x: x as u16,
+                    y: inner_area.y,
+                    width: (width.min(right - x)) as u16,
+                    text: path.clone(),
+                });
+            }
+        }
+        tab_spans.push(tui::text::Span::styled(title, style));
         
         // Add separator
         tab_spans.push(tui::text::Span::raw(" | "));
@@ -306,6 +404,7 @@ fn render_tab_bar<B: Backend>(f: &mut Frame<B>, editor: &Editor, area: Rect) {
     
     // Render the tabs
     f.render_widget(tabs_paragraph, inner_area);
+    targets
 }
 
 // Common rendering function that can optionally highlight modified or diff lines
@@ -316,10 +415,6 @@ fn render_editor_area_inner<B: Backend>(
     highlight_modified: bool,
     is_diff_mode: bool
 ) -> Option<ViewportUpdate> {
-    // Create a cache for highlighted lines
-    let mut highlight_cache = HashMap::new();
-    std::mem::swap(&mut highlight_cache, &mut editor.highlighted_lines_cache);
-    
     // Get the current tab
     let tab = editor.current_tab();
     
@@ -330,6 +425,11 @@ fn render_editor_area_inner<B: Backend>(
     // Get the inner area dimensions (accounting for borders)
     let inner_area = editor_block.inner(area);
     
+    if tab.buffer.paged.is_some() {
+        f.render_widget(editor_block, area);
+        return render_paged(f, editor, inner_area);
+    }
+    
     // Update only the viewport dimensions in the editor (not top_line/left_column)
     // This allows for proper scrolling calculations while avoiding jiggling
     let mut viewport = tab.viewport.clone();
@@ -337,8 +437,7 @@ fn render_editor_area_inner<B: Backend>(
     // Calculate needed space for line numbers based on total line count
     // Add 2 to account for 1 space and 1 character for diagnostic indicator
     let total_lines = tab.buffer.line_count();
-    let line_num_width = total_lines.to_string().len();
-    let line_number_width = line_num_width + 2; // width + diagnostic indicator + space
+    let line_number_width = line_number_column_width(editor, total_lines) + 1; // numbers + diagnostic indicator
     let content_width = inner_area.width.saturating_sub(line_number_width as u16);
     
     viewport.update_dimensions(content_width as usize, inner_area.height as usize);
@@ -346,26 +445,32 @@ fn render_editor_area_inner<B: Backend>(
     // Calculate visible range
     let (start_line, end_line) = viewport.get_visible_range(tab.buffer.line_count());
     
-    // We'll avoid changing left_column during rendering to prevent jiggling
-    let left_column = tab.viewport.left_column;
+    let left_column = shown_left_column(editor, tab, content_width as usize);
     
     // Format to get max line number width
     let total_lines = tab.buffer.line_count();
     let line_num_width = total_lines.to_string().len();
     
+    let bracket_pair = editor.bracket_pair();
+    // Write and reload confirmation show their own diff instead
+    let unsaved = if highlight_modified { Default::default() } else { unsaved_lines(editor, tab) };
+
     // Convert only visible buffer lines to Lines for rendering with line numbers
     let lines: Vec<Line> = tab.buffer.lines[start_line..end_line].iter()
         .enumerate()
         .map(|(idx, line)| {
-            let line_number = start_line + idx + 1; // 1-indexed line numbers
             let current_line_idx = start_line + idx;
             let is_modified = tab.buffer.is_line_modified(current_line_idx);
             let is_diff = editor.diff_lines.contains(&current_line_idx);
+            let is_excluded = !is_diff_mode
+                && editor.write_review.as_ref().is_some_and(|review| review.is_excluded(current_line_idx));
             
             // Style the line number based on modification/diff status if highlighting is enabled
             let number_style = if highlight_modified {
                 if is_diff_mode && is_diff {
                     Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD)
+                } else if is_excluded {
+                    Style::default().fg(Color::DarkGray)
                 } else if !is_diff_mode && is_modified {
                     Style::default().fg(Color::Green).add_modifier(Modifier::BOLD)
                 } else {
@@ -375,7 +480,7 @@ fn render_editor_area_inner<B: Backend>(
                 Style::default().fg(Color::DarkGray)
             };
             
-            let number_str = format!("{:>width$} ", line_number, width=line_num_width);
+            let number_str = line_number_label(editor, tab, current_line_idx, line_num_width);
             
             // Check if this line has diagnostics and add a gutter indicator
             let has_diagnostics = tab.diagnostics.get_diagnostics_for_line(current_line_idx).is_some();
@@ -383,14 +488,18 @@ fn render_editor_area_inner<B: Backend>(
                 let diagnostics = tab.diagnostics.get_diagnostics_for_line(current_line_idx).unwrap();
                 let has_error = diagnostics.iter().any(|d| d.severity == crate::editor::DiagnosticSeverity::Error);
                 let has_warning = diagnostics.iter().any(|d| d.severity == crate::editor::DiagnosticSeverity::Warning);
+                // A diamond marks lines with a fix `:fix` can apply
+                let indicator = if diagnostics.iter().any(|d| d.fix.is_some()) { "◆" } else { "●" };
                 
                 if has_error {
-                    ("●", Style::default().fg(Color::Red).add_modifier(Modifier::BOLD))
+                    (indicator, Style::default().fg(Color::Red).add_modifier(Modifier::BOLD))
                 } else if has_warning {
-                    ("●", Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD))
+                    (indicator, Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD))
                 } else {
-                    ("●", Style::default().fg(Color::Blue))
+                    (indicator, Style::default().fg(Color::Blue))
                 }
+            } else if unsaved.contains(&current_line_idx) {
+                unsaved_marker()
             } else {
                 (" ", Style::default())
             };
@@ -419,12 +528,17 @@ fn render_editor_area_inner<B: Backend>(
                         content,
                         Style::default().fg(Color::Yellow)
                     ));
-                } else if !is_diff_mode && is_modified {
-                    // In WriteConfirm mode, highlight the entire modified line in green
+                } else if is_excluded {
+                    // Hunks left out of the save stay in the buffer only
                     spans.push(tui::text::Span::styled(
                         content,
-                        Style::default().fg(Color::Green)
+                        Style::default().fg(Color::DarkGray).add_modifier(Modifier::CROSSED_OUT)
                     ));
+                } else if !is_diff_mode && is_modified {
+                    // In WriteConfirm mode, highlight the modified line in
+                    // green, and the words that changed on it more strongly
+                    let changed = editor.write_review.as_ref().and_then(|review| review.words.get(&current_line));
+                    spans.extend(word_diff_spans(&content, left_column, changed.map_or(&[][..], |ranges| ranges.as_slice())));
                 } else if let Some(line_diagnostics) = tab.diagnostics.get_diagnostics_for_line(current_line) {
                     // If there are diagnostics, create styled spans based on the diagnostics
                     if !line_diagnostics.is_empty() && !content.is_empty() {
@@ -489,32 +603,7 @@ fn render_editor_area_inner<B: Backend>(
                     } else {
                         // Apply syntax highlighting if available
                         if let Some(syntax_ref) = &tab.buffer.syntax {
-                            // Get line for highlighting
-                            let line_for_highlight = if current_line < tab.buffer.lines.len() {
-                                &tab.buffer.lines[current_line]
-                            } else {
-                                ""
-                            };
-                            
-                            // Use the cache to avoid recomputing syntax highlights
-                            let tab_idx = editor.current_tab;
-                            let cache_key = (tab_idx, current_line);
-                            
-                            // Get or compute the highlighted line
-                            let highlighted = if let Some(cached) = highlight_cache.get(&cache_key) {
-                                cached.clone()
-                            } else {
-                                // Highlight the line
-                                let highlighted = editor.syntax_highlighter.highlight_text(
-                                    &format!("{}\n", line_for_highlight), 
-                                    syntax_ref.clone()
-                                );
-                                
-                                // Store for later caching
-                                let result = highlighted.clone();
-                                highlight_cache.insert(cache_key, result);
-                                highlighted
-                            };
+                            let highlighted = line_highlight(editor, tab, current_line, syntax_ref);
                             
                             if !highlighted.is_empty() {
                                 let line_spans = highlighted[0].ranges.iter()
@@ -543,32 +632,7 @@ fn render_editor_area_inner<B: Backend>(
                 } else {
                     // Apply syntax highlighting if available
                     if let Some(syntax_ref) = &tab.buffer.syntax {
-                        // Get line for highlighting
-                        let line_for_highlight = if current_line < tab.buffer.lines.len() {
-                            &tab.buffer.lines[current_line]
-                        } else {
-                            ""
-                        };
-                        
-                        // Use the cache to avoid recomputing syntax highlights
-                        let tab_idx = editor.current_tab;
-                        let cache_key = (tab_idx, current_line);
-                        
-                        // Get or compute the highlighted line
-                        let highlighted = if let Some(cached) = highlight_cache.get(&cache_key) {
-                            cached.clone()
-                        } else {
-                            // Highlight the line
-                            let highlighted = editor.syntax_highlighter.highlight_text(
-                                &format!("{}\n", line_for_highlight), 
-                                syntax_ref.clone()
-                            );
-                            
-                            // Store for later caching
-                            let result = highlighted.clone();
-                            highlight_cache.insert(cache_key, result);
-                            highlighted
-                        };
+                        let highlighted = line_highlight(editor, tab, current_line, syntax_ref);
                         
                         // Use the helper function to create highlighted spans
                         let line_spans = create_highlighted_spans(&highlighted);
@@ -584,32 +648,7 @@ fn render_editor_area_inner<B: Backend>(
             } else {
                 // Apply syntax highlighting if available
                 if let Some(syntax_ref) = &tab.buffer.syntax {
-                    // Get line for highlighting
-                    let line_for_highlight = if current_line < tab.buffer.lines.len() {
-                        &tab.buffer.lines[current_line]
-                    } else {
-                        ""
-                    };
-                    
-                    // Use the cache to avoid recomputing syntax highlights
-                    let tab_idx = editor.current_tab;
-                    let cache_key = (tab_idx, current_line);
-                    
-                    // Get or compute the highlighted line
-                    let highlighted = if let Some(cached) = highlight_cache.get(&cache_key) {
-                        cached.clone()
-                    } else {
-                        // Highlight the line
-                        let highlighted = editor.syntax_highlighter.highlight_text(
-                            &format!("{}\n", line_for_highlight), 
-                            syntax_ref.clone()
-                        );
-                        
-                        // Store for later caching
-                        let result = highlighted.clone();
-                        highlight_cache.insert(cache_key, result);
-                        highlighted
-                    };
+                    let highlighted = line_highlight(editor, tab, current_line, syntax_ref);
                     
                     // Use the helper function to create highlighted spans
                     let line_spans = create_highlighted_spans(&highlighted);
@@ -622,6 +661,36 @@ fn render_editor_area_inner<B: Backend>(
                     spans.push(tui::text::Span::raw(content));
                 }
             }
+
+            // Syntax highlighted lines come out whole, so scroll them here
+            if left_column > 0 && shown_from(&spans, line) == 0 {
+                let content_spans = spans.split_off(2);
+                spans.extend(skip_columns(content_spans, left_column));
+            }
+
+            // Highlight the bracket under the cursor and its pair
+            let bracket_columns: Vec<usize> = bracket_pair.iter()
+                .flat_map(|(at, pair)| [*at, *pair])
+                .filter(|(_, y)| *y == current_line)
+                .map(|(x, _)| x)
+                .collect();
+            if !bracket_columns.is_empty() {
+                let shown_from = shown_from(&spans, line);
+                let content_spans = spans.split_off(2);
+                let columns: Vec<usize> = bracket_columns.iter()
+                    .filter_map(|x| x.checked_sub(shown_from))
+                    .collect();
+                spans.extend(style_columns(content_spans, &columns, matching_bracket_style()));
+            }
+            let shown_from = shown_from(&spans, line);
+            add_guides(&mut spans, editor, tab, current_line, shown_from, content_width as usize);
+            show_whitespace(&mut spans, editor, tab, current_line, line, shown_from);
+            if editor.config.diagnostic_virtual_text {
+                if let Some(diagnostics) = tab.diagnostics.get_diagnostics_for_line(current_line) {
+                    let used: usize = spans[2..].iter().map(|span| span.width()).sum();
+                    spans.extend(diagnostic_virtual_text(diagnostics, (content_width as usize).saturating_sub(used)));
+                }
+            }
             
             Line::from(spans)
         })
@@ -629,10 +698,15 @@ fn render_editor_area_inner<B: Backend>(
     
     let paragraph = Paragraph::new(lines)
         .block(editor_block)
-        .style(Style::default())
-        .wrap(Wrap { trim: false });
+        .style(Style::default());
+    let paragraph = if editor.wrap_text() {
+        paragraph.wrap(Wrap { trim: false })
+    } else {
+        paragraph
+    };
     
     f.render_widget(paragraph, area);
+    let scrollbar = render_scrollbar(f, editor, area, start_line, viewport.height);
 
     // Set cursor position relative to viewport
     // Use the same left_column we used for rendering to ensure consistency
@@ -641,21 +715,116 @@ fn render_editor_area_inner<B: Backend>(
     
     // Adjust cursor position for line numbers
     // Add the number width to the cursor x position, plus 1 for the diagnostic indicator
-    let line_number_offset = line_num_width + 2; // width + space + indicator
+    let line_number_offset = line_number_column_width(editor, total_lines) + 1; // numbers + indicator
     
     f.set_cursor(
         area.x + cursor_x as u16 + line_number_offset as u16 + 1, // +1 for the border
         area.y + cursor_y as u16 + 1, // +1 for the border
     );
-    
-    // Restore the cache
-    std::mem::swap(&mut highlight_cache, &mut editor.highlighted_lines_cache);
-    
-    // Return viewport dimensions for safe update
-    Some(ViewportUpdate {
+    render_ghost_text(f, editor, area, cursor_x + line_number_offset + 1, cursor_y + 1);
+
+    if let Some(line) = editor.diagnostic_popup.filter(|line| *line == tab.cursor.y) {
+        if let Some(diagnostics) = tab.diagnostics.get_diagnostics_for_line(line) {
+            let column = area.x + cursor_x as u16 + line_number_offset as u16 + 1;
+            render_diagnostic_popup(f, diagnostics, inner_area, column, area.y + cursor_y as u16 + 1);
+        }
+    }
+
+    // Diagnostic dots show their messages on hover. Rows below a wrapped
+    // line can't be placed reliably, so they're left out.
+    let mut hover_targets = Vec::new();
+    for (row, index) in (start_line..end_line).enumerate().take(inner_area.height as usize) {
+        if let Some(diagnostics) = tab.diagnostics.get_diagnostics_for_line(index) {
+            let text = diagnostics.iter()
+                .map(|diagnostic| {
+                    let severity = match diagnostic.severity {
+                        crate::editor::DiagnosticSeverity::Error => "error",
+                        crate::editor::DiagnosticSeverity::Warning => "warning",
+                        crate::editor::DiagnosticSeverity::Information => "info",
+                        crate::editor::DiagnosticSeverity::Hint => "hint",
+                    };
+                    format!("{}: {}", severity, diagnostic.message)
+                })
+                .collect::<Vec<_>>()
+                .join("\n");
+            hover_targets.push(HoverTarget { x: inner_area.x, y: inner_area.y + row as u16, width: 1, text });
+        }
+        let shown_width = line_number_offset + tab.buffer.lines[index].chars().count();
+        if editor.wrap_text() && shown_width > inner_area.width as usize {
+            break;
+        }
+    }
+    let viewport_update = ViewportUpdate {
         width: viewport.width,
         height: viewport.height,
-    })
+        left_column,
+    };
+    editor.hover.add_targets(hover_targets);
+    set_scrollbar(editor, scrollbar);
+    
+    // Return viewport dimensions for safe update
+    Some(viewport_update)
+}
+
+/// Draw the overview ruler over the editor block's right border: the
+/// view's place in the file, with ticks where functions, types and
+/// sections start (`scrollbar = false` turns it off)
+///
+/// Returns the ruler, for clicks, and a tooltip naming each tick row's items.
+fn render_scrollbar<B: Backend>(f: &mut Frame<B>, editor: &Editor, area: Rect, top_line: usize, visible: usize) -> Option<(Scrollbar, Vec<HoverTarget>)> {
+    if !editor.config.scrollbar || area.width < 3 || area.height < 3 {
+        return None;
+    }
+    let tab = editor.current_tab();
+    let markdown = tab.buffer.syntax.as_ref().is_some_and(|syntax| syntax.name == "Markdown");
+    let scrollbar = Scrollbar {
+        x: area.x + area.width - 1,
+        y: area.y + 1,
+        height: area.height - 2,
+        line_count: tab.buffer.line_count(),
+        ticks: structure_lines(&tab.buffer.lines, markdown),
+    };
+
+    let mut targets = Vec::new();
+    for (index, row) in scrollbar.rows(top_line, visible).into_iter().enumerate() {
+        let y = scrollbar.y + index as u16;
+        let (symbol, style) = match (row.thumb, row.tick) {
+            (true, true) => ("╋", Style::default().fg(Color::Cyan).add_modifier(Modifier::BOLD)),
+            (false, true) => ("━", Style::default().fg(Color::Cyan)),
+            (true, false) => ("┃", Style::default().fg(Color::White)),
+            (false, false) => continue,
+        };
+        f.render_widget(Paragraph::new(symbol).style(style), Rect::new(scrollbar.x, y, 1, 1));
+        if row.tick {
+            let text = scrollbar.ticks_on(y)
+                .map(|line| format!("{}: {}", line + 1, tab.buffer.lines[line].trim()))
+                .collect::<Vec<_>>()
+                .join("\n");
+            targets.push(HoverTarget { x: scrollbar.x, y, width: 1, text });
+        }
+    }
+    Some((scrollbar, targets))
+}
+
+fn set_scrollbar(editor: &mut Editor, scrollbar: Option<(Scrollbar, Vec<HoverTarget>)>) {
+    if let Some((scrollbar, targets)) = scrollbar {
+        editor.hover.add_targets(targets);
+        editor.scrollbar = Some(scrollbar);
+    }
+}
+
+/// Gutter mark for a line that differs from the file on disk
+fn unsaved_marker() -> (&'static str, Style) {
+    ("▎", Style::default().fg(Color::Green))
+}
+
+/// Lines to mark as unsaved in the gutter, with `unsaved_markers` on
+fn unsaved_lines(editor: &Editor, tab: &Tab) -> std::collections::HashSet<usize> {
+    if editor.config.unsaved_markers {
+        tab.buffer.unsaved_lines()
+    } else {
+        Default::default()
+    }
 }
 
 fn render_editor_area<B: Backend>(f: &mut Frame<B>, editor: &mut Editor, area: Rect) -> Option<ViewportUpdate> {
@@ -666,6 +835,32 @@ fn render_editor_area_with_highlights<B: Backend>(f: &mut Frame<B>, editor: &mut
     render_editor_area_inner(f, editor, area, true, is_reload_mode)
 }
 
+/// A modified line's visible `content` in green, with the `changed` byte
+/// ranges of the whole line marked on a green background
+fn word_diff_spans(content: &str, left_column: usize, changed: &[std::ops::Range<usize>]) -> Vec<tui::text::Span<'static>> {
+    let line_style = Style::default().fg(Color::Green);
+    let changed_style = Style::default().fg(Color::Black).bg(Color::Green);
+    let mut spans = Vec::new();
+    let mut pos = 0;
+    for range in changed {
+        let start = range.start.saturating_sub(left_column).min(content.len());
+        let end = range.end.saturating_sub(left_column).min(content.len());
+        if end <= pos || !content.is_char_boundary(start) || !content.is_char_boundary(end) {
+            continue;
+        }
+        let start = start.max(pos);
+        if start > pos {
+            spans.push(tui::text::Span::styled(content[pos..start].to_string(), line_style));
+        }
+        spans.push(tui::text::Span::styled(content[start..end].to_string(), changed_style));
+        pos = end;
+    }
+    if pos < content.len() || spans.is_empty() {
+        spans.push(tui::text::Span::styled(content[pos..].to_string(), line_style));
+    }
+    spans
+}
+
 fn render_editor_area_with_diff_highlights<B: Backend>(f: &mut Frame<B>, editor: &mut Editor, area: Rect) -> Option<ViewportUpdate> {
     render_editor_area_inner(f, editor, area, true, true)
 }
@@ -689,8 +884,7 @@ fn render_editor_area_with_selection<B: Backend>(f: &mut Frame<B>, editor: &mut
     // Calculate needed space for line numbers based on total line count
     // Add 2 to account for 1 space and 1 character for diagnostic indicator
     let total_lines = tab.buffer.line_count();
-    let line_num_width = total_lines.to_string().len();
-    let line_number_width = line_num_width + 2; // width + diagnostic indicator + space
+    let line_number_width = line_number_column_width(editor, total_lines) + 1; // numbers + diagnostic indicator
     let content_width = inner_area.width.saturating_sub(line_number_width as u16);
     
     viewport.update_dimensions(content_width as usize, inner_area.height as usize);
@@ -698,23 +892,22 @@ fn render_editor_area_with_selection<B: Backend>(f: &mut Frame<B>, editor: &mut
     // Calculate visible range
     let (start_line, end_line) = viewport.get_visible_range(tab.buffer.line_count());
     
-    // We'll avoid changing left_column during rendering to prevent jiggling
-    let left_column = tab.viewport.left_column;
+    let left_column = shown_left_column(editor, tab, content_width as usize);
     
     // Format to get max line number width
     let total_lines = tab.buffer.line_count();
     let line_num_width = total_lines.to_string().len();
+    let unsaved = unsaved_lines(editor, tab);
     
     // Convert only visible buffer lines to Lines for rendering with line numbers
     let lines: Vec<Line> = tab.buffer.lines[start_line..end_line].iter()
         .enumerate()
         .map(|(idx, line)| {
-            let line_number = start_line + idx + 1; // 1-indexed line numbers
             let current_line_idx = start_line + idx;
             
             // Style the line number
             let number_style = Style::default().fg(Color::DarkGray);
-            let number_str = format!("{:>width$} ", line_number, width=line_num_width);
+            let number_str = line_number_label(editor, tab, current_line_idx, line_num_width);
             
             // Check if this line has diagnostics and add a gutter indicator
             let current_line = start_line + idx;
@@ -723,14 +916,18 @@ fn render_editor_area_with_selection<B: Backend>(f: &mut Frame<B>, editor: &mut
                 let diagnostics = tab.diagnostics.get_diagnostics_for_line(current_line).unwrap();
                 let has_error = diagnostics.iter().any(|d| d.severity == crate::editor::DiagnosticSeverity::Error);
                 let has_warning = diagnostics.iter().any(|d| d.severity == crate::editor::DiagnosticSeverity::Warning);
+                // A diamond marks lines with a fix `:fix` can apply
+                let indicator = if diagnostics.iter().any(|d| d.fix.is_some()) { "◆" } else { "●" };
                 
                 if has_error {
-                    ("●", Style::default().fg(Color::Red).add_modifier(Modifier::BOLD))
+                    (indicator, Style::default().fg(Color::Red).add_modifier(Modifier::BOLD))
                 } else if has_warning {
-                    ("●", Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD))
+                    (indicator, Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD))
                 } else {
-                    ("●", Style::default().fg(Color::Blue))
+                    (indicator, Style::default().fg(Color::Blue))
                 }
+            } else if unsaved.contains(&current_line) {
+                unsaved_marker()
             } else {
                 (" ", Style::default())
             };
@@ -957,6 +1154,15 @@ fn render_editor_area_with_selection<B: Backend>(f: &mut Frame<B>, editor: &mut
                 // No diagnostics but might be syntax highlighting
                 add_syntax_or_selection_spans(&mut spans, editor, tab, current_line, &content, left_column);
             }
+            let shown_from = shown_from(&spans, line);
+            add_guides(&mut spans, editor, tab, current_line, shown_from, content_width as usize);
+            show_whitespace(&mut spans, editor, tab, current_line, line, shown_from);
+            if editor.config.diagnostic_virtual_text {
+                if let Some(diagnostics) = tab.diagnostics.get_diagnostics_for_line(current_line) {
+                    let used: usize = spans[2..].iter().map(|span| span.width()).sum();
+                    spans.extend(diagnostic_virtual_text(diagnostics, (content_width as usize).saturating_sub(used)));
+                }
+            }
             
             Line::from(spans)
         })
@@ -964,28 +1170,64 @@ fn render_editor_area_with_selection<B: Backend>(f: &mut Frame<B>, editor: &mut
     
     let paragraph = Paragraph::new(lines)
         .block(editor_block)
-        .style(Style::default())
-        .wrap(Wrap { trim: false });
+        .style(Style::default());
+    let paragraph = if editor.wrap_text() {
+        paragraph.wrap(Wrap { trim: false })
+    } else {
+        paragraph
+    };
     
     f.render_widget(paragraph, area);
+    let scrollbar = render_scrollbar(f, editor, area, start_line, viewport.height);
     
     // Set cursor position relative to viewport
     let cursor_x = tab.cursor.x.saturating_sub(left_column);
     let cursor_y = tab.cursor.y.saturating_sub(viewport.top_line);
     
     // Adjust cursor position for line numbers
-    let line_number_offset = line_num_width + 2; // width + space + indicator
+    let line_number_offset = line_number_column_width(editor, total_lines) + 1; // numbers + indicator
     
     f.set_cursor(
         area.x + cursor_x as u16 + line_number_offset as u16 + 1, // +1 for the border
         area.y + cursor_y as u16 + 1, // +1 for the border
     );
-    
-    // Return viewport dimensions for safe update
-    Some(ViewportUpdate {
+    let viewport_update = ViewportUpdate {
         width: viewport.width,
         height: viewport.height,
-    })
+        left_column,
+    };
+    set_scrollbar(editor, scrollbar);
+    
+    // Return viewport dimensions for safe update
+    Some(viewport_update)
+}
+
+/// Draw the AI suggestion dimmed over the rest of the cursor line, from
+/// `column` and `row` of `area`; a suggestion of several lines shows its
+/// first and how many more there are
+fn render_ghost_text<B: Backend>(f: &mut Frame<B>, editor: &Editor, area: Rect, column: usize, row: usize) {
+    let Some(text) = editor.ghost_text() else { return };
+    // Stay inside the border
+    let width = (area.width as usize).saturating_sub(column + 1);
+    if width == 0 || row + 1 >= area.height as usize {
+        return;
+    }
+    let mut lines = text.lines();
+    let mut shown = lines.next().unwrap_or("").to_string();
+    let more = lines.count();
+    if more > 0 {
+        shown.push_str(&format!("  (+{} lines, Tab)", more));
+    }
+    // The rest of the line moves over to make room, as if the text were there
+    let tab = editor.current_tab();
+    let rest = tab.buffer.get_line(tab.cursor.y).get(tab.cursor.x..).unwrap_or("").to_string();
+    let ghost = Paragraph::new(Line::from(vec![
+        tui::text::Span::styled(shown, Style::default().fg(Color::DarkGray).add_modifier(Modifier::ITALIC)),
+        tui::text::Span::raw(rest),
+    ]));
+    let rect = Rect::new(area.x + column as u16, area.y + row as u16, width as u16, 1);
+    f.render_widget(tui::widgets::Clear, rect);
+    f.render_widget(ghost, rect);
 }
 
 /// Helper function to add either syntax highlighted spans or selection spans
@@ -997,27 +1239,8 @@ fn add_syntax_or_selection_spans(spans: &mut Vec<tui::text::Span<'static>>,
                               left_column: usize) {
     // First check if we have syntax highlighting
     if let Some(syntax_ref) = &tab.buffer.syntax {
-        // Get line for highlighting
-        let line_for_highlight = if current_line < tab.buffer.lines.len() {
-            &tab.buffer.lines[current_line]
-        } else {
-            ""
-        };
-        
-        // Use the cache to avoid recomputing syntax highlights
-        let tab_idx = editor.current_tab;
-        let cache_key = (tab_idx, current_line);
-        
-        // Get highlighted line from cache if available, or compute it
-        let highlighted = if let Some(cached) = editor.highlighted_lines_cache.get(&cache_key) {
-            cached.clone()
-        } else {
-            // Highlight the line (don't try to update cache since we have immutable reference)
-            editor.syntax_highlighter.highlight_text(
-                &format!("{}\n", line_for_highlight), 
-                syntax_ref.clone()
-            )
-        };
+        let line_for_highlight = tab.buffer.get_line(current_line);
+        let highlighted = line_highlight(editor, tab, current_line, syntax_ref);
         
         if !highlighted.is_empty() {
             // Process syntax highlighting with selection overlay
@@ -1042,70 +1265,69 @@ fn add_syntax_or_selection_spans(spans: &mut Vec<tui::text::Span<'static>>,
                     0
                 };
                 
-                if rel_start >= left_column {
-                    let rel_text = &text[..];
-                    let start_col = rel_start;
-                    
-                    // Check if any part of this span is selected
-                    let mut current_selected = false;
-                    let mut segment_start = 0;
-                    let mut segments = Vec::new();
+                let rel_text = &text[..];
+                let start_col = rel_start;
+                
+                // Check if any part of this span is selected
+                let mut current_selected = false;
+                let mut segment_start = 0;
+                let mut segments = Vec::new();
+                
+                // Iterate through characters and check selection status
+                for (i, _) in rel_text.char_indices() {
+                    let col = start_col + i;
+                    let is_selected = tab.buffer.is_position_selected(
+                        current_line, 
+                        col, 
+                        &tab.cursor, 
+                        tab.buffer.selection_start.is_some() && editor.mode == crate::editor::Mode::VisualLine
+                    );
                     
-                    // Iterate through characters and check selection status
-                    for (i, _) in rel_text.char_indices() {
-                        let col = start_col + i;
-                        let is_selected = tab.buffer.is_position_selected(
-                            current_line, 
-                            col, 
-                            &tab.cursor, 
-                            tab.buffer.selection_start.is_some() && editor.mode == crate::editor::Mode::VisualLine
-                        );
-                        
-                        if is_selected != current_selected {
-                            // Transition between selected/unselected
-                            if segment_start < i {
-                                let segment = &rel_text[segment_start..i];
-                                if current_selected {
-                                    // Selected - use base style but with selection background
-                                    segments.push(tui::text::Span::styled(
-                                        segment.to_string(),
-                                        tui_style.patch(Style::default().bg(Color::LightBlue).fg(Color::Black).add_modifier(Modifier::BOLD))
-                                    ));
-                                } else {
-                                    // Not selected - use base syntax style
-                                    segments.push(tui::text::Span::styled(
-                                        segment.to_string(),
-                                        tui_style
-                                    ));
-                                }
+                    if is_selected != current_selected {
+                        // Transition between selected/unselected
+                        if segment_start < i {
+                            let segment = &rel_text[segment_start..i];
+                            if current_selected {
+                                // Selected - use base style but with selection background
+                                segments.push(tui::text::Span::styled(
+                                    segment.to_string(),
+                                    tui_style.patch(Style::default().bg(Color::LightBlue).fg(Color::Black).add_modifier(Modifier::BOLD))
+                                ));
+                            } else {
+                                // Not selected - use base syntax style
+                                segments.push(tui::text::Span::styled(
+                                    segment.to_string(),
+                                    tui_style
+                                ));
                             }
-                            segment_start = i;
-                            current_selected = is_selected;
                         }
+                        segment_start = i;
+                        current_selected = is_selected;
                     }
-                    
-                    // Add the final segment
-                    if segment_start < rel_text.len() {
-                        let segment = &rel_text[segment_start..];
-                        if current_selected {
-                            segments.push(tui::text::Span::styled(
-                                segment.to_string(),
-                                tui_style.patch(Style::default().bg(Color::LightBlue).fg(Color::Black).add_modifier(Modifier::BOLD))
-                            ));
-                        } else {
-                            segments.push(tui::text::Span::styled(
-                                segment.to_string(),
-                                tui_style
-                            ));
-                        }
+                }
+                
+                // Add the final segment
+                if segment_start < rel_text.len() {
+                    let segment = &rel_text[segment_start..];
+                    if current_selected {
+                        segments.push(tui::text::Span::styled(
+                            segment.to_string(),
+                            tui_style.patch(Style::default().bg(Color::LightBlue).fg(Color::Black).add_modifier(Modifier::BOLD))
+                        ));
+                    } else {
+                        segments.push(tui::text::Span::styled(
+                            segment.to_string(),
+                            tui_style
+                        ));
                     }
-                    
-                    line_spans.extend(segments);
                 }
+                
+                line_spans.extend(segments);
             }
             
             if !line_spans.is_empty() {
-                spans.extend(line_spans);
+                // Highlighting covers the whole line, so scroll it here
+                spans.extend(skip_columns(line_spans, left_column));
             } else {
                 add_selection_only_spans(spans, tab, current_line, content, left_column, editor.mode == crate::editor::Mode::VisualLine);
             }
@@ -1280,6 +1502,18 @@ fn convert_syntect_style(style: &SyntectStyle) -> Style {
     tui_style
 }
 
+/// The highlight of a line, or the line highlighted on its own while the
+/// background highlighter catches up with an edit
+fn line_highlight(editor: &Editor, tab: &Tab, line: usize, syntax: &Arc<SyntaxReference>) -> Vec<HighlightedLine> {
+    match tab.highlights.line(line) {
+        Some(highlighted) => vec![highlighted.clone()],
+        None => editor.syntax_highlighter.highlight_text(
+            &format!("{}\n", tab.buffer.get_line(line)),
+            syntax.clone(),
+        ),
+    }
+}
+
 /// Creates line spans from highlighted text
 /// 
 /// This helper function extracts the common pattern of converting highlighted text
@@ -1305,100 +1539,568 @@ fn create_highlighted_spans(highlighted: &[HighlightedLine]) -> Vec<Span<'static
         .collect()
 }
 
-// Helper function to create a centered rect using percentage of the available space
-fn centered_rect(percent_x: u16, percent_y: u16, r: Rect) -> Rect {
-    let popup_layout = Layout::default()
-        .direction(Direction::Vertical)
-        .constraints([
-            Constraint::Percentage((100 - percent_y) / 2),
-            Constraint::Percentage(percent_y),
-            Constraint::Percentage((100 - percent_y) / 2),
-        ].as_ref())
-        .split(r);
+/// Keys that continue the sequence being typed, shown in the bottom right
+/// corner while the keymap waits for the next key
+fn render_which_key<B: Backend>(f: &mut Frame<B>, keymap: &Keymap, area: Rect) {
+    let options = keymap.options();
+    if options.is_empty() {
+        return;
+    }
 
-    Layout::default()
-        .direction(Direction::Horizontal)
-        .constraints([
-            Constraint::Percentage((100 - percent_x) / 2),
-            Constraint::Percentage(percent_x),
-            Constraint::Percentage((100 - percent_x) / 2),
-        ].as_ref())
-        .split(popup_layout[1])[1]
+    let key_width = options.iter().map(|(key, _)| key.len()).max().unwrap_or(0);
+    let description_width = options.iter().map(|(_, description)| description.len()).max().unwrap_or(0);
+    let lines: Vec<Line> = options.into_iter()
+        .map(|(key, description)| {
+            let description_style = if description.starts_with('+') {
+                Style::default().fg(Color::Cyan)
+            } else {
+                Style::default()
+            };
+            Line::from(vec![
+                Span::styled(format!("{:>width$}", key, width = key_width), Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD)),
+                Span::raw(" → "),
+                Span::styled(description, description_style),
+            ])
+        })
+        .collect();
+
+    let width = ((key_width + description_width + 5) as u16).min(area.width);
+    let height = (lines.len() as u16 + 2).min(area.height);
+    let popup = Rect {
+        x: area.x + area.width - width,
+        y: area.y + area.height - height,
+        width,
+        height,
+    };
+    let block = Block::default()
+        .title(format!(" {} ", keymap.pending_keys()))
+        .borders(Borders::ALL);
+    f.render_widget(tui::widgets::Clear, popup);
+    f.render_widget(Paragraph::new(lines).block(block), popup);
 }
 
-/// Render the diagnostics panel interface
-fn render_diagnostics_panel<B: Backend>(f: &mut Frame<B>, editor: &Editor, area: Rect) {
-    // Create a block for the diagnostics panel
-    let diagnostics_block = Block::default()
-        .title(" Diagnostics ")
+/// Render the read-only viewer for a location peeked at from a panel
+fn render_peek<B: Backend>(f: &mut Frame<B>, editor: &Editor, area: Rect) {
+    let Some(peek) = &editor.peek else { return };
+    let popup = centered_rect(90, 80, area);
+    let block = Block::default()
+        .title(format!(" {}:{} (read-only) ", relative_path(&peek.path), peek.line + 1))
         .title_style(Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD))
         .borders(Borders::ALL)
-        .border_style(Style::default().fg(Color::Red));
+        .border_style(Style::default().fg(Color::Cyan));
+    let inner = block.inner(popup);
+    f.render_widget(tui::widgets::Clear, popup);
+    f.render_widget(block, popup);
+    if inner.height < 2 {
+        return;
+    }
 
-    let inner_area = diagnostics_block.inner(area);
-    f.render_widget(diagnostics_block, area);
+    // The last row explains the keys
+    let height = inner.height as usize - 1;
+    let top = peek.top_line(height);
+    let number_width = (peek.first_line + peek.lines.len()).to_string().len();
+    let lines: Vec<Line> = peek.lines.iter()
+        .enumerate()
+        .skip(top - peek.first_line)
+        .take(height)
+        .map(|(index, line)| {
+            let line_number = peek.first_line + index;
+            let mut spans = vec![Span::styled(
+                format!("{:>width$} ", line_number + 1, width = number_width),
+                Style::default().fg(Color::DarkGray),
+            )];
+            spans.extend(create_highlighted_spans(std::slice::from_ref(line)));
+            if line_number == peek.line {
+                spans = spans.into_iter()
+                    .map(|span| Span::styled(span.content, span.style.bg(Color::DarkGray)))
+                    .collect();
+            }
+            Line::from(spans)
+        })
+        .collect();
+    let text_area = Rect { height: height as u16, ..inner };
+    f.render_widget(Paragraph::new(lines), text_area);
 
-    // Create layout for filter controls and diagnostics list
-    let main_layout = Layout::default()
-        .direction(Direction::Vertical)
-        .constraints([
-            Constraint::Length(3), // Filter/controls
-            Constraint::Min(1),    // Diagnostics list
-        ].as_ref())
-        .split(inner_area);
+    let help = Paragraph::new("Enter: open, Tab/Esc: close, PageUp/PageDown: scroll")
+        .style(Style::default().fg(Color::DarkGray))
+        .alignment(Alignment::Center);
+    f.render_widget(help, Rect { y: inner.y + height as u16, height: 1, ..inner });
+}
 
-    // Render filter controls
-    let filter_block = Block::default()
-        .title(" Filter ")
-        .title_style(Style::default().fg(Color::LightBlue))
-        .borders(Borders::ALL);
-    
-    // Determine which filter is active and style it appropriately
-    let (error_style, warning_style, info_style, all_style) = match editor.diagnostics_filter {
-        crate::editor::DiagnosticFilter::Errors => (
-            Style::default().fg(Color::Red).add_modifier(Modifier::BOLD | Modifier::REVERSED),
-            Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD),
-            Style::default().fg(Color::Blue).add_modifier(Modifier::ITALIC),
-            Style::default()
-        ),
-        crate::editor::DiagnosticFilter::Warnings => (
-            Style::default().fg(Color::Red).add_modifier(Modifier::BOLD),
-            Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD | Modifier::REVERSED),
-            Style::default().fg(Color::Blue).add_modifier(Modifier::ITALIC),
-            Style::default()
-        ),
-        crate::editor::DiagnosticFilter::Info => (
-            Style::default().fg(Color::Red).add_modifier(Modifier::BOLD),
-            Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD),
-            Style::default().fg(Color::Blue).add_modifier(Modifier::ITALIC | Modifier::REVERSED),
-            Style::default()
-        ),
-        crate::editor::DiagnosticFilter::All => (
-            Style::default().fg(Color::Red).add_modifier(Modifier::BOLD),
-            Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD),
-            Style::default().fg(Color::Blue).add_modifier(Modifier::ITALIC),
-            Style::default().add_modifier(Modifier::REVERSED)
-        ),
+/// Show the rows of a paged file that fit in `area`, reading them from disk
+fn render_paged<B: Backend>(f: &mut Frame<B>, editor: &Editor, area: Rect) -> Option<ViewportUpdate> {
+    let tab = editor.current_tab();
+    let paged = tab.buffer.paged.as_ref()?;
+    let height = area.height as usize;
+    let number_width = paged.row_count().to_string().len();
+    let lines: Vec<Line> = match paged.rows(paged.top, height) {
+        Ok(rows) => rows.into_iter()
+            .enumerate()
+            .map(|(index, row)| match paged.kind {
+                // Hex dump rows start with their own offset
+                PagedKind::Binary => Line::from(row),
+                PagedKind::Large if editor.config.line_numbers => Line::from(vec![
+                    Span::styled(
+                        format!("{:>width$} ", paged.top + index + 1, width = number_width),
+                        Style::default().fg(Color::DarkGray),
+                    ),
+                    Span::raw(row.replace('\t', &" ".repeat(editor.tab_size()))),
+                ]),
+                PagedKind::Large => Line::from(row.replace('\t', &" ".repeat(editor.tab_size()))),
+            })
+            .collect(),
+        Err(e) => vec![Line::from(Span::styled(format!("Error: {}", e), Style::default().fg(Color::Red)))],
     };
-    
-    // Show filter controls and keyboard shortcuts
-    let filter_text = vec![
-        Line::from(vec![
-            Span::styled("Errors: ", error_style),
-            Span::styled("E", error_style),
-            Span::styled(" | ", Style::default()),
-            Span::styled("Warnings: ", warning_style),
-            Span::styled("W", warning_style),
-            Span::styled(" | ", Style::default()),
-            Span::styled("Info: ", info_style),
-            Span::styled("I", info_style),
-            Span::styled(" | ", Style::default()),
-            Span::styled("All: ", all_style),
+    f.render_widget(Paragraph::new(lines), area);
+
+    // Keep the viewport size current for paging by a screen
+    Some(ViewportUpdate { width: area.width as usize, height, left_column: 0 })
+}
+
+/// Width of the line number column, including the space after the
+/// numbers; nothing when line numbers are turned off
+fn line_number_column_width(editor: &Editor, line_count: usize) -> usize {
+    if editor.config.line_numbers {
+        line_count.to_string().len() + 1
+    } else {
+        0
+    }
+}
+
+/// What the line number column shows for a line, padded to `width`: its
+/// number, or its distance from the cursor line in relative modes
+fn line_number_label(editor: &Editor, tab: &Tab, line: usize, width: usize) -> String {
+    if !editor.config.line_numbers {
+        return String::new();
+    }
+    let distance = line.abs_diff(tab.cursor.y);
+    let number = match editor.config.line_number_mode.as_str() {
+        "relative" => distance,
+        "hybrid" if distance > 0 => distance,
+        _ => line + 1,
+    };
+    format!("{:>width$} ", number, width = width)
+}
+
+/// Soft mode's bar of keys for the current mode: the key, what it does and
+/// the vim way to do the same
+fn render_hint_bar<B: Backend>(f: &mut Frame<B>, editor: &Editor, area: Rect) {
+    let hints: &[(&str, &str, &str)] = match editor.mode {
+        Mode::Normal => &[
+            ("i", "type", ""),
+            ("v", "select", ""),
+            ("←↓↑→", "move", "h j k l"),
+            ("Ctrl+S", "save", ":w"),
+            ("Ctrl+Q", "quit", ":q"),
+            ("Ctrl+C", "copy line", "yy"),
+            ("Ctrl+V", "paste", "P"),
+            ("u", "undo", ""),
+            (":help", "all keys", ""),
+        ],
+        Mode::Insert => &[
+            ("Esc", "stop typing", ""),
+            ("←↓↑→", "move", "h j k l"),
+            ("Ctrl+S", "save", ":w"),
+            ("Ctrl+Q", "quit", ":q"),
+            ("Ctrl+C", "copy line", "yy"),
+            ("Ctrl+V", "paste", "P"),
+        ],
+        Mode::Visual | Mode::VisualLine => &[
+            ("←↓↑→", "extend", "h j k l"),
+            ("Ctrl+C", "copy", "y"),
+            ("d", "cut", ""),
+            ("Esc", "cancel", ""),
+        ],
+        _ => &[("Esc", "back to the editor", "")],
+    };
+
+    let mut spans = Vec::new();
+    for (key, action, vim) in hints {
+        spans.push(Span::styled(*key, Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD)));
+        spans.push(Span::raw(format!(" {}", action)));
+        if !vim.is_empty() {
+            spans.push(Span::styled(format!(" ({})", vim), Style::default().fg(Color::DarkGray)));
+        }
+        spans.push(Span::raw("  "));
+    }
+    f.render_widget(Paragraph::new(Line::from(spans)), area);
+}
+
+/// The most severe diagnostic of a line as dim text after its end, cut to
+/// the `width` left on the row (`diagnostic_virtual_text = false` hides it)
+fn diagnostic_virtual_text(diagnostics: &[crate::editor::Diagnostic], width: usize) -> Option<Span<'static>> {
+    use crate::editor::DiagnosticSeverity;
+
+    // Too little room to say anything useful
+    if width < 12 {
+        return None;
+    }
+    let diagnostic = diagnostics.iter()
+        .find(|d| d.severity == DiagnosticSeverity::Error)
+        .or_else(|| diagnostics.iter().find(|d| d.severity == DiagnosticSeverity::Warning))
+        .or_else(|| diagnostics.first())?;
+    let color = match diagnostic.severity {
+        DiagnosticSeverity::Error => Color::Red,
+        DiagnosticSeverity::Warning => Color::Yellow,
+        DiagnosticSeverity::Information => Color::Blue,
+        DiagnosticSeverity::Hint => Color::Green,
+    };
+    let message = diagnostic.message.lines().next().unwrap_or_default();
+    let mut text = match diagnostics.len() {
+        1 => format!("    ■ {}", message),
+        count => format!("    ■ {} (+{} more)", message, count - 1),
+    };
+    if text.chars().count() > width {
+        text = text.chars().take(width - 1).chain(std::iter::once('…')).collect();
+    }
+    Some(Span::styled(text, Style::default().fg(color).add_modifier(Modifier::DIM | Modifier::ITALIC)))
+}
+
+/// The full diagnostics of the cursor line, with their help and notes, in
+/// a popup below the cursor (`K`), wrapped to fit the editor area
+fn render_diagnostic_popup<B: Backend>(f: &mut Frame<B>, diagnostics: &[crate::editor::Diagnostic], area: Rect, column: u16, row: u16) {
+    let text = diagnostics.iter()
+        .map(|diagnostic| diagnostic.format_full_message())
+        .collect::<String>();
+    let text = text.trim_end();
+    let max_width = (area.width.min(80) as usize).saturating_sub(2).max(1);
+    let text_width = text.lines().map(|line| line.chars().count()).max().unwrap_or(0).min(max_width);
+    let rows: usize = text.lines().map(|line| line.chars().count().max(1).div_ceil(max_width)).sum();
+    let width = (text_width as u16 + 2).min(area.width);
+    let height = (rows as u16 + 2).min(area.height);
+
+    // Below the cursor if there's room, otherwise above it
+    let y = if row + 1 + height <= area.bottom() {
+        row + 1
+    } else {
+        row.saturating_sub(height).max(area.y)
+    };
+    let popup = Rect {
+        x: column.min(area.right().saturating_sub(width)).max(area.x),
+        y,
+        width,
+        height,
+    };
+    let block = Block::default()
+        .title(" Diagnostics ")
+        .borders(Borders::ALL)
+        .border_style(Style::default().fg(Color::DarkGray));
+    f.render_widget(tui::widgets::Clear, popup);
+    f.render_widget(Paragraph::new(text.to_string()).wrap(Wrap { trim: false }).block(block), popup);
+}
+
+/// Render the tooltip of whatever the mouse rests on, next to the mouse
+fn render_tooltip<B: Backend>(f: &mut Frame<B>, editor: &Editor, area: Rect) {
+    let Some((target, column, row)) = editor.hover.tooltip(HOVER_DELAY) else { return };
+    let max_width = (area.width.min(80) as usize).saturating_sub(2).max(1);
+    let text_width = target.text.lines().map(|line| line.chars().count()).max().unwrap_or(0).min(max_width);
+    let rows: usize = target.text.lines().map(|line| line.chars().count().max(1).div_ceil(max_width)).sum();
+    let width = (text_width as u16 + 2).min(area.width);
+    let height = (rows as u16 + 2).min(area.height);
+
+    // Below the mouse if there's room, otherwise above it
+    let y = if row + 1 + height <= area.bottom() {
+        row + 1
+    } else {
+        row.saturating_sub(height).max(area.y)
+    };
+    let popup = Rect {
+        x: column.min(area.right().saturating_sub(width)),
+        y,
+        width,
+        height,
+    };
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .border_style(Style::default().fg(Color::DarkGray));
+    f.render_widget(tui::widgets::Clear, popup);
+    f.render_widget(Paragraph::new(target.text.clone()).wrap(Wrap { trim: false }).block(block), popup);
+}
+
+/// First column shown of long lines: none are scrolled while lines wrap,
+/// and otherwise the cursor is kept on screen at the width being drawn,
+/// which shrinks as the line numbers get wider. Only cursor movement
+/// applies `sidescrolloff`, so redrawing never shifts the view.
+fn shown_left_column(editor: &Editor, tab: &Tab, width: usize) -> usize {
+    if editor.wrap_text() {
+        return 0;
+    }
+    let mut viewport = tab.viewport.clone();
+    viewport.width = width;
+    viewport.ensure_column_visible(tab.cursor.x, 0);
+    viewport.left_column
+}
+
+/// Drop the first `columns` bytes of a line's content spans
+fn skip_columns(spans: Vec<Span<'static>>, columns: usize) -> Vec<Span<'static>> {
+    let mut skip = columns;
+    spans.into_iter()
+        .filter_map(|span| {
+            let len = span.content.len();
+            if skip >= len {
+                skip -= len;
+                return None;
+            }
+            let mut start = skip;
+            while !span.content.is_char_boundary(start) {
+                start += 1;
+            }
+            skip = 0;
+            Some(Span::styled(span.content[start..].to_string(), span.style))
+        })
+        .collect()
+}
+
+/// Byte column where the shown part of a line starts, given the spans
+/// rendered for it. The first two spans are the gutter.
+fn shown_from(spans: &[Span], line: &str) -> usize {
+    let shown_len: usize = spans.iter().skip(2).map(|span| span.content.len()).sum();
+    line.len().saturating_sub(shown_len)
+}
+
+/// Give the cursor line, the cursor column and the color column rulers
+/// their backgrounds, padding the line with spaces to reach past its end.
+/// The first two spans are the gutter, and the line is shown from byte
+/// `shown_from`.
+fn add_guides(spans: &mut Vec<Span<'static>>, editor: &Editor, tab: &Tab, line_index: usize, shown_from: usize, width: usize) {
+    let config = &editor.config;
+    let is_cursor_line = line_index == tab.cursor.y;
+    let highlight_line = config.cursorline && is_cursor_line;
+    if !highlight_line && !config.cursorcolumn && config.color_columns.is_empty() {
+        return;
+    }
+
+    let mut content = spans.split_off(2.min(spans.len()));
+    let shown_len = tab.buffer.line_length(line_index).saturating_sub(shown_from);
+    let rulers: Vec<usize> = config.color_columns.iter()
+        .filter_map(|column| column.checked_sub(1 + shown_from))
+        .filter(|column| *column < width)
+        .collect();
+    let cursor_column = tab.cursor.x.checked_sub(shown_from)
+        .filter(|column| config.cursorcolumn && !is_cursor_line && *column < width);
+
+    let padded_len = if highlight_line {
+        width
+    } else {
+        rulers.iter().chain(&cursor_column).max().map_or(0, |column| column + 1)
+    };
+    if padded_len > shown_len {
+        content.push(Span::raw(" ".repeat(padded_len - shown_len)));
+    }
+
+    let cursorline_style = Style::default().bg(theme_color(&config.theme.cursorline));
+    if highlight_line {
+        for span in &mut content {
+            span.style = span.style.patch(cursorline_style);
+        }
+    }
+    let mut content = style_columns(content, &rulers, Style::default().bg(theme_color(&config.theme.color_column)));
+    if let Some(column) = cursor_column {
+        content = style_columns(content, &[column], cursorline_style);
+    }
+    spans.extend(content);
+}
+
+/// Draw tabs, trailing spaces and non-breaking spaces with the `list_chars`
+/// glyphs in list mode, and highlight trailing whitespace. The first two
+/// spans are the gutter, and the line is shown from byte `shown_from`.
+fn show_whitespace(spans: &mut Vec<Span<'static>>, editor: &Editor, tab: &Tab, line_index: usize, line: &str, shown_from: usize) {
+    let config = &editor.config;
+    let trailing_from = line.trim_end_matches([' ', '\t']).len();
+    // Spaces being typed at the end of the line aren't flagged yet
+    let typing_here = editor.mode == Mode::Insert && line_index == tab.cursor.y;
+    let highlight_trailing = config.highlight_trailing_whitespace && trailing_from < line.len() && !typing_here;
+    let list = config.list && (trailing_from < line.len() || line.contains(['\t', '\u{a0}']));
+    if !highlight_trailing && !list {
+        return;
+    }
+
+    // Padding added after the line isn't whitespace in it
+    let shown_end = line.len().saturating_sub(shown_from);
+    let content = spans.split_off(2.min(spans.len()));
+    let mut offset = 0;
+    for span in content {
+        let mut run = String::new();
+        let mut run_style = span.style;
+        for (index, c) in span.content.char_indices() {
+            let position = offset + index;
+            let trailing = position < shown_end && position + shown_from >= trailing_from;
+            let glyph = match c {
+                '\t' if config.list && position < shown_end => Some(config.list_chars.tab.as_str()),
+                '\u{a0}' if config.list && position < shown_end => Some(config.list_chars.nbsp.as_str()),
+                ' ' if config.list && trailing => Some(config.list_chars.trail.as_str()),
+                _ => None,
+            };
+            let mut style = span.style;
+            if glyph.is_some() {
+                style = style.patch(Style::default().fg(Color::DarkGray));
+            }
+            if trailing && highlight_trailing {
+                style = style.patch(Style::default().bg(Color::Red));
+            }
+            if style != run_style && !run.is_empty() {
+                spans.push(Span::styled(std::mem::take(&mut run), run_style));
+            }
+            run_style = style;
+            match glyph {
+                Some(glyph) => run.push_str(glyph),
+                None => run.push(c),
+            }
+        }
+        offset += span.content.len();
+        if !run.is_empty() {
+            spans.push(Span::styled(run, run_style));
+        }
+    }
+}
+
+/// A color from the config's theme, written as "#rrggbb"
+fn theme_color(hex: &str) -> Color {
+    let hex = hex.trim_start_matches('#');
+    let channel = |range| hex.get(range).and_then(|digits| u8::from_str_radix(digits, 16).ok());
+    match (hex.len(), channel(0..2), channel(2..4), channel(4..6)) {
+        (6, Some(r), Some(g), Some(b)) => Color::Rgb(r, g, b),
+        _ => Color::DarkGray,
+    }
+}
+
+fn matching_bracket_style() -> Style {
+    Style::default().bg(Color::DarkGray).add_modifier(Modifier::BOLD)
+}
+
+/// Restyle the characters starting at the given byte columns of a line's
+/// spans, splitting spans where needed
+fn style_columns<'a>(spans: Vec<Span<'a>>, columns: &[usize], style: Style) -> Vec<Span<'a>> {
+    let mut result = Vec::new();
+    let mut start = 0;
+    for span in spans {
+        let text = span.content.as_ref();
+        let end = start + text.len();
+        let mut inside: Vec<usize> = columns.iter()
+            .filter(|column| (start..end).contains(*column))
+            .map(|column| column - start)
+            .collect();
+        if inside.is_empty() {
+            start = end;
+            result.push(span);
+            continue;
+        }
+
+        inside.sort_unstable();
+        let mut pos = 0;
+        for column in inside {
+            let Some(ch) = text[column..].chars().next() else { continue };
+            if column > pos {
+                result.push(Span::styled(text[pos..column].to_string(), span.style));
+            }
+            let char_end = column + ch.len_utf8();
+            result.push(Span::styled(text[column..char_end].to_string(), span.style.patch(style)));
+            pos = char_end;
+        }
+        if pos < text.len() {
+            result.push(Span::styled(text[pos..].to_string(), span.style));
+        }
+        start = end;
+    }
+    result
+}
+
+// Helper function to create a centered rect using percentage of the available space
+fn centered_rect(percent_x: u16, percent_y: u16, r: Rect) -> Rect {
+    let popup_layout = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Percentage((100 - percent_y) / 2),
+            Constraint::Percentage(percent_y),
+            Constraint::Percentage((100 - percent_y) / 2),
+        ].as_ref())
+        .split(r);
+
+    Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([
+            Constraint::Percentage((100 - percent_x) / 2),
+            Constraint::Percentage(percent_x),
+            Constraint::Percentage((100 - percent_x) / 2),
+        ].as_ref())
+        .split(popup_layout[1])[1]
+}
+
+/// Render the diagnostics panel interface
+fn render_diagnostics_panel<B: Backend>(f: &mut Frame<B>, editor: &Editor, area: Rect) {
+    // Create a block for the diagnostics panel
+    let diagnostics_block = Block::default()
+        .title(" Diagnostics ")
+        .title_style(Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD))
+        .borders(Borders::ALL)
+        .border_style(Style::default().fg(Color::Red));
+
+    let inner_area = diagnostics_block.inner(area);
+    f.render_widget(diagnostics_block, area);
+
+    // Create layout for filter controls and diagnostics list
+    let main_layout = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Length(3), // Filter/controls
+            Constraint::Min(1),    // Diagnostics list
+        ].as_ref())
+        .split(inner_area);
+
+    // Render filter controls
+    let filter_block = Block::default()
+        .title(" Filter ")
+        .title_style(Style::default().fg(Color::LightBlue))
+        .borders(Borders::ALL);
+    
+    // Determine which filter is active and style it appropriately
+    let (error_style, warning_style, info_style, all_style) = match editor.diagnostics_filter {
+        crate::editor::DiagnosticFilter::Errors => (
+            Style::default().fg(Color::Red).add_modifier(Modifier::BOLD | Modifier::REVERSED),
+            Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD),
+            Style::default().fg(Color::Blue).add_modifier(Modifier::ITALIC),
+            Style::default()
+        ),
+        crate::editor::DiagnosticFilter::Warnings => (
+            Style::default().fg(Color::Red).add_modifier(Modifier::BOLD),
+            Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD | Modifier::REVERSED),
+            Style::default().fg(Color::Blue).add_modifier(Modifier::ITALIC),
+            Style::default()
+        ),
+        crate::editor::DiagnosticFilter::Info => (
+            Style::default().fg(Color::Red).add_modifier(Modifier::BOLD),
+            Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD),
+            Style::default().fg(Color::Blue).add_modifier(Modifier::ITALIC | Modifier::REVERSED),
+            Style::default()
+        ),
+        crate::editor::DiagnosticFilter::All => (
+            Style::default().fg(Color::Red).add_modifier(Modifier::BOLD),
+            Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD),
+            Style::default().fg(Color::Blue).add_modifier(Modifier::ITALIC),
+            Style::default().add_modifier(Modifier::REVERSED)
+        ),
+    };
+    
+    // Show filter controls and keyboard shortcuts
+    let filter_text = vec![
+        Line::from(vec![
+            Span::styled("Errors: ", error_style),
+            Span::styled("E", error_style),
+            Span::styled(" | ", Style::default()),
+            Span::styled("Warnings: ", warning_style),
+            Span::styled("W", warning_style),
+            Span::styled(" | ", Style::default()),
+            Span::styled("Info: ", info_style),
+            Span::styled("I", info_style),
+            Span::styled(" | ", Style::default()),
+            Span::styled("All: ", all_style),
             Span::styled("A", all_style),
         ]),
         Line::from(vec![
             Span::styled("Navigation: ", Style::default().add_modifier(Modifier::BOLD)),
-            Span::raw("j/k or up/down, Enter to go to error, Esc to exit"),
+            Span::raw("j/k or up/down, Enter to go to error, Esc to exit | "),
+            Span::styled("Sort: ", Style::default().add_modifier(Modifier::BOLD)),
+            Span::raw(format!("s ({}) | f to apply a fix", editor.diagnostics_sort.name())),
         ]),
     ];
     
@@ -1411,7 +2113,7 @@ fn render_diagnostics_panel<B: Backend>(f: &mut Frame<B>, editor: &Editor, area:
     // Render diagnostics list
     let tab = editor.current_tab();
     let all_diagnostics = tab.diagnostics.get_all_diagnostics();
-    let filtered_diagnostics = tab.diagnostics.get_filtered_diagnostics(&editor.diagnostics_filter);
+    let filtered_diagnostics = editor.panel_diagnostics();
     
     // Collect counts by severity
     let error_count = all_diagnostics.iter()
@@ -1433,8 +2135,8 @@ fn render_diagnostics_panel<B: Backend>(f: &mut Frame<B>, editor: &Editor, area:
     };
     
     let list_block = Block::default()
-        .title(format!(" Issues ({} total - {} errors, {} warnings, {} info) - Showing: {} ", 
-            all_diagnostics.len(), error_count, warning_count, info_count, filter_name))
+        .title(format!(" Issues ({} total - {} errors, {} warnings, {} info) - Showing: {} by {} ", 
+            all_diagnostics.len(), error_count, warning_count, info_count, filter_name, editor.diagnostics_sort.name()))
         .title_style(Style::default().fg(Color::Green))
         .borders(Borders::ALL);
 
@@ -1503,10 +2205,19 @@ fn render_diagnostics_panel<B: Backend>(f: &mut Frame<B>, editor: &Editor, area:
                         format!("{}:{} ", file_display, diagnostic.span.line + 1), // +1 for 1-based display
                         Style::default().fg(Color::Blue)
                     ),
+                    // Which tool reported it, so check and clippy results can be told apart
+                    Span::styled(
+                        if diagnostic.source.is_empty() { String::new() } else { format!("[{}] ", diagnostic.source) },
+                        Style::default().fg(Color::DarkGray)
+                    ),
                     Span::styled(
                         &diagnostic.message,
                         severity_style
                     ),
+                    Span::styled(
+                        if diagnostic.fix.is_some() { " (fix available: f)" } else { "" },
+                        Style::default().fg(Color::Green)
+                    ),
                 ]);
                 
                 // First, create content line
@@ -1556,6 +2267,396 @@ fn render_diagnostics_panel<B: Backend>(f: &mut Frame<B>, editor: &Editor, area:
     }
 }
 
+/// Show each file of a pending multi-file edit with its changed lines
+fn render_workspace_edit_preview<B: Backend>(f: &mut Frame<B>, editor: &Editor, area: Rect) {
+    let Some(edit) = &editor.workspace_edit else { return };
+
+    let block = Block::default()
+        .title(format!(" {} ", edit.title))
+        .title_style(Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD))
+        .borders(Borders::ALL)
+        .border_style(Style::default().fg(Color::Cyan));
+    let inner_area = block.inner(area);
+    f.render_widget(block, area);
+
+    let mut lines: Vec<Line> = Vec::new();
+    let mut selected_row = 0;
+    let mut edit_index = 0;
+
+    for file in &edit.files {
+        lines.push(Line::from(Span::styled(
+            format!("{} ({} edits)", relative_path(&file.path), file.edits.len()),
+            Style::default().fg(Color::Cyan).add_modifier(Modifier::BOLD),
+        )));
+
+        for line_edit in &file.edits {
+            let is_current = edit_index == edit.selected_index;
+            if is_current {
+                selected_row = lines.len();
+            }
+            let highlight = if is_current {
+                Style::default().bg(Color::DarkGray)
+            } else {
+                Style::default()
+            };
+            let checkbox = if line_edit.selected { "[x]" } else { "[ ]" };
+            let prefix = format!("  {} {:>5}: ", checkbox, line_edit.line + 1);
+            let padding = " ".repeat(prefix.len());
+
+            let mut first = true;
+            if let Some(old) = &line_edit.old {
+                lines.push(Line::from(vec![
+                    Span::styled(prefix.clone(), highlight),
+                    Span::styled(format!("- {}", old), highlight.fg(Color::Red)),
+                ]));
+                first = false;
+            }
+            if let Some(new) = &line_edit.new {
+                let lead = if first { prefix.clone() } else { padding.clone() };
+                lines.push(Line::from(vec![
+                    Span::styled(lead, highlight),
+                    Span::styled(format!("+ {}", new), highlight.fg(Color::Green)),
+                ]));
+            }
+            edit_index += 1;
+        }
+        lines.push(Line::from(""));
+    }
+
+    // Keep the highlighted edit in view
+    let height = inner_area.height as usize;
+    let scroll = (selected_row + 2).saturating_sub(height);
+
+    let paragraph = Paragraph::new(lines).scroll((scroll as u16, 0));
+    f.render_widget(paragraph, inner_area);
+}
+
+fn render_undo_tree<B: Backend>(f: &mut Frame<B>, editor: &Editor, area: Rect) {
+    let block = Block::default()
+        .title(" Undo Tree ")
+        .title_style(Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD))
+        .borders(Borders::ALL)
+        .border_style(Style::default().fg(Color::Cyan));
+    let inner_area = block.inner(area);
+    f.render_widget(block, area);
+
+    let rows = editor.current_tab().buffer.history.tree_rows();
+    let height = (inner_area.height as usize).max(1);
+    let first_row = (editor.undo_tree_selected + 1).saturating_sub(height);
+    let lines: Vec<Line> = rows.iter().enumerate().skip(first_row).take(height).map(|(index, row)| {
+        let style = if index == editor.undo_tree_selected {
+            Style::default().bg(Color::DarkGray)
+        } else {
+            Style::default()
+        };
+        let graph_style = if row.current { style.fg(Color::Green).add_modifier(Modifier::BOLD) } else { style.fg(Color::Cyan) };
+        Line::from(vec![
+            Span::styled(format!("{} ", row.graph), graph_style),
+            Span::styled(format!("{:>4} ", row.seq), style.fg(Color::Yellow)),
+            Span::styled(row.summary.clone(), style),
+            Span::styled(format!("  {}", row.age), style.fg(Color::DarkGray)),
+        ])
+    }).collect();
+    f.render_widget(Paragraph::new(lines), inner_area);
+}
+
+fn render_jobs_panel<B: Backend>(f: &mut Frame<B>, editor: &Editor, area: Rect) {
+    let block = Block::default()
+        .title(" Jobs ")
+        .title_style(Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD))
+        .borders(Borders::ALL)
+        .border_style(Style::default().fg(Color::Cyan));
+    let inner_area = block.inner(area);
+    f.render_widget(block, area);
+
+    let mut lines: Vec<Line> = Vec::new();
+    let jobs = editor.jobs.list();
+    if jobs.is_empty() {
+        lines.push(Line::from(Span::styled("No jobs yet; :!command & starts one", Style::default().fg(Color::DarkGray))));
+    }
+    let mut selected_line = 0;
+    for (index, job) in jobs.iter().enumerate() {
+        if index == editor.jobs_selected {
+            selected_line = lines.len();
+        }
+        let style = if index == editor.jobs_selected {
+            Style::default().bg(Color::DarkGray)
+        } else {
+            Style::default()
+        };
+        let status_style = match job.status {
+            JobStatus::Running => style.fg(Color::Green).add_modifier(Modifier::BOLD),
+            JobStatus::Exited(Some(0)) => style.fg(Color::Cyan),
+            _ => style.fg(Color::Red),
+        };
+        lines.push(Line::from(vec![
+            Span::styled(format!("{:>3} ", job.id), style.fg(Color::Yellow)),
+            Span::styled(format!("{:<10}", job.status.label()), status_style),
+            Span::styled(format!("{:>7}  ", format_runtime(job.runtime())), style.fg(Color::DarkGray)),
+            Span::styled(job.command.clone(), style),
+            Span::styled(format!("  in {}", job.dir.display()), style.fg(Color::DarkGray)),
+        ]));
+        if !job.env.is_empty() {
+            let env: Vec<String> = job.env.iter().map(|(name, value)| format!("{}={}", name, value)).collect();
+            lines.push(Line::from(Span::styled(format!("{:22}{}", "", env.join(" ")), Style::default().fg(Color::DarkGray))));
+        }
+    }
+
+    let activities = editor.background_activities();
+    if !activities.is_empty() {
+        lines.push(Line::from(""));
+        lines.push(Line::from(Span::styled("Editor", Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD))));
+        for activity in activities {
+            lines.push(Line::from(Span::styled(format!("    {}", activity), Style::default().fg(Color::Green))));
+        }
+    }
+
+    let height = (inner_area.height as usize).max(1);
+    let scroll = (selected_line + 1).saturating_sub(height);
+    f.render_widget(Paragraph::new(lines).scroll((scroll as u16, 0)), inner_area);
+}
+
+/// Key bindings worth knowing from the start, shown on the dashboard
+const CHEATSHEET: [(&str, &str); 8] = [
+    ("Ctrl+o", "find files"),
+    ("Ctrl+t", "search code"),
+    ("Ctrl+n", "new tab"),
+    ("Ctrl+e", "diagnostics"),
+    ("i / Esc", "insert / normal mode"),
+    (":w", "write"),
+    (":q", "quit"),
+    ("Ctrl+h", "all commands"),
+];
+
+/// The start dashboard: actions, recent projects and files, a cheatsheet
+/// and the version, centered in the editor area
+fn render_dashboard<B: Backend>(f: &mut Frame<B>, editor: &Editor, area: Rect) {
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .border_style(Style::default().fg(Color::Cyan));
+    let inner_area = block.inner(area);
+    f.render_widget(block, area);
+
+    let entries = &editor.dashboard;
+    let selected = editor.dashboard_selected.min(entries.len().saturating_sub(1));
+    let heading = Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD);
+    let mut lines = vec![
+        Line::from(vec![
+            Span::styled("ZIM", Style::default().fg(Color::LightCyan).add_modifier(Modifier::BOLD)),
+            Span::styled(format!(" v{}", env!("CARGO_PKG_VERSION")), Style::default().fg(Color::DarkGray)),
+        ]),
+        Line::from(""),
+    ];
+    let mut section = "";
+    let mut selected_line = 0;
+    for (index, entry) in entries.iter().enumerate() {
+        if entry.section != section {
+            if !section.is_empty() {
+                lines.push(Line::from(""));
+            }
+            section = entry.section;
+            lines.push(Line::from(Span::styled(section, heading)));
+        }
+        let style = if index == selected {
+            selected_line = lines.len();
+            Style::default().bg(Color::DarkGray)
+        } else {
+            Style::default()
+        };
+        let key = entry.key.map(|key| format!("[{}] ", key)).unwrap_or_else(|| "    ".to_string());
+        let label_style = match entry.action {
+            DashboardAction::OpenProject(_) => style.fg(Color::LightBlue),
+            _ => style.fg(Color::White),
+        };
+        lines.push(Line::from(vec![
+            Span::styled(format!("  {}", key), style.fg(Color::Cyan)),
+            Span::styled(entry.label.clone(), label_style),
+            Span::styled(if entry.detail.is_empty() { String::new() } else { format!("  {}", entry.detail) }, style.fg(Color::DarkGray)),
+        ]));
+    }
+
+    lines.push(Line::from(""));
+    lines.push(Line::from(Span::styled("Keys", heading)));
+    for (keys, description) in CHEATSHEET {
+        lines.push(Line::from(vec![
+            Span::styled(format!("  {:<10}", keys), Style::default().fg(Color::Cyan)),
+            Span::styled(description, Style::default().fg(Color::Gray)),
+        ]));
+    }
+
+    // Centered while it fits, scrolling to the selection when it doesn't
+    let width = lines.iter().map(|line| line.width()).max().unwrap_or(0) as u16;
+    let height = lines.len() as u16;
+    let x = inner_area.x + inner_area.width.saturating_sub(width) / 2;
+    let y = inner_area.y + inner_area.height.saturating_sub(height) / 2;
+    let content = Rect::new(x, y, width.min(inner_area.width), height.min(inner_area.height));
+    let scroll = (selected_line + 1).saturating_sub(content.height.max(1) as usize);
+    f.render_widget(Paragraph::new(lines).scroll((scroll as u16, 0)), content);
+}
+
+/// Files opened in this and earlier sessions, newest first, with when
+/// they were last opened and how often
+fn render_recent_files_panel<B: Backend>(f: &mut Frame<B>, editor: &Editor, area: Rect) {
+    let block = Block::default()
+        .title(" Recent Files ")
+        .title_style(Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD))
+        .borders(Borders::ALL)
+        .border_style(Style::default().fg(Color::Cyan));
+    let inner_area = block.inner(area);
+    f.render_widget(block, area);
+
+    let files = editor.file_finder.recent_files();
+    let mut lines: Vec<Line> = Vec::new();
+    if files.is_empty() {
+        lines.push(Line::from(Span::styled("No recent files yet", Style::default().fg(Color::DarkGray))));
+    }
+    let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_secs();
+    let selected = editor.recent_selected.min(files.len().saturating_sub(1));
+    for (index, file) in files.iter().enumerate() {
+        let style = if index == selected {
+            Style::default().bg(Color::DarkGray)
+        } else {
+            Style::default()
+        };
+        let age = format_age(std::time::Duration::from_secs(now.saturating_sub(file.last_opened)));
+        let opened = if file.count == 1 { "opened once".to_string() } else { format!("opened {} times", file.count) };
+        lines.push(Line::from(vec![
+            Span::styled(format!("{:>8}  ", age), style.fg(Color::DarkGray)),
+            Span::styled(file.path.clone(), style.fg(Color::White)),
+            Span::styled(format!("  {}", opened), style.fg(Color::DarkGray)),
+        ]));
+    }
+
+    let height = (inner_area.height as usize).max(1);
+    let scroll = (selected + 1).saturating_sub(height);
+    f.render_widget(Paragraph::new(lines).scroll((scroll as u16, 0)), inner_area);
+}
+
+/// A directory's entries netrw-style, with their size and when they were
+/// last changed
+fn render_directory_listing<B: Backend>(f: &mut Frame<B>, editor: &Editor, area: Rect) {
+    let Some(listing) = &editor.listing else { return };
+    let order = if listing.reversed { ", reversed" } else { "" };
+    let block = Block::default()
+        .title(format!(" {} (by {}{}) ", listing.dir.display(), listing.sort.name(), order))
+        .title_style(Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD))
+        .borders(Borders::ALL)
+        .border_style(Style::default().fg(Color::Cyan));
+    let inner_area = block.inner(area);
+    f.render_widget(block, area);
+
+    let mut lines: Vec<Line> = Vec::new();
+    if listing.entries.is_empty() {
+        lines.push(Line::from(Span::styled("Empty directory", Style::default().fg(Color::DarkGray))));
+    }
+    let now = std::time::SystemTime::now();
+    let name_width = listing.entries.iter().map(|entry| entry.name.chars().count() + 1).max().unwrap_or(0).min(48);
+    for (index, entry) in listing.entries.iter().enumerate() {
+        let style = if index == listing.selected {
+            Style::default().bg(Color::DarkGray)
+        } else {
+            Style::default()
+        };
+        let (name, name_style) = if entry.is_dir {
+            (format!("{}/", entry.name), style.fg(Color::LightBlue).add_modifier(Modifier::BOLD))
+        } else {
+            (entry.name.clone(), style.fg(Color::White))
+        };
+        let size = if entry.is_dir { String::new() } else { file_size(entry.size) };
+        let age = entry.modified
+            .and_then(|modified| now.duration_since(modified).ok())
+            .map(format_age)
+            .unwrap_or_default();
+        let mut spans = vec![Span::styled(format!("{:<width$}", name, width = name_width), name_style)];
+        if entry.name != ".." {
+            spans.push(Span::styled(format!("  {:>8}  {:>8}", size, age), style.fg(Color::DarkGray)));
+        }
+        lines.push(Line::from(spans));
+    }
+
+    let height = (inner_area.height as usize).max(1);
+    let scroll = (listing.selected + 1).saturating_sub(height);
+    f.render_widget(Paragraph::new(lines).scroll((scroll as u16, 0)), inner_area);
+}
+
+/// A file size in bytes, or K, M or G to one decimal place
+fn file_size(bytes: u64) -> String {
+    if bytes < 1024 {
+        return bytes.to_string();
+    }
+    let mut size = bytes as f64 / 1024.0;
+    for unit in ["K", "M"] {
+        if size < 1024.0 {
+            return format!("{:.1}{}", size, unit);
+        }
+        size /= 1024.0;
+    }
+    format!("{:.1}G", size)
+}
+
+/// The `:build` or `:run` output with its colors, following the end while
+/// it runs unless a line was picked with j/k
+fn render_build_panel<B: Backend>(f: &mut Frame<B>, editor: &Editor, area: Rect) {
+    let job = editor.build_panel.as_ref().and_then(|panel| editor.jobs.get(panel.job));
+    let title = match job {
+        Some(job) => format!(" {} ({}, {}) ", job.command, job.status.label(), format_runtime(job.runtime())),
+        None => " Build ".to_string(),
+    };
+    let border_color = match job.map(|job| job.status) {
+        Some(JobStatus::Running) => Color::Yellow,
+        Some(JobStatus::Exited(Some(0))) => Color::Green,
+        _ => Color::Red,
+    };
+    let block = Block::default()
+        .title(title)
+        .title_style(Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD))
+        .borders(Borders::ALL)
+        .border_style(Style::default().fg(border_color));
+    let inner_area = block.inner(area);
+    f.render_widget(block, area);
+
+    let output = editor.build_output();
+    let selected = editor.build_panel.as_ref().map_or(0, |panel| panel.selected(output.len()));
+    let height = (inner_area.height as usize).max(1);
+    let scroll = (selected + 1).saturating_sub(height);
+    let lines: Vec<Line> = output.iter().enumerate().skip(scroll).take(height)
+        .map(|(index, line)| {
+            let spans = crate::editor::ansi_runs(line).into_iter()
+                .map(|(style, text)| Span::styled(text, ansi_style(style)))
+                .collect::<Vec<_>>();
+            let mut line = Line::from(spans);
+            if index == selected {
+                line.patch_style(Style::default().bg(Color::DarkGray));
+            }
+            line
+        })
+        .collect();
+    f.render_widget(Paragraph::new(lines), inner_area);
+}
+
+/// The terminal style for a run of ANSI-colored output
+fn ansi_style(style: crate::editor::AnsiStyle) -> Style {
+    use crate::editor::AnsiColor;
+
+    let color = |color: AnsiColor| match color {
+        AnsiColor::Indexed(index) => Color::Indexed(index),
+        AnsiColor::Rgb(r, g, b) => Color::Rgb(r, g, b),
+    };
+    let mut result = Style::default();
+    if let Some(fg) = style.fg {
+        result = result.fg(color(fg));
+    }
+    if let Some(bg) = style.bg {
+        result = result.bg(color(bg));
+    }
+    for (on, modifier) in [(style.bold, Modifier::BOLD), (style.dim, Modifier::DIM), (style.italic, Modifier::ITALIC), (style.underline, Modifier::UNDERLINED)] {
+        if on {
+            result = result.add_modifier(modifier);
+        }
+    }
+    result
+}
+
 /// Render the token search interface
 fn render_token_search<B: Backend>(f: &mut Frame<B>, editor: &Editor, area: Rect) {
     // Create a block for the token search
@@ -1568,45 +2669,117 @@ fn render_token_search<B: Backend>(f: &mut Frame<B>, editor: &Editor, area: Rect
     let inner_area = token_search_block.inner(area);
     f.render_widget(token_search_block, area);
 
-    // Create layout for search query and results
+    let search = &editor.token_search;
+    let show_replacement = search.replacing || !search.replacement.is_empty();
+
+    // Create layout for search query, file glob, replacement and results
+    let mut constraints = vec![
+        Constraint::Length(3), // Search query
+        Constraint::Length(3), // File glob
+    ];
+    if show_replacement {
+        constraints.push(Constraint::Length(3)); // Replacement
+    }
+    constraints.push(Constraint::Min(1)); // Search results
     let main_layout = Layout::default()
         .direction(Direction::Vertical)
-        .constraints([
-            Constraint::Length(3), // Search query
-            Constraint::Min(1),    // Search results
-        ].as_ref())
+        .constraints(constraints)
         .split(inner_area);
+    let results_chunk = main_layout[main_layout.len() - 1];
 
+    let focused_style = Style::default().fg(Color::LightBlue);
+    let unfocused_style = Style::default().fg(Color::DarkGray);
+    
+    // Show the search options in the query header, highlighting active ones
+    let toggle = |label: &'static str, active: bool| {
+        if active {
+            Span::styled(label, Style::default().fg(Color::Black).bg(Color::Yellow).add_modifier(Modifier::BOLD))
+        } else {
+            Span::styled(label, Style::default().fg(Color::DarkGray))
+        }
+    };
+    let search_title = Line::from(vec![
+        Span::styled(" Search Query ", if search.editing_glob || search.replacing { unfocused_style } else { focused_style }),
+        toggle(" .* ", search.regex),
+        Span::raw(" "),
+        toggle(" Aa ", search.case_sensitive),
+        Span::raw(" "),
+        toggle(" \\b ", search.whole_word),
+        Span::styled(" Alt+r/c/w ", Style::default().fg(Color::DarkGray)),
+    ]);
+    
     // Render search query
     let search_block = Block::default()
-        .title(" Search Query ")
-        .title_style(Style::default().fg(Color::LightBlue))
+        .title(search_title)
         .borders(Borders::ALL);
     
-    let search_text = Paragraph::new(editor.token_search.query.clone())
+    let search_text = Paragraph::new(search.query.clone())
         .block(search_block)
         .style(Style::default());
     
     f.render_widget(search_text, main_layout[0]);
+    
+    // Render the include/exclude globs
+    let glob_block = Block::default()
+        .title(" Files (e.g. *.rs !target/**) Alt+g ")
+        .title_style(if search.editing_glob { focused_style } else { unfocused_style })
+        .borders(Borders::ALL);
+    
+    let glob_text = Paragraph::new(search.glob.clone())
+        .block(glob_block)
+        .style(Style::default());
+    
+    f.render_widget(glob_text, main_layout[1]);
+
+    if show_replacement {
+        let replace_block = Block::default()
+            .title(" Replace with (Enter to preview) Ctrl+r ")
+            .title_style(if search.replacing { focused_style } else { unfocused_style })
+            .borders(Borders::ALL);
+
+        let replace_text = Paragraph::new(search.replacement.clone())
+            .block(replace_block)
+            .style(Style::default());
+
+        f.render_widget(replace_text, main_layout[2]);
+    }
 
     // Render search results
+    // Matches and files so far, and where the selection is in its file
+    let result_count = editor.token_search.results.len();
+    let file_count = editor.token_search.file_count();
+    let mut counts = format!("{} in {} file{}", result_count, file_count, if file_count == 1 { "" } else { "s" });
+    if let Some((position, total)) = editor.token_search.position_in_file().filter(|_| file_count > 1) {
+        counts.push_str(&format!("; {} of {} in this file", position, total));
+    }
+    let results_title = if editor.token_search.is_searching() && editor.token_search.query.len() >= MIN_QUERY_LEN {
+        format!(" Results ({}, searching... {} files searched) ", counts, editor.token_search.files_searched())
+    } else if editor.token_search.truncated {
+        format!(" Results ({}, limit reached) ", counts)
+    } else {
+        format!(" Results ({}) Alt+e to edit ", counts)
+    };
     let results_block = Block::default()
-        .title(format!(" Results ({}) ", editor.token_search.results.len()))
+        .title(results_title)
         .title_style(Style::default().fg(Color::Green))
         .borders(Borders::ALL);
 
-    let results_area = results_block.inner(main_layout[1]);
-    f.render_widget(results_block, main_layout[1]);
+    let results_area = results_block.inner(results_chunk);
+    f.render_widget(results_block, results_chunk);
 
     let results = &editor.token_search.results;
     let selected_index = editor.token_search.selected_index;
     
     if results.is_empty() {
         // Show a message when there are no results
-        let help_text = if editor.token_search.query.is_empty() {
+        let help_text = if let Some(error) = &editor.token_search.error {
+            error.as_str()
+        } else if editor.token_search.query.is_empty() {
             "Type to search for tokens in your code\nPress Ctrl+h for help"
-        } else if editor.token_search.query.len() < 3 {
+        } else if editor.token_search.query.len() < MIN_QUERY_LEN {
             "Enter at least 3 characters to search\nPress Ctrl+h for help"
+        } else if editor.token_search.is_searching() {
+            "Searching..."
         } else {
             "No matching results found"
         };
@@ -1617,10 +2790,16 @@ fn render_token_search<B: Backend>(f: &mut Frame<B>, editor: &Editor, area: Rect
         
         f.render_widget(help_paragraph, results_area);
     } else {
+        // Only build items for the rows that fit, scrolled to keep the selection visible
+        let visible_rows = (results_area.height as usize).max(1);
+        let first_row = (selected_index + 1).saturating_sub(visible_rows);
+        
         // Create list items from search results
         let items: Vec<ListItem> = results
             .iter()
             .enumerate()
+            .skip(first_row)
+            .take(visible_rows)
             .map(|(i, result)| {
                 // Format the line content to show the match with context
                 let line_content = &result.line_content;
@@ -1687,10 +2866,17 @@ fn render_token_search<B: Backend>(f: &mut Frame<B>, editor: &Editor, area: Rect
         f.render_widget(results_list, results_area);
     }
 
-    // Set cursor at the end of the search query
+    // Set cursor at the end of the field being edited
+    let (field_area, field_text) = if search.editing_glob {
+        (main_layout[1], &search.glob)
+    } else if search.replacing {
+        (main_layout[2], &search.replacement)
+    } else {
+        (main_layout[0], &search.query)
+    };
     f.set_cursor(
-        main_layout[0].x + editor.token_search.query.len() as u16 + 1,
-        main_layout[0].y + 1,
+        field_area.x + field_text.len() as u16 + 1,
+        field_area.y + 1,
     );
 }
 
@@ -1705,46 +2891,36 @@ fn render_file_finder<B: Backend>(f: &mut Frame<B>, editor: &Editor, area: Rect)
     let inner_area = file_finder_block.inner(area);
     f.render_widget(file_finder_block, area);
 
-    // Create overall layout with welcome header, search input, and file list
+    // Create overall layout with search input and file list
     let main_layout = Layout::default()
         .direction(Direction::Vertical)
         .constraints([
-            Constraint::Length(5), // Welcome header
             Constraint::Length(3), // Search input
             Constraint::Min(1),    // File list
         ].as_ref())
         .split(inner_area);
 
-    // Render welcome header only if query is empty (initial state)
-    if editor.file_finder.query().is_empty() {
-        let welcome_text = vec![
-            Line::from(vec![
-                Span::styled("Welcome to ", Style::default().fg(Color::White)),
-                Span::styled("ZIM Editor", Style::default().fg(Color::LightCyan).add_modifier(Modifier::BOLD)),
-            ]),
-            Line::from(""),
-            Line::from(vec![
-                Span::styled("Type to search for files or use arrow keys to navigate", 
-                    Style::default().fg(Color::Gray)),
-            ]),
-            Line::from(""),
-            Line::from(vec![
-                Span::styled("Press Ctrl+h for help", 
-                    Style::default().fg(Color::Yellow)),
-            ]),
-        ];
-        
-        let welcome_paragraph = Paragraph::new(welcome_text)
-            .alignment(tui::layout::Alignment::Center)
-            .block(Block::default()
-                .borders(Borders::NONE));
-        
-        f.render_widget(welcome_paragraph, main_layout[0]);
-    }
-
     // Render search query
+    // Where the files come from, relative to the current directory if it's under it
+    let cwd = std::env::current_dir().and_then(|dir| dir.canonicalize()).ok();
+    let root = match (editor.file_finder.root(), &cwd) {
+        (Some(root), Some(cwd)) => match root.strip_prefix(cwd) {
+            Ok(relative) if relative.as_os_str().is_empty() => ".".to_string(),
+            Ok(relative) => relative.display().to_string(),
+            Err(_) => root.display().to_string(),
+        },
+        (Some(root), None) => root.display().to_string(),
+        (None, _) => ".".to_string(),
+    };
+    let search_title = format!(
+        " Search Files in {} [{}]{} (Alt+s scope, Alt+h {}, Alt+n new file, dir/ to narrow) ",
+        root,
+        editor.file_finder.scope().label(),
+        if editor.file_finder.include_hidden() { " [hidden]" } else { "" },
+        if editor.file_finder.include_hidden() { "to hide" } else { "for hidden" },
+    );
     let search_block = Block::default()
-        .title(" Search Files ")
+        .title(search_title)
         .title_style(Style::default().fg(Color::LightBlue))
         .borders(Borders::ALL);
     
@@ -1752,7 +2928,7 @@ fn render_file_finder<B: Backend>(f: &mut Frame<B>, editor: &Editor, area: Rect)
         .block(search_block)
         .style(Style::default());
     
-    f.render_widget(search_text, main_layout[1]);
+    f.render_widget(search_text, main_layout[0]);
 
     // Render file list
     let list_title = if editor.file_finder.query().is_empty() {
@@ -1769,31 +2945,58 @@ fn render_file_finder<B: Backend>(f: &mut Frame<B>, editor: &Editor, area: Rect)
     let matches = editor.file_finder.matches();
     let selected_index = editor.file_finder.selected_index();
     
-    let items: Vec<ListItem> = if matches.is_empty() && editor.file_finder.query().is_empty() {
+    // Show a preview of the selected file beside the list when there's room
+    let (list_area, preview_area) = if main_layout[1].width >= 80 && !matches.is_empty() {
+        let columns = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Percentage(45), Constraint::Percentage(55)].as_ref())
+            .split(main_layout[1]);
+        (columns[0], Some(columns[1]))
+    } else {
+        (main_layout[1], None)
+    };
+
+    let items: Vec<ListItem> = if matches.is_empty() && editor.file_finder.is_indexing() {
+        // The initial scan runs in the background after startup
+        vec![ListItem::new("Indexing files...")]
+    } else if matches.is_empty() && editor.file_finder.query().is_empty() {
         // Show a message when there are no recent files
         vec![ListItem::new("No recent files. Type to search or press Esc to open a blank file.")]
     } else if matches.is_empty() {
         // Show a message when there are no search results
         vec![ListItem::new("No matching files found. Press Esc to cancel.")]
     } else {
+        // Only build items for the rows that fit, scrolled to keep the selection visible
+        let visible_rows = (list_area.height.saturating_sub(2) as usize).max(1);
+        let first_row = (selected_index + 1).saturating_sub(visible_rows);
+        
         matches
             .iter()
             .enumerate()
-            .map(|(i, (path, _score))| {
-                // Extract just the filename for display
-                let display_path = if let Some(file_name) = std::path::Path::new(path).file_name() {
-                    format!("{} ({})", file_name.to_string_lossy(), path)
-                } else {
-                    path.clone()
-                };
-                
+            .skip(first_row)
+            .take(visible_rows)
+            .map(|(i, file_match)| {
+                let path = &file_match.path;
                 let style = if i == selected_index {
                     Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD)
                 } else {
                     Style::default()
                 };
+                let matched_style = style.fg(Color::Green).add_modifier(Modifier::BOLD | Modifier::UNDERLINED);
+                
+                // Show the file name, then the full path, with the characters
+                // that matched the query highlighted in both
+                let file_name = std::path::Path::new(path).file_name()
+                    .map(|name| name.to_string_lossy().to_string())
+                    .unwrap_or_else(|| path.clone());
+                let name_offset = path.chars().count() - file_name.chars().count();
                 
-                ListItem::new(display_path).style(style)
+                let mut spans = highlight_positions(&file_name, name_offset, &file_match.positions, style, matched_style);
+                spans.push(Span::styled(" (", style));
+                spans.extend(highlight_positions(path, 0, &file_match.positions, style, matched_style));
+                spans.push(Span::styled(")", style));
+                
+                ListItem::new(Line::from(spans))
             })
             .collect()
     };
@@ -1802,19 +3005,35 @@ fn render_file_finder<B: Backend>(f: &mut Frame<B>, editor: &Editor, area: Rect)
         .block(list_block)
         .highlight_style(Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD));
     
-    f.render_widget(file_list, main_layout[2]);
+    f.render_widget(file_list, list_area);
+
+    if let (Some(preview_area), Some(preview)) = (preview_area, &editor.file_preview) {
+        let preview_block = Block::default()
+            .title(" Preview ")
+            .title_style(Style::default().fg(Color::Green))
+            .borders(Borders::ALL);
+        
+        let lines: Vec<Line> = match &preview.message {
+            Some(message) => vec![Line::from(Span::styled(message.clone(), Style::default().fg(Color::DarkGray)))],
+            None => preview.lines.iter()
+                .map(|line| Line::from(create_highlighted_spans(std::slice::from_ref(line))))
+                .collect(),
+        };
+        
+        f.render_widget(Paragraph::new(lines).block(preview_block), preview_area);
+    }
 
     // Add a small help text at the bottom of the file list area
-    let help_text = "Enter: open in current tab, Ctrl+Enter: open in new tab, Esc: normal mode, Ctrl+n: new file";
+    let help_text = "Enter: open in current tab, Ctrl+Enter: open in new tab, Tab: peek, Esc: normal mode, Ctrl+n: new file";
     let help_paragraph = Paragraph::new(help_text)
         .style(Style::default().fg(Color::DarkGray))
         .alignment(tui::layout::Alignment::Center);
     
     // Calculate a small area at the bottom for the help text
     let help_area = Rect {
-        x: main_layout[2].x,
-        y: main_layout[2].y + main_layout[2].height.saturating_sub(1),
-        width: main_layout[2].width,
+        x: main_layout[1].x,
+        y: main_layout[1].y + main_layout[1].height.saturating_sub(1),
+        width: main_layout[1].width,
         height: 1,
     };
     
@@ -1822,217 +3041,222 @@ fn render_file_finder<B: Backend>(f: &mut Frame<B>, editor: &Editor, area: Rect)
 
     // Set cursor at the end of the search query
     f.set_cursor(
-        main_layout[1].x + editor.file_finder.query().len() as u16 + 1,
-        main_layout[1].y + 1,
+        main_layout[0].x + editor.file_finder.query().len() as u16 + 1,
+        main_layout[0].y + 1,
     );
 }
 
-fn render_help_page<B: Backend>(f: &mut Frame<B>, editor: &Editor, area: Rect) {
+/// The command palette: the query over the matching commands, laid out
+/// like the file finder, with each command's keys on the right
+fn render_command_palette<B: Backend>(f: &mut Frame<B>, editor: &Editor, area: Rect) {
+    let block = Block::default()
+        .title(" Command Palette ")
+        .title_style(Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD))
+        .borders(Borders::ALL)
+        .border_style(Style::default().fg(Color::Cyan));
+    let inner_area = block.inner(area);
+    f.render_widget(block, area);
+
+    let rows = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Length(3), Constraint::Min(1)].as_ref())
+        .split(inner_area);
+    let search_block = Block::default()
+        .title(" Run a command ")
+        .title_style(Style::default().fg(Color::LightBlue))
+        .borders(Borders::ALL);
+    f.render_widget(Paragraph::new(editor.palette.query()).block(search_block), rows[0]);
+
+    let list_block = Block::default().borders(Borders::ALL);
+    let width = rows[1].width.saturating_sub(2) as usize;
+    let visible_rows = (rows[1].height.saturating_sub(2) as usize).max(1);
+    let selected_index = editor.palette.selected_index();
+    let first_row = (selected_index + 1).saturating_sub(visible_rows);
+    let mut items: Vec<ListItem> = editor.palette.matches()
+        .enumerate()
+        .skip(first_row)
+        .take(visible_rows)
+        .map(|(i, (item, positions))| {
+            let style = if i == selected_index {
+                Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD)
+            } else {
+                Style::default()
+            };
+            let matched_style = style.fg(Color::Green).add_modifier(Modifier::BOLD | Modifier::UNDERLINED);
+            let mut spans = highlight_positions(&item.name, 0, positions, style, matched_style);
+            let keys = item.keys.join(", ");
+            let used = item.name.chars().count() + keys.chars().count();
+            if !keys.is_empty() {
+                spans.push(Span::raw(" ".repeat(width.saturating_sub(used).max(2))));
+                spans.push(Span::styled(keys, Style::default().fg(Color::Cyan)));
+            }
+            ListItem::new(Line::from(spans))
+        })
+        .collect();
+    if items.is_empty() {
+        items.push(ListItem::new("No matching commands. Press Esc to cancel."));
+    }
+    f.render_widget(List::new(items).block(list_block), rows[1]);
+
+    f.set_cursor(rows[0].x + editor.palette.query().chars().count() as u16 + 1, rows[0].y + 1);
+}
+
+/// Split text into spans, styling the characters at the matched positions
+///
+/// `offset` is where `text` starts within the string the positions refer to.
+fn highlight_positions(text: &str, offset: usize, positions: &[usize], style: Style, matched_style: Style) -> Vec<Span<'static>> {
+    let mut spans: Vec<Span<'static>> = Vec::new();
+    let mut current = String::new();
+    let mut current_matched = false;
+    
+    for (i, c) in text.chars().enumerate() {
+        let matched = positions.contains(&(i + offset));
+        if matched != current_matched && !current.is_empty() {
+            let span_style = if current_matched { matched_style } else { style };
+            spans.push(Span::styled(std::mem::take(&mut current), span_style));
+        }
+        current_matched = matched;
+        current.push(c);
+    }
+    if !current.is_empty() {
+        spans.push(Span::styled(current, if current_matched { matched_style } else { style }));
+    }
+    spans
+}
+
+fn render_help_page<B: Backend>(f: &mut Frame<B>, editor: &mut Editor, area: Rect) {
     let help_block = Block::default()
-        .title(" Help - Press ESC or q to exit ")
+        .title(" Help - j/k scroll, / search, ESC or q to exit ")
         .borders(Borders::ALL)
         .border_style(Style::default().fg(Color::LightBlue));
     
     let inner_area = help_block.inner(area);
     f.render_widget(help_block, area);
-    
-    // Create sections of help content
+
+    // The search line takes the bottom row while there is one
+    let searching = editor.help.searching || !editor.help.query.is_empty();
+    let (text_area, search_area) = if searching && inner_area.height > 1 {
+        let rows = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Min(1), Constraint::Length(1)].as_ref())
+            .split(inner_area);
+        (rows[0], Some(rows[1]))
+    } else {
+        (inner_area, None)
+    };
+
+    let sections = editor.help_sections();
+    let key_width = sections.iter()
+        .flat_map(|section| &section.entries)
+        .map(help_keys_width)
+        .filter(|width| *width <= 24)
+        .max()
+        .unwrap_or(0);
     let mut text = Vec::new();
-    
-    // Title section with modern styling
-    text.push(Line::from(vec![
-        tui::text::Span::styled(
-            "╔═════════════════════════════════════════════╗",
-            Style::default().fg(Color::Cyan)
-        )
-    ]));
-    text.push(Line::from(vec![
-        tui::text::Span::styled(
-            "║          ZIM EDITOR COMMAND REFERENCE       ║",
-            Style::default().fg(Color::Cyan).add_modifier(Modifier::BOLD)
-        )
-    ]));
-    text.push(Line::from(vec![
-        tui::text::Span::styled(
-            "╚═════════════════════════════════════════════╝",
-            Style::default().fg(Color::Cyan)
-        )
-    ]));
-    text.push(Line::from(""));
-    
-    // Essential Commands section
-    text.push(Line::from(vec![
-        tui::text::Span::styled(
-            "┏━━━━━━━━━━━━━━━━━━━━━━━━━━━━┓",
-            Style::default().fg(Color::Green)
-        )
-    ]));
-    text.push(Line::from(vec![
-        tui::text::Span::styled(
-            "┃     ESSENTIAL COMMANDS     ┃",
-            Style::default().fg(Color::Green).add_modifier(Modifier::BOLD)
-        )
-    ]));
-    text.push(Line::from(vec![
-        tui::text::Span::styled(
-            "┗━━━━━━━━━━━━━━━━━━━━━━━━━━━━┛",
-            Style::default().fg(Color::Green)
-        )
-    ]));
-    text.push(Line::from(""));
-    
-    // Mode switching commands
-    text.push(Line::from(vec![
-        tui::text::Span::styled("➤ Mode Switching:", Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD))
-    ]));
-    text.push(Line::from("ESC      - Return to Normal mode (from any mode)"));
-    text.push(Line::from("i        - Enter Insert mode"));
-    text.push(Line::from(":        - Enter Command mode"));
-    text.push(Line::from("v        - Enter Visual mode"));
-    text.push(Line::from("V        - Enter Visual Line mode"));
-    text.push(Line::from(""));
-    
-    // Basic navigation
-    text.push(Line::from(vec![
-        tui::text::Span::styled("➤ Navigation:", Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD))
-    ]));
-    text.push(Line::from("h, j, k, l - Move left, down, up, right"));
-    text.push(Line::from("^        - Move to start of line"));
-    text.push(Line::from("$        - Move to end of line"));
-    text.push(Line::from("g        - Move to top of file"));
-    text.push(Line::from("G        - Move to bottom of file"));
-    text.push(Line::from("Ctrl+b   - Page up"));
-    text.push(Line::from("Ctrl+f   - Page down"));
-    text.push(Line::from(""));
-    
-    // File operations
-    text.push(Line::from(vec![
-        tui::text::Span::styled("➤ File Operations:", Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD))
-    ]));
-    text.push(Line::from("Ctrl+o   - Open file (finder)"));
-    text.push(Line::from("w        - Save current file"));
-    text.push(Line::from("w → a    - Save all files in all tabs (press 'a' in write confirm mode)"));
-    text.push(Line::from("e        - Reload file from disk"));
-    text.push(Line::from("q        - Quit editor"));
-    text.push(Line::from(":q!      - Force quit (discard changes)"));
-    text.push(Line::from("X or ZZ  - Save and quit"));
-    text.push(Line::from(""));
-    
-    // Tab management section
-    text.push(Line::from(vec![
-        tui::text::Span::styled("➤ Tab Management:", Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD))
-    ]));
-    text.push(Line::from("Ctrl+n       - New tab"));
-    text.push(Line::from("Ctrl+w       - Close current tab"));
-    text.push(Line::from("Ctrl+right   - Next tab"));
-    text.push(Line::from("Ctrl+left    - Previous tab"));
-    text.push(Line::from("F1-F12       - Switch directly to tabs 1-12"));
-    text.push(Line::from(""));
-    
-    // Editing section
-    text.push(Line::from(vec![
-        tui::text::Span::styled("➤ Editing:", Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD))
-    ]));
-    text.push(Line::from("d        - Delete current line"));
-    text.push(Line::from("x        - Delete character at cursor and enter insert mode"));
-    text.push(Line::from("y        - Yank (copy) selection or line"));
-    text.push(Line::from("p        - Paste clipboard content"));
-    text.push(Line::from("u        - Undo last action"));
-    text.push(Line::from("Ctrl+r   - Redo previously undone action"));
-    text.push(Line::from("Backspace - Delete character or join with previous line"));
-    text.push(Line::from(""));
-    
-    // Search & Diagnostics
-    text.push(Line::from(vec![
-        tui::text::Span::styled("➤ Search & Diagnostics:", Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD))
-    ]));
-    text.push(Line::from("Ctrl+t   - Search for code tokens across files"));
-    text.push(Line::from("Ctrl+e   - Open diagnostics panel"));
-    text.push(Line::from("n/p      - Navigate to next/previous diagnostic"));
-    text.push(Line::from(""));
-    
-    // Development & Integration
-    text.push(Line::from(vec![
-        tui::text::Span::styled("➤ Development:", Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD))
-    ]));
-    text.push(Line::from("Ctrl+d   - Run cargo check and show diagnostics"));
-    text.push(Line::from("Ctrl+y   - Run cargo clippy and show diagnostics"));
-    text.push(Line::from(""));
-    
-    // Help and Access
-    text.push(Line::from(vec![
-        tui::text::Span::styled("➤ Help and Documentation:", Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD))
-    ]));
-    text.push(Line::from("Ctrl+h   - Show this help page"));
-    text.push(Line::from("ESC or q - Exit help and return to normal mode"));
-    
-    // Visual Mode section
-    text.push(Line::from(""));
-    text.push(Line::from(vec![
-        tui::text::Span::styled(
-            "┏━━━━━━━━━━━━━━━━━━━━━━━━━━━━┓",
-            Style::default().fg(Color::Magenta)
-        )
-    ]));
-    text.push(Line::from(vec![
-        tui::text::Span::styled(
-            "┃     VISUAL MODE COMMANDS   ┃",
-            Style::default().fg(Color::Magenta).add_modifier(Modifier::BOLD)
-        )
-    ]));
-    text.push(Line::from(vec![
-        tui::text::Span::styled(
-            "┗━━━━━━━━━━━━━━━━━━━━━━━━━━━━┛",
-            Style::default().fg(Color::Magenta)
-        )
-    ]));
-    text.push(Line::from(""));
-    text.push(Line::from("h, j, k, l - Extend selection"));
-    text.push(Line::from("ESC        - Return to normal mode"));
-    text.push(Line::from("y          - Yank (copy) selected text"));
-    text.push(Line::from("d          - Delete selected text"));
-    
-    // Render the help text
-    // Add footer
-    text.push(Line::from(""));
-    text.push(Line::from(vec![
-        tui::text::Span::styled(
-            "═════════════════════════════════════════════",
-            Style::default().fg(Color::Cyan)
-        )
-    ]));
     text.push(Line::from(vec![
-        tui::text::Span::styled(
-            "    Zim: A modern, fast terminal text editor    ",
-            Style::default().fg(Color::Cyan).add_modifier(Modifier::ITALIC)
-        )
+        Span::styled("ZIM EDITOR COMMAND REFERENCE", Style::default().fg(Color::Cyan).add_modifier(Modifier::BOLD)),
+        Span::styled("  keys you've changed are ", Style::default().fg(Color::DarkGray)),
+        Span::styled("highlighted", Style::default().fg(Color::LightMagenta).add_modifier(Modifier::BOLD)),
     ]));
-    
+    if sections.is_empty() {
+        text.push(Line::from(""));
+        text.push(Line::from(Span::styled(format!("Nothing matches \"{}\"", editor.help.query), Style::default().fg(Color::DarkGray))));
+    }
+    for section in &sections {
+        text.push(Line::from(""));
+        text.push(Line::from(Span::styled(format!("➤ {}:", section.title), Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD))));
+        for entry in &section.entries {
+            let mut spans = Vec::new();
+            for (index, key) in entry.keys.iter().enumerate() {
+                if index > 0 {
+                    spans.push(Span::styled(", ", Style::default().fg(Color::DarkGray)));
+                }
+                let style = if key.custom {
+                    Style::default().fg(Color::LightMagenta).add_modifier(Modifier::BOLD)
+                } else {
+                    Style::default().fg(Color::Cyan)
+                };
+                spans.push(Span::styled(key.label.clone(), style));
+            }
+            let padding = key_width.saturating_sub(help_keys_width(entry));
+            spans.push(Span::raw(format!("{} - ", " ".repeat(padding))));
+            spans.push(Span::raw(entry.description.clone()));
+            text.push(Line::from(spans));
+        }
+    }
+
+    // Keep the scroll within the page, so G lands on the last screenful
+    let height = text_area.height as usize;
+    editor.help.height = height;
+    editor.help.scroll = editor.help.scroll.min(text.len().saturating_sub(height));
     let help_text = Paragraph::new(text)
         .alignment(tui::layout::Alignment::Left)
-        .scroll((0, 0));
-    
-    f.render_widget(help_text, inner_area);
+        .scroll((editor.help.scroll as u16, 0));
+    f.render_widget(help_text, text_area);
+
+    if let Some(search_area) = search_area {
+        let search = Paragraph::new(format!("/{}", editor.help.query)).style(Style::default().fg(Color::Yellow));
+        f.render_widget(search, search_area);
+        if editor.help.searching {
+            f.set_cursor(search_area.x + 1 + editor.help.query.chars().count() as u16, search_area.y);
+        }
+    }
+}
+
+/// How wide an entry's keys are, joined by commas
+fn help_keys_width(entry: &HelpEntry) -> usize {
+    entry.keys.iter().map(|key| key.label.chars().count()).sum::<usize>() + entry.keys.len().saturating_sub(1) * 2
 }
 
 fn render_status_line<B: Backend>(f: &mut Frame<B>, editor: &Editor, area: Rect) {
     // Mode text with command text if in command mode
     let mode_text = match editor.mode {
-        Mode::Normal => "NORMAL".to_string(),
+        Mode::Normal => match editor.count {
+            Some(count) => format!("NORMAL {}", count),
+            None => "NORMAL".to_string(),
+        },
         Mode::Insert => "INSERT".to_string(),
         Mode::Command => {
             format!(":{}", editor.command_text)
         },
         Mode::FileFinder => "FILE FINDER".to_string(),
-        Mode::TokenSearch => format!("TOKEN SEARCH: {}", editor.token_search.query),
+        Mode::TokenSearch => match editor.token_search.match_status() {
+            Some(status) => format!("TOKEN SEARCH: {} ({})", editor.token_search.query, status),
+            None => format!("TOKEN SEARCH: {}", editor.token_search.query),
+        },
         Mode::Help => "HELP".to_string(),
         Mode::WriteConfirm => "WRITE? (y/n/q/a)".to_string(),
         Mode::ReloadConfirm => "RELOAD? (y/n)".to_string(),
+        Mode::OverwriteConfirm => "OVERWRITE? (y/n)".to_string(),
+        Mode::DeleteConfirm => "DELETE? (y/n)".to_string(),
+        Mode::TrustConfirm => "TRUST? (y/n)".to_string(),
+        Mode::QuitConfirm => "UNSAVED CHANGES (s/d/c)".to_string(),
         Mode::FilenamePrompt => format!("FILENAME: {}", editor.filename_prompt_text),
         Mode::DiagnosticsPanel => "DIAGNOSTICS".to_string(),
+        Mode::WorkspaceEditPreview => "PREVIEW EDIT".to_string(),
+        Mode::UndoTree => "UNDO TREE".to_string(),
+        Mode::Jobs => format!("JOBS ({} running)", editor.jobs.running()),
+        Mode::RecentFiles => "RECENT FILES".to_string(),
+        Mode::DirectoryListing => "DIRECTORY".to_string(),
+        Mode::Dashboard => "DASHBOARD".to_string(),
+        Mode::CommandPalette => "COMMAND PALETTE".to_string(),
+        Mode::BuildOutput => {
+            let status = editor.build_panel.as_ref()
+                .and_then(|panel| editor.jobs.get(panel.job))
+                .map(|job| job.status.label())
+                .unwrap_or_default();
+            format!("BUILD ({})", status)
+        },
         Mode::Visual => "VISUAL".to_string(),
         Mode::VisualLine => "VISUAL LINE".to_string(),
         Mode::Delete => "DELETE".to_string(),
+        Mode::Change => "CHANGE".to_string(),
+        Mode::ReplaceChar => "REPLACE CHAR".to_string(),
+        Mode::FindChar => "FIND CHAR".to_string(),
+        Mode::SetMark => "MARK".to_string(),
+        Mode::GoToMark => "GO TO MARK".to_string(),
+        Mode::Replace => "REPLACE".to_string(),
+        Mode::Operator => editor.pending_operator.map_or("OPERATOR", |operator| operator.name()).to_string(),
         Mode::Snake => {
             if let Some(snake) = &editor.snake_game {
                 match snake.state() {
@@ -2045,13 +3269,42 @@ fn render_status_line<B: Backend>(f: &mut Frame<B>, editor: &Editor, area: Rect)
             }
         },
     };
+    let mode_text = if editor.trust.safe { format!("{} [safe]", mode_text) } else { mode_text };
+    
+    // Where Enter opens the selection of the diagnostics and token search panels
+    let target_tab = match editor.panel_count {
+        Some(count) => format!("tab {}", count),
+        None => "this tab".to_string(),
+    };
     
     let status = match editor.mode {
         Mode::FileFinder => format!("{} | Press Enter to select, Esc to cancel", mode_text),
-        Mode::TokenSearch => format!("{} | Press Enter to go to selection, Esc to cancel", mode_text),
+        Mode::TokenSearch => format!("{} | Press Enter to go to selection in {} (Alt+number: other tab), Esc to cancel", mode_text, target_tab),
         Mode::FilenamePrompt => format!("{} | Press Enter to save, Esc to cancel", mode_text),
-        Mode::DiagnosticsPanel => format!("{} | Press Enter to go to selected error, n/p for next/prev, Esc to exit", mode_text),
+        Mode::DiagnosticsPanel => format!("{} | Press Enter to go to selected error in {} (number: other tab), n/p for next/prev, Esc to exit", mode_text, target_tab),
+        Mode::WorkspaceEditPreview => {
+            let (selected, total) = editor.workspace_edit.as_ref()
+                .map(|edit| (edit.selected_count(), edit.edit_count()))
+                .unwrap_or_default();
+            format!("{} | {}/{} edits selected | Space: toggle, a: toggle all, Enter: apply, Esc: cancel", mode_text, selected, total)
+        },
+        Mode::UndoTree => format!("{} | j/k: move through states, Enter: keep this one, Esc: go back", mode_text),
+        Mode::Jobs => format!("{} | Enter: show output, r: restart, x: kill, Esc: close (jobs keep running)", mode_text),
+        Mode::CommandPalette => format!("{} | Type to search, Up/Down: pick, Enter: run, Esc: cancel", mode_text),
+        Mode::Dashboard => format!("{} | Press a key in [ ] or j/k and Enter, Esc for an empty buffer", mode_text),
+        Mode::DirectoryListing => format!("{} | Enter: open, t: in a new tab, -: up, s: sort, r: reverse, Esc: close", mode_text),
+        Mode::RecentFiles => format!("{} | Enter: open where you left it, Ctrl+Enter: in a new tab, x: forget, Esc: close", mode_text),
+        Mode::BuildOutput => format!("{} | j/k: pick a line, Enter: open its file:line, r: run again, x: stop, Esc: close (:cw reopens)", mode_text),
         Mode::Delete => format!("{} | d: delete line, w: delete word, $: delete to end, ^/0: delete to start, Esc: cancel", mode_text),
+        Mode::Change => format!("{} | c: change line, w: change word, $: change to end, ^/0: change to start, Esc: cancel", mode_text),
+        Mode::ReplaceChar => format!("{} | Type the character to put under the cursor, Esc: cancel", mode_text),
+        Mode::FindChar => format!("{} | Type the character to find on the line, Esc: cancel", mode_text),
+        Mode::SetMark => format!("{} | Type a letter to mark the line with, Esc: cancel", mode_text),
+        Mode::GoToMark => format!("{} | Type the letter of the mark to go to, Esc: cancel", mode_text),
+        Mode::Operator => {
+            let line_key = editor.pending_operator.map_or('q', |operator| operator.line_key());
+            format!("{} | {}: line, w: word, $: to end, ^/0: to start, j/k: two lines, Esc: cancel", mode_text, line_key)
+        },
         Mode::Snake => format!("{} | Use h,j,k,l or arrow keys to move | r: restart | q/ESC: exit", mode_text),
         Mode::WriteConfirm => {
             // Get current file info for write confirmation
@@ -2067,9 +3320,41 @@ fn render_status_line<B: Backend>(f: &mut Frame<B>, editor: &Editor, area: Rect)
             
             // Count modified lines
             let modified_line_count = editor.current_tab().buffer.get_modified_lines().len();
+            let left_out = match &editor.write_review {
+                Some(review) if !review.excluded.is_empty() => format!(" | {} of {} hunks left out", review.excluded.len(), review.hunks.len()),
+                _ => String::new(),
+            };
             
-            format!("{} | Save file: {} | {} modified lines | Press Y to confirm, N to cancel, Q to quit without saving, A to save all tabs", 
-                mode_text, file_info, modified_line_count)
+            format!("{} | Save file: {} | {} modified lines{} | Press Y to confirm, N to cancel, Q to quit without saving, A to save all tabs, J/K to move between hunks, X to leave one out", 
+                mode_text, file_info, modified_line_count, left_out)
+        },
+        Mode::OverwriteConfirm => {
+            let target = editor.pending_file_operation.as_ref()
+                .map(|operation| operation.target().to_string())
+                .unwrap_or_default();
+            
+            format!("{} | {} already exists | Press Y to overwrite, N to cancel", mode_text, target)
+        },
+        Mode::DeleteConfirm => {
+            let target = editor.pending_file_operation.as_ref()
+                .map(|operation| operation.target().to_string())
+                .unwrap_or_default();
+            let unsaved = if editor.current_tab().buffer.is_modified { " (unsaved changes are lost)" } else { "" };
+            format!("{} | Move {} to the trash{} | Press Y to delete, N to cancel", mode_text, target, unsaved)
+        },
+        Mode::QuitConfirm => {
+            let close = editor.pending_close.unwrap_or(PendingClose::Quit);
+            let names: Vec<String> = editor.unsaved_tabs(close).into_iter().map(|index| editor.tab_title(index)).collect();
+            let action = match close {
+                PendingClose::Quit => "quit",
+                PendingClose::CloseTab => "close",
+            };
+            format!("{} | Unsaved: {} | s: save and {}, d: discard changes, c/Esc: keep editing", mode_text, names.join(", "), action)
+        },
+        Mode::TrustConfirm => {
+            let workspace = editor.pending_trust().unwrap_or_default();
+            format!("{} | Let {} run cargo, compilers and formatters? Its build scripts can run any code | Y: trust, N: don't, Esc: ask later",
+                mode_text, workspace)
         },
         Mode::ReloadConfirm => {
             // Get current file info for reload confirmation
@@ -2088,10 +3373,13 @@ fn render_status_line<B: Backend>(f: &mut Frame<B>, editor: &Editor, area: Rect)
         _ => {
             // Get current tab info
             let tab = editor.current_tab();
-            let total_lines = tab.buffer.line_count();
-            let viewport = &tab.viewport;
+            // Paged files have no cursor; their position is the first row shown
+            let (line, top_line, total_lines) = match &tab.buffer.paged {
+                Some(paged) => (paged.top, paged.top, paged.row_count()),
+                None => (tab.cursor.y, tab.viewport.top_line, tab.buffer.line_count()),
+            };
             let top_percent = if total_lines > 0 {
-                (viewport.top_line * 100) / total_lines
+                (top_line * 100) / total_lines
             } else {
                 0
             };
@@ -2124,22 +3412,64 @@ fn render_status_line<B: Backend>(f: &mut Frame<B>, editor: &Editor, area: Rect)
                 "".to_string()
             };
             
-            format!("{} | {} | Tab {}/{} | Ln: {}/{} ({}%), Col: {}{}", 
+            // Mark buffers that can't be edited or are following their file
+            let read_only_info = match (tab.buffer.read_only, tab.buffer.follow) {
+                (true, true) => " [RO] [follow]",
+                (true, false) => " [RO]",
+                (false, true) => " [follow]",
+                (false, false) => "",
+            };
+            
+            // How the file is stored, e.g. "utf-8 unix"
+            let format_info = match tab.buffer.paged.as_ref().map(|paged| paged.kind) {
+                Some(PagedKind::Large) => " | large file".to_string(),
+                Some(PagedKind::Binary) => " | binary".to_string(),
+                None => format!(
+                    " | {} {}{}{}",
+                    tab.buffer.encoding.name().to_lowercase(),
+                    tab.buffer.line_ending.name(),
+                    // Bytes that don't show in the text but do in diffs
+                    if tab.buffer.bom { " [bom]" } else { "" },
+                    if tab.buffer.has_final_newline() || tab.buffer.read_only { "" } else { " [noeol]" },
+                ),
+            };
+            
+            // Byte and character offsets, for errors reported "at byte N"
+            let offset_info = if editor.config.show_offset {
+                let (bytes, chars) = tab.buffer.offsets_at(tab.cursor.x, tab.cursor.y);
+                format!(", Byte: {}, Char: {}", bytes, chars)
+            } else {
+                "".to_string()
+            };
+            
+            // Show the latest message until the next key press
+            let message_info = match &editor.status_message {
+                Some(message) => format!(" | {}", message),
+                None => "".to_string(),
+            };
+            
+            format!("{} | {}{}{} | Tab {}/{} | Ln: {}/{} ({}%), Col: {}{}{}{}", 
                 mode_text,
                 file_info, 
+                read_only_info,
+                format_info,
                 editor.current_tab + 1,
                 editor.tabs.len(),
-                tab.cursor.y + 1, 
+                line + 1, 
                 total_lines,
                 top_percent,
                 tab.cursor.x + 1,
-                diagnostic_info
+                offset_info,
+                diagnostic_info,
+                message_info
             )
         },
     };
     
+    // A finished background job can flash the status line
+    let background = if editor.notifier.is_flashing() { Color::Yellow } else { Color::LightBlue };
     let status_bar = Paragraph::new(status)
-        .style(Style::default().bg(Color::LightBlue).fg(Color::Black).add_modifier(Modifier::BOLD));
+        .style(Style::default().bg(background).fg(Color::Black).add_modifier(Modifier::BOLD));
     
     f.render_widget(status_bar, area);
-}
\ No newline at end of file
+}
@@ -0,0 +1,128 @@
+use super::{apply_viewport_update, render};
+use crate::editor::Editor;
+use anyhow::{Context, Result};
+use similar::TextDiff;
+use std::path::Path;
+use tui::{
+    backend::TestBackend,
+    buffer::{Buffer, Cell},
+    Terminal,
+};
+
+/// Set to rewrite snapshot files with what's drawn now instead of
+/// comparing against them
+pub const UPDATE_VAR: &str = "ZIM_UPDATE_SNAPSHOTS";
+
+/// The screen as zim drew it off screen, for tests of what the UI shows
+pub struct Snapshot {
+    buffer: Buffer,
+}
+
+/// Draw `editor` on a `width` by `height` screen the way the main loop
+/// does: the first frame sizes the view and the second draws with it
+pub fn render_snapshot(editor: &mut Editor, width: u16, height: u16) -> Result<Snapshot> {
+    let mut terminal = Terminal::new(TestBackend::new(width, height))?;
+    for _ in 0..2 {
+        let mut viewport_update = None;
+        terminal.draw(|f| viewport_update = render(f, editor))?;
+        if let Some(update) = viewport_update {
+            apply_viewport_update(editor, update);
+        }
+    }
+    Ok(Snapshot { buffer: terminal.backend().buffer().clone() })
+}
+
+impl Snapshot {
+    /// The whole screen as text, one line per row with trailing spaces
+    /// left off
+    pub fn text(&self) -> String {
+        self.text_where(|_| true)
+    }
+
+    /// One row of the screen, like `text` but for a single line
+    pub fn row(&self, y: u16) -> String {
+        self.row_where(y, &|_| true)
+    }
+
+    /// The screen with only the cells `keep` picks, and spaces for the rest,
+    /// to check what's drawn in a style, like the selected text
+    pub fn text_where(&self, keep: impl Fn(&Cell) -> bool) -> String {
+        (0..self.buffer.area.height)
+            .map(|y| self.row_where(y, &keep))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    fn row_where(&self, y: u16, keep: &dyn Fn(&Cell) -> bool) -> String {
+        let row: String = (0..self.buffer.area.width)
+            .map(|x| self.buffer.get(x, y))
+            .map(|cell| if keep(cell) { cell.symbol.as_str() } else { " " })
+            .collect();
+        row.trim_end().to_string()
+    }
+
+    /// The cell at a column and row, to check its colors and modifiers
+    pub fn cell(&self, x: u16, y: u16) -> &Cell {
+        self.buffer.get(x, y)
+    }
+
+    /// Check the screen's text against the snapshot file at `path`,
+    /// panicking with a diff if they differ
+    ///
+    /// A missing file is written with the screen as it is now, and so is
+    /// every file when `ZIM_UPDATE_SNAPSHOTS` is set, to be reviewed and
+    /// committed with the test.
+    pub fn assert_matches(&self, path: impl AsRef<Path>) {
+        let path = path.as_ref();
+        let actual = format!("{}\n", self.text());
+        if std::env::var_os(UPDATE_VAR).is_some() || !path.exists() {
+            write_snapshot(path, &actual).unwrap_or_else(|e| panic!("{:#}", e));
+            return;
+        }
+        let expected = std::fs::read_to_string(path).unwrap_or_else(|e| panic!("Failed to read {}: {}", path.display(), e));
+        if expected != actual {
+            let diff = TextDiff::from_lines(&expected, &actual);
+            panic!(
+                "Screen differs from {} (set {}=1 to update it):\n{}",
+                path.display(),
+                UPDATE_VAR,
+                diff.unified_diff().header("expected", "actual"),
+            );
+        }
+    }
+}
+
+fn write_snapshot(path: &Path, text: &str) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    }
+    std::fs::write(path, text).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::editor::Mode;
+    use tui::style::Color;
+
+    #[test]
+    fn test_snapshots_show_the_text_and_styles_drawn() -> Result<()> {
+        let mut editor = Editor::new();
+        editor.mode = Mode::Normal;
+        editor.current_tab_mut().buffer.lines = vec!["hello world".to_string()];
+        let snapshot = render_snapshot(&mut editor, 40, 10)?;
+        assert!(snapshot.text().lines().any(|line| line.contains("hello world")), "{}", snapshot.text());
+        assert!(snapshot.text().contains("NORMAL"));
+        assert_eq!(snapshot.text().split('\n').count(), 10);
+
+        // Nothing is drawn in a color that isn't used
+        assert!(snapshot.text_where(|cell| cell.bg == Color::Rgb(1, 2, 3)).trim().is_empty());
+
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("screen.txt");
+        snapshot.assert_matches(&path);
+        assert_eq!(std::fs::read_to_string(&path)?, format!("{}\n", snapshot.text()));
+        snapshot.assert_matches(&path);
+        Ok(())
+    }
+}
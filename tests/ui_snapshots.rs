@@ -0,0 +1,98 @@
+use anyhow::Result;
+use std::path::Path;
+use tui::style::{Color, Modifier};
+use zim::config::Config;
+use zim::editor::{Cursor, DiagnosticCollection, Editor, Mode};
+use zim::ui::snapshot::render_snapshot;
+
+/// An editor in normal mode with a small Rust file in an unnamed buffer
+fn editor_with_code() -> Editor {
+    let mut editor = Editor::new_with_config(Config::default());
+    editor.mode = Mode::Normal;
+    editor.current_tab_mut().buffer.lines = ["fn main() {", "    let x: u32 = \"one\";", "}"]
+        .iter()
+        .map(|line| line.to_string())
+        .collect();
+    editor
+}
+
+#[test]
+fn test_tab_bar_lists_every_tab() -> Result<()> {
+    let mut editor = editor_with_code();
+    editor.add_tab();
+    editor.current_tab = 0;
+    let snapshot = render_snapshot(&mut editor, 60, 12)?;
+    snapshot.assert_matches("tests/snapshots/tab_bar.txt");
+    Ok(())
+}
+
+#[test]
+fn test_diagnostics_panel_lists_errors() -> Result<()> {
+    let mut editor = editor_with_code();
+    let output = "error[E0308]: mismatched types\n --> src/main.rs:2:18\n";
+    editor.current_tab_mut().diagnostics = DiagnosticCollection::new().parse_cargo_output(output, Path::new("."), "src/main.rs");
+    editor.mode = Mode::DiagnosticsPanel;
+    let snapshot = render_snapshot(&mut editor, 70, 24)?;
+    snapshot.assert_matches("tests/snapshots/diagnostics_panel.txt");
+    Ok(())
+}
+
+#[test]
+fn test_selection_is_highlighted() -> Result<()> {
+    let mut editor = editor_with_code();
+    let tab = editor.current_tab_mut();
+    tab.buffer.start_selection((0, 3));
+    tab.cursor = Cursor { x: 7, y: 0 };
+    editor.mode = Mode::Visual;
+    let snapshot = render_snapshot(&mut editor, 60, 12)?;
+    assert_eq!(snapshot.row(5), " │ 1 fn main() {                                          ━");
+    let selected = snapshot.text_where(|cell| cell.bg == Color::LightBlue);
+    assert_eq!(selected.lines().nth(5).map(str::trim), Some("main"));
+    Ok(())
+}
+
+#[test]
+fn test_diagnostics_show_after_their_line_and_in_a_popup() -> Result<()> {
+    let mut editor = editor_with_code();
+    let output = "error[E0308]: mismatched types\n --> src/main.rs:2:18\n  |\n  = note: expected `u32`, found `&str`\n";
+    editor.current_tab_mut().diagnostics = DiagnosticCollection::new().parse_cargo_output(output, Path::new("."), "src/main.rs");
+    let snapshot = render_snapshot(&mut editor, 60, 12)?;
+    assert!(snapshot.row(6).contains("let x: u32 = \"one\";    ■ mismatched types"), "{}", snapshot.row(6));
+
+    editor.current_tab_mut().cursor = Cursor { x: 4, y: 1 };
+    editor.handle_key(crossterm::event::KeyEvent::new(crossterm::event::KeyCode::Char('K'), crossterm::event::KeyModifiers::NONE))?;
+    let snapshot = render_snapshot(&mut editor, 60, 20)?;
+    assert!(snapshot.text().contains("error: mismatched types"));
+    assert!(snapshot.text().contains("expected `u32`, found `&str`"), "{}", snapshot.text());
+
+    editor.config.diagnostic_virtual_text = false;
+    editor.diagnostic_popup = None;
+    let snapshot = render_snapshot(&mut editor, 60, 12)?;
+    assert!(!snapshot.row(6).contains("■"));
+    Ok(())
+}
+
+#[test]
+fn test_ghost_text_shows_after_the_cursor() -> Result<()> {
+    let mut editor = editor_with_code();
+    editor.config.ai.command = Some("printf ' + 1;'".to_string());
+    editor.config.ai.inline_completion = true;
+    editor.config.ai.inline_delay_ms = 0;
+    editor.current_tab_mut().buffer.lines[1] = "    let x: u32 = 1".to_string();
+    editor.current_tab_mut().cursor = Cursor { x: 18, y: 1 };
+    editor.mode = Mode::Insert;
+    // Drawn once so the view knows its size before typing scrolls it
+    render_snapshot(&mut editor, 60, 12)?;
+    editor.handle_key(crossterm::event::KeyEvent::new(crossterm::event::KeyCode::Char('0'), crossterm::event::KeyModifiers::NONE))?;
+    let started = std::time::Instant::now();
+    while editor.ghost_text().is_none() && started.elapsed() < std::time::Duration::from_secs(5) {
+        editor.on_tick();
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+
+    let snapshot = render_snapshot(&mut editor, 60, 12)?;
+    assert!(snapshot.row(6).contains("let x: u32 = 10 + 1;"), "{}", snapshot.row(6));
+    let ghost = snapshot.text_where(|cell| cell.fg == Color::DarkGray && cell.modifier.contains(Modifier::ITALIC));
+    assert_eq!(ghost.lines().nth(6).map(str::trim), Some("+ 1;"));
+    Ok(())
+}